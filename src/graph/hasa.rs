@@ -75,6 +75,10 @@ impl Shape for HasA {
         Some(vec![self.primary.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.primary = f(self.primary.clone());
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::HasA
     }
@@ -0,0 +1,225 @@
+use super::Shape;
+use super::recursive::DEFAULT_MAX_RECURSIVE_STEPS;
+use super::super::refs::Ref;
+use super::super::quad::{QuadStore, Direction};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+// the quad of a link and the node at its other end
+type Link = (Ref, Ref);
+
+// a node on the path being extended, and the next of its links to try
+struct Frame {
+    node: u64,
+    links: Rc<Vec<Link>>,
+    next: usize
+}
+
+
+// Every simple path, one that never visits a node twice, from a node of
+// `from` to a node of `to` in at most max_depth links, following links from
+// their subject to their object with a predicate of `via`, or any without it.
+// Paths are found lazily, all paths of one length before the longer ones,
+// each as the quads of its links. Only nodes that can still reach a target
+// in the links left are visited.
+pub struct AllPaths {
+    qs: Rc<RefCell<dyn QuadStore>>,
+    from: Rc<RefCell<dyn Shape>>,
+    to: Rc<RefCell<dyn Shape>>,
+    via: Option<Rc<RefCell<dyn Shape>>>,
+    max_depth: i32,
+    limit: usize,
+    started: bool,
+    sources: Vec<Ref>,
+    preds: Option<HashSet<u64>>,
+    // the fewest links from a node to a target
+    dist: HashMap<u64, i32>,
+    links: HashMap<u64, Rc<Vec<Link>>>,
+    // the length of the paths being looked for, and the source they start at
+    depth: i32,
+    source: usize,
+    stack: Vec<Frame>,
+    path: Vec<Ref>,
+    on_path: HashSet<u64>,
+    found: usize,
+    err: Option<String>
+}
+
+impl AllPaths {
+    // with 0 for max_depth the default depth, and no limit for limit 0
+    pub fn new(qs: Rc<RefCell<dyn QuadStore>>, from: Rc<RefCell<dyn Shape>>, to: Rc<RefCell<dyn Shape>>, via: Option<Rc<RefCell<dyn Shape>>>, max_depth: i32, limit: usize) -> AllPaths {
+        AllPaths {
+            qs,
+            from,
+            to,
+            via,
+            max_depth: if max_depth == 0 { DEFAULT_MAX_RECURSIVE_STEPS } else { max_depth },
+            limit,
+            started: false,
+            sources: Vec::new(),
+            preds: None,
+            dist: HashMap::new(),
+            links: HashMap::new(),
+            depth: 1,
+            source: 0,
+            stack: Vec::new(),
+            path: Vec::new(),
+            on_path: HashSet::new(),
+            found: 0,
+            err: None
+        }
+    }
+
+    // the error that ended iteration, if any
+    pub fn err(&self) -> Option<String> {
+        self.err.clone()
+    }
+
+    fn keys(&mut self, s: &Rc<RefCell<dyn Shape>>) -> Vec<Ref> {
+        let it = s.borrow().iterate();
+        let mut res = Vec::new();
+        let mut seen = HashSet::new();
+        while it.borrow_mut().next() {
+            if let Some(r) = it.borrow().result().filter(|r| r.key().is_some_and(|k| seen.insert(k))) {
+                res.push(r);
+            }
+        }
+        self.err = self.err.take().or(it.borrow().err());
+        let _ = it.borrow_mut().close();
+        res
+    }
+
+    fn start(&mut self) {
+        self.started = true;
+        self.sources = self.keys(&self.from.clone());
+        if let Some(via) = self.via.clone() {
+            self.preds = Some(self.keys(&via).iter().filter_map(|r| r.key()).collect());
+        }
+
+        // back from the targets, as far as a path can be long
+        let mut level = self.keys(&self.to.clone());
+        for t in &level {
+            self.dist.insert(t.key().unwrap(), 0);
+        }
+        for d in 1..=self.max_depth {
+            let mut next = Vec::new();
+            for n in &level {
+                for (_, m) in self.links_at(n, Direction::Object, Direction::Subject) {
+                    if let std::collections::hash_map::Entry::Vacant(e) = self.dist.entry(m.key().unwrap()) {
+                        e.insert(d);
+                        next.push(m);
+                    }
+                }
+            }
+            if next.is_empty() {
+                break
+            }
+            level = next;
+        }
+    }
+
+    // the links with a node at start, and the nodes at their end
+    fn links_at(&self, node: &Ref, start: Direction, end: Direction) -> Vec<Link> {
+        let qs = self.qs.borrow();
+        let mut res = Vec::new();
+        let it = qs.quad_iterator(&start, node).borrow().iterate();
+        while it.borrow_mut().next() {
+            let q = it.borrow().result().unwrap();
+            if let Some(preds) = &self.preds {
+                let p = qs.quad_direction(&q, &Direction::Predicate).and_then(|p| p.key());
+                if !p.is_some_and(|p| preds.contains(&p)) {
+                    continue
+                }
+            }
+            if let Some(n) = qs.quad_direction(&q, &end).filter(|n| n.key().is_some()) {
+                res.push((q, n));
+            }
+        }
+        let _ = it.borrow_mut().close();
+        res
+    }
+
+    // the links leaving a node, to nodes that can reach a target
+    fn links(&mut self, node: &Ref) -> Rc<Vec<Link>> {
+        let k = node.key().unwrap();
+        if let Some(l) = self.links.get(&k) {
+            return l.clone()
+        }
+        let res: Vec<Link> = self.links_at(node, Direction::Subject, Direction::Object).into_iter()
+            .filter(|(_, m)| self.dist.contains_key(&m.key().unwrap()))
+            .collect();
+        let res = Rc::new(res);
+        self.links.insert(k, res.clone());
+        res
+    }
+
+    fn push(&mut self, node: &Ref) {
+        let links = self.links(node);
+        let k = node.key().unwrap();
+        self.on_path.insert(k);
+        self.stack.push(Frame { node: k, links, next: 0 });
+    }
+}
+
+impl Iterator for AllPaths {
+    type Item = Vec<Ref>;
+
+    fn next(&mut self) -> Option<Vec<Ref>> {
+        if !self.started {
+            self.start();
+        }
+        if self.err.is_some() || (self.limit > 0 && self.found >= self.limit) {
+            return None
+        }
+        loop {
+            let top = match self.stack.last_mut() {
+                Some(f) => f,
+                None => {
+                    // the next source, or the next length from the first one
+                    if self.source >= self.sources.len() {
+                        self.source = 0;
+                        self.depth += 1;
+                    }
+                    if self.depth > self.max_depth || self.sources.is_empty() {
+                        return None
+                    }
+                    let s = self.sources[self.source].clone();
+                    if self.dist.get(&s.key().unwrap()).is_some_and(|d| *d <= self.depth) {
+                        self.push(&s);
+                    } else {
+                        self.source += 1;
+                    }
+                    continue
+                }
+            };
+            if top.next >= top.links.len() {
+                let k = top.node;
+                self.on_path.remove(&k);
+                self.stack.pop();
+                self.path.pop();
+                if self.stack.is_empty() {
+                    self.source += 1;
+                }
+                continue
+            }
+            let (q, m) = top.links[top.next].clone();
+            top.next += 1;
+
+            let k = m.key().unwrap();
+            let left = self.depth - self.path.len() as i32 - 1;
+            if self.on_path.contains(&k) || self.dist.get(&k).is_none_or(|d| *d > left) {
+                continue
+            }
+            if left == 0 {
+                let mut found = self.path.clone();
+                found.push(q);
+                self.found += 1;
+                return Some(found)
+            }
+            self.path.push(q);
+            self.push(&m);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use super::{Base, Shape, Scanner, Costs, Index, Null, height, is_null, ShapeType};
 use super::materialize::Materialize;
+use super::join;
+use super::bitmap::{self, Bitmap, BitmapSet};
 use super::super::refs;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -110,8 +112,21 @@ impl Shape for And {
             return Some(out.unwrap())
         }
 
+        if self.opt.is_none() {
+            if let Some(b) = intersect_bitmaps(&its) {
+                return Some(BitmapSet::new(Rc::new(b)))
+            }
+        }
+
         let its = optimize_order(&its);
 
+        // two iterators that are both large can be cheaper to scan side by side
+        if its.len() == 2 && self.opt.is_none() {
+            if let Some(j) = join::join(&its[0], &its[1]) {
+                return Some(j)
+            }
+        }
+
         let its = materialize_its(&its).unwrap(); // TODO: why is there even an error?
 
         let new_and = And::new(its);
@@ -142,11 +157,59 @@ impl Shape for And {
         Some(iters)
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        let mapped: Vec<Rc<RefCell<dyn Shape>>> = self.sub.iter().map(|s| f(s.clone())).collect();
+
+        // the check list holds the same iterators as sub in contains-cost order
+        if let Some(check_list) = self.check_list.as_mut() {
+            for c in check_list.iter_mut() {
+                if let Some(i) = self.sub.iter().position(|s| Rc::ptr_eq(s, c)) {
+                    *c = mapped[i].clone();
+                }
+            }
+        }
+
+        self.sub = mapped;
+
+        if let Some(opt) = self.opt.as_mut() {
+            *opt = opt.iter().map(|s| f(s.clone())).collect();
+        }
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::And
     }
 }
 
+// Intersects the sub-iterators a set at a time when all of them can be
+// listed as bitmaps, the values of a Not are subtracted instead.
+fn intersect_bitmaps(its: &Vec<Rc<RefCell<dyn Shape>>>) -> Option<Bitmap> {
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    for it in its {
+        match it.borrow_mut().shape_type() {
+            ShapeType::Not(n) => exclude.push(n.primary()),
+            _ => include.push(it.clone())
+        }
+    }
+    if include.is_empty() {
+        return None
+    }
+
+    let n = include.len();
+    include.extend(exclude);
+    let bits = bitmap::bitmaps(&include)?;
+
+    let mut res = (*bits[0]).clone();
+    for b in &bits[1..n] {
+        res = res.and(b);
+    }
+    for b in &bits[n..] {
+        res = res.and_not(b);
+    }
+    Some(res)
+}
+
 fn optimize_replacement(its: &Vec<Rc<RefCell<dyn Shape>>>) -> Option<Rc<RefCell<dyn Shape>>> {
 
     if its.is_empty() {
@@ -0,0 +1,451 @@
+use super::{Shape, Base, Index, Scanner, Costs, ShapeType};
+use super::super::refs::{Ref, Size, Content};
+use std::collections::{BTreeMap, HashMap};
+use std::iter::FromIterator;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+// a container switches from a sorted array to a bitset above this many values
+const ARRAY_MAX: usize = 4096;
+const WORDS: usize = 1024;
+
+// below this many input results sets are combined one contains at a time
+const BITMAP_MIN_SIZE: i64 = 64;
+
+
+// The low 16 bits of the values sharing the same high bits.
+#[derive(Debug, Clone)]
+enum Container {
+    Array(Vec<u16>),
+    Bits(Box<[u64; WORDS]>)
+}
+
+impl Container {
+    fn len(&self) -> usize {
+        match self {
+            Container::Array(a) => a.len(),
+            Container::Bits(b) => b.iter().map(|w| w.count_ones() as usize).sum()
+        }
+    }
+
+    fn contains(&self, v: u16) -> bool {
+        match self {
+            Container::Array(a) => a.binary_search(&v).is_ok(),
+            Container::Bits(b) => b[v as usize / 64] & (1 << (v % 64)) != 0
+        }
+    }
+
+    fn insert(&mut self, v: u16) {
+        match self {
+            Container::Array(a) => {
+                if let Err(i) = a.binary_search(&v) {
+                    a.insert(i, v);
+                    if a.len() > ARRAY_MAX {
+                        *self = Container::Bits(to_bits(a));
+                    }
+                }
+            },
+            Container::Bits(b) => b[v as usize / 64] |= 1 << (v % 64)
+        }
+    }
+
+    // the smallest value not below v
+    fn seek(&self, v: u16) -> Option<u16> {
+        match self {
+            Container::Array(a) => a.get(a.partition_point(|x| *x < v)).copied(),
+            Container::Bits(b) => {
+                let mut i = v as usize / 64;
+                let mut w = b[i] & (!0u64 << (v % 64));
+                loop {
+                    if w != 0 {
+                        return Some((i * 64 + w.trailing_zeros() as usize) as u16)
+                    }
+                    i += 1;
+                    if i == WORDS {
+                        return None
+                    }
+                    w = b[i];
+                }
+            }
+        }
+    }
+
+    fn and(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Array(a), Container::Array(b)) => {
+                let mut res = Vec::new();
+                let (mut i, mut j) = (0, 0);
+                while i < a.len() && j < b.len() {
+                    if a[i] < b[j] {
+                        i += 1;
+                    } else if a[i] > b[j] {
+                        j += 1;
+                    } else {
+                        res.push(a[i]);
+                        i += 1;
+                        j += 1;
+                    }
+                }
+                Container::Array(res)
+            },
+            (Container::Array(a), b) | (b, Container::Array(a)) => {
+                Container::Array(a.iter().copied().filter(|v| b.contains(*v)).collect())
+            },
+            (Container::Bits(a), Container::Bits(b)) => from_words(|i| a[i] & b[i])
+        }
+    }
+
+    fn or(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Array(a), Container::Array(b)) if a.len() + b.len() <= ARRAY_MAX => {
+                let mut res = Vec::with_capacity(a.len() + b.len());
+                let (mut i, mut j) = (0, 0);
+                while i < a.len() || j < b.len() {
+                    if j == b.len() || (i < a.len() && a[i] < b[j]) {
+                        res.push(a[i]);
+                        i += 1;
+                    } else {
+                        if i < a.len() && a[i] == b[j] {
+                            i += 1;
+                        }
+                        res.push(b[j]);
+                        j += 1;
+                    }
+                }
+                Container::Array(res)
+            },
+            (a, b) => {
+                let (a, b) = (words(a), words(b));
+                from_words(|i| a[i] | b[i])
+            }
+        }
+    }
+
+    fn and_not(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Array(a), b) => Container::Array(a.iter().copied().filter(|v| !b.contains(*v)).collect()),
+            (Container::Bits(a), b) => {
+                let b = words(b);
+                from_words(|i| a[i] & !b[i])
+            }
+        }
+    }
+}
+
+fn to_bits(a: &[u16]) -> Box<[u64; WORDS]> {
+    let mut b = Box::new([0u64; WORDS]);
+    for v in a {
+        b[*v as usize / 64] |= 1 << (v % 64);
+    }
+    b
+}
+
+fn words(c: &Container) -> Box<[u64; WORDS]> {
+    match c {
+        Container::Array(a) => to_bits(a),
+        Container::Bits(b) => b.clone()
+    }
+}
+
+// builds a container from bitset words, as an array if it is small enough
+fn from_words(f: impl Fn(usize) -> u64) -> Container {
+    let mut b = Box::new([0u64; WORDS]);
+    let mut len = 0;
+    for (i, w) in b.iter_mut().enumerate() {
+        *w = f(i);
+        len += w.count_ones() as usize;
+    }
+    if len > ARRAY_MAX {
+        return Container::Bits(b)
+    }
+    let mut a = Vec::with_capacity(len);
+    for (i, w) in b.iter().enumerate() {
+        let mut w = *w;
+        while w != 0 {
+            a.push((i * 64 + w.trailing_zeros() as usize) as u16);
+            w &= w - 1;
+        }
+    }
+    Container::Array(a)
+}
+
+
+// A compressed set of keys, split by their high bits into sorted arrays
+// for sparse ranges and bitsets for dense ones, so sets can be intersected,
+// merged and subtracted a container at a time.
+#[derive(Debug, Clone, Default)]
+pub struct Bitmap {
+    containers: BTreeMap<u64, Container>
+}
+
+impl Bitmap {
+    pub fn new() -> Bitmap {
+        Bitmap {
+            containers: BTreeMap::new()
+        }
+    }
+
+    pub fn insert(&mut self, v: u64) {
+        self.containers.entry(v >> 16).or_insert_with(|| Container::Array(Vec::new())).insert(v as u16);
+    }
+
+    pub fn contains(&self, v: u64) -> bool {
+        self.containers.get(&(v >> 16)).is_some_and(|c| c.contains(v as u16))
+    }
+
+    pub fn len(&self) -> usize {
+        self.containers.values().map(|c| c.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.containers.is_empty()
+    }
+
+    // the smallest value not below v
+    pub fn seek(&self, v: u64) -> Option<u64> {
+        for (high, c) in self.containers.range(v >> 16..) {
+            let low = if *high == v >> 16 { v as u16 } else { 0 };
+            if let Some(l) = c.seek(low) {
+                return Some(high << 16 | l as u64)
+            }
+        }
+        None
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.containers.iter().flat_map(|(high, c)| {
+            let high = *high;
+            let mut next = c.seek(0);
+            std::iter::from_fn(move || {
+                let v = next?;
+                next = if v == u16::MAX { None } else { c.seek(v + 1) };
+                Some(high << 16 | v as u64)
+            })
+        })
+    }
+
+    pub fn and(&self, other: &Bitmap) -> Bitmap {
+        let mut res = Bitmap::new();
+        for (high, a) in &self.containers {
+            if let Some(b) = other.containers.get(high) {
+                res.put(*high, a.and(b));
+            }
+        }
+        res
+    }
+
+    pub fn or(&self, other: &Bitmap) -> Bitmap {
+        let mut res = self.clone();
+        for (high, b) in &other.containers {
+            let c = match res.containers.get(high) {
+                Some(a) => a.or(b),
+                None => b.clone()
+            };
+            res.containers.insert(*high, c);
+        }
+        res
+    }
+
+    pub fn and_not(&self, other: &Bitmap) -> Bitmap {
+        let mut res = Bitmap::new();
+        for (high, a) in &self.containers {
+            match other.containers.get(high) {
+                Some(b) => res.put(*high, a.and_not(b)),
+                None => res.put(*high, a.clone())
+            }
+        }
+        res
+    }
+
+    // keeps only non-empty containers
+    fn put(&mut self, high: u64, c: Container) {
+        if c.len() > 0 {
+            self.containers.insert(high, c);
+        }
+    }
+}
+
+impl FromIterator<u64> for Bitmap {
+    fn from_iter<I: IntoIterator<Item = u64>>(iter: I) -> Bitmap {
+        let mut b = Bitmap::new();
+        for v in iter {
+            b.insert(v);
+        }
+        b
+    }
+}
+
+
+// The bitmap of every iterator, None if one of them can't produce it. Only
+// tried when the iterators hold enough results for it to pay off.
+pub fn bitmaps(its: &[Rc<RefCell<dyn Shape>>]) -> Option<Vec<Rc<Bitmap>>> {
+    let mut size = 0;
+    for it in its {
+        size += it.borrow_mut().stats().ok()?.size.value;
+    }
+    if size < BITMAP_MIN_SIZE {
+        return None
+    }
+    its.iter().map(|it| it.borrow_mut().bitmap()).collect()
+}
+
+
+// Iterates the keys of a bitmap in ascending order.
+pub struct BitmapSet {
+    bits: Rc<Bitmap>
+}
+
+impl BitmapSet {
+    pub fn new(bits: Rc<Bitmap>) -> Rc<RefCell<BitmapSet>> {
+        Rc::new(RefCell::new(BitmapSet {
+            bits
+        }))
+    }
+}
+
+impl Shape for BitmapSet {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        BitmapNext::new(self.bits.clone())
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        BitmapContains::new(self.bits.clone())
+    }
+
+    fn stats(&mut self) -> Result<Costs, String> {
+        Ok(Costs {
+            contains_cost: 1,
+            next_cost: 1,
+            size: Size {
+                value: self.bits.len() as i64,
+                exact: true
+            }
+        })
+    }
+
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        None
+    }
+
+    fn sorted(&mut self) -> bool {
+        true
+    }
+
+    fn bitmap(&mut self) -> Option<Rc<Bitmap>> {
+        Some(self.bits.clone())
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::Bitmap
+    }
+}
+
+
+fn key_ref(k: Option<u64>) -> Option<Ref> {
+    k.map(|k| Ref {
+        k: Some(k),
+        content: Content::None
+    })
+}
+
+
+struct BitmapNext {
+    bits: Rc<Bitmap>,
+    cur: Option<u64>,
+    done: bool
+}
+
+impl BitmapNext {
+    fn new(bits: Rc<Bitmap>) -> Rc<RefCell<BitmapNext>> {
+        Rc::new(RefCell::new(BitmapNext {
+            bits,
+            cur: None,
+            done: false
+        }))
+    }
+}
+
+impl Base for BitmapNext {
+    fn tag_results(&self, _tags: &mut HashMap<String, Ref>) {}
+
+    fn result(&self) -> Option<Ref> {
+        key_ref(self.cur)
+    }
+
+    fn next_path(&mut self) -> bool {
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        None
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl Scanner for BitmapNext {
+    fn next(&mut self) -> bool {
+        if self.done {
+            return false
+        }
+        self.cur = match self.cur {
+            None => self.bits.seek(0),
+            Some(u64::MAX) => None,
+            Some(c) => self.bits.seek(c + 1)
+        };
+        if self.cur.is_none() {
+            self.done = true;
+            return false
+        }
+        true
+    }
+}
+
+
+struct BitmapContains {
+    bits: Rc<Bitmap>,
+    cur: Option<u64>
+}
+
+impl BitmapContains {
+    fn new(bits: Rc<Bitmap>) -> Rc<RefCell<BitmapContains>> {
+        Rc::new(RefCell::new(BitmapContains {
+            bits,
+            cur: None
+        }))
+    }
+}
+
+impl Base for BitmapContains {
+    fn tag_results(&self, _tags: &mut HashMap<String, Ref>) {}
+
+    fn result(&self) -> Option<Ref> {
+        key_ref(self.cur)
+    }
+
+    fn next_path(&mut self) -> bool {
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        None
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl Index for BitmapContains {
+    fn contains(&mut self, v: &Ref) -> bool {
+        self.cur = v.key().filter(|k| self.bits.contains(*k));
+        self.cur.is_some()
+    }
+}
@@ -0,0 +1,476 @@
+use super::{Shape, Base, Index, Scanner, Costs, Morphism, ShapeType};
+use super::super::refs;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContextError {
+    Cancelled,
+    Timeout,
+    // more rows than the limit were produced
+    RowLimit(i64),
+    // materializing iterators buffered more results than the limit
+    MaterializeLimit(i64)
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContextError::Cancelled => write!(f, "query cancelled"),
+            ContextError::Timeout => write!(f, "query timed out"),
+            ContextError::RowLimit(n) => write!(f, "query returned more than {} rows", n),
+            ContextError::MaterializeLimit(n) => write!(f, "query buffered more than {} results", n)
+        }
+    }
+}
+
+
+// Cancels the queries of a QueryContext. Can be sent to another thread.
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+
+#[derive(Debug, Default)]
+struct RunState {
+    deadline: Option<Instant>,
+    rows: i64,
+    err: Option<ContextError>
+}
+
+
+// Limits for a single query run. Every iterator of the tree checks it
+// before doing any work and stops with an error once a limit is hit.
+#[derive(Debug, Clone)]
+pub struct QueryContext {
+    deadline: Option<Instant>,
+    timeout: Option<Duration>,
+    max_rows: Option<i64>,
+    max_materialized: Option<i64>,
+    cancelled: Arc<AtomicBool>,
+    // shared with the workers of a parallel query
+    materialized: Arc<AtomicI64>,
+    state: Rc<RefCell<RunState>>
+}
+
+impl QueryContext {
+    pub fn new() -> QueryContext {
+        QueryContext {
+            deadline: None,
+            timeout: None,
+            max_rows: None,
+            max_materialized: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            materialized: Arc::new(AtomicI64::new(0)),
+            state: Rc::new(RefCell::new(RunState::default()))
+        }
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> QueryContext {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // the clock starts when the query starts iterating
+    pub fn with_timeout(mut self, timeout: Duration) -> QueryContext {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_max_rows(mut self, max_rows: i64) -> QueryContext {
+        self.max_rows = Some(max_rows);
+        self
+    }
+
+    // caps the number of results held by Materialize, Sort, Unique and Recursive
+    pub fn with_max_materialized(mut self, max_materialized: i64) -> QueryContext {
+        self.max_materialized = Some(max_materialized);
+        self
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle {
+            cancelled: self.cancelled.clone()
+        }
+    }
+
+    // the error that ended the last run, if any
+    pub fn err(&self) -> Option<ContextError> {
+        self.state.borrow().err.clone()
+    }
+
+    fn start(&self) {
+        let mut state = self.state.borrow_mut();
+        let deadline = self.timeout.map(|t| Instant::now() + t);
+        state.deadline = match (self.deadline, deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        };
+        state.rows = 0;
+        state.err = None;
+        self.materialized.store(0, Ordering::Relaxed);
+    }
+
+    // the limits of the current run, for the worker threads of a parallel query
+    pub fn worker(&self) -> WorkerContext {
+        WorkerContext {
+            deadline: self.state.borrow().deadline.or(self.deadline),
+            max_materialized: self.max_materialized,
+            cancelled: self.cancelled.clone(),
+            materialized: self.materialized.clone()
+        }
+    }
+
+    // ends the run with an error found on another thread
+    pub fn fail_with(&self, err: ContextError) {
+        self.fail(err);
+    }
+
+    fn fail(&self, err: ContextError) -> bool {
+        self.state.borrow_mut().err = Some(err);
+        false
+    }
+
+    // false once the run has to stop, the reason is left in err()
+    pub fn check(&self) -> bool {
+        if self.state.borrow().err.is_some() {
+            return false
+        }
+        if self.cancelled.load(Ordering::Relaxed) {
+            return self.fail(ContextError::Cancelled)
+        }
+        let deadline = self.state.borrow().deadline.or(self.deadline);
+        if let Some(d) = deadline {
+            if Instant::now() >= d {
+                return self.fail(ContextError::Timeout)
+            }
+        }
+        true
+    }
+
+    fn add_row(&self) -> bool {
+        let rows = {
+            let mut state = self.state.borrow_mut();
+            state.rows += 1;
+            state.rows
+        };
+        match self.max_rows {
+            Some(max) if rows > max => self.fail(ContextError::RowLimit(max)),
+            _ => true
+        }
+    }
+
+    fn add_materialized(&self) -> bool {
+        let materialized = self.materialized.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.max_materialized {
+            Some(max) if materialized > max => self.fail(ContextError::MaterializeLimit(max)),
+            _ => true
+        }
+    }
+}
+
+impl Default for QueryContext {
+    fn default() -> Self {
+        QueryContext::new()
+    }
+}
+
+
+// The deadline, cancellation and materialize limit of a run, sent to the
+// worker threads of a parallel query. Rows are counted where the results
+// are read, the buffered results are counted for the whole query.
+#[derive(Debug, Clone)]
+pub struct WorkerContext {
+    deadline: Option<Instant>,
+    max_materialized: Option<i64>,
+    cancelled: Arc<AtomicBool>,
+    materialized: Arc<AtomicI64>
+}
+
+impl WorkerContext {
+    // a context for the iterators built on this thread
+    pub fn context(&self) -> QueryContext {
+        QueryContext {
+            deadline: self.deadline,
+            timeout: None,
+            max_rows: None,
+            max_materialized: self.max_materialized,
+            cancelled: self.cancelled.clone(),
+            materialized: self.materialized.clone(),
+            state: Rc::new(RefCell::new(RunState::default()))
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct Counts {
+    // the node is the root, every result is a row
+    rows: bool,
+    // every result is held in memory by the parent
+    materialized: bool
+}
+
+
+// Checks the context around every call to the wrapped shape's iterators.
+pub struct Guard {
+    it: Rc<RefCell<dyn Shape>>,
+    ctx: QueryContext,
+    counts: Counts
+}
+
+impl Guard {
+    fn new(it: Rc<RefCell<dyn Shape>>, ctx: QueryContext, counts: Counts) -> Rc<RefCell<Guard>> {
+        Rc::new(RefCell::new(Guard {
+            it,
+            ctx,
+            counts
+        }))
+    }
+}
+
+impl Shape for Guard {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        if self.counts.rows {
+            self.ctx.start();
+        }
+        GuardNext::new(self.it.borrow().iterate(), self.ctx.clone(), self.counts)
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        if self.counts.rows {
+            self.ctx.start();
+        }
+        GuardContains::new(self.it.borrow().lookup(), self.ctx.clone(), self.counts)
+    }
+
+    fn stats(&mut self) -> Result<Costs, String> {
+        self.it.borrow_mut().stats()
+    }
+
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        let res = self.it.borrow_mut().optimize();
+        if let Some(it) = res {
+            self.it = it;
+        }
+        None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        Some(vec![self.it.clone()])
+    }
+
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.it = f(self.it.clone());
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::Guard
+    }
+}
+
+
+// checks the context before and after a call and counts its result, a
+// result found while a limit was hit below is not returned
+fn guarded(ctx: &QueryContext, counts: Counts, call: impl FnOnce() -> bool) -> bool {
+    if !ctx.check() {
+        return false
+    }
+    if !call() || !ctx.check() {
+        return false
+    }
+    if counts.rows && !ctx.add_row() {
+        return false
+    }
+    if counts.materialized && !ctx.add_materialized() {
+        return false
+    }
+    true
+}
+
+
+struct GuardNext {
+    it: Rc<RefCell<dyn Scanner>>,
+    ctx: QueryContext,
+    counts: Counts
+}
+
+impl GuardNext {
+    fn new(it: Rc<RefCell<dyn Scanner>>, ctx: QueryContext, counts: Counts) -> Rc<RefCell<GuardNext>> {
+        Rc::new(RefCell::new(GuardNext {
+            it,
+            ctx,
+            counts
+        }))
+    }
+}
+
+impl Base for GuardNext {
+    fn tag_results(&self, dst: &mut HashMap<String, refs::Ref>) {
+        self.it.borrow().tag_results(dst)
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.it.borrow().result()
+    }
+
+    fn next_path(&mut self) -> bool {
+        let it = &self.it;
+        guarded(&self.ctx, self.counts, || it.borrow_mut().next_path())
+    }
+
+    fn err(&self) -> Option<String> {
+        match self.ctx.err() {
+            Some(e) => Some(e.to_string()),
+            None => self.it.borrow().err()
+        }
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.it.borrow_mut().close()
+    }
+}
+
+impl Scanner for GuardNext {
+    fn next(&mut self) -> bool {
+        let it = &self.it;
+        guarded(&self.ctx, self.counts, || it.borrow_mut().next())
+    }
+}
+
+
+struct GuardContains {
+    it: Rc<RefCell<dyn Index>>,
+    ctx: QueryContext,
+    counts: Counts
+}
+
+impl GuardContains {
+    fn new(it: Rc<RefCell<dyn Index>>, ctx: QueryContext, counts: Counts) -> Rc<RefCell<GuardContains>> {
+        Rc::new(RefCell::new(GuardContains {
+            it,
+            ctx,
+            counts
+        }))
+    }
+}
+
+impl Base for GuardContains {
+    fn tag_results(&self, dst: &mut HashMap<String, refs::Ref>) {
+        self.it.borrow().tag_results(dst)
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.it.borrow().result()
+    }
+
+    fn next_path(&mut self) -> bool {
+        let it = &self.it;
+        guarded(&self.ctx, self.counts, || it.borrow_mut().next_path())
+    }
+
+    fn err(&self) -> Option<String> {
+        match self.ctx.err() {
+            Some(e) => Some(e.to_string()),
+            None => self.it.borrow().err()
+        }
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.it.borrow_mut().close()
+    }
+}
+
+impl Index for GuardContains {
+    fn contains(&mut self, v: &refs::Ref) -> bool {
+        let it = &self.it;
+        guarded(&self.ctx, self.counts, || it.borrow_mut().contains(v))
+    }
+}
+
+
+// Guards the trees a Recursive iterator builds at every depth, and the
+// ones CountPer builds for every node.
+struct GuardMorphism {
+    morphism: Rc<dyn Morphism>,
+    ctx: QueryContext
+}
+
+impl Morphism for GuardMorphism {
+    fn morph(&self, shape: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>> {
+        wrap(self.morphism.morph(shape), &self.ctx, Counts { rows: false, materialized: false })
+    }
+}
+
+
+fn wrap(it: Rc<RefCell<dyn Shape>>, ctx: &QueryContext, counts: Counts) -> Rc<RefCell<dyn Shape>> {
+    let mut materialized = counts.materialized;
+
+    let buffers_sub = match it.borrow_mut().shape_type() {
+        ShapeType::Materialize | ShapeType::Sort | ShapeType::Unique => true,
+        ShapeType::Recursive(r) => {
+            // recursive remembers everything it has returned
+            materialized = true;
+            r.set_morphism(Rc::new(GuardMorphism {
+                morphism: r.morphism(),
+                ctx: ctx.clone()
+            }));
+            false
+        }
+        ShapeType::CountPer(c) => {
+            c.set_morphism(Rc::new(GuardMorphism {
+                morphism: c.morphism(),
+                ctx: ctx.clone()
+            }));
+            false
+        }
+        ShapeType::Parallel(p) => {
+            p.set_context(ctx.clone());
+            false
+        }
+        // searches check it as they expand nodes
+        ShapeType::ShortestPath(p) => {
+            p.set_context(ctx.clone());
+            false
+        }
+        ShapeType::WeightedPath(p) => {
+            p.set_context(ctx.clone());
+            false
+        }
+        _ => false
+    };
+
+    let sub_counts = Counts { rows: false, materialized: buffers_sub };
+    it.borrow_mut().map_sub_iterators(&|s| wrap(s, ctx, sub_counts));
+
+    Guard::new(it, ctx.clone(), Counts { rows: counts.rows, materialized })
+}
+
+// Guards a branch a parallel worker built, its rows are counted by the
+// reader.
+pub fn guard_branch(it: Rc<RefCell<dyn Shape>>, ctx: &QueryContext) -> Rc<RefCell<dyn Shape>> {
+    wrap(it, ctx, Counts { rows: false, materialized: false })
+}
+
+// Wraps every node of the tree so iteration stops as soon as
+// the context is cancelled, times out or a limit is exceeded.
+pub fn guard(it: Rc<RefCell<dyn Shape>>, ctx: &QueryContext) -> Rc<RefCell<dyn Shape>> {
+    wrap(it, ctx, Counts { rows: true, materialized: false })
+}
@@ -1,4 +1,6 @@
-use super::{Shape, Base, Index, Scanner, Costs, ShapeType};
+use super::{Shape, Base, Index, Scanner, Costs, ShapeType, Morphism};
+use super::fixed::Fixed;
+use super::value_filter::Operator;
 use super::super::refs;
 use super::super::value::Value;
 use std::collections::HashMap;
@@ -61,6 +63,10 @@ impl Shape for Count {
         return Some(vec![self.it.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.it = f(self.it.clone());
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::Count
     }
@@ -204,4 +210,251 @@ impl Index for CountContains {
         }
         false
     }
-}
\ No newline at end of file
+}
+
+
+
+// Every node of `it` with the number of results of the morphism from it,
+// saved as `tag` when given. With a filter only the nodes whose number
+// compares to the bound as the operator says are kept.
+pub struct CountPer {
+    it: Rc<RefCell<dyn Shape>>,
+    morphism: Rc<dyn Morphism>,
+    tag: Option<String>,
+    filter: Option<(Operator, i64)>
+}
+
+impl CountPer {
+    pub fn new(it: Rc<RefCell<dyn Shape>>, morphism: Rc<dyn Morphism>, tag: Option<String>, filter: Option<(Operator, i64)>) -> Rc<RefCell<CountPer>> {
+        Rc::new(RefCell::new(CountPer {
+            it,
+            morphism,
+            tag,
+            filter
+        }))
+    }
+
+    pub fn morphism(&self) -> Rc<dyn Morphism> {
+        self.morphism.clone()
+    }
+
+    pub fn set_morphism(&mut self, morphism: Rc<dyn Morphism>) {
+        self.morphism = morphism;
+    }
+
+    fn counter(&self) -> Counter {
+        Counter {
+            morphism: self.morphism.clone(),
+            tag: self.tag.clone(),
+            filter: self.filter.clone(),
+            count: 0
+        }
+    }
+}
+
+
+impl Shape for CountPer {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        CountPerNext::new(self.it.borrow().iterate(), self.counter())
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        CountPerContains::new(self.it.borrow().lookup(), self.counter())
+    }
+
+    fn stats(&mut self) -> Result<Costs, String> {
+        let base = Fixed::new(Vec::new());
+        base.borrow_mut().add(refs::Ref::new_i64_node(20));
+
+        let fanout = self.morphism.morph(base).borrow_mut().stats()?;
+        let sub = self.it.borrow_mut().stats()?;
+        let per_node = fanout.next_cost * fanout.size.value.max(1);
+        Ok(Costs {
+            next_cost: sub.next_cost + per_node,
+            contains_cost: sub.contains_cost + per_node,
+            size: refs::Size {
+                value: sub.size.value,
+                exact: sub.size.exact && self.filter.is_none()
+            }
+        })
+    }
+
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        let optimized = self.it.borrow_mut().optimize();
+        if optimized.is_some() { self.it = optimized.unwrap(); }
+        return None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        return Some(vec![self.it.clone()])
+    }
+
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.it = f(self.it.clone());
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::CountPer(self)
+    }
+}
+
+
+// counts the results from one node at a time
+struct Counter {
+    morphism: Rc<dyn Morphism>,
+    tag: Option<String>,
+    filter: Option<(Operator, i64)>,
+    count: i64
+}
+
+impl Counter {
+    // whether the node is kept, counting no further than needed to tell
+    // when the count isn't tagged
+    fn count(&mut self, node: &refs::Ref) -> Result<bool, String> {
+        let enough = match (&self.tag, &self.filter) {
+            (None, Some((Operator::GT, b))) | (None, Some((Operator::LTE, b))) => Some(b.saturating_add(1)),
+            (None, Some((Operator::GTE, b))) | (None, Some((Operator::LT, b))) => Some(*b),
+            _ => None
+        };
+
+        let it = self.morphism.morph(Fixed::new(vec![node.clone()])).borrow().iterate();
+        self.count = 0;
+        while enough.is_none_or(|e| self.count < e) && it.borrow_mut().next() {
+            self.count += 1;
+        }
+        let err = it.borrow().err();
+        let _ = it.borrow_mut().close();
+        if let Some(e) = err {
+            return Err(e)
+        }
+
+        Ok(match &self.filter {
+            None => true,
+            Some((Operator::GT, b)) => self.count > *b,
+            Some((Operator::GTE, b)) => self.count >= *b,
+            Some((Operator::LT, b)) => self.count < *b,
+            Some((Operator::LTE, b)) => self.count <= *b
+        })
+    }
+
+    fn tag_results(&self, tags: &mut HashMap<String, refs::Ref>) {
+        if let Some(t) = &self.tag {
+            tags.insert(t.clone(), refs::pre_fetched(Value::from(self.count)));
+        }
+    }
+}
+
+
+struct CountPerNext {
+    it: Rc<RefCell<dyn Scanner>>,
+    counter: Counter,
+    err: Option<String>
+}
+
+impl CountPerNext {
+    fn new(it: Rc<RefCell<dyn Scanner>>, counter: Counter) -> Rc<RefCell<CountPerNext>> {
+        Rc::new(RefCell::new(CountPerNext {
+            it,
+            counter,
+            err: None
+        }))
+    }
+}
+
+impl Base for CountPerNext {
+    fn tag_results(&self, tags: &mut HashMap<String, refs::Ref>) {
+        self.it.borrow().tag_results(tags);
+        self.counter.tag_results(tags);
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.it.borrow().result()
+    }
+
+    fn next_path(&mut self) -> bool {
+        self.it.borrow_mut().next_path()
+    }
+
+    fn err(&self) -> Option<String> {
+        self.err.clone().or(self.it.borrow().err())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.it.borrow_mut().close()
+    }
+}
+
+impl Scanner for CountPerNext {
+    fn next(&mut self) -> bool {
+        while self.it.borrow_mut().next() {
+            let node = match self.it.borrow().result() {
+                Some(n) => n,
+                None => continue
+            };
+            match self.counter.count(&node) {
+                Ok(true) => return true,
+                Ok(false) => continue,
+                Err(e) => {
+                    self.err = Some(e);
+                    return false
+                }
+            }
+        }
+        false
+    }
+}
+
+
+struct CountPerContains {
+    it: Rc<RefCell<dyn Index>>,
+    counter: Counter,
+    err: Option<String>
+}
+
+impl CountPerContains {
+    fn new(it: Rc<RefCell<dyn Index>>, counter: Counter) -> Rc<RefCell<CountPerContains>> {
+        Rc::new(RefCell::new(CountPerContains {
+            it,
+            counter,
+            err: None
+        }))
+    }
+}
+
+impl Base for CountPerContains {
+    fn tag_results(&self, tags: &mut HashMap<String, refs::Ref>) {
+        self.it.borrow().tag_results(tags);
+        self.counter.tag_results(tags);
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.it.borrow().result()
+    }
+
+    fn next_path(&mut self) -> bool {
+        self.it.borrow_mut().next_path()
+    }
+
+    fn err(&self) -> Option<String> {
+        self.err.clone().or(self.it.borrow().err())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.it.borrow_mut().close()
+    }
+}
+
+impl Index for CountPerContains {
+    fn contains(&mut self, v:&refs::Ref) -> bool {
+        if !self.it.borrow_mut().contains(v) {
+            return false
+        }
+        match self.counter.count(v) {
+            Ok(keep) => keep,
+            Err(e) => {
+                self.err = Some(e);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use super::{Shape, ShapeType, Costs};
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fmt;
+use serde_json::json;
+
+
+// Describes one node of an iterator tree as it will be run.
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub kind: String,
+    pub tags: Vec<String>,
+    pub costs: Result<Costs, String>,
+    // true if the node was created by optimize() rather than built from the query shape
+    pub replaced: bool,
+    pub sub: Vec<Explanation>
+}
+
+impl Explanation {
+    pub fn to_json(&self) -> serde_json::Value {
+        let costs = match &self.costs {
+            Ok(c) => json!({
+                "next_cost": c.next_cost,
+                "contains_cost": c.contains_cost,
+                "size": c.size.value,
+                "exact": c.size.exact
+            }),
+            Err(e) => json!({ "error": e })
+        };
+
+        json!({
+            "kind": self.kind,
+            "tags": self.tags,
+            "costs": costs,
+            "replaced": self.replaced,
+            "sub": self.sub.iter().map(|s| s.to_json()).collect::<Vec<serde_json::Value>>()
+        })
+    }
+
+    fn write_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{:indent$}{}", "", self.kind, indent = depth * 2)?;
+        if !self.tags.is_empty() {
+            write!(f, " tags={:?}", self.tags)?;
+        }
+        match &self.costs {
+            Ok(c) => write!(
+                f, 
+                " (next: {}, contains: {}, size: {}{})", 
+                c.next_cost, 
+                c.contains_cost, 
+                if c.size.exact { "" } else { "~" }, 
+                c.size.value
+            )?,
+            Err(e) => write!(f, " (stats error: {})", e)?
+        }
+        if self.replaced {
+            write!(f, " [optimized]")?;
+        }
+        writeln!(f)?;
+        for s in &self.sub {
+            s.write_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_indented(f, 0)
+    }
+}
+
+
+// Optimizes the tree the same way a query does before running it and
+// describes the result, marking every node the optimizer introduced.
+pub fn explain(it: Rc<RefCell<dyn Shape>>) -> Explanation {
+    // the built nodes are kept alive, otherwise a node the optimizer creates
+    // could reuse the address of one it dropped
+    let mut nodes = Vec::new();
+    collect_nodes(&it, &mut nodes);
+    let built = nodes.iter().map(node_id).collect();
+
+    let root = super::optimized(it);
+
+    describe(&root, &built)
+}
+
+fn node_id(it: &Rc<RefCell<dyn Shape>>) -> usize {
+    Rc::as_ptr(it) as *const () as usize
+}
+
+fn collect_nodes(it: &Rc<RefCell<dyn Shape>>, nodes: &mut Vec<Rc<RefCell<dyn Shape>>>) {
+    nodes.push(it.clone());
+    if let Some(subs) = it.borrow().sub_iterators() {
+        for s in &subs {
+            collect_nodes(s, nodes);
+        }
+    }
+}
+
+// The name shown for a node and the tags it sets.
+pub fn kind_and_tags(shape: &mut dyn Shape) -> (String, Vec<String>) {
+    match shape.shape_type() {
+        ShapeType::Save(s) => ("Save".to_string(), s.tag_names()),
+        ShapeType::Fixed(f) => (format!("Fixed({})", f.values.borrow().len()), Vec::new()),
+        t => (t.to_string(), Vec::new())
+    }
+}
+
+fn describe(it: &Rc<RefCell<dyn Shape>>, built: &HashSet<usize>) -> Explanation {
+    let mut shape = it.borrow_mut();
+
+    let (kind, tags) = kind_and_tags(&mut*shape);
+
+    let costs = shape.stats();
+
+    let sub = match shape.sub_iterators() {
+        Some(subs) => subs.iter().map(|s| describe(s, built)).collect(),
+        None => Vec::new()
+    };
+
+    Explanation {
+        kind,
+        tags,
+        costs,
+        replaced: !built.contains(&node_id(it)),
+        sub
+    }
+}
@@ -1,4 +1,5 @@
 use super::{Shape, Base, Index, Scanner, Costs, Null, ShapeType};
+use super::bitmap::Bitmap;
 use super::super::refs;
 use super::super::value::Value;
 use std::collections::HashMap;
@@ -65,6 +66,27 @@ impl Shape for Fixed {
         None
     }
 
+    fn sorted(&mut self) -> bool {
+        let values = self.values.borrow();
+        values.iter().all(|v| v.key().is_some()) && values.windows(2).all(|w| w[0].key() < w[1].key())
+    }
+
+    // only for store nodes, values with content would lose it
+    fn bitmap(&mut self) -> Option<Rc<Bitmap>> {
+        let values = self.values.borrow();
+        let mut bits = Bitmap::new();
+        for v in values.iter() {
+            if v.content != refs::Content::None {
+                return None
+            }
+            bits.insert(v.key()?);
+        }
+        if bits.len() != values.len() {
+            return None
+        }
+        Some(Rc::new(bits))
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::Fixed(self)
     }
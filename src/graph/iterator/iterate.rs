@@ -11,24 +11,29 @@ pub struct BaseIterator {
     it: Option<Rc<RefCell<dyn Scanner>>>,
     paths: bool,
     optimize: bool,
-    n: i64
+    n: i64,
+    err: Option<String>
 }
 
 impl BaseIterator {
     pub fn start(&mut self) {
         if self.optimize {
-            let shape = self.s.clone().borrow_mut().optimize();
-
-            if let Some(s) = shape {
-                self.s = s;
-            }
+            self.s = super::optimized(self.s.clone());
         }
         self.it = Some(self.s.borrow().iterate());
     }
 
+    // keeps the first error the iterator reports instead of panicking on it
     pub fn end(&mut self) {
         let i = &mut*self.it.as_ref().unwrap().borrow_mut();
-        i.close().unwrap();
+        if self.err.is_none() {
+            self.err = i.err();
+        }
+        if let Err(e) = i.close() {
+            if self.err.is_none() {
+                self.err = Some(e);
+            }
+        }
     }
 
 
@@ -67,11 +72,17 @@ impl TagEachIterator {
                 it: None,
                 paths,
                 optimize,
-                n: 0
+                n: 0,
+                err: None
             }
         }
     }
 
+    // the error that ended iteration, if any
+    pub fn err(&self) -> Option<String> {
+        self.base.err.clone()
+    }
+
     fn do_val(&mut self) -> Option<HashMap<String, Ref>> {
 
         if self.base.next_val() {
@@ -130,11 +141,17 @@ impl EachIterator {
                 it: None,
                 paths,
                 optimize,
-                n: 0
+                n: 0,
+                err: None
             }
         }
     }
 
+    // the error that ended iteration, if any
+    pub fn err(&self) -> Option<String> {
+        self.base.err.clone()
+    }
+
     fn do_val(&mut self) -> Option<Ref> {
 
         if self.base.next_val() {
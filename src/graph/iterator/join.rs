@@ -0,0 +1,571 @@
+use super::{Shape, ShapeType, Base, Index, Scanner, Costs};
+use super::super::refs;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+// a hash table entry costs more than scanning the value
+const HASH_BUILD_FACTOR: i64 = 2;
+
+// a join that reads both sides whole has to be clearly cheaper than probing
+const JOIN_GAIN: i64 = 2;
+
+// below this many primary results the nested loop is kept, the estimates
+// are too rough to be worth reading the other side whole
+const MIN_JOIN_SIZE: i64 = 64;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinKind {
+    NestedLoop,
+    Hash,
+    Merge
+}
+
+// Picks how to intersect a primary iterator with another one. A nested loop
+// scans the primary and checks each result on the other side, a hash join
+// scans both and keeps the smaller one in memory, a merge join scans both
+// side by side and needs both sorted.
+pub fn choose(primary: &Costs, other: &Costs, sorted: bool) -> JoinKind {
+    if primary.size.value < MIN_JOIN_SIZE {
+        return JoinKind::NestedLoop
+    }
+    let nested = primary.size.value * (primary.next_cost + other.contains_cost);
+
+    let (small, large) = if primary.size.value <= other.size.value { (primary, other) } else { (other, primary) };
+    let hash = small.size.value * small.next_cost * HASH_BUILD_FACTOR + large.size.value * large.next_cost;
+    let merge = primary.size.value * primary.next_cost + other.size.value * other.next_cost;
+
+    if sorted && merge * JOIN_GAIN < nested && merge <= hash {
+        return JoinKind::Merge
+    }
+    if hash * JOIN_GAIN < nested {
+        return JoinKind::Hash
+    }
+    JoinKind::NestedLoop
+}
+
+// Replaces the intersection of two iterators with a hash or merge join when
+// their costs favor it.
+pub fn join(primary: &Rc<RefCell<dyn Shape>>, other: &Rc<RefCell<dyn Shape>>) -> Option<Rc<RefCell<dyn Shape>>> {
+    let a = primary.borrow_mut().stats().ok()?;
+    let b = other.borrow_mut().stats().ok()?;
+    let sorted = primary.borrow_mut().sorted() && other.borrow_mut().sorted();
+
+    match choose(&a, &b, sorted) {
+        JoinKind::Merge => Some(MergeJoin::new(primary.clone(), other.clone())),
+        JoinKind::Hash if a.size.value <= b.size.value => Some(HashJoin::new(primary.clone(), other.clone())),
+        JoinKind::Hash => Some(HashJoin::new(other.clone(), primary.clone())),
+        JoinKind::NestedLoop => None
+    }
+}
+
+
+fn join_stats(a: &Rc<RefCell<dyn Shape>>, b: &Rc<RefCell<dyn Shape>>) -> Result<(Costs, Costs), String> {
+    let a = a.borrow_mut().stats()?;
+    let b = b.borrow_mut().stats()?;
+    Ok((a, b))
+}
+
+fn smaller(a: &Costs, b: &Costs) -> refs::Size {
+    if a.size.value <= b.size.value { a.size.clone() } else { b.size.clone() }
+}
+
+fn optimize_pair(a: &mut Rc<RefCell<dyn Shape>>, b: &mut Rc<RefCell<dyn Shape>>) {
+    let n = a.borrow_mut().optimize();
+    if let Some(n) = n {
+        *a = n;
+    }
+    let n = b.borrow_mut().optimize();
+    if let Some(n) = n {
+        *b = n;
+    }
+}
+
+
+/////////////////////////////////////////////// hash join
+
+
+// the tags of every path of the build side, by result
+type Table = HashMap<u64, Vec<HashMap<String, refs::Ref>>>;
+
+fn build_table(build: &Rc<RefCell<dyn Shape>>) -> Result<Table, String> {
+    let mut table = Table::new();
+    let it = build.borrow().iterate();
+    let mut it = it.borrow_mut();
+
+    while it.next() {
+        let key = match it.result().and_then(|r| r.key()) {
+            Some(k) => k,
+            None => continue
+        };
+        let paths = table.entry(key).or_default();
+        loop {
+            let mut tags = HashMap::new();
+            it.tag_results(&mut tags);
+            paths.push(tags);
+            if !it.next_path() {
+                break;
+            }
+        }
+    }
+
+    match it.err() {
+        Some(e) => Err(e),
+        None => Ok(table)
+    }
+}
+
+
+// Intersects two iterators by loading the results of the build side into a
+// hash table and streaming the probe side through it. The build side should
+// be the smaller one, its paths are all kept in memory.
+pub struct HashJoin {
+    build: Rc<RefCell<dyn Shape>>,
+    probe: Rc<RefCell<dyn Shape>>
+}
+
+impl HashJoin {
+    pub fn new(build: Rc<RefCell<dyn Shape>>, probe: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<HashJoin>> {
+        Rc::new(RefCell::new(HashJoin {
+            build,
+            probe
+        }))
+    }
+}
+
+impl Shape for HashJoin {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        HashJoinNext::new(self.build.clone(), self.probe.borrow().iterate())
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        HashJoinContains::new(self.build.clone(), self.probe.borrow().lookup())
+    }
+
+    fn stats(&mut self) -> Result<Costs, String> {
+        let (build, probe) = join_stats(&self.build, &self.probe)?;
+        Ok(Costs {
+            contains_cost: probe.contains_cost + 1,
+            next_cost: probe.next_cost + 1,
+            size: smaller(&build, &probe)
+        })
+    }
+
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_pair(&mut self.build, &mut self.probe);
+        None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        Some(vec![self.build.clone(), self.probe.clone()])
+    }
+
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.build = f(self.build.clone());
+        self.probe = f(self.probe.clone());
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::HashJoin
+    }
+}
+
+
+// The hash table of a join and the build path the result is on.
+struct Matches {
+    build: Rc<RefCell<dyn Shape>>,
+    table: Option<Table>,
+    key: Option<u64>,
+    path: usize,
+    err: Option<String>
+}
+
+impl Matches {
+    fn new(build: Rc<RefCell<dyn Shape>>) -> Matches {
+        Matches {
+            build,
+            table: None,
+            key: None,
+            path: 0,
+            err: None
+        }
+    }
+
+    // builds the table on first use, false if that failed
+    fn ready(&mut self) -> bool {
+        if self.table.is_none() && self.err.is_none() {
+            match build_table(&self.build) {
+                Ok(t) => self.table = Some(t),
+                Err(e) => self.err = Some(e)
+            }
+        }
+        self.table.is_some()
+    }
+
+    fn find(&mut self, key: u64) -> bool {
+        let found = self.table.as_ref().is_some_and(|t| t.contains_key(&key));
+        self.key = if found { Some(key) } else { None };
+        self.path = 0;
+        found
+    }
+
+    fn next_path(&mut self) -> bool {
+        let paths = match (&self.table, self.key) {
+            (Some(t), Some(k)) => t[&k].len(),
+            _ => 0
+        };
+        if self.path + 1 < paths {
+            self.path += 1;
+            return true
+        }
+        false
+    }
+
+    fn tag_results(&self, tags: &mut HashMap<String, refs::Ref>) {
+        if let (Some(t), Some(k)) = (&self.table, self.key) {
+            for (tag, v) in &t[&k][self.path] {
+                tags.insert(tag.clone(), v.clone());
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        self.table = None;
+        self.key = None;
+        self.path = 0;
+    }
+}
+
+
+struct HashJoinNext {
+    matches: Matches,
+    probe: Rc<RefCell<dyn Scanner>>,
+    result: Option<refs::Ref>
+}
+
+impl HashJoinNext {
+    fn new(build: Rc<RefCell<dyn Shape>>, probe: Rc<RefCell<dyn Scanner>>) -> Rc<RefCell<HashJoinNext>> {
+        Rc::new(RefCell::new(HashJoinNext {
+            matches: Matches::new(build),
+            probe,
+            result: None
+        }))
+    }
+}
+
+impl Base for HashJoinNext {
+    fn tag_results(&self, tags: &mut HashMap<String, refs::Ref>) {
+        self.probe.borrow().tag_results(tags);
+        self.matches.tag_results(tags);
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.result.clone()
+    }
+
+    fn next_path(&mut self) -> bool {
+        if self.matches.next_path() {
+            return true
+        }
+        if self.probe.borrow_mut().next_path() {
+            self.matches.path = 0;
+            return true
+        }
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        self.matches.err.clone().or_else(|| self.probe.borrow().err())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.matches.close();
+        self.probe.borrow_mut().close()
+    }
+}
+
+impl Scanner for HashJoinNext {
+    fn next(&mut self) -> bool {
+        if !self.matches.ready() {
+            return false
+        }
+        let mut probe = self.probe.borrow_mut();
+        while probe.next() {
+            let r = probe.result();
+            if let Some(k) = r.as_ref().and_then(|r| r.key()) {
+                if self.matches.find(k) {
+                    self.result = r;
+                    return true
+                }
+            }
+        }
+        self.result = None;
+        false
+    }
+}
+
+
+struct HashJoinContains {
+    matches: Matches,
+    probe: Rc<RefCell<dyn Index>>,
+    result: Option<refs::Ref>
+}
+
+impl HashJoinContains {
+    fn new(build: Rc<RefCell<dyn Shape>>, probe: Rc<RefCell<dyn Index>>) -> Rc<RefCell<HashJoinContains>> {
+        Rc::new(RefCell::new(HashJoinContains {
+            matches: Matches::new(build),
+            probe,
+            result: None
+        }))
+    }
+}
+
+impl Base for HashJoinContains {
+    fn tag_results(&self, tags: &mut HashMap<String, refs::Ref>) {
+        self.probe.borrow().tag_results(tags);
+        self.matches.tag_results(tags);
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.result.clone()
+    }
+
+    fn next_path(&mut self) -> bool {
+        if self.matches.next_path() {
+            return true
+        }
+        if self.probe.borrow_mut().next_path() {
+            self.matches.path = 0;
+            return true
+        }
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        self.matches.err.clone().or_else(|| self.probe.borrow().err())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.matches.close();
+        self.probe.borrow_mut().close()
+    }
+}
+
+impl Index for HashJoinContains {
+    fn contains(&mut self, v: &refs::Ref) -> bool {
+        self.result = None;
+        if !self.matches.ready() {
+            return false
+        }
+        let found = match v.key() {
+            Some(k) => self.matches.find(k) && self.probe.borrow_mut().contains(v),
+            None => false
+        };
+        if found {
+            self.result = Some(v.clone());
+        }
+        found
+    }
+}
+
+
+/////////////////////////////////////////////// merge join
+
+
+// Intersects two iterators that both return their results in ascending key
+// order, like the direction indexes of the stores, by advancing whichever
+// side is behind. Nothing is kept in memory.
+pub struct MergeJoin {
+    left: Rc<RefCell<dyn Shape>>,
+    right: Rc<RefCell<dyn Shape>>
+}
+
+impl MergeJoin {
+    pub fn new(left: Rc<RefCell<dyn Shape>>, right: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<MergeJoin>> {
+        Rc::new(RefCell::new(MergeJoin {
+            left,
+            right
+        }))
+    }
+}
+
+impl Shape for MergeJoin {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        MergeJoinNext::new(self.left.borrow().iterate(), self.right.borrow().iterate())
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        MergeJoinContains::new(self.left.borrow().lookup(), self.right.borrow().lookup())
+    }
+
+    fn stats(&mut self) -> Result<Costs, String> {
+        let (left, right) = join_stats(&self.left, &self.right)?;
+        Ok(Costs {
+            contains_cost: left.contains_cost + right.contains_cost,
+            next_cost: left.next_cost + right.next_cost,
+            size: smaller(&left, &right)
+        })
+    }
+
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_pair(&mut self.left, &mut self.right);
+        None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        Some(vec![self.left.clone(), self.right.clone()])
+    }
+
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.left = f(self.left.clone());
+        self.right = f(self.right.clone());
+    }
+
+    fn sorted(&mut self) -> bool {
+        true
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::MergeJoin
+    }
+}
+
+
+struct MergeJoinNext {
+    left: Rc<RefCell<dyn Scanner>>,
+    right: Rc<RefCell<dyn Scanner>>,
+    // the key the right side is on, None before it started
+    right_key: Option<u64>,
+    done: bool,
+    result: Option<refs::Ref>
+}
+
+impl MergeJoinNext {
+    fn new(left: Rc<RefCell<dyn Scanner>>, right: Rc<RefCell<dyn Scanner>>) -> Rc<RefCell<MergeJoinNext>> {
+        Rc::new(RefCell::new(MergeJoinNext {
+            left,
+            right,
+            right_key: None,
+            done: false,
+            result: None
+        }))
+    }
+
+    // moves the right side to the first key not below the given one
+    fn seek_right(&mut self, key: u64) -> bool {
+        let mut right = self.right.borrow_mut();
+        while self.right_key.is_none_or(|k| k < key) {
+            if !right.next() {
+                return false
+            }
+            self.right_key = right.result().and_then(|r| r.key());
+        }
+        true
+    }
+}
+
+impl Base for MergeJoinNext {
+    fn tag_results(&self, tags: &mut HashMap<String, refs::Ref>) {
+        self.left.borrow().tag_results(tags);
+        self.right.borrow().tag_results(tags);
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.result.clone()
+    }
+
+    fn next_path(&mut self) -> bool {
+        self.left.borrow_mut().next_path() || self.right.borrow_mut().next_path()
+    }
+
+    fn err(&self) -> Option<String> {
+        self.left.borrow().err().or_else(|| self.right.borrow().err())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        let left = self.left.borrow_mut().close();
+        let right = self.right.borrow_mut().close();
+        left.and(right)
+    }
+}
+
+impl Scanner for MergeJoinNext {
+    fn next(&mut self) -> bool {
+        self.result = None;
+        if self.done {
+            return false
+        }
+        loop {
+            if !self.left.borrow_mut().next() {
+                self.done = true;
+                return false
+            }
+            let r = self.left.borrow().result();
+            let key = match r.as_ref().and_then(|r| r.key()) {
+                Some(k) => k,
+                None => continue
+            };
+            if !self.seek_right(key) {
+                self.done = true;
+                return false
+            }
+            if self.right_key == Some(key) {
+                self.result = r;
+                return true
+            }
+        }
+    }
+}
+
+
+struct MergeJoinContains {
+    left: Rc<RefCell<dyn Index>>,
+    right: Rc<RefCell<dyn Index>>,
+    result: Option<refs::Ref>
+}
+
+impl MergeJoinContains {
+    fn new(left: Rc<RefCell<dyn Index>>, right: Rc<RefCell<dyn Index>>) -> Rc<RefCell<MergeJoinContains>> {
+        Rc::new(RefCell::new(MergeJoinContains {
+            left,
+            right,
+            result: None
+        }))
+    }
+}
+
+impl Base for MergeJoinContains {
+    fn tag_results(&self, tags: &mut HashMap<String, refs::Ref>) {
+        self.left.borrow().tag_results(tags);
+        self.right.borrow().tag_results(tags);
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.result.clone()
+    }
+
+    fn next_path(&mut self) -> bool {
+        self.left.borrow_mut().next_path() || self.right.borrow_mut().next_path()
+    }
+
+    fn err(&self) -> Option<String> {
+        self.left.borrow().err().or_else(|| self.right.borrow().err())
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        let left = self.left.borrow_mut().close();
+        let right = self.right.borrow_mut().close();
+        left.and(right)
+    }
+}
+
+impl Index for MergeJoinContains {
+    fn contains(&mut self, v: &refs::Ref) -> bool {
+        let found = self.left.borrow_mut().contains(v) && self.right.borrow_mut().contains(v);
+        self.result = if found { Some(v.clone()) } else { None };
+        found
+    }
+}
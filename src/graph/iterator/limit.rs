@@ -48,7 +48,9 @@ impl Shape for Limit {
         if self.limit <= 0 {
             return nit
         }
-        self.it = nit.unwrap();
+        if let Some(nit) = nit {
+            self.it = nit;
+        }
         return None
     }
 
@@ -56,6 +58,10 @@ impl Shape for Limit {
         return Some(vec![self.it.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.it = f(self.it.clone());
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::Limit
     }
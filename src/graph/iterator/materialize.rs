@@ -1,4 +1,5 @@
 use super::{Shape, Scanner, Index, Costs, Base, is_null, ShapeType};
+use super::bitmap::Bitmap;
 use super::super::refs;
 use super::super::value::Value;
 use std::rc::Rc;
@@ -76,6 +77,16 @@ impl Shape for Materialize {
         return Some(vec![self.sub.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.sub = f(self.sub.clone());
+    }
+
+    // the bitmap of the sub-iterator if it already has one, the results
+    // themselves are only read when the iterator runs
+    fn bitmap(&mut self) -> Option<Rc<Bitmap>> {
+        self.sub.borrow_mut().bitmap()
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::Materialize
     }
@@ -14,14 +14,32 @@ pub mod unique;
 pub mod value_filter;
 pub mod quad_ids;
 pub mod iterate;
+pub mod explain;
+pub mod profile;
+pub mod context;
+pub mod triejoin;
+pub mod join;
+pub mod bitmap;
+pub mod parallel;
+pub mod shortest_path;
+pub mod weighted_path;
+pub mod all_paths;
 
 use std::collections::HashMap;
+use std::fmt;
 use super::refs;
 use std::rc::Rc;
 use std::cell::RefCell;
 
 use super::iterator::fixed::Fixed;
 use super::iterator::save::Save;
+use super::iterator::profile::Profile;
+use super::iterator::recursive::Recursive;
+use super::iterator::not::Not;
+use super::iterator::count::CountPer;
+use super::iterator::parallel::Parallel;
+use super::iterator::shortest_path::ShortestPath;
+use super::iterator::weighted_path::WeightedPath;
 
 #[derive(Clone)]
 pub struct Tags {
@@ -90,18 +108,21 @@ impl Costs {
 pub enum ShapeType<'a> {
     And,
     Count,
+    CountPer(&'a mut CountPer),
     Error,
     Fixed(&'a mut Fixed),
+    Guard,
     HasA,
     Int64,
     Limit,
     LinksTo,
     Materialize,
-    Not,
+    Not(&'a mut Not),
     Null,
     Or,
-    Recursive,
+    Recursive(&'a mut Recursive),
     Resolver,
+    Profile(&'a mut Profile),
     Save(&'a mut Save),
     Skip,
     Sort,
@@ -109,37 +130,55 @@ pub enum ShapeType<'a> {
     Unique,
     ValueFilter,
     QuadIds,
-    StoreIterator
+    StoreIterator,
+    TrieJoin,
+    HashJoin,
+    MergeJoin,
+    Bitmap,
+    Parallel(&'a mut Parallel),
+    ShortestPath(&'a mut ShortestPath),
+    WeightedPath(&'a mut WeightedPath)
 }
 
 
-// impl<'a> fmt::Display for ShapeType<'a> {
-//     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-//         match self {
-//             ShapeType::And => write!(f, "And"),
-//             ShapeType::Count => write!(f, "Count"),
-//             ShapeType::Error => write!(f, "Error"),
-//             ShapeType::Fixed(_) => write!(f, "Fixed"),
-//             ShapeType::HasA => write!(f, "HasA"),
-//             ShapeType::Int64 => write!(f, "Int64"),
-//             ShapeType::Limit => write!(f, "Limit"),
-//             ShapeType::LinksTo => write!(f, "LinksTo"),
-//             ShapeType::Materialize => write!(f, "Materialize"),
-//             ShapeType::Not => write!(f, "Not"),
-//             ShapeType::Null => write!(f, "Null"),
-//             ShapeType::Or => write!(f, "Or"),
-//             ShapeType::Recursive => write!(f, "Recursive"),
-//             ShapeType::Resolver => write!(f, "Resolver"),
-//             ShapeType::Save(_) => write!(f, "Save"),
-//             ShapeType::Skip => write!(f, "Skip"),
-//             ShapeType::Sort => write!(f, "Sort"),
-//             ShapeType::Test => write!(f, "Test"),
-//             ShapeType::Unique => write!(f, "Unique"),
-//             ShapeType::ValueFilter => write!(f, "ValueFilter"),
-//             ShapeType::MemStoreIterator => write!(f, "MemStoreIterator"),
-//         }
-//     }
-// }
+impl<'a> fmt::Display for ShapeType<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShapeType::And => write!(f, "And"),
+            ShapeType::Count => write!(f, "Count"),
+            ShapeType::CountPer(_) => write!(f, "CountPer"),
+            ShapeType::Error => write!(f, "Error"),
+            ShapeType::Fixed(_) => write!(f, "Fixed"),
+            ShapeType::Guard => write!(f, "Guard"),
+            ShapeType::HasA => write!(f, "HasA"),
+            ShapeType::Int64 => write!(f, "Int64"),
+            ShapeType::Limit => write!(f, "Limit"),
+            ShapeType::LinksTo => write!(f, "LinksTo"),
+            ShapeType::Materialize => write!(f, "Materialize"),
+            ShapeType::Not(_) => write!(f, "Not"),
+            ShapeType::Null => write!(f, "Null"),
+            ShapeType::Or => write!(f, "Or"),
+            ShapeType::Recursive(_) => write!(f, "Recursive"),
+            ShapeType::Resolver => write!(f, "Resolver"),
+            ShapeType::Profile(_) => write!(f, "Profile"),
+            ShapeType::Save(_) => write!(f, "Save"),
+            ShapeType::Skip => write!(f, "Skip"),
+            ShapeType::Sort => write!(f, "Sort"),
+            ShapeType::Test => write!(f, "Test"),
+            ShapeType::Unique => write!(f, "Unique"),
+            ShapeType::ValueFilter => write!(f, "ValueFilter"),
+            ShapeType::QuadIds => write!(f, "QuadIds"),
+            ShapeType::StoreIterator => write!(f, "StoreIterator"),
+            ShapeType::TrieJoin => write!(f, "TrieJoin"),
+            ShapeType::HashJoin => write!(f, "HashJoin"),
+            ShapeType::MergeJoin => write!(f, "MergeJoin"),
+            ShapeType::Bitmap => write!(f, "Bitmap"),
+            ShapeType::Parallel(_) => write!(f, "Parallel"),
+            ShapeType::ShortestPath(_) => write!(f, "ShortestPath"),
+            ShapeType::WeightedPath(_) => write!(f, "WeightedPath"),
+        }
+    }
+}
 
 pub trait Shape {
     fn iterate(&self) -> Rc<RefCell<dyn Scanner>>;
@@ -156,6 +195,24 @@ pub trait Shape {
 
     fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>>;
 
+    // Replaces every sub-iterator with the result of calling f on it,
+    // so a tree can be rebuilt with wrappers around each node.
+    // Iterators without sub-iterators keep the default, which does nothing.
+    #[allow(unused)]
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {}
+
+    // Whether the iterator returns its results in ascending key order,
+    // which lets a merge join intersect it without lookups.
+    fn sorted(&mut self) -> bool {
+        false
+    }
+
+    // The results as a bitmap of keys, for iterators that return no tags
+    // and can list their results without running a query.
+    fn bitmap(&mut self) -> Option<Rc<bitmap::Bitmap>> {
+        None
+    }
+
     fn shape_type(&mut self) -> ShapeType;
 }
 
@@ -168,6 +225,13 @@ pub trait Morphism {
 }
 
 
+// The tree that replaces it once optimized, it itself if nothing changed.
+pub fn optimized(it: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>> {
+    let o = it.borrow_mut().optimize();
+    o.unwrap_or(it)
+}
+
+
 pub fn is_null(it: &mut dyn Shape) -> bool {
     if let ShapeType::Null = it.shape_type() {
         return true
@@ -1,5 +1,6 @@
 use super::{Shape, Base, Index, Scanner, Costs, ShapeType};
 use super::materialize::Materialize;
+use super::bitmap::{self, BitmapSet};
 use super::super::refs;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -17,6 +18,10 @@ impl Not {
             all_it: all_it,
         }))
     }
+
+    pub fn primary(&self) -> Rc<RefCell<dyn Shape>> {
+        self.primary.clone()
+    }
 }
 
 
@@ -54,6 +59,9 @@ impl Shape for Not {
         if optimized_primary_it.is_some() {
             self.primary = optimized_primary_it.unwrap();
         }
+        if let Some(b) = bitmap::bitmaps(&[self.all_it.clone(), self.primary.clone()]) {
+            return Some(BitmapSet::new(Rc::new(b[0].and_not(&b[1]))))
+        }
         self.primary = Materialize::new(self.primary.clone());
         return None
     }
@@ -62,8 +70,13 @@ impl Shape for Not {
         Some(vec![self.primary.clone(), self.all_it.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.primary = f(self.primary.clone());
+        self.all_it = f(self.all_it.clone());
+    }
+
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Not
+        ShapeType::Not(self)
     }
 }
 
@@ -135,6 +148,10 @@ impl Scanner for NotNext {
         while self.all_it.borrow_mut().next() {
             let curr = self.all_it.borrow().result();
             if !self.primary_it.borrow_mut().contains(curr.as_ref().unwrap()) {
+                // a node the primary failed on isn't known to be absent
+                if self.primary_it.borrow().err().is_some() {
+                    return false
+                }
                 self.result = curr;
                 return true
             }
@@ -1,5 +1,6 @@
 use super::{Shape, Base, Index, Scanner, Costs, ShapeType};
 use super::and::optimize_sub_iterators;
+use super::bitmap::{self, Bitmap, BitmapSet};
 use super::super::refs;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -40,6 +41,27 @@ impl Or {
 }
 
 
+// The results of an Or over bitmaps. A short circuiting Or returns its first
+// non-empty sub-iterator, otherwise every sub-iterator returns its results in
+// turn, so a value in more than one would be returned more than once and
+// the union only stands in for them when they are disjoint.
+fn union(bits: &[Rc<Bitmap>], short_circuit: bool) -> Option<Bitmap> {
+    if short_circuit {
+        return Some(bits.iter().find(|b| !b.is_empty()).map(|b| (**b).clone()).unwrap_or_default())
+    }
+    let mut res = Bitmap::new();
+    let mut len = 0;
+    for b in bits {
+        res = res.or(b);
+        len += b.len();
+    }
+    if res.len() != len {
+        return None
+    }
+    Some(res)
+}
+
+
 // impl fmt::Display for Or {
 //     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 //         write!(f, "Or")
@@ -96,6 +118,13 @@ impl Shape for Or {
     fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
         let old = self.sub_iterators();
         let opt_its = optimize_sub_iterators(&old.unwrap());
+
+        if let Some(b) = bitmap::bitmaps(&opt_its) {
+            if let Some(u) = union(&b, self.is_short_circuiting) {
+                return Some(BitmapSet::new(Rc::new(u)))
+            }
+        }
+
         let new_or = Or::new(vec![]);
         new_or.borrow_mut().is_short_circuiting = self.is_short_circuiting;
 
@@ -110,6 +139,10 @@ impl Shape for Or {
         Some(self.sub.iter().map(|s| s.clone()).collect())
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.sub = self.sub.iter().map(|s| f(s.clone())).collect();
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::Or
     }
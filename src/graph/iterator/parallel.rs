@@ -0,0 +1,303 @@
+use super::{Shape, Base, Index, Scanner, Costs, ShapeType};
+use super::or::Or;
+use super::context::{self, QueryContext, WorkerContext, ContextError};
+use super::super::refs::Ref;
+use super::super::quad::{QuadStore, SharedQuadStore};
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+
+// how many results a worker runs ahead of the reader in each branch
+const BUFFER: usize = 256;
+
+
+// Builds the iterators of one branch, on the thread that runs it.
+pub type Branch = Arc<dyn Fn(Rc<RefCell<dyn QuadStore>>) -> Rc<RefCell<dyn Shape>> + Send + Sync>;
+
+
+// Runs branches on a pool of worker threads and returns their results like
+// an Or: every branch in turn, in the order given. Iterators can't be moved
+// between threads, so every worker opens the store and builds the branches
+// it takes, and sends back each result with the tags of all its paths.
+pub struct Parallel {
+    store: Arc<dyn SharedQuadStore>,
+    branches: Vec<Branch>,
+    workers: usize,
+    stats: Option<Costs>,
+    ctx: Option<QueryContext>
+}
+
+impl Parallel {
+    // with no workers given, one per available core
+    pub fn new(store: Arc<dyn SharedQuadStore>, branches: Vec<Branch>, workers: usize) -> Rc<RefCell<Parallel>> {
+        let workers = if workers > 0 {
+            workers
+        } else {
+            thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+        };
+        Rc::new(RefCell::new(Parallel {
+            store,
+            branches,
+            workers,
+            stats: None,
+            ctx: None
+        }))
+    }
+
+    // the workers guard the branches they build with the context's limits
+    pub fn set_context(&mut self, ctx: QueryContext) {
+        self.ctx = Some(ctx);
+    }
+
+    // the branches built on this thread, to look values up and estimate costs
+    fn local(&self) -> Rc<RefCell<dyn Shape>> {
+        let qs = self.store.open();
+        Or::new(self.branches.iter().map(|b| b(qs.clone())).collect())
+    }
+}
+
+impl Shape for Parallel {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        ParallelNext::new(self.store.clone(), self.branches.clone(), self.workers, self.ctx.clone())
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        self.local().borrow().lookup()
+    }
+
+    // building the branches takes a pass over the store, so it is done once
+    fn stats(&mut self) -> Result<Costs, String> {
+        if let Some(st) = &self.stats {
+            return Ok(st.clone())
+        }
+        let mut st = self.local().borrow_mut().stats()?;
+        st.next_cost = st.next_cost / self.workers as i64 + 1;
+        self.stats = Some(st.clone());
+        Ok(st)
+    }
+
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        None
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::Parallel(self)
+    }
+}
+
+
+enum Message {
+    Result(Option<Ref>, Vec<HashMap<String, Ref>>),
+    Err(String),
+    // the context of the query ended the branch
+    Context(ContextError)
+}
+
+
+// Iterates a branch and sends its results, false once the reader is gone
+// or the branch failed.
+fn run(qs: &Rc<RefCell<dyn QuadStore>>, branch: &Branch, tx: &SyncSender<Message>, cancelled: &AtomicBool, ctx: &Option<QueryContext>) -> bool {
+    let mut shape = branch(qs.clone());
+    if let Some(ctx) = ctx {
+        shape = context::guard_branch(shape, ctx);
+    }
+    let it = shape.borrow().iterate();
+    while !cancelled.load(Ordering::Relaxed) && it.borrow_mut().next() {
+        let result = it.borrow().result();
+        let mut paths = Vec::new();
+        loop {
+            let mut tags = HashMap::new();
+            it.borrow().tag_results(&mut tags);
+            paths.push(tags);
+            if !it.borrow_mut().next_path() {
+                break
+            }
+        }
+        if tx.send(Message::Result(result, paths)).is_err() {
+            return false
+        }
+    }
+    let err = it.borrow().err();
+    let closed = it.borrow_mut().close();
+    if let Some(e) = ctx.as_ref().and_then(|c| c.err()) {
+        let _ = tx.send(Message::Context(e));
+        return false
+    }
+    match err.or(closed.err()) {
+        Some(e) => {
+            let _ = tx.send(Message::Err(e));
+            false
+        },
+        None => true
+    }
+}
+
+
+struct ParallelNext {
+    store: Arc<dyn SharedQuadStore>,
+    branches: Vec<Branch>,
+    workers: usize,
+    ctx: Option<QueryContext>,
+    started: bool,
+    cancelled: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+    results: Vec<Receiver<Message>>,
+    cur: usize,
+    result: Option<Ref>,
+    paths: Vec<HashMap<String, Ref>>,
+    path: usize,
+    err: Option<String>
+}
+
+impl ParallelNext {
+    fn new(store: Arc<dyn SharedQuadStore>, branches: Vec<Branch>, workers: usize, ctx: Option<QueryContext>) -> Rc<RefCell<ParallelNext>> {
+        Rc::new(RefCell::new(ParallelNext {
+            store,
+            branches,
+            workers,
+            ctx,
+            started: false,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            handles: Vec::new(),
+            results: Vec::new(),
+            cur: 0,
+            result: None,
+            paths: Vec::new(),
+            path: 0,
+            err: None
+        }))
+    }
+
+    // Workers take branches in order, so the branch being read is always
+    // running or done, and the ones after it can only fill their buffers.
+    fn start(&mut self) {
+        self.started = true;
+        let mut tasks = VecDeque::new();
+        for b in &self.branches {
+            let (tx, rx) = sync_channel(BUFFER);
+            tasks.push_back((b.clone(), tx));
+            self.results.push(rx);
+        }
+        let tasks = Arc::new(Mutex::new(tasks));
+        // the run has started, so its deadline is known
+        let limits: Option<WorkerContext> = self.ctx.as_ref().map(|c| c.worker());
+
+        for _ in 0..self.workers.min(self.branches.len()) {
+            let tasks = tasks.clone();
+            let store = self.store.clone();
+            let cancelled = self.cancelled.clone();
+            let limits = limits.clone();
+            self.handles.push(thread::spawn(move || {
+                let qs = store.open();
+                let ctx = limits.map(|l| l.context());
+                loop {
+                    let task = tasks.lock().unwrap().pop_front();
+                    let (branch, tx) = match task {
+                        Some(t) => t,
+                        None => return
+                    };
+                    let ok = panic::catch_unwind(AssertUnwindSafe(|| run(&qs, &branch, &tx, &cancelled, &ctx)));
+                    match ok {
+                        Ok(true) => {},
+                        Ok(false) => return,
+                        Err(_) => {
+                            let _ = tx.send(Message::Err("parallel worker panicked".to_string()));
+                            return
+                        }
+                    }
+                }
+            }));
+        }
+    }
+
+    // stops the workers, a worker waiting to send sees the reader is gone
+    fn stop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.results.clear();
+        for h in self.handles.drain(..) {
+            let _ = h.join();
+        }
+    }
+}
+
+impl Drop for ParallelNext {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Base for ParallelNext {
+    fn tag_results(&self, tags: &mut HashMap<String, Ref>) {
+        if let Some(t) = self.paths.get(self.path) {
+            for (k, v) in t {
+                tags.insert(k.clone(), v.clone());
+            }
+        }
+    }
+
+    fn result(&self) -> Option<Ref> {
+        self.result.clone()
+    }
+
+    fn next_path(&mut self) -> bool {
+        if self.path + 1 < self.paths.len() {
+            self.path += 1;
+            return true
+        }
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        self.err.clone()
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.stop();
+        Ok(())
+    }
+}
+
+impl Scanner for ParallelNext {
+    fn next(&mut self) -> bool {
+        if !self.started {
+            self.start();
+        }
+        while self.cur < self.results.len() {
+            match self.results[self.cur].recv() {
+                Ok(Message::Result(r, paths)) => {
+                    self.result = r;
+                    self.paths = paths;
+                    self.path = 0;
+                    return true
+                },
+                Ok(Message::Err(e)) => {
+                    self.err = Some(e);
+                    self.stop();
+                },
+                Ok(Message::Context(e)) => {
+                    self.err = Some(e.to_string());
+                    if let Some(ctx) = &self.ctx {
+                        ctx.fail_with(e);
+                    }
+                    self.stop();
+                },
+                // every result of the branch was read
+                Err(_) => self.cur += 1
+            }
+        }
+        self.result = None;
+        self.paths.clear();
+        self.stop();
+        false
+    }
+}
@@ -0,0 +1,311 @@
+use super::{Shape, Base, Index, Scanner, Costs, ShapeType};
+use super::explain;
+use super::super::refs;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fmt;
+use std::time::{Duration, Instant};
+use serde_json::json;
+
+
+// Runtime counters shared by every Scanner and Index a Profile shape creates.
+#[derive(Debug, Clone, Default)]
+pub struct Counters {
+    pub next: i64,
+    pub contains: i64,
+    pub next_path: i64,
+    // calls to next, contains or next_path that returned true
+    pub results: i64,
+    // time spent in this node, sub-iterators included
+    pub time: Duration
+}
+
+impl Counters {
+    fn record(&mut self, ok: bool, start: Instant) {
+        if ok {
+            self.results += 1;
+        }
+        self.time += start.elapsed();
+    }
+}
+
+
+// Wraps a shape and counts what happens to the iterators built from it.
+pub struct Profile {
+    it: Rc<RefCell<dyn Shape>>,
+    kind: String,
+    tags: Vec<String>,
+    estimated: Result<Costs, String>,
+    counters: Rc<RefCell<Counters>>
+}
+
+impl Profile {
+    pub fn new(it: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<Profile>> {
+        let (kind, tags) = explain::kind_and_tags(&mut*it.borrow_mut());
+        let estimated = it.borrow_mut().stats();
+        Rc::new(RefCell::new(Profile {
+            it,
+            kind,
+            tags,
+            estimated,
+            counters: Rc::new(RefCell::new(Counters::default()))
+        }))
+    }
+}
+
+
+impl Shape for Profile {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        ProfileNext::new(self.it.borrow().iterate(), self.counters.clone())
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        ProfileContains::new(self.it.borrow().lookup(), self.counters.clone())
+    }
+
+    fn stats(&mut self) -> Result<Costs, String> {
+        self.it.borrow_mut().stats()
+    }
+
+    // keeps counting for whatever the wrapped shape is replaced with
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        let res = self.it.borrow_mut().optimize();
+        if let Some(it) = res {
+            self.it = it;
+        }
+        None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        Some(vec![self.it.clone()])
+    }
+
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.it = f(self.it.clone());
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::Profile(self)
+    }
+}
+
+
+struct ProfileNext {
+    it: Rc<RefCell<dyn Scanner>>,
+    counters: Rc<RefCell<Counters>>
+}
+
+impl ProfileNext {
+    fn new(it: Rc<RefCell<dyn Scanner>>, counters: Rc<RefCell<Counters>>) -> Rc<RefCell<ProfileNext>> {
+        Rc::new(RefCell::new(ProfileNext {
+            it,
+            counters
+        }))
+    }
+}
+
+impl Base for ProfileNext {
+    fn tag_results(&self, dst: &mut HashMap<String, refs::Ref>) {
+        self.it.borrow().tag_results(dst)
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.it.borrow().result()
+    }
+
+    fn next_path(&mut self) -> bool {
+        let start = Instant::now();
+        let ok = self.it.borrow_mut().next_path();
+        let mut c = self.counters.borrow_mut();
+        c.next_path += 1;
+        c.record(ok, start);
+        ok
+    }
+
+    fn err(&self) -> Option<String> {
+        self.it.borrow().err()
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.it.borrow_mut().close()
+    }
+}
+
+impl Scanner for ProfileNext {
+    fn next(&mut self) -> bool {
+        let start = Instant::now();
+        let ok = self.it.borrow_mut().next();
+        let mut c = self.counters.borrow_mut();
+        c.next += 1;
+        c.record(ok, start);
+        ok
+    }
+}
+
+
+struct ProfileContains {
+    it: Rc<RefCell<dyn Index>>,
+    counters: Rc<RefCell<Counters>>
+}
+
+impl ProfileContains {
+    fn new(it: Rc<RefCell<dyn Index>>, counters: Rc<RefCell<Counters>>) -> Rc<RefCell<ProfileContains>> {
+        Rc::new(RefCell::new(ProfileContains {
+            it,
+            counters
+        }))
+    }
+}
+
+impl Base for ProfileContains {
+    fn tag_results(&self, dst: &mut HashMap<String, refs::Ref>) {
+        self.it.borrow().tag_results(dst)
+    }
+
+    fn result(&self) -> Option<refs::Ref> {
+        self.it.borrow().result()
+    }
+
+    fn next_path(&mut self) -> bool {
+        let start = Instant::now();
+        let ok = self.it.borrow_mut().next_path();
+        let mut c = self.counters.borrow_mut();
+        c.next_path += 1;
+        c.record(ok, start);
+        ok
+    }
+
+    fn err(&self) -> Option<String> {
+        self.it.borrow().err()
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.it.borrow_mut().close()
+    }
+}
+
+impl Index for ProfileContains {
+    fn contains(&mut self, v: &refs::Ref) -> bool {
+        let start = Instant::now();
+        let ok = self.it.borrow_mut().contains(v);
+        let mut c = self.counters.borrow_mut();
+        c.contains += 1;
+        c.record(ok, start);
+        ok
+    }
+}
+
+
+// Wraps every node of the tree in a Profile shape.
+pub fn instrument(it: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>> {
+    // estimates are taken before the sub-iterators are wrapped,
+    // as some iterators look at the concrete type of their children
+    let profile = Profile::new(it.clone());
+    it.borrow_mut().map_sub_iterators(&instrument);
+    profile
+}
+
+
+// What one node of an instrumented tree estimated and actually did.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub kind: String,
+    pub tags: Vec<String>,
+    pub estimated: Result<Costs, String>,
+    pub actual: Counters,
+    pub sub: Vec<Report>
+}
+
+impl Report {
+    // time spent in this node alone, sub-iterators excluded
+    pub fn self_time(&self) -> Duration {
+        let children: Duration = self.sub.iter().map(|s| s.actual.time).sum();
+        self.actual.time.checked_sub(children).unwrap_or_default()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let estimated = match &self.estimated {
+            Ok(c) => json!({
+                "next_cost": c.next_cost,
+                "contains_cost": c.contains_cost,
+                "size": c.size.value,
+                "exact": c.size.exact
+            }),
+            Err(e) => json!({ "error": e })
+        };
+
+        json!({
+            "kind": self.kind,
+            "tags": self.tags,
+            "estimated": estimated,
+            "actual": {
+                "next": self.actual.next,
+                "contains": self.actual.contains,
+                "next_path": self.actual.next_path,
+                "results": self.actual.results,
+                "time_us": self.actual.time.as_micros() as u64,
+                "self_time_us": self.self_time().as_micros() as u64
+            },
+            "sub": self.sub.iter().map(|s| s.to_json()).collect::<Vec<serde_json::Value>>()
+        })
+    }
+
+    fn write_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{:indent$}{}", "", self.kind, indent = depth * 2)?;
+        if !self.tags.is_empty() {
+            write!(f, " tags={:?}", self.tags)?;
+        }
+        match &self.estimated {
+            Ok(c) => write!(f, " (est. size: {}{})", if c.size.exact { "" } else { "~" }, c.size.value)?,
+            Err(e) => write!(f, " (stats error: {})", e)?
+        }
+        writeln!(
+            f,
+            " next: {}, contains: {}, next_path: {}, results: {}, time: {:?} (self: {:?})",
+            self.actual.next,
+            self.actual.contains,
+            self.actual.next_path,
+            self.actual.results,
+            self.actual.time,
+            self.self_time()
+        )?;
+        for s in &self.sub {
+            s.write_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_indented(f, 0)
+    }
+}
+
+
+// Collects the counters of an instrumented tree.
+// Nodes that were not wrapped are skipped, their sub-iterators are still reported.
+pub fn report(it: &Rc<RefCell<dyn Shape>>) -> Vec<Report> {
+    let mut shape = it.borrow_mut();
+
+    if let ShapeType::Profile(p) = shape.shape_type() {
+        let sub = match p.it.borrow().sub_iterators() {
+            Some(subs) => subs.iter().flat_map(report).collect(),
+            None => Vec::new()
+        };
+
+        return vec![Report {
+            kind: p.kind.clone(),
+            tags: p.tags.clone(),
+            estimated: p.estimated.clone(),
+            actual: p.counters.borrow().clone(),
+            sub
+        }]
+    }
+
+    match shape.sub_iterators() {
+        Some(subs) => subs.iter().flat_map(report).collect(),
+        None => Vec::new()
+    }
+}
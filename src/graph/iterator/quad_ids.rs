@@ -1,5 +1,6 @@
 use crate::graph::refs::{Size, Ref, Content};
 use crate::graph::iterator::{Base, Scanner, Index, Shape, Costs, ShapeType};
+use crate::graph::iterator::bitmap::Bitmap;
 use crate::graph::quad::{Direction};
 
 use std::rc::Rc;
@@ -54,6 +55,14 @@ impl Shape for QuadIds {
         None
     }
 
+    fn sorted(&mut self) -> bool {
+        true
+    }
+
+    fn bitmap(&mut self) -> Option<Rc<Bitmap>> {
+        Some(Rc::new(self.quad_ids.iter().copied().collect()))
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::QuadIds
     }
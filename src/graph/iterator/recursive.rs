@@ -3,24 +3,53 @@ use super::fixed::Fixed;
 use super::save::tag;
 use super::super::refs;
 use super::super::value::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
 use std::cell::RefCell;
 
-struct SeenAt {
-    depth: i32,
-    tags: HashMap<String, refs::Ref>,
-    val: Option<refs::Ref>
+// How a node was first reached: at which depth, from which node and, when
+// known, through which quad.
+pub(super) struct SeenAt {
+    pub(super) depth: i32,
+    pub(super) tags: HashMap<String, refs::Ref>,
+    pub(super) val: Option<refs::Ref>,
+    pub(super) link: Option<refs::Ref>
+}
+
+// Which of the nodes it reaches a recursive traversal returns: all of them,
+// or only the leaves, those without links to follow or where it stops.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Emit {
+    #[default]
+    All,
+    Leaves
+}
+
+// Whether a recursive traversal returns nodes level by level, or down one
+// branch before the next. Depths are the fewest steps to a node either way,
+// so depth first reaches every node as deep as the one it returns first.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Order {
+    #[default]
+    BreadthFirst,
+    DepthFirst
 }
 
 pub struct Recursive {
     sub_it: Rc<RefCell<dyn Shape>>,
     morphism: Rc<dyn Morphism>,
     max_depth: i32,
+    min_depth: i32,
+    emit: Emit,
+    order: Order,
+    until: Option<Rc<RefCell<dyn Shape>>>,
     depth_tags: Vec<String>
 }
 
-const DEFAULT_MAX_RECURSIVE_STEPS:i32 = 50;
+pub(super) const DEFAULT_MAX_RECURSIVE_STEPS:i32 = 50;
+
+// the node a result was reached from, saved on the nodes of a level
+const RECURSIVE_BASE_TAG:&str = "__base_recursive";
 
 impl Recursive {
     pub fn new(sub_it: Rc<RefCell<dyn Shape>>, morphism: Rc<dyn Morphism>, max_depth: i32) -> Rc<RefCell<Recursive>> {
@@ -28,6 +57,10 @@ impl Recursive {
             sub_it,
             morphism,
             max_depth: if max_depth == 0 { DEFAULT_MAX_RECURSIVE_STEPS } else { max_depth },
+            min_depth: 1,
+            emit: Emit::All,
+            order: Order::BreadthFirst,
+            until: None,
             depth_tags: Vec::new()
         }))
     }
@@ -35,6 +68,33 @@ impl Recursive {
     pub fn add_depth_tag(&mut self, s: String) {
         self.depth_tags.push(s);
     }
+
+    pub fn morphism(&self) -> Rc<dyn Morphism> {
+        self.morphism.clone()
+    }
+
+    pub fn set_morphism(&mut self, morphism: Rc<dyn Morphism>) {
+        self.morphism = morphism;
+    }
+
+    // nodes reached in fewer steps are followed but not returned. With 0 the
+    // start nodes are returned too, at depth 0, and never reached again
+    pub fn set_min_depth(&mut self, min_depth: i32) {
+        self.min_depth = min_depth;
+    }
+
+    pub fn set_emit(&mut self, emit: Emit) {
+        self.emit = emit;
+    }
+
+    pub fn set_order(&mut self, order: Order) {
+        self.order = order;
+    }
+
+    // the traversal is not followed any further from the nodes of until
+    pub fn set_until(&mut self, until: Rc<RefCell<dyn Shape>>) {
+        self.until = Some(until);
+    }
 }
 
 
@@ -47,11 +107,11 @@ impl Recursive {
 
 impl Shape for Recursive {
     fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
-        return RecursiveNext::new(self.sub_it.borrow().iterate(), self.morphism.clone(), self.max_depth, self.depth_tags.clone())
+        return RecursiveNext::new(self)
     }
 
     fn lookup(&self) -> Rc<RefCell<dyn Index>> {
-        return RecursiveContains::new(RecursiveNext::new(self.sub_it.borrow().iterate(), self.morphism.clone(), self.max_depth, self.depth_tags.clone()))
+        return RecursiveContains::new(RecursiveNext::new(self))
     }
 
     fn stats(&mut self) -> Result<Costs, String> {
@@ -78,15 +138,28 @@ impl Shape for Recursive {
         if new_it.is_some() {
             self.sub_it = new_it.unwrap();
         }
+        if let Some(until) = self.until.as_ref() {
+            let new_it = until.borrow_mut().optimize();
+            if new_it.is_some() {
+                self.until = new_it;
+            }
+        }
         return None
     }
 
     fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
-        return Some(vec![self.sub_it.clone()])
+        let mut subs = vec![self.sub_it.clone()];
+        subs.extend(self.until.clone());
+        return Some(subs)
+    }
+
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.sub_it = f(self.sub_it.clone());
+        self.until = self.until.as_ref().map(|u| f(u.clone()));
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Recursive
+        ShapeType::Recursive(self)
     }
 }
 
@@ -99,38 +172,72 @@ struct RecursiveNext {
 
     morphism: Rc<dyn Morphism>,
     seen: HashMap<u64, SeenAt>,
+    // the nodes returned so far
+    emitted: HashSet<u64>,
+    started: bool,
+    // the nodes reached but not yet returned by visit, with their depth
+    pending: VecDeque<(refs::Ref, i32)>,
+    // the nodes of the next level to follow
+    frontier: Vec<refs::Ref>,
+    // the level being followed by next_it, its depth and the nodes of it
+    // that have any link to follow
+    expanding: Option<(Vec<refs::Ref>, i32)>,
+    linked: HashSet<u64>,
+    // the nodes of the last level followed, with whether they are leaves
+    done: VecDeque<(refs::Ref, i32, bool)>,
+    // the deepest level whose nodes are all known to be leaves or not
+    finished: i32,
+    // depth first: the nodes first reached from each node, whether each
+    // node is a leaf and the nodes left to return
+    children: HashMap<u64, Vec<refs::Ref>>,
+    leaves: HashMap<u64, bool>,
+    walk: Option<Vec<(refs::Ref, i32)>>,
     next_it: Rc<RefCell<dyn Scanner>>,
-    depth: i32,
     max_depth: i32,
+    min_depth: i32,
+    emit: Emit,
+    order: Order,
+    until: Option<Rc<RefCell<dyn Index>>>,
     path_map: HashMap<u64, Vec<HashMap<String, refs::Ref>>>,
     path_index: usize,
     contains_value: Option<refs::Ref>,
-    depth_tags: Vec<String>,
-    depth_cache: Vec<refs::Ref>,
-    base_it: Rc<RefCell<dyn Shape>>
+    depth_tags: Vec<String>
 }
 
 impl RecursiveNext {
-    fn new(sub_it: Rc<RefCell<dyn Scanner>>, morphism: Rc<dyn Morphism>, max_depth: i32, depth_tags: Vec<String>) -> Rc<RefCell<RecursiveNext>> {
+    fn new(r: &Recursive) -> Rc<RefCell<RecursiveNext>> {
         Rc::new(RefCell::new(RecursiveNext {
-            sub_it,
+            sub_it: r.sub_it.borrow().iterate(),
             result: SeenAt {
                 depth: 0,
                 tags: HashMap::new(),
-                val: None
+                val: None,
+                link: None
             },
             err: None,
-            morphism,
+            morphism: r.morphism.clone(),
             seen: HashMap::new(),
+            emitted: HashSet::new(),
+            started: false,
+            pending: VecDeque::new(),
+            frontier: Vec::new(),
+            expanding: None,
+            linked: HashSet::new(),
+            done: VecDeque::new(),
+            finished: -1,
+            children: HashMap::new(),
+            leaves: HashMap::new(),
+            walk: None,
             next_it: Null::new(),
-            depth: 0,
-            max_depth,
+            max_depth: r.max_depth,
+            min_depth: r.min_depth,
+            emit: r.emit,
+            order: r.order,
+            until: r.until.as_ref().map(|u| u.borrow().lookup()),
             path_map: HashMap::new(),
             path_index: 0,
             contains_value: None,
-            depth_tags,
-            depth_cache: Vec::new(),
-            base_it: Fixed::new(vec![])
+            depth_tags: r.depth_tags.clone()
         }))
     }
 
@@ -142,11 +249,7 @@ impl RecursiveNext {
 
                 let mut at = at;
 
-                while at.depth != 1 {
-                    if at.depth == 0 {
-                        panic!("seen chain is broken");
-                    }
-                    
+                while at.depth > 1 {
                     let v = &at.val.as_ref().unwrap().key().unwrap(); // TODO: FIX THIS
                     at = self.seen.get(v).unwrap();
     
@@ -164,9 +267,189 @@ impl RecursiveNext {
 
 
     }
-}
 
-const RECURSEIVE_BASE_TAG:&str = "__base_recursive";
+    // collects the paths of the start nodes, which are followed first
+    fn start(&mut self) {
+        self.started = true;
+        while self.sub_it.borrow_mut().next() {
+            let res = self.sub_it.borrow().result().unwrap();
+
+            if res.key().is_none() { continue }
+
+            let key = res.key().unwrap();
+
+            let mut tags:HashMap<String, refs::Ref> = HashMap::new();
+            self.sub_it.borrow().tag_results(&mut tags);
+
+            if !self.path_map.contains_key(&key) {
+                self.path_map.insert(key, vec![tags]);
+                if self.min_depth <= 0 {
+                    self.seen.insert(key, SeenAt {
+                        val: Some(res.clone()),
+                        depth: 0,
+                        tags: HashMap::new(),
+                        link: None
+                    });
+                }
+                self.pending.push_back((res, 0));
+            } else {
+                self.path_map.get_mut(&key).unwrap().push(tags);
+            }
+
+            while self.sub_it.borrow_mut().next_path() {
+                let mut tags:HashMap<String, refs::Ref> = HashMap::new();
+                self.sub_it.borrow().tag_results(&mut tags);
+                self.path_map.get_mut(&key).unwrap().push(tags);
+            }
+        }
+        self.err = self.sub_it.borrow().err();
+    }
+
+    // makes a node reached the current result
+    fn set_result(&mut self, val: refs::Ref) {
+        let at = self.seen.get(&val.key().unwrap()).unwrap();
+        self.result.depth = at.depth;
+        self.result.tags = at.tags.clone();
+        self.contains_value = Some(self.get_base_value(&val));
+        self.result.val = Some(val);
+    }
+
+    // returns a node unless it is not deep enough or was returned before
+    fn emit(&mut self, val: refs::Ref, depth: i32) -> bool {
+        if depth < self.min_depth || !self.emitted.insert(val.key().unwrap()) {
+            return false
+        }
+        self.set_result(val);
+        true
+    }
+
+    // Takes the traversal a step further, breadth first, following all the
+    // nodes of a level with one morphism. Returns a node when it is reached,
+    // with no leaf flag unless it is a stop, and once its level is followed,
+    // flagged a leaf if it had no links.
+    fn visit(&mut self) -> Option<(refs::Ref, i32, Option<bool>)> {
+        loop {
+            if self.err.is_some() {
+                return None
+            }
+
+            if let Some((node, depth)) = self.pending.pop_front() {
+                let stop = (self.max_depth > 0 && depth >= self.max_depth)
+                    || (depth > 0 && self.until.as_ref().is_some_and(|u| u.borrow_mut().contains(&node)));
+                if stop {
+                    return Some((node, depth, Some(true)))
+                }
+                self.frontier.push(node.clone());
+                return Some((node, depth, None))
+            }
+
+            if let Some((_, depth)) = self.expanding.as_ref() {
+                let depth = *depth;
+                if self.next_it.borrow_mut().next() {
+                    let val = self.next_it.borrow().result().unwrap();
+                    let key = match val.key() {
+                        Some(k) => k,
+                        None => continue
+                    };
+                    let mut tags:HashMap<String, refs::Ref> = HashMap::new();
+                    self.next_it.borrow().tag_results(&mut tags);
+                    let base = match tags.remove(RECURSIVE_BASE_TAG) {
+                        Some(b) => b,
+                        None => continue
+                    };
+                    self.linked.insert(base.key().unwrap());
+                    if self.seen.contains_key(&key) { continue }
+
+                    if self.order == Order::DepthFirst {
+                        self.children.entry(base.key().unwrap()).or_default().push(val.clone());
+                    }
+                    self.seen.insert(key, SeenAt {
+                        val: Some(base),
+                        depth: depth + 1,
+                        tags,
+                        link: None
+                    });
+                    self.pending.push_back((val, depth + 1));
+                    continue
+                }
+
+                self.err = self.next_it.borrow().err();
+                let _ = self.next_it.borrow_mut().close();
+                let (level, depth) = self.expanding.take().unwrap();
+                for node in level {
+                    let leaf = !self.linked.contains(&node.key().unwrap());
+                    self.done.push_back((node, depth, leaf));
+                }
+                self.linked.clear();
+                continue
+            }
+
+            if let Some((node, depth, leaf)) = self.done.pop_front() {
+                if self.done.is_empty() {
+                    self.finished = depth;
+                }
+                return Some((node, depth, Some(leaf)))
+            }
+
+            let level = std::mem::take(&mut self.frontier);
+            let depth = match level.first() {
+                Some(n) => self.seen.get(&n.key().unwrap()).map_or(0, |at| at.depth),
+                None => {
+                    self.finished = i32::MAX;
+                    return None
+                }
+            };
+            let base: Rc<RefCell<dyn Shape>> = Fixed::new(level.clone());
+            self.next_it = self.morphism.morph(tag(&base, &RECURSIVE_BASE_TAG)).borrow().iterate();
+            self.expanding = Some((level, depth));
+        }
+    }
+
+    // knows whether the nodes down to the depth are leaves and which nodes
+    // they reached first
+    fn expand_to(&mut self, depth: i32) {
+        while self.finished < depth {
+            match self.visit() {
+                Some((node, _, Some(leaf))) => {
+                    self.leaves.insert(node.key().unwrap(), leaf);
+                },
+                Some(_) => {},
+                None => return
+            }
+        }
+    }
+
+    // Returns the nodes down the tree of the nodes they were first reached
+    // from, following a level only once a node of it is returned.
+    fn next_depth_first(&mut self) -> bool {
+        if self.walk.is_none() {
+            let mut walk: Vec<(refs::Ref, i32)> = self.pending.iter().cloned().collect();
+            walk.reverse();
+            self.walk = Some(walk);
+        }
+
+        while let Some((node, depth)) = self.walk.as_mut().unwrap().pop() {
+            self.expand_to(depth);
+            if self.err.is_some() {
+                return false
+            }
+            let key = node.key().unwrap();
+            if let Some(children) = self.children.remove(&key) {
+                let seen = &self.seen;
+                let children = children.into_iter().rev().map(|c| {
+                    let depth = seen[&c.key().unwrap()].depth;
+                    (c, depth)
+                });
+                self.walk.as_mut().unwrap().extend(children);
+            }
+            let leaf = self.leaves.get(&key) == Some(&true);
+            if (self.emit == Emit::All || leaf) && self.emit(node, depth) {
+                return true
+            }
+        }
+        false
+    }
+}
 
 
 // impl fmt::Display for RecursiveNext {
@@ -196,8 +479,9 @@ impl Base for RecursiveNext {
             }
         }
         
-        self.next_it.borrow().tag_results(tags);
-        tags.remove(RECURSEIVE_BASE_TAG);
+        for (k, v) in &self.result.tags {
+            tags.insert(k.clone(), v.clone());
+        }
     }
 
     fn result(&self) -> Option<refs::Ref> {
@@ -238,6 +522,12 @@ impl Base for RecursiveNext {
         if res.is_err() {
             return res;
         }
+        if let Some(until) = self.until.as_ref() {
+            let res = until.borrow_mut().close();
+            if res.is_err() {
+                return res;
+            }
+        }
         self.seen = HashMap::new();
         if self.err.is_some() { Err(self.err.as_ref().unwrap().clone()) } else { Ok(()) }
     }
@@ -247,90 +537,34 @@ impl Scanner for RecursiveNext {
     fn next(&mut self) -> bool {
         self.path_index = 0;
 
-        if self.depth == 0 {
-            while self.sub_it.borrow_mut().next() {
-                let res = self.sub_it.borrow().result().unwrap();
-
-                if res.key().is_none() { continue }
-
-                let key = res.key().unwrap();
-
-                self.depth_cache.push(self.sub_it.borrow().result().unwrap());
-                let mut tags:HashMap<String, refs::Ref> = HashMap::new();
-                self.sub_it.borrow().tag_results(&mut tags);
-
-                if !self.path_map.contains_key(&key) {
-                    self.path_map.insert(key.clone(), vec![tags]);
-                } else {
-                    self.path_map.get_mut(&key).unwrap().push(tags);
-                }
-
-                while self.sub_it.borrow_mut().next_path() {
-                    let mut tags:HashMap<String, refs::Ref> = HashMap::new();
-                    self.sub_it.borrow().tag_results(&mut tags);
-
-                    if !self.path_map.contains_key(&key) {
-                        self.path_map.insert(key.clone(), vec![tags]);
-                    } else {
-                        self.path_map.get_mut(&key).unwrap().push(tags);
-                    }
-                }
-            }
+        if !self.started {
+            self.start();
         }
 
-        loop {
-            if !self.next_it.borrow_mut().next() {
-                if self.max_depth > 0 && self.depth >= self.max_depth {
-                    return false
-                } else if self.depth_cache.is_empty() {
-                    return false
-                }
-                self.depth += 1;
-                self.base_it = Fixed::new(self.depth_cache.clone());
-                self.depth_cache = Vec::new();
-                let _ = self.next_it.borrow_mut().close();
-
-                self.next_it = self.morphism.morph(tag(&self.base_it, &RECURSEIVE_BASE_TAG).clone()).borrow().iterate();
-
-                continue
-            }
-            let val = self.next_it.borrow().result().unwrap();
-            if val.key().is_none() { continue }
-
-            let mut results:HashMap<String, refs::Ref> = HashMap::new();
-            self.next_it.borrow().tag_results(&mut results);
-            if !self.seen.contains_key(&val.key().unwrap()) {
+        if self.order == Order::DepthFirst {
+            return self.next_depth_first()
+        }
 
-                let base = results.get(RECURSEIVE_BASE_TAG).unwrap().clone();
-                results.remove(RECURSEIVE_BASE_TAG);
-                
-                self.seen.insert(val.key().unwrap().clone(), SeenAt {
-                    val: Some(base),
-                    depth: self.depth,
-                    tags: results
-                });
-                self.result.depth = self.depth;
-                self.result.val = Some(val.clone());
-                self.contains_value = Some(self.get_base_value(&val));
-                self.depth_cache.push(val.clone());
+        // every node is returned on its first visit, or leaves once done
+        while let Some((node, depth, leaf)) = self.visit() {
+            if (self.emit == Emit::All || leaf == Some(true)) && self.emit(node, depth) {
                 return true
             }
         }
+        false
     }
 }
 
 
 
 struct RecursiveContains {
-    next: Rc<RefCell<RecursiveNext>>,
-    tags: HashMap<String, refs::Ref>
+    next: Rc<RefCell<RecursiveNext>>
 }
 
 impl RecursiveContains {
     fn new(next: Rc<RefCell<RecursiveNext>>) -> Rc<RefCell<RecursiveContains>> {
         Rc::new(RefCell::new(RecursiveContains {
-           next,
-           tags: HashMap::new()
+           next
         }))
     }
 }
@@ -345,9 +579,6 @@ impl Base for RecursiveContains {
 
     fn tag_results(&self, tags: &mut HashMap<String, refs::Ref>) {
         self.next.borrow().tag_results(tags);
-        for (key, val) in &self.tags {
-            tags.insert(key.clone(), val.clone());
-        }
     }
 
     fn result(&self) -> Option<refs::Ref> {
@@ -371,27 +602,21 @@ impl Index for RecursiveContains {
     fn contains(&mut self, val:&refs::Ref) -> bool {
         self.next.borrow_mut().path_index = 0;
 
-        if val.key().is_none() { return false }
-
-        let depth = self.next.borrow_mut().seen.get(&val.key().unwrap()).map(|x| x.depth);
-        let tags = self.next.borrow_mut().seen.get(&val.key().unwrap()).map(|x| x.tags.clone());
+        let key = match val.key() {
+            Some(k) => k,
+            None => return false
+        };
 
-        if depth.is_some() && tags.is_some() {
-            let contains_value = Some(self.next.borrow().get_base_value(val));
-            self.next.borrow_mut().contains_value = contains_value;
-            self.next.borrow_mut().result.depth = depth.unwrap();
-            self.next.borrow_mut().result.val = Some(val.clone());
-            self.tags = tags.unwrap();
+        if self.next.borrow().emitted.contains(&key) {
+            self.next.borrow_mut().set_result(val.clone());
             return true
         }
         while self.next.borrow_mut().next() {
             let n = self.next.borrow().result().unwrap();
-            let nkey = n.key();
-            if nkey.is_none() { return false }
-            if nkey.unwrap() == val.key().unwrap() {
+            if n.key() == Some(key) {
                 return true
             }
         }
         return false
     }
-}
\ No newline at end of file
+}
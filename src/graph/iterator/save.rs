@@ -27,6 +27,19 @@ impl Save {
             }))
         }))
     }
+
+    // names of every tag this iterator sets, fixed tags included
+    pub fn tag_names(&self) -> Vec<String> {
+        let tags = self.tags.borrow();
+        let mut names = tags.tags.clone();
+        names.extend(tags.fixed_tags.keys().cloned());
+        names
+    }
+
+    // the iterator whose results are saved, and the tags without fixed values
+    pub fn saved(&self) -> (Rc<RefCell<dyn Shape>>, Vec<String>) {
+        (self.it.clone(), self.tags.borrow().tags.clone())
+    }
 }
 
 
@@ -75,6 +88,13 @@ impl Shape for Save {
         Some(vec![self.it.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.it = f(self.it.clone());
+    }
+
+    fn sorted(&mut self) -> bool {
+        self.it.borrow_mut().sorted()
+    }
 
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::Save(self)
@@ -0,0 +1,458 @@
+use super::{Shape, Base, Index, Scanner, Costs, ShapeType};
+use super::context::QueryContext;
+use super::recursive::{SeenAt, DEFAULT_MAX_RECURSIVE_STEPS};
+use super::super::refs::{Ref, Size};
+use super::super::quad::{QuadStore, Direction};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+// Tags of a found path: "path:0" is the start, "path:<i>" the i-th node
+// after it and "path:<i>:via" the predicate of the link leading to it.
+pub const PATH_TAG: &str = "path";
+
+pub fn node_tag(i: usize) -> String {
+    format!("{}:{}", PATH_TAG, i)
+}
+
+pub fn via_tag(i: usize) -> String {
+    format!("{}:{}:via", PATH_TAG, i)
+}
+
+
+// The nodes of `to` that can be reached from a node of `from` following
+// links with a predicate of `via`, or any link without it, together with
+// the shortest way to get there. Every search goes from both ends at once,
+// a level at a time from the side with fewer nodes to expand, and stops
+// at the first level where they meet.
+pub struct ShortestPath {
+    qs: Rc<RefCell<dyn QuadStore>>,
+    from: Rc<RefCell<dyn Shape>>,
+    to: Rc<RefCell<dyn Shape>>,
+    via: Option<Rc<RefCell<dyn Shape>>>,
+    max_depth: i32,
+    rev: bool,
+    ctx: Option<QueryContext>
+}
+
+impl ShortestPath {
+    // a reversed search follows links from their object to their subject
+    pub fn new(qs: Rc<RefCell<dyn QuadStore>>, from: Rc<RefCell<dyn Shape>>, to: Rc<RefCell<dyn Shape>>, via: Option<Rc<RefCell<dyn Shape>>>, max_depth: i32, rev: bool) -> Rc<RefCell<ShortestPath>> {
+        Rc::new(RefCell::new(ShortestPath {
+            qs,
+            from,
+            to,
+            via,
+            max_depth: if max_depth == 0 { DEFAULT_MAX_RECURSIVE_STEPS } else { max_depth },
+            rev,
+            ctx: None
+        }))
+    }
+
+    // the search stops expanding nodes once the context ends the run
+    pub fn set_context(&mut self, ctx: QueryContext) {
+        self.ctx = Some(ctx);
+    }
+
+    fn search(&self) -> Search {
+        Search {
+            qs: self.qs.clone(),
+            from: self.from.clone(),
+            via: self.via.clone(),
+            max_depth: self.max_depth,
+            rev: self.rev,
+            started: false,
+            sources: Vec::new(),
+            source_tags: HashMap::new(),
+            preds: None,
+            links: HashMap::new(),
+            ctx: self.ctx.clone(),
+            err: None
+        }
+    }
+}
+
+impl Shape for ShortestPath {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        ShortestPathNext::new(self.search(), self.to.borrow().iterate())
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        ShortestPathContains::new(self.search(), self.to.borrow().lookup())
+    }
+
+    fn stats(&mut self) -> Result<Costs, String> {
+        // every target may search the whole graph within the depth
+        let from = self.from.borrow_mut().stats()?;
+        let to = self.to.borrow_mut().stats()?;
+        let search = from.next_cost.saturating_mul(from.size.value).saturating_add(self.max_depth as i64);
+        Ok(Costs {
+            next_cost: to.next_cost.saturating_add(search),
+            contains_cost: to.contains_cost.saturating_add(search),
+            size: Size {
+                value: to.size.value,
+                exact: false
+            }
+        })
+    }
+
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        let optimized = |s: &Rc<RefCell<dyn Shape>>| {
+            let n = s.borrow_mut().optimize();
+            n.unwrap_or_else(|| s.clone())
+        };
+        self.from = optimized(&self.from);
+        self.to = optimized(&self.to);
+        self.via = self.via.as_ref().map(optimized);
+        None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        Some(vec![self.from.clone(), self.to.clone()].into_iter().chain(self.via.clone()).collect())
+    }
+
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.from = f(self.from.clone());
+        self.to = f(self.to.clone());
+        self.via = self.via.as_ref().map(|v| f(v.clone()));
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::ShortestPath(self)
+    }
+}
+
+
+// the quad of a link and the node at its other end
+type Link = (Ref, Ref);
+
+
+// The state shared by the searches for every target: the start nodes,
+// the predicates to follow and the links of the nodes expanded so far.
+struct Search {
+    qs: Rc<RefCell<dyn QuadStore>>,
+    from: Rc<RefCell<dyn Shape>>,
+    via: Option<Rc<RefCell<dyn Shape>>>,
+    max_depth: i32,
+    rev: bool,
+    started: bool,
+    sources: Vec<Ref>,
+    source_tags: HashMap<u64, HashMap<String, Ref>>,
+    preds: Option<HashSet<u64>>,
+    links: HashMap<(bool, u64), Rc<Vec<Link>>>,
+    ctx: Option<QueryContext>,
+    err: Option<String>
+}
+
+impl Search {
+    fn alive(&self) -> bool {
+        self.ctx.as_ref().is_none_or(|c| c.check())
+    }
+
+    fn start(&mut self) -> bool {
+        if self.started {
+            return self.err.is_none()
+        }
+        self.started = true;
+
+        let it = self.from.borrow().iterate();
+        while it.borrow_mut().next() {
+            let r = it.borrow().result().unwrap();
+            let k = match r.key() {
+                Some(k) => k,
+                None => continue
+            };
+            if let std::collections::hash_map::Entry::Vacant(e) = self.source_tags.entry(k) {
+                let mut tags = HashMap::new();
+                it.borrow().tag_results(&mut tags);
+                e.insert(tags);
+                self.sources.push(r);
+            }
+        }
+        self.err = it.borrow().err();
+        let _ = it.borrow_mut().close();
+
+        if let Some(via) = &self.via {
+            let mut preds = HashSet::new();
+            let it = via.borrow().iterate();
+            while it.borrow_mut().next() {
+                if let Some(k) = it.borrow().result().and_then(|r| r.key()) {
+                    preds.insert(k);
+                }
+            }
+            self.err = self.err.take().or(it.borrow().err());
+            let _ = it.borrow_mut().close();
+            self.preds = Some(preds);
+        }
+        self.err.is_none()
+    }
+
+    // the links leaving a node, or arriving at it for the backward search
+    fn links(&mut self, node: &Ref, forward: bool) -> Rc<Vec<Link>> {
+        let k = node.key().unwrap();
+        if let Some(l) = self.links.get(&(forward, k)) {
+            return l.clone()
+        }
+        let (start, end) = if forward != self.rev {
+            (Direction::Subject, Direction::Object)
+        } else {
+            (Direction::Object, Direction::Subject)
+        };
+        let qs = self.qs.borrow();
+        let mut res = Vec::new();
+        let it = qs.quad_iterator(&start, node).borrow().iterate();
+        while it.borrow_mut().next() {
+            let q = it.borrow().result().unwrap();
+            if let Some(preds) = &self.preds {
+                let p = qs.quad_direction(&q, &Direction::Predicate).and_then(|p| p.key());
+                if !p.is_some_and(|p| preds.contains(&p)) {
+                    continue
+                }
+            }
+            if let Some(n) = qs.quad_direction(&q, &end).filter(|n| n.key().is_some()) {
+                res.push((q, n));
+            }
+        }
+        let _ = it.borrow_mut().close();
+        let res = Rc::new(res);
+        self.links.insert((forward, k), res.clone());
+        res
+    }
+
+    // Expands the nodes of one side a level further. Returns the next level
+    // and the node of this level where the sides meet on the shortest path.
+    fn expand(&mut self, frontier: &[Ref], forward: bool, seen: &mut HashMap<u64, SeenAt>, other: &HashMap<u64, SeenAt>, nodes: &mut HashMap<u64, Ref>) -> (Vec<Ref>, Option<u64>) {
+        let mut next = Vec::new();
+        let mut best: Option<(i32, u64)> = None;
+        for n in frontier {
+            if !self.alive() {
+                break
+            }
+            let depth = seen[&n.key().unwrap()].depth + 1;
+            for (q, m) in self.links(n, forward).iter() {
+                let k = m.key().unwrap();
+                if seen.contains_key(&k) {
+                    continue
+                }
+                seen.insert(k, SeenAt {
+                    depth,
+                    tags: HashMap::new(),
+                    val: Some(n.clone()),
+                    link: Some(q.clone())
+                });
+                if let Some(o) = other.get(&k) {
+                    if best.is_none_or(|(d, _)| depth + o.depth < d) {
+                        best = Some((depth + o.depth, k));
+                    }
+                }
+                nodes.entry(k).or_insert_with(|| m.clone());
+                next.push(m.clone());
+            }
+        }
+        (next, best.map(|b| b.1))
+    }
+
+    // The nodes from a start node to the target, each with the quad of the
+    // link leading to it, None if it is too far or can't be reached.
+    fn find(&mut self, target: &Ref) -> Option<Vec<(Ref, Option<Ref>)>> {
+        let tk = target.key()?;
+        if self.source_tags.contains_key(&tk) {
+            return Some(vec![(target.clone(), None)])
+        }
+
+        let root = || SeenAt { depth: 0, tags: HashMap::new(), val: None, link: None };
+        let mut fwd: HashMap<u64, SeenAt> = self.sources.iter().map(|s| (s.key().unwrap(), root())).collect();
+        let mut bwd: HashMap<u64, SeenAt> = HashMap::new();
+        bwd.insert(tk, root());
+        let mut nodes: HashMap<u64, Ref> = self.sources.iter().map(|s| (s.key().unwrap(), s.clone())).collect();
+        nodes.insert(tk, target.clone());
+
+        let mut ff = self.sources.clone();
+        let mut bf = vec![target.clone()];
+        let mut depth = 0;
+        let meet = loop {
+            if ff.is_empty() || bf.is_empty() || depth >= self.max_depth || !self.alive() {
+                return None
+            }
+            depth += 1;
+            let meet = if ff.len() <= bf.len() {
+                let (next, meet) = self.expand(&ff, true, &mut fwd, &bwd, &mut nodes);
+                ff = next;
+                meet
+            } else {
+                let (next, meet) = self.expand(&bf, false, &mut bwd, &fwd, &mut nodes);
+                bf = next;
+                meet
+            };
+            if let Some(m) = meet {
+                break m
+            }
+        };
+
+        // back from where the sides met to the start, then on to the target
+        let mut path = Vec::new();
+        let mut k = meet;
+        loop {
+            let at = &fwd[&k];
+            path.push((nodes[&k].clone(), at.link.clone()));
+            match &at.val {
+                Some(prev) => k = prev.key().unwrap(),
+                None => break
+            }
+        }
+        path.reverse();
+
+        let mut k = meet;
+        while let Some(next) = &bwd[&k].val {
+            path.push((next.clone(), bwd[&k].link.clone()));
+            k = next.key().unwrap();
+        }
+        Some(path)
+    }
+
+    fn tags(&self, path: &[(Ref, Option<Ref>)], tags: &mut HashMap<String, Ref>) {
+        if let Some(t) = path.first().and_then(|(s, _)| self.source_tags.get(&s.key().unwrap())) {
+            for (k, v) in t {
+                tags.insert(k.clone(), v.clone());
+            }
+        }
+        let qs = self.qs.borrow();
+        for (i, (n, link)) in path.iter().enumerate() {
+            tags.insert(node_tag(i), n.clone());
+            if let Some(p) = link.as_ref().and_then(|l| qs.quad_direction(l, &Direction::Predicate)) {
+                tags.insert(via_tag(i), p);
+            }
+        }
+    }
+}
+
+
+struct ShortestPathNext {
+    search: Search,
+    to: Rc<RefCell<dyn Scanner>>,
+    path: Vec<(Ref, Option<Ref>)>,
+    result: Option<Ref>,
+    err: Option<String>
+}
+
+impl ShortestPathNext {
+    fn new(search: Search, to: Rc<RefCell<dyn Scanner>>) -> Rc<RefCell<ShortestPathNext>> {
+        Rc::new(RefCell::new(ShortestPathNext {
+            search,
+            to,
+            path: Vec::new(),
+            result: None,
+            err: None
+        }))
+    }
+}
+
+impl Base for ShortestPathNext {
+    fn tag_results(&self, tags: &mut HashMap<String, Ref>) {
+        self.search.tags(&self.path, tags);
+        self.to.borrow().tag_results(tags);
+    }
+
+    fn result(&self) -> Option<Ref> {
+        self.result.clone()
+    }
+
+    fn next_path(&mut self) -> bool {
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        self.err.clone()
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.to.borrow_mut().close()
+    }
+}
+
+impl Scanner for ShortestPathNext {
+    fn next(&mut self) -> bool {
+        if !self.search.start() {
+            self.err = self.search.err.clone();
+            return false
+        }
+        while self.to.borrow_mut().next() {
+            let t = self.to.borrow().result().unwrap();
+            if let Some(path) = self.search.find(&t) {
+                self.path = path;
+                self.result = Some(t);
+                return true
+            }
+        }
+        self.err = self.to.borrow().err();
+        self.result = None;
+        false
+    }
+}
+
+
+struct ShortestPathContains {
+    search: Search,
+    to: Rc<RefCell<dyn Index>>,
+    path: Vec<(Ref, Option<Ref>)>,
+    result: Option<Ref>,
+    err: Option<String>
+}
+
+impl ShortestPathContains {
+    fn new(search: Search, to: Rc<RefCell<dyn Index>>) -> Rc<RefCell<ShortestPathContains>> {
+        Rc::new(RefCell::new(ShortestPathContains {
+            search,
+            to,
+            path: Vec::new(),
+            result: None,
+            err: None
+        }))
+    }
+}
+
+impl Base for ShortestPathContains {
+    fn tag_results(&self, tags: &mut HashMap<String, Ref>) {
+        self.search.tags(&self.path, tags);
+        self.to.borrow().tag_results(tags);
+    }
+
+    fn result(&self) -> Option<Ref> {
+        self.result.clone()
+    }
+
+    fn next_path(&mut self) -> bool {
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        self.err.clone()
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.to.borrow_mut().close()
+    }
+}
+
+impl Index for ShortestPathContains {
+    fn contains(&mut self, v: &Ref) -> bool {
+        self.result = None;
+        if !self.search.start() {
+            self.err = self.search.err.clone();
+            return false
+        }
+        if !self.to.borrow_mut().contains(v) {
+            self.err = self.to.borrow().err();
+            return false
+        }
+        match self.search.find(v) {
+            Some(path) => {
+                self.path = path;
+                self.result = Some(v.clone());
+                true
+            },
+            None => false
+        }
+    }
+}
@@ -51,7 +51,9 @@ impl Shape for Skip {
         if self.skip == 0 {
             return optimized_primary_it
         }
-        self.primary_it = optimized_primary_it.unwrap();
+        if let Some(it) = optimized_primary_it {
+            self.primary_it = it;
+        }
         return None
     }
 
@@ -59,6 +61,10 @@ impl Shape for Skip {
         Some(vec![self.primary_it.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.primary_it = f(self.primary_it.clone());
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::Skip
     }
@@ -2,22 +2,50 @@ use super::{Shape, ShapeType, Base, Index, Scanner, Costs};
 use super::materialize::MaterializeResult;
 use super::super::refs;
 use super::super::quad::QuadStore;
-use std::collections::HashMap;
+use super::super::value::Value;
+use std::collections::{HashMap, BinaryHeap};
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::cmp::Ordering;
+use crate::query::expr::order_values;
 
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc
+}
+
+// A value the results are sorted on: the one saved as tag, or the result
+// itself for None.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub tag: Option<String>,
+    pub order: SortOrder
+}
+
+
+// Sorts the results on the keys, one after the other, or on the name of
+// the result when there are none. With a limit only that many of the first
+// results are kept while sorting.
 pub struct Sort {
     qs: Rc<RefCell<dyn QuadStore>>,
     sub_it: Rc<RefCell<dyn Shape>>,
+    keys: Vec<SortKey>,
+    limit: usize
 }
 
 impl Sort {
     pub fn new(qs: Rc<RefCell<dyn QuadStore>>, sub_it: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<Sort>> {
+        Sort::new_with_keys(qs, sub_it, Vec::new(), 0)
+    }
+
+    pub fn new_with_keys(qs: Rc<RefCell<dyn QuadStore>>, sub_it: Rc<RefCell<dyn Shape>>, keys: Vec<SortKey>, limit: usize) -> Rc<RefCell<Sort>> {
         Rc::new(RefCell::new(Sort {
             qs,
-            sub_it
+            sub_it,
+            keys,
+            limit
         }))
     }
 }
@@ -32,7 +60,7 @@ impl Sort {
 
 impl Shape for Sort {
     fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
-        SortNext::new(self.qs.clone(), self.sub_it.borrow().iterate())
+        SortNext::new(self.qs.clone(), self.sub_it.borrow().iterate(), self.keys.clone(), self.limit)
     }
 
     fn lookup(&self) -> Rc<RefCell<dyn Index>> {
@@ -45,7 +73,7 @@ impl Shape for Sort {
             next_cost: sub_stats.next_cost * 2,
             contains_cost: sub_stats.contains_cost,
             size: refs::Size {
-                value: sub_stats.size.value,
+                value: if self.limit > 0 { sub_stats.size.value.min(self.limit as i64) } else { sub_stats.size.value },
                 exact: true
             }
         })
@@ -63,6 +91,10 @@ impl Shape for Sort {
         Some(vec![self.sub_it.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.sub_it = f(self.sub_it.clone());
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::Sort
     }
@@ -72,12 +104,26 @@ impl Shape for Sort {
 struct SortValue  {
     result: MaterializeResult,
     string: String,
-    paths: Vec<MaterializeResult>
+    paths: Vec<MaterializeResult>,
+    // the values of the keys, with the order of each
+    keys: Vec<(Option<Value>, SortOrder)>,
+    // equal results keep the order they came in
+    seq: usize
 }
 
 impl Ord for SortValue {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.string.cmp(&other.string)
+        if self.keys.is_empty() {
+            return self.string.cmp(&other.string).then(self.seq.cmp(&other.seq))
+        }
+        for ((a, order), (b, _)) in self.keys.iter().zip(&other.keys) {
+            let o = order_values(a.as_ref(), b.as_ref());
+            let o = if *order == SortOrder::Desc { o.reverse() } else { o };
+            if o != Ordering::Equal {
+                return o
+            }
+        }
+        self.seq.cmp(&other.seq)
     }
 }
 
@@ -89,7 +135,7 @@ impl PartialOrd for SortValue {
 
 impl PartialEq for SortValue {
     fn eq(&self, other: &Self) -> bool {
-        self.string == other.string
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -100,6 +146,8 @@ impl Eq for SortValue {}
 struct SortNext {
     qs: Rc<RefCell<dyn QuadStore>>,
     sub_it: Rc<RefCell<dyn Scanner>>,
+    keys: Vec<SortKey>,
+    limit: usize,
     ordered: Option<Vec<SortValue>>,
     result: Option<MaterializeResult>,
     err: Option<String>,
@@ -108,10 +156,12 @@ struct SortNext {
 }
 
 impl SortNext {
-    fn new(qs: Rc<RefCell<dyn QuadStore>>, sub_it: Rc<RefCell<dyn Scanner>>) -> Rc<RefCell<SortNext>> {
+    fn new(qs: Rc<RefCell<dyn QuadStore>>, sub_it: Rc<RefCell<dyn Scanner>>, keys: Vec<SortKey>, limit: usize) -> Rc<RefCell<SortNext>> {
        Rc::new(RefCell::new(SortNext {
            qs,
            sub_it,
+           keys,
+           limit,
            ordered: None,
            result: None,
            err: None,
@@ -174,7 +224,7 @@ impl Scanner for SortNext {
         }
 
         if self.ordered.is_none() {
-            let v = get_sorted_values(&self.qs, &self.sub_it);
+            let v = get_sorted_values(&self.qs, &self.sub_it, &self.keys, self.limit);
             if let Err(e) = v {
                 self.err = Some(e);
                 return false
@@ -196,8 +246,11 @@ impl Scanner for SortNext {
     }
 }
 
-fn get_sorted_values(qs: &Rc<RefCell<dyn QuadStore>>, it: &Rc<RefCell<dyn Scanner>>) -> Result<Vec<SortValue>, String> {
+fn get_sorted_values(qs: &Rc<RefCell<dyn QuadStore>>, it: &Rc<RefCell<dyn Scanner>>, keys: &[SortKey], limit: usize) -> Result<Vec<SortValue>, String> {
     let mut v:Vec<SortValue> = Vec::new();
+    // the best results so far, the worst of them on top
+    let mut top:BinaryHeap<SortValue> = BinaryHeap::new();
+    let mut seq = 0;
 
     while it.borrow_mut().next() {
         let id = it.borrow().result().unwrap();
@@ -205,14 +258,24 @@ fn get_sorted_values(qs: &Rc<RefCell<dyn QuadStore>>, it: &Rc<RefCell<dyn Scanne
         let string = name.to_string();
         let mut tags = HashMap::new();
         it.borrow().tag_results(&mut tags);
+        let keys = keys.iter().map(|k| {
+            let value = match &k.tag {
+                Some(t) => tags.get(t).and_then(|r| qs.borrow().name_of(r)),
+                None => Some(name.clone())
+            };
+            (value, k.order)
+        }).collect();
         let mut val = SortValue {
             result: MaterializeResult {
                 id: id.clone(),
                 tags
             },
             string,
-            paths: Vec::new()
+            paths: Vec::new(),
+            keys,
+            seq
         };
+        seq += 1;
         while it.borrow_mut().next_path() {
             tags = HashMap::new();
             it.borrow().tag_results(&mut tags);
@@ -221,13 +284,23 @@ fn get_sorted_values(qs: &Rc<RefCell<dyn QuadStore>>, it: &Rc<RefCell<dyn Scanne
                 tags
             });
         }
-        v.push(val);
+        if limit > 0 {
+            top.push(val);
+            if top.len() > limit {
+                top.pop();
+            }
+        } else {
+            v.push(val);
+        }
     }
 
     if it.borrow().err().is_some() {
         return Err(it.borrow().err().unwrap());
     }
 
+    if limit > 0 {
+        return Ok(top.into_sorted_vec())
+    }
     v.sort();
     return Ok(v)
-} 
\ No newline at end of file
+}
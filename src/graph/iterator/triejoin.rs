@@ -0,0 +1,553 @@
+use super::{Shape, ShapeType, Base, Index, Scanner, Costs};
+use super::fixed::Fixed;
+use super::super::refs::{self, Ref};
+use super::super::quad::{QuadStore, Direction};
+use std::collections::{HashMap, BTreeMap};
+use std::ops::Bound::{Excluded, Unbounded};
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+// A position of a quad pattern: a variable, by its place in the join order, or a fixed node.
+#[derive(Debug, Clone)]
+pub enum Slot {
+    Var(usize),
+    Fixed(Ref)
+}
+
+// A quad pattern of the join, or a set of nodes a variable is one of.
+#[derive(Debug, Clone)]
+pub struct Atom {
+    pub slots: Vec<(Direction, Slot)>,
+    nodes: Option<(usize, Vec<Ref>)>
+}
+
+impl Atom {
+    pub fn new(slots: Vec<(Direction, Slot)>) -> Atom {
+        Atom {
+            slots,
+            nodes: None
+        }
+    }
+
+    pub fn nodes(var: usize, nodes: Vec<Ref>) -> Atom {
+        Atom {
+            slots: Vec::new(),
+            nodes: Some((var, nodes))
+        }
+    }
+
+    // the variables of the atom in join order, the levels of its trie
+    fn vars(&self) -> Vec<usize> {
+        let mut vars: Vec<usize> = self.slots.iter().filter_map(|(_, s)| match s {
+            Slot::Var(v) => Some(*v),
+            Slot::Fixed(_) => None
+        }).chain(self.nodes.as_ref().map(|(v, _)| *v)).collect();
+        vars.sort_unstable();
+        vars.dedup();
+        vars
+    }
+}
+
+
+// Leapfrog triejoin over quad patterns. Every pattern is read once into a
+// trie of its variables in join order, from its sub-iterator: the quads of
+// the smallest index of its fixed nodes, all quads without one, or the nodes
+// of a set. Variables are bound one at a time in
+// the given order, the candidates for a variable are the intersection of the
+// trie levels below the bound prefix, found by seeking every level to the
+// largest head. A binding is returned once for every combination of quads
+// that make it, the result of the iterator is the first variable and every
+// variable is saved under its tags.
+pub struct TrieJoin {
+    qs: Rc<RefCell<dyn QuadStore>>,
+    vars: Vec<String>,
+    tags: Rc<Vec<Vec<String>>>,
+    atoms: Rc<Vec<Atom>>,
+    sources: Vec<Rc<RefCell<dyn Shape>>>
+}
+
+impl TrieJoin {
+    pub fn new(qs: Rc<RefCell<dyn QuadStore>>, vars: Vec<String>, atoms: Vec<Atom>) -> Rc<RefCell<TrieJoin>> {
+        let tags = vars.iter().map(|v| vec![v.clone()]).collect();
+        TrieJoin::with_tags(qs, vars, tags, atoms)
+    }
+
+    // a join that saves every variable under the given tags, none for variables only used to join
+    pub fn with_tags(qs: Rc<RefCell<dyn QuadStore>>, vars: Vec<String>, tags: Vec<Vec<String>>, atoms: Vec<Atom>) -> Rc<RefCell<TrieJoin>> {
+        let sources = atoms.iter().map(|a| source(&*qs.borrow(), a)).collect();
+        Rc::new(RefCell::new(TrieJoin {
+            qs,
+            vars,
+            tags: Rc::new(tags),
+            atoms: Rc::new(atoms),
+            sources
+        }))
+    }
+
+    fn join(&self) -> Join {
+        Join::new(self.qs.clone(), self.vars.len(), self.tags.clone(), self.atoms.clone(), self.sources.clone())
+    }
+}
+
+impl Shape for TrieJoin {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        TrieJoinNext::new(self.join())
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        TrieJoinContains::new(self.join())
+    }
+
+    fn stats(&mut self) -> Result<Costs, String> {
+        // the join yields at most as many results as its smallest pattern has quads,
+        // once per quad of the patterns over the first variable
+        let qs = self.qs.borrow();
+        let quads = qs.stats(false)?.quads.value;
+        let mut size = quads;
+        for a in self.atoms.iter() {
+            size = size.min(atom_size(&*qs, a, quads));
+        }
+        Ok(Costs {
+            contains_cost: self.atoms.len() as i64 * ((size.max(1) as f64).ln() as i64 + 1),
+            next_cost: self.atoms.len() as i64,
+            size: refs::Size {
+                value: size,
+                exact: false
+            }
+        })
+    }
+
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        Some(self.sources.clone())
+    }
+
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.sources = self.sources.iter().map(|s| f(s.clone())).collect();
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::TrieJoin
+    }
+}
+
+
+// the number of quads a pattern matches, from the smallest index of its fixed nodes
+fn atom_size(qs: &dyn QuadStore, a: &Atom, quads: i64) -> i64 {
+    if let Some((_, nodes)) = &a.nodes {
+        return nodes.len() as i64
+    }
+    let mut size = quads;
+    for (d, s) in &a.slots {
+        if let Slot::Fixed(r) = s {
+            size = size.min(qs.quad_iterator_size(d, r).map(|s| s.value).unwrap_or(quads));
+        }
+    }
+    size
+}
+
+
+// what an atom is read from: the smallest index of its fixed nodes
+fn source(qs: &dyn QuadStore, a: &Atom) -> Rc<RefCell<dyn Shape>> {
+    if let Some((_, nodes)) = &a.nodes {
+        return Fixed::new(nodes.clone())
+    }
+    let mut best: Option<(&Direction, &Ref, i64)> = None;
+    for (d, s) in &a.slots {
+        if let Slot::Fixed(r) = s {
+            let size = qs.quad_iterator_size(d, r).map(|s| s.value).unwrap_or(i64::MAX);
+            if best.is_none_or(|(_, _, b)| size < b) {
+                best = Some((d, r, size));
+            }
+        }
+    }
+    match best {
+        Some((d, r, _)) => qs.quad_iterator(d, r),
+        None => qs.quads_all_iterator()
+    }
+}
+
+
+// The values of the variables of a pattern, one level per variable, and at
+// the end the number of quads with those values.
+#[derive(Default)]
+struct Trie {
+    count: usize,
+    next: BTreeMap<u64, Trie>
+}
+
+impl Trie {
+    fn insert(&mut self, keys: &[u64]) {
+        match keys.split_first() {
+            Some((k, rest)) => self.next.entry(*k).or_default().insert(rest),
+            None => self.count += 1
+        }
+    }
+
+    fn walk(&self, keys: &[u64]) -> Option<&Trie> {
+        match keys.split_first() {
+            Some((k, rest)) => self.next.get(k)?.walk(rest),
+            None => Some(self)
+        }
+    }
+
+    // reads the quads of the pattern, or its nodes, from its source
+    fn build(qs: &dyn QuadStore, a: &Atom, source: &Rc<RefCell<dyn Shape>>) -> Result<Trie, String> {
+        let mut trie = Trie::default();
+        let vars = a.vars();
+
+        let scan = source.borrow().iterate();
+        let mut scan = scan.borrow_mut();
+        let mut values: HashMap<usize, u64> = HashMap::new();
+        'quads: while scan.next() {
+            let q = match scan.result() {
+                Some(q) => q,
+                None => continue
+            };
+            if a.nodes.is_some() {
+                if let Some(k) = q.key() {
+                    if !trie.next.contains_key(&k) {
+                        trie.insert(&[k]);
+                    }
+                }
+                continue;
+            }
+            values.clear();
+            for (d, s) in &a.slots {
+                let k = match qs.quad_direction(&q, d).and_then(|r| r.key()) {
+                    Some(k) => k,
+                    None => continue 'quads
+                };
+                match s {
+                    Slot::Fixed(r) => if r.key().unwrap_or(0) != k {
+                        continue 'quads;
+                    },
+                    // a variable used twice in the pattern takes the same node
+                    Slot::Var(v) => if *values.entry(*v).or_insert(k) != k {
+                        continue 'quads;
+                    }
+                }
+            }
+            let keys: Vec<u64> = vars.iter().map(|v| values[v]).collect();
+            trie.insert(&keys);
+        }
+        let err = scan.err();
+        let closed = scan.close();
+        match err.or(closed.err()) {
+            Some(e) => Err(e),
+            None => Ok(trie)
+        }
+    }
+}
+
+
+// Intersects trie levels. Every level is sought forward to the largest head
+// until all heads agree.
+fn leapfrog(levels: &[&BTreeMap<u64, Trie>]) -> Vec<u64> {
+    let mut res = Vec::new();
+    let mut max = match levels.iter().map(|l| l.keys().next().copied()).collect::<Option<Vec<u64>>>() {
+        Some(heads) if !heads.is_empty() => *heads.iter().max().unwrap(),
+        _ => return res
+    };
+
+    loop {
+        let mut agreed = true;
+        for l in levels {
+            // seek
+            match l.range(max..).next() {
+                Some((k, _)) if *k != max => {
+                    max = *k;
+                    agreed = false;
+                },
+                Some(_) => {},
+                None => return res
+            }
+        }
+        if agreed {
+            res.push(max);
+            match levels[0].range((Excluded(max), Unbounded)).next() {
+                Some((k, _)) => max = *k,
+                None => return res
+            }
+        }
+    }
+}
+
+
+// The state of a join: the candidates left at every level and the bound prefix.
+struct Join {
+    qs: Rc<RefCell<dyn QuadStore>>,
+    vars: usize,
+    tags: Rc<Vec<Vec<String>>>,
+    atoms: Rc<Vec<Atom>>,
+    sources: Vec<Rc<RefCell<dyn Shape>>>,
+    // the tries of the atoms, read on the first use and kept across resets
+    tries: Option<Rc<Vec<Trie>>>,
+    err: Option<String>,
+    candidates: Vec<Vec<u64>>,
+    pos: Vec<usize>,
+    bound: Vec<u64>,
+    // the times the current binding is still to be returned
+    repeat: usize,
+    // the value of the first variable when the join is used for contains
+    first: Option<u64>,
+    started: bool
+}
+
+impl Join {
+    fn new(qs: Rc<RefCell<dyn QuadStore>>, vars: usize, tags: Rc<Vec<Vec<String>>>, atoms: Rc<Vec<Atom>>, sources: Vec<Rc<RefCell<dyn Shape>>>) -> Join {
+        Join {
+            qs,
+            vars,
+            tags,
+            atoms,
+            sources,
+            tries: None,
+            err: None,
+            candidates: Vec::new(),
+            pos: Vec::new(),
+            bound: Vec::new(),
+            repeat: 0,
+            first: None,
+            started: false
+        }
+    }
+
+    fn reset(&mut self, first: Option<u64>) {
+        self.candidates.clear();
+        self.pos.clear();
+        self.bound.clear();
+        self.repeat = 0;
+        self.first = first;
+        self.started = false;
+    }
+
+    // reads the tries, false if a source failed
+    fn load(&mut self) -> bool {
+        if self.tries.is_none() && self.err.is_none() {
+            let qs = self.qs.borrow();
+            let tries: Result<Vec<Trie>, String> = self.atoms.iter().zip(&self.sources).map(|(a, s)| Trie::build(&*qs, a, s)).collect();
+            match tries {
+                Ok(t) => self.tries = Some(Rc::new(t)),
+                Err(e) => self.err = Some(e)
+            }
+        }
+        self.tries.is_some()
+    }
+
+    fn tries(&self) -> Rc<Vec<Trie>> {
+        self.tries.clone().unwrap()
+    }
+
+    // the trie of the atom below the values of its variables bound so far
+    fn prefix<'a>(&self, a: &Atom, t: &'a Trie, upto: usize) -> Option<&'a Trie> {
+        let keys: Vec<u64> = a.vars().into_iter().filter(|v| *v < upto).map(|v| self.bound[v]).collect();
+        t.walk(&keys)
+    }
+
+    // the candidates of the variable are the values every atom that uses it allows
+    fn open(&mut self, var: usize) {
+        let tries = self.tries();
+        let mut levels = Vec::new();
+        let mut empty = false;
+        for (a, t) in self.atoms.iter().zip(tries.iter()) {
+            if !a.vars().contains(&var) {
+                continue;
+            }
+            match self.prefix(a, t, var) {
+                Some(t) => levels.push(&t.next),
+                None => empty = true
+            }
+        }
+        let mut cands = if empty { Vec::new() } else { leapfrog(&levels) };
+
+        if var == 0 {
+            if let Some(f) = self.first {
+                cands.retain(|x| *x == f);
+            }
+        }
+
+        self.candidates.push(cands);
+        self.pos.push(0);
+    }
+
+    // the number of quad combinations that make the full binding
+    fn paths(&mut self) -> usize {
+        let tries = self.tries();
+        self.atoms.iter().zip(tries.iter())
+            .map(|(a, t)| self.prefix(a, t, self.vars).map(|t| t.count).unwrap_or(0))
+            .product()
+    }
+
+    // moves to the next full binding, or to the next path to the current one
+    fn advance(&mut self) -> bool {
+        if self.vars == 0 {
+            return false
+        }
+
+        if !self.started {
+            if !self.load() {
+                return false
+            }
+            self.started = true;
+            self.open(0);
+        } else if self.repeat > 0 {
+            self.repeat -= 1;
+            return true
+        } else {
+            // step past the current binding of the last variable
+            self.bound.pop();
+            let last = self.pos.len() - 1;
+            self.pos[last] += 1;
+        }
+
+        loop {
+            let level = self.pos.len() - 1;
+            if self.pos[level] < self.candidates[level].len() {
+                self.bound.push(self.candidates[level][self.pos[level]]);
+                if level + 1 < self.vars {
+                    self.open(level + 1);
+                    continue;
+                }
+                let paths = self.paths();
+                if paths > 0 {
+                    self.repeat = paths - 1;
+                    return true
+                }
+                self.bound.pop();
+                self.pos[level] += 1;
+                continue;
+            }
+
+            // this level is exhausted, go back up
+            self.candidates.pop();
+            self.pos.pop();
+            if self.pos.is_empty() {
+                return false
+            }
+            self.bound.pop();
+            let up = self.pos.len() - 1;
+            self.pos[up] += 1;
+        }
+    }
+
+    fn result(&self) -> Option<Ref> {
+        if self.bound.len() != self.vars || self.vars == 0 {
+            return None
+        }
+        Some(Ref {
+            k: Some(self.bound[0]),
+            content: refs::Content::None
+        })
+    }
+
+    fn tag_results(&self, tags: &mut HashMap<String, Ref>) {
+        if self.bound.len() != self.vars {
+            return
+        }
+        for (names, k) in self.tags.iter().zip(&self.bound) {
+            for t in names {
+                tags.insert(t.clone(), Ref {
+                    k: Some(*k),
+                    content: refs::Content::None
+                });
+            }
+        }
+    }
+}
+
+
+struct TrieJoinNext {
+    join: Join
+}
+
+impl TrieJoinNext {
+    fn new(join: Join) -> Rc<RefCell<TrieJoinNext>> {
+        Rc::new(RefCell::new(TrieJoinNext {
+            join
+        }))
+    }
+}
+
+impl Base for TrieJoinNext {
+    fn tag_results(&self, tags: &mut HashMap<String, Ref>) {
+        self.join.tag_results(tags)
+    }
+
+    fn result(&self) -> Option<Ref> {
+        self.join.result()
+    }
+
+    fn next_path(&mut self) -> bool {
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        self.join.err.clone()
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.join.reset(None);
+        Ok(())
+    }
+}
+
+impl Scanner for TrieJoinNext {
+    fn next(&mut self) -> bool {
+        self.join.advance()
+    }
+}
+
+
+// Checks a value of the first variable, the other bindings for it are
+// returned by next_path.
+struct TrieJoinContains {
+    join: Join
+}
+
+impl TrieJoinContains {
+    fn new(join: Join) -> Rc<RefCell<TrieJoinContains>> {
+        Rc::new(RefCell::new(TrieJoinContains {
+            join
+        }))
+    }
+}
+
+impl Base for TrieJoinContains {
+    fn tag_results(&self, tags: &mut HashMap<String, Ref>) {
+        self.join.tag_results(tags)
+    }
+
+    fn result(&self) -> Option<Ref> {
+        self.join.result()
+    }
+
+    fn next_path(&mut self) -> bool {
+        self.join.first.is_some() && self.join.advance()
+    }
+
+    fn err(&self) -> Option<String> {
+        self.join.err.clone()
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.join.reset(None);
+        Ok(())
+    }
+}
+
+impl Index for TrieJoinContains {
+    fn contains(&mut self, v: &Ref) -> bool {
+        match v.key() {
+            Some(k) => {
+                self.join.reset(Some(k));
+                self.join.advance()
+            },
+            None => false
+        }
+    }
+}
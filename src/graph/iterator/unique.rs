@@ -61,6 +61,10 @@ impl Shape for Unique {
         Some(vec![self.sub_it.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.sub_it = f(self.sub_it.clone());
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::Unique
     }
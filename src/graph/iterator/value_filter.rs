@@ -255,6 +255,10 @@ impl Shape for ValueFilter {
         Some(vec![self.sub.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.sub = f(self.sub.clone());
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::ValueFilter
     }
@@ -408,7 +412,9 @@ impl Index for ValueFilterContains {
             return false
         }
         let ok = self.sub.borrow_mut().contains(v);
-        if !ok {
+        if ok {
+            self.result = Some(v.clone());
+        } else {
             self.err = self.sub.borrow().err();
         }
         return ok
@@ -0,0 +1,563 @@
+use super::{Shape, Base, Index, Scanner, Costs, ShapeType};
+use super::context::QueryContext;
+use super::shortest_path::{node_tag, via_tag};
+use super::super::refs::{Ref, Size, pre_fetched};
+use super::super::quad::{QuadStore, Direction};
+use super::super::value::Value;
+use std::collections::{HashMap, HashSet, BinaryHeap};
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+// the tag with the total cost of a found path
+pub const COST_TAG: &str = "path:cost";
+
+
+// How the cost of a link is read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Weight {
+    // the number in the label of the link
+    Label,
+    // Links lead to an edge node, which links on to the next node with the
+    // predicate `to` and has its cost as the object of `cost`.
+    Edge { to: Value, cost: Value }
+}
+
+// An estimate of the cost from a node to the target, for an A* search. It
+// must never be more than the real cost, or a costlier path may be found.
+pub type Heuristic = Rc<dyn Fn(&Value, &Value) -> f64>;
+
+
+// The nodes of `to` that can be reached from a node of `from`, together with
+// the path to them of least total cost, following links with a predicate of
+// `via`, or any link without it. Links without a cost are not followed.
+pub struct WeightedPath {
+    qs: Rc<RefCell<dyn QuadStore>>,
+    from: Rc<RefCell<dyn Shape>>,
+    to: Rc<RefCell<dyn Shape>>,
+    via: Option<Rc<RefCell<dyn Shape>>>,
+    weight: Weight,
+    heuristic: Option<Heuristic>,
+    rev: bool,
+    ctx: Option<QueryContext>
+}
+
+impl WeightedPath {
+    // a reversed search follows links from their object to their subject
+    pub fn new(qs: Rc<RefCell<dyn QuadStore>>, from: Rc<RefCell<dyn Shape>>, to: Rc<RefCell<dyn Shape>>, via: Option<Rc<RefCell<dyn Shape>>>, weight: Weight, heuristic: Option<Heuristic>, rev: bool) -> Rc<RefCell<WeightedPath>> {
+        Rc::new(RefCell::new(WeightedPath {
+            qs,
+            from,
+            to,
+            via,
+            weight,
+            heuristic,
+            rev,
+            ctx: None
+        }))
+    }
+
+    // no more nodes are taken off the queue once the context ends the run
+    pub fn set_context(&mut self, ctx: QueryContext) {
+        self.ctx = Some(ctx);
+    }
+
+    fn search(&self) -> Search {
+        Search {
+            qs: self.qs.clone(),
+            from: self.from.clone(),
+            via: self.via.clone(),
+            weight: self.weight.clone(),
+            heuristic: self.heuristic.clone(),
+            rev: self.rev,
+            started: false,
+            sources: Vec::new(),
+            source_tags: HashMap::new(),
+            preds: None,
+            edge: None,
+            links: HashMap::new(),
+            ctx: self.ctx.clone(),
+            err: None
+        }
+    }
+}
+
+impl Shape for WeightedPath {
+    fn iterate(&self) -> Rc<RefCell<dyn Scanner>> {
+        WeightedPathNext::new(self.search(), self.to.borrow().iterate())
+    }
+
+    fn lookup(&self) -> Rc<RefCell<dyn Index>> {
+        WeightedPathContains::new(self.search(), self.to.borrow().lookup())
+    }
+
+    fn stats(&mut self) -> Result<Costs, String> {
+        // every target may search the whole graph
+        let from = self.from.borrow_mut().stats()?;
+        let to = self.to.borrow_mut().stats()?;
+        let search = from.next_cost.saturating_mul(from.size.value);
+        Ok(Costs {
+            next_cost: to.next_cost.saturating_add(search),
+            contains_cost: to.contains_cost.saturating_add(search),
+            size: Size {
+                value: to.size.value,
+                exact: false
+            }
+        })
+    }
+
+    fn optimize(&mut self) -> Option<Rc<RefCell<dyn Shape>>> {
+        let optimized = |s: &Rc<RefCell<dyn Shape>>| {
+            let n = s.borrow_mut().optimize();
+            n.unwrap_or_else(|| s.clone())
+        };
+        self.from = optimized(&self.from);
+        self.to = optimized(&self.to);
+        self.via = self.via.as_ref().map(optimized);
+        None
+    }
+
+    fn sub_iterators(&self) -> Option<Vec<Rc<RefCell<dyn Shape>>>> {
+        Some(vec![self.from.clone(), self.to.clone()].into_iter().chain(self.via.clone()).collect())
+    }
+
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.from = f(self.from.clone());
+        self.to = f(self.to.clone());
+        self.via = self.via.as_ref().map(|v| f(v.clone()));
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::WeightedPath(self)
+    }
+}
+
+
+// the nodes of a path, each with the way it was reached
+type Steps = Vec<(Ref, Option<Ref>)>;
+
+// A link to a node: the node, what it is tagged with as the way to it (the
+// predicate, or the edge node) and its cost.
+struct Link {
+    node: Ref,
+    via: Ref,
+    cost: f64
+}
+
+// A node to expand, the one with the least estimated total first.
+struct Queued {
+    estimate: f64,
+    cost: f64,
+    node: Ref
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+
+fn quads(qs: &dyn QuadStore, d: &Direction, node: &Ref) -> Vec<Ref> {
+    let it = qs.quad_iterator(d, node).borrow().iterate();
+    let mut res = Vec::new();
+    while it.borrow_mut().next() {
+        res.push(it.borrow().result().unwrap());
+    }
+    let _ = it.borrow_mut().close();
+    res
+}
+
+fn number(qs: &dyn QuadStore, r: &Ref) -> Option<f64> {
+    match qs.name_of(r) {
+        Some(Value::Number(n)) => n.as_f64(),
+        _ => None
+    }
+}
+
+fn predicate_in(qs: &dyn QuadStore, q: &Ref, preds: &HashSet<u64>) -> bool {
+    qs.quad_direction(q, &Direction::Predicate).and_then(|p| p.key()).is_some_and(|p| preds.contains(&p))
+}
+
+
+// The state shared by the searches for every target: the start nodes, the
+// predicates to follow and the links of the nodes expanded so far.
+struct Search {
+    qs: Rc<RefCell<dyn QuadStore>>,
+    from: Rc<RefCell<dyn Shape>>,
+    via: Option<Rc<RefCell<dyn Shape>>>,
+    weight: Weight,
+    heuristic: Option<Heuristic>,
+    rev: bool,
+    started: bool,
+    sources: Vec<Ref>,
+    source_tags: HashMap<u64, HashMap<String, Ref>>,
+    preds: Option<HashSet<u64>>,
+    // the predicates of an edge node, to the next node and to the cost
+    edge: Option<(HashSet<u64>, HashSet<u64>)>,
+    links: HashMap<u64, Rc<Vec<Link>>>,
+    ctx: Option<QueryContext>,
+    err: Option<String>
+}
+
+impl Search {
+    fn alive(&self) -> bool {
+        self.ctx.as_ref().is_none_or(|c| c.check())
+    }
+
+    fn start(&mut self) -> bool {
+        if self.started {
+            return self.err.is_none()
+        }
+        self.started = true;
+
+        let it = self.from.borrow().iterate();
+        while it.borrow_mut().next() {
+            let r = it.borrow().result().unwrap();
+            let k = match r.key() {
+                Some(k) => k,
+                None => continue
+            };
+            if let std::collections::hash_map::Entry::Vacant(e) = self.source_tags.entry(k) {
+                let mut tags = HashMap::new();
+                it.borrow().tag_results(&mut tags);
+                e.insert(tags);
+                self.sources.push(r);
+            }
+        }
+        self.err = it.borrow().err();
+        let _ = it.borrow_mut().close();
+
+        if let Some(via) = &self.via {
+            let mut preds = HashSet::new();
+            let it = via.borrow().iterate();
+            while it.borrow_mut().next() {
+                if let Some(k) = it.borrow().result().and_then(|r| r.key()) {
+                    preds.insert(k);
+                }
+            }
+            self.err = self.err.take().or(it.borrow().err());
+            let _ = it.borrow_mut().close();
+            self.preds = Some(preds);
+        }
+
+        if let Weight::Edge { to, cost } = &self.weight {
+            let qs = self.qs.borrow();
+            let key = |v: &Value| qs.value_of(v).and_then(|r| r.key()).into_iter().collect();
+            self.edge = Some((key(to), key(cost)));
+        }
+        self.err.is_none()
+    }
+
+    fn follows(&self, qs: &dyn QuadStore, q: &Ref) -> bool {
+        match &self.preds {
+            Some(preds) => predicate_in(qs, q, preds),
+            None => true
+        }
+    }
+
+    // the cost of an edge node, its first number with the cost predicate
+    fn edge_cost(&self, qs: &dyn QuadStore, e: &Ref) -> Option<f64> {
+        let (_, cost) = self.edge.as_ref()?;
+        quads(qs, &Direction::Subject, e).iter()
+            .filter(|q| predicate_in(qs, q, cost))
+            .find_map(|q| qs.quad_direction(q, &Direction::Object).and_then(|o| number(qs, &o)))
+    }
+
+    // the links leaving a node, or arriving at it for a reversed search
+    fn links(&mut self, node: &Ref) -> Rc<Vec<Link>> {
+        let k = node.key().unwrap();
+        if let Some(l) = self.links.get(&k) {
+            return l.clone()
+        }
+        let (start, end) = if self.rev {
+            (Direction::Object, Direction::Subject)
+        } else {
+            (Direction::Subject, Direction::Object)
+        };
+        let qs = self.qs.borrow();
+        let qs = &*qs;
+        let mut res = Vec::new();
+        match &self.edge {
+            None => {
+                for q in quads(qs, &start, node) {
+                    if !self.follows(qs, &q) {
+                        continue
+                    }
+                    let cost = qs.quad_direction(&q, &Direction::Label).and_then(|l| number(qs, &l));
+                    let next = qs.quad_direction(&q, &end).filter(|n| n.key().is_some());
+                    let via = qs.quad_direction(&q, &Direction::Predicate);
+                    if let (Some(cost), Some(node), Some(via)) = (cost, next, via) {
+                        res.push(Link { node, via, cost });
+                    }
+                }
+            },
+            Some((to, _)) => {
+                // A link is node -via-> edge -to-> next, so a reversed search
+                // takes the `to` quads first.
+                let step = |n: &Ref, to_next: bool| -> Vec<Ref> {
+                    quads(qs, &start, n).into_iter()
+                        .filter(|q| if to_next { predicate_in(qs, q, to) } else { self.follows(qs, q) })
+                        .collect()
+                };
+                for q in step(node, self.rev) {
+                    let e = match qs.quad_direction(&q, &end).filter(|e| e.key().is_some()) {
+                        Some(e) => e,
+                        None => continue
+                    };
+                    let cost = match self.edge_cost(qs, &e) {
+                        Some(c) => c,
+                        None => continue
+                    };
+                    for q in step(&e, !self.rev) {
+                        if let Some(node) = qs.quad_direction(&q, &end).filter(|n| n.key().is_some()) {
+                            res.push(Link { node, via: e.clone(), cost });
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(l) = res.iter().find(|l| l.cost < 0.0) {
+            self.err = Some(format!("negative link cost {} on a weighted path", l.cost));
+            res.clear();
+        }
+        let res = Rc::new(res);
+        self.links.insert(k, res.clone());
+        res
+    }
+
+    // The nodes from a start node to the target, each with the way it was
+    // reached, and the total cost. None if the target can't be reached.
+    fn find(&mut self, target: &Ref) -> Option<(Steps, f64)> {
+        let tk = target.key()?;
+        let goal = match &self.heuristic {
+            Some(h) => self.qs.borrow().name_of(target).map(|t| (h.clone(), t)),
+            None => None
+        };
+        let estimate = |qs: &Rc<RefCell<dyn QuadStore>>, n: &Ref| match &goal {
+            Some((h, t)) => qs.borrow().name_of(n).map_or(0.0, |v| h(&v, t).max(0.0)),
+            None => 0.0
+        };
+
+        let mut costs: HashMap<u64, f64> = HashMap::new();
+        let mut prev: HashMap<u64, (Ref, Ref)> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        for s in &self.sources {
+            costs.insert(s.key().unwrap(), 0.0);
+            heap.push(Queued { estimate: estimate(&self.qs, s), cost: 0.0, node: s.clone() });
+        }
+
+        while let Some(Queued { cost, node, .. }) = heap.pop() {
+            if !self.alive() {
+                return None
+            }
+            let k = node.key().unwrap();
+            // already reached for less since it was queued
+            if cost > costs[&k] {
+                continue
+            }
+            if k == tk {
+                // back to the start, each node with the way it was reached
+                let mut path = Vec::new();
+                let mut n = node;
+                while let Some((p, via)) = prev.get(&n.key().unwrap()) {
+                    path.push((n, Some(via.clone())));
+                    n = p.clone();
+                }
+                path.push((n, None));
+                path.reverse();
+                return Some((path, cost))
+            }
+            for l in self.links(&node).iter() {
+                let c = cost + l.cost;
+                let nk = l.node.key().unwrap();
+                if costs.get(&nk).is_none_or(|d| c < *d) {
+                    costs.insert(nk, c);
+                    prev.insert(nk, (node.clone(), l.via.clone()));
+                    heap.push(Queued { estimate: c + estimate(&self.qs, &l.node), cost: c, node: l.node.clone() });
+                }
+            }
+            if self.err.is_some() {
+                return None
+            }
+        }
+        None
+    }
+
+    fn tags(&self, path: &[(Ref, Option<Ref>)], cost: f64, tags: &mut HashMap<String, Ref>) {
+        let first = match path.first() {
+            Some((s, _)) => s,
+            None => return
+        };
+        if let Some(t) = self.source_tags.get(&first.key().unwrap()) {
+            for (k, v) in t {
+                tags.insert(k.clone(), v.clone());
+            }
+        }
+        for (i, (n, via)) in path.iter().enumerate() {
+            tags.insert(node_tag(i), n.clone());
+            if let Some(v) = via {
+                tags.insert(via_tag(i), v.clone());
+            }
+        }
+        tags.insert(COST_TAG.to_string(), pre_fetched(Value::from(cost)));
+    }
+}
+
+
+struct WeightedPathNext {
+    search: Search,
+    to: Rc<RefCell<dyn Scanner>>,
+    path: Steps,
+    cost: f64,
+    result: Option<Ref>,
+    err: Option<String>
+}
+
+impl WeightedPathNext {
+    fn new(search: Search, to: Rc<RefCell<dyn Scanner>>) -> Rc<RefCell<WeightedPathNext>> {
+        Rc::new(RefCell::new(WeightedPathNext {
+            search,
+            to,
+            path: Vec::new(),
+            cost: 0.0,
+            result: None,
+            err: None
+        }))
+    }
+}
+
+impl Base for WeightedPathNext {
+    fn tag_results(&self, tags: &mut HashMap<String, Ref>) {
+        self.search.tags(&self.path, self.cost, tags);
+        self.to.borrow().tag_results(tags);
+    }
+
+    fn result(&self) -> Option<Ref> {
+        self.result.clone()
+    }
+
+    fn next_path(&mut self) -> bool {
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        self.err.clone()
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.to.borrow_mut().close()
+    }
+}
+
+impl Scanner for WeightedPathNext {
+    fn next(&mut self) -> bool {
+        self.result = None;
+        if !self.search.start() {
+            self.err = self.search.err.clone();
+            return false
+        }
+        while self.to.borrow_mut().next() {
+            let t = self.to.borrow().result().unwrap();
+            let found = self.search.find(&t);
+            if self.search.err.is_some() {
+                self.err = self.search.err.clone();
+                return false
+            }
+            if let Some((path, cost)) = found {
+                self.path = path;
+                self.cost = cost;
+                self.result = Some(t);
+                return true
+            }
+        }
+        self.err = self.to.borrow().err();
+        false
+    }
+}
+
+
+struct WeightedPathContains {
+    search: Search,
+    to: Rc<RefCell<dyn Index>>,
+    path: Steps,
+    cost: f64,
+    result: Option<Ref>,
+    err: Option<String>
+}
+
+impl WeightedPathContains {
+    fn new(search: Search, to: Rc<RefCell<dyn Index>>) -> Rc<RefCell<WeightedPathContains>> {
+        Rc::new(RefCell::new(WeightedPathContains {
+            search,
+            to,
+            path: Vec::new(),
+            cost: 0.0,
+            result: None,
+            err: None
+        }))
+    }
+}
+
+impl Base for WeightedPathContains {
+    fn tag_results(&self, tags: &mut HashMap<String, Ref>) {
+        self.search.tags(&self.path, self.cost, tags);
+        self.to.borrow().tag_results(tags);
+    }
+
+    fn result(&self) -> Option<Ref> {
+        self.result.clone()
+    }
+
+    fn next_path(&mut self) -> bool {
+        false
+    }
+
+    fn err(&self) -> Option<String> {
+        self.err.clone()
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        self.to.borrow_mut().close()
+    }
+}
+
+impl Index for WeightedPathContains {
+    fn contains(&mut self, v: &Ref) -> bool {
+        self.result = None;
+        if !self.search.start() {
+            self.err = self.search.err.clone();
+            return false
+        }
+        if !self.to.borrow_mut().contains(v) {
+            self.err = self.to.borrow().err();
+            return false
+        }
+        let found = self.search.find(v);
+        self.err = self.search.err.clone();
+        match found {
+            Some((path, cost)) => {
+                self.path = path;
+                self.cost = cost;
+                self.result = Some(v.clone());
+                true
+            },
+            None => false
+        }
+    }
+}
@@ -2,6 +2,7 @@
 use super::refs::{Ref, Size};
 use super::quad::{Direction, QuadStore};
 use super::iterator::{Shape, Scanner, Costs, Index, Base, ShapeType, Null, is_null};
+use super::iterator::bitmap::Bitmap;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -112,6 +113,32 @@ impl Shape for LinksTo {
         Some(vec![self.primary.clone()])
     }
 
+    fn map_sub_iterators(&mut self, f: &dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>) {
+        self.primary = f(self.primary.clone());
+    }
+
+    // the quads of a single node come from one index, in id order
+    fn sorted(&mut self) -> bool {
+        match self.primary.borrow_mut().shape_type() {
+            ShapeType::Fixed(fixed) => fixed.values.borrow().len() <= 1,
+            _ => false
+        }
+    }
+
+    // the union of the postings of the nodes, when there are few of them
+    fn bitmap(&mut self) -> Option<Rc<Bitmap>> {
+        let values = match self.primary.borrow_mut().shape_type() {
+            ShapeType::Fixed(fixed) => fixed.values.borrow().clone(),
+            _ => return None
+        };
+        let mut bits = Bitmap::new();
+        for v in &values {
+            let b = self.qs.borrow().quad_iterator(&self.dir, v).borrow_mut().bitmap()?;
+            bits = bits.or(&b);
+        }
+        Some(Rc::new(bits))
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::LinksTo
     }
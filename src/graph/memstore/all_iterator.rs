@@ -1,6 +1,7 @@
 use crate::graph::value::Value;
 use crate::graph::refs::{Size, Ref, Content};
 use crate::graph::iterator::{Base, Scanner, Index, Shape, Costs, ShapeType};
+use crate::graph::iterator::bitmap::Bitmap;
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -59,6 +60,16 @@ impl Shape for MemStoreAllIterator {
         None
     }
 
+    fn sorted(&mut self) -> bool {
+        true
+    }
+
+    // every node or quad id up to maxid, so a Not can subtract from it
+    fn bitmap(&mut self) -> Option<Rc<Bitmap>> {
+        let all = self.all.read().unwrap();
+        Some(Rc::new(all.iter().filter(|(k, v)| **k <= self.maxid && v.is_node() == self.nodes).map(|(k, _)| *k).collect()))
+    }
+
     fn shape_type(&mut self) -> ShapeType {
         ShapeType::StoreIterator
     }
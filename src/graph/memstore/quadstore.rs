@@ -2,7 +2,7 @@ use crate::graph::value::Value;
 use crate::graph::refs::{Size, Ref, Namer, Content};
 use crate::graph::iterator::{Shape, Null};
 use crate::graph::iterator::quad_ids::QuadIds;
-use crate::graph::quad::{QuadStore, InternalQuad, Quad, Direction, Stats, Delta, IgnoreOptions, Procedure};
+use crate::graph::quad::{QuadStore, SharedQuadStore, InternalQuad, Quad, Direction, Stats, Delta, IgnoreOptions, Procedure};
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -454,6 +454,25 @@ impl QuadStore for MemStore {
     fn close(&self) -> Option<String> {
         None
     }
+
+    fn shared(&self) -> Option<Arc<dyn SharedQuadStore>> {
+        Some(Arc::new(SharedMemStore {
+            store: self.store.clone()
+        }))
+    }
+}
+
+
+struct SharedMemStore {
+    store: Arc<RwLock<InternalMemStore>>
+}
+
+impl SharedQuadStore for SharedMemStore {
+    fn open(&self) -> Rc<RefCell<dyn QuadStore>> {
+        Rc::new(RefCell::new(MemStore {
+            store: self.store.clone()
+        }))
+    }
 }
 
 
@@ -4,6 +4,7 @@ use super::iterator::Shape;
 use super::transaction::Transaction;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::sync::Arc;
 use std::fmt;
 use std::slice::Iter;
 use std::io::Cursor;
@@ -212,6 +213,18 @@ pub trait QuadStore : Namer {
     fn nodes_all_iterator(&self) -> Rc<RefCell<dyn Shape>>;
     fn quads_all_iterator(&self) -> Rc<RefCell<dyn Shape>>;
     fn close(&self) -> Option<String>;
+
+    // a handle to open the store from other threads, None for stores
+    // that can only be read from the thread they were made on
+    fn shared(&self) -> Option<Arc<dyn SharedQuadStore>> {
+        None
+    }
+}
+
+// Opens a quad store on the calling thread. The opened stores read the
+// same data, so queries can be split between threads.
+pub trait SharedQuadStore: Send + Sync {
+    fn open(&self) -> Rc<RefCell<dyn QuadStore>>;
 }
 
 pub struct QuadWriter {
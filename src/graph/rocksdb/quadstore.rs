@@ -1,7 +1,7 @@
 use crate::graph::value::Value;
 use crate::graph::refs::{Size, Ref, Namer, Content};
 use crate::graph::iterator::{Shape, Null};
-use crate::graph::quad::{QuadStore, InternalQuad, Quad, Direction, Stats, Delta, IgnoreOptions, Procedure};
+use crate::graph::quad::{QuadStore, SharedQuadStore, InternalQuad, Quad, Direction, Stats, Delta, IgnoreOptions, Procedure};
 use crate::graph::iterator::quad_ids::QuadIds;
 
 use std::rc::Rc;
@@ -567,6 +567,25 @@ impl QuadStore for RocksDB {
         // TODO: how to close the RocksDB, destroy()?
         return None
     }
+
+    fn shared(&self) -> Option<Arc<dyn SharedQuadStore>> {
+        Some(Arc::new(SharedRocksDB {
+            store: self.store.clone()
+        }))
+    }
+}
+
+
+struct SharedRocksDB {
+    store: Arc<InternalRocksDB>
+}
+
+impl SharedQuadStore for SharedRocksDB {
+    fn open(&self) -> Rc<RefCell<dyn QuadStore>> {
+        Rc::new(RefCell::new(RocksDB {
+            store: self.store.clone()
+        }))
+    }
 }
 
 pub struct PrimitiveCount {
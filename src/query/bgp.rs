@@ -0,0 +1,649 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use super::shape::{self, Shape, AllNodes, Lookup, Intersect, IntersectOpt, NodesFrom, QuadFilter, Quads, Save, Union, Filter, ValueFilter, TrieJoin};
+use super::super::graph::iterator;
+use super::super::graph::quad::{QuadStore, Direction};
+use super::super::graph::value::Value;
+
+
+// prefix of the tags that check a variable reached twice in the pattern
+const ALIAS: &str = "__alias_";
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Var(String),
+    Value(Value)
+}
+
+impl Term {
+    pub fn var(&self) -> Option<&str> {
+        match self {
+            Term::Var(v) => Some(v),
+            Term::Value(_) => None
+        }
+    }
+}
+
+impl From<Value> for Term {
+    fn from(v: Value) -> Self {
+        Term::Value(v)
+    }
+}
+
+// "?x" is a variable, anything else a value
+impl From<&str> for Term {
+    fn from(s: &str) -> Self {
+        match s.strip_prefix('?') {
+            Some(v) if !v.is_empty() => Term::Var(v.to_string()),
+            _ => Term::Value(Value::from(s))
+        }
+    }
+}
+
+
+// A quad pattern, every position is either a variable or a constant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub subject: Term,
+    pub predicate: Term,
+    pub object: Term,
+    pub label: Option<Term>
+}
+
+impl Pattern {
+    pub fn new(subject: Term, predicate: Term, object: Term) -> Pattern {
+        Pattern {
+            subject,
+            predicate,
+            object,
+            label: None
+        }
+    }
+
+    pub fn with_label(mut self, label: Term) -> Pattern {
+        self.label = Some(label);
+        self
+    }
+
+    fn terms(&self) -> Vec<(Direction, &Term)> {
+        let mut terms = vec![
+            (Direction::Subject, &self.subject),
+            (Direction::Predicate, &self.predicate),
+            (Direction::Object, &self.object)
+        ];
+        if let Some(l) = &self.label {
+            terms.push((Direction::Label, l));
+        }
+        terms
+    }
+
+    pub fn vars(&self) -> Vec<&str> {
+        let mut vars: Vec<&str> = Vec::new();
+        for (_, t) in self.terms() {
+            if let Some(v) = t.var() {
+                if !vars.contains(&v) {
+                    vars.push(v);
+                }
+            }
+        }
+        vars
+    }
+
+    fn mentions(&self, var: &str) -> bool {
+        self.terms().iter().any(|(_, t)| t.var() == Some(var))
+    }
+}
+
+
+// A basic graph pattern: quad patterns that must all match, groups of
+// patterns that may match, and value filters pushed down onto variables.
+#[derive(Clone, Default)]
+pub struct Bgp {
+    pub patterns: Vec<Pattern>,
+    pub optional: Vec<Vec<Pattern>>,
+    pub filters: Vec<(String, Rc<dyn ValueFilter>)>
+}
+
+impl Bgp {
+    pub fn new(patterns: Vec<Pattern>) -> Bgp {
+        Bgp {
+            patterns,
+            optional: Vec::new(),
+            filters: Vec::new()
+        }
+    }
+
+    // variables of the required patterns, in order of appearance
+    pub fn vars(&self) -> Vec<String> {
+        let mut vars: Vec<String> = Vec::new();
+        for p in &self.patterns {
+            for v in p.vars() {
+                if !vars.iter().any(|x| x == v) {
+                    vars.push(v.to_string());
+                }
+            }
+        }
+        vars
+    }
+}
+
+
+#[derive(Debug, Clone)]
+struct Alias {
+    tag: String,
+    var: String,
+    // the optional group the alias was created in
+    group: Option<usize>
+}
+
+
+#[derive(Clone)]
+struct Component {
+    shape: Rc<RefCell<dyn Shape>>,
+    root: Option<String>
+}
+
+
+// A compiled pattern. Every connected part of it is a tree of shapes
+// rooted at one variable, the parts are joined when the plan is run.
+#[derive(Clone)]
+pub struct Plan {
+    components: Vec<Component>,
+    aliases: Vec<Alias>,
+    // the tags bound inside each optional group
+    groups: Vec<Vec<String>>
+}
+
+impl Plan {
+    // the variable the first part of the plan starts from
+    pub fn root(&self) -> Option<&str> {
+        self.components.first().and_then(|c| c.root.as_deref())
+    }
+
+    pub fn shapes(&self) -> Vec<Rc<RefCell<dyn Shape>>> {
+        self.components.iter().map(|c| c.shape.clone()).collect()
+    }
+
+    // Checks variables that were reached more than once. A mismatch drops the row,
+    // or only the bindings of the optional group the second occurrence came from.
+    fn resolve(&self, mut row: HashMap<String, Value>) -> Option<HashMap<String, Value>> {
+        let mut failed = HashSet::new();
+
+        for a in &self.aliases {
+            if let (Some(v), Some(cur)) = (row.get(&a.tag), row.get(&a.var)) {
+                if v != cur {
+                    match a.group {
+                        Some(g) => { failed.insert(g); },
+                        None => return None
+                    }
+                }
+            }
+        }
+
+        for g in &failed {
+            for tag in &self.groups[*g] {
+                row.remove(tag);
+            }
+        }
+
+        for a in &self.aliases {
+            if let Some(v) = row.remove(&a.tag) {
+                row.entry(a.var.clone()).or_insert(v);
+            }
+        }
+
+        Some(row)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope {
+    Required,
+    Optional(usize)
+}
+
+
+struct Compiler<'a> {
+    bgp: &'a Bgp,
+    used: Vec<bool>,
+    opt_used: Vec<Vec<bool>>,
+    bound: HashSet<String>,
+    // node shapes of the required variables, optional groups are attached to them
+    nodes: HashMap<String, Rc<RefCell<IntersectOpt>>>,
+    filters: HashMap<String, Vec<Rc<dyn ValueFilter>>>,
+    aliases: Vec<Alias>,
+    groups: Vec<Vec<String>>
+}
+
+impl<'a> Compiler<'a> {
+    fn new(bgp: &'a Bgp) -> Compiler<'a> {
+        let mut filters: HashMap<String, Vec<Rc<dyn ValueFilter>>> = HashMap::new();
+        for (var, f) in &bgp.filters {
+            filters.entry(var.clone()).or_default().push(f.clone());
+        }
+
+        Compiler {
+            bgp,
+            used: vec![false; bgp.patterns.len()],
+            opt_used: bgp.optional.iter().map(|g| vec![false; g.len()]).collect(),
+            bound: HashSet::new(),
+            nodes: HashMap::new(),
+            filters,
+            aliases: Vec::new(),
+            groups: vec![Vec::new(); bgp.optional.len()]
+        }
+    }
+
+    fn patterns(&self, scope: Scope) -> &'a [Pattern] {
+        match scope {
+            Scope::Required => &self.bgp.patterns,
+            Scope::Optional(g) => &self.bgp.optional[g]
+        }
+    }
+
+    fn used(&mut self, scope: Scope) -> &mut Vec<bool> {
+        match scope {
+            Scope::Required => &mut self.used,
+            Scope::Optional(g) => &mut self.opt_used[g]
+        }
+    }
+
+    // takes the unused patterns of the scope that mention the variable
+    fn take(&mut self, var: &str, scope: Scope) -> Vec<&'a Pattern> {
+        let patterns = self.patterns(scope);
+        let used = self.used(scope);
+        let mut res = Vec::new();
+        for (i, p) in patterns.iter().enumerate() {
+            if !used[i] && p.mentions(var) {
+                used[i] = true;
+                res.push(p);
+            }
+        }
+        res
+    }
+
+    // the variable that appears in most of the unused required patterns
+    fn pick_root(&self) -> Option<String> {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for (i, p) in self.bgp.patterns.iter().enumerate() {
+            if self.used[i] {
+                continue;
+            }
+            for v in p.vars() {
+                match counts.iter_mut().find(|(x, _)| x == v) {
+                    Some((_, n)) => *n += 1,
+                    None => counts.push((v.to_string(), 1))
+                }
+            }
+        }
+        let mut best: Option<(String, usize)> = None;
+        for (v, n) in counts {
+            if best.as_ref().is_none_or(|(_, m)| n > *m) {
+                best = Some((v, n));
+            }
+        }
+        best.map(|(v, _)| v)
+    }
+
+    fn node(&mut self, var: &str, scope: Scope) -> Rc<RefCell<dyn Shape>> {
+        self.bound.insert(var.to_string());
+        if let Scope::Optional(g) = scope {
+            self.groups[g].push(var.to_string());
+        }
+
+        let mut constraints = Vec::new();
+        for p in self.take(var, scope) {
+            constraints.push(self.edge(p, var, scope));
+        }
+        if constraints.is_empty() {
+            constraints.push(AllNodes::new());
+        }
+
+        let mut node: Rc<RefCell<dyn Shape>> = match scope {
+            Scope::Required => {
+                let io = Rc::new(RefCell::new(IntersectOpt {
+                    sub: Intersect(constraints),
+                    opt: Vec::new()
+                }));
+                self.nodes.insert(var.to_string(), io.clone());
+                io
+            },
+            Scope::Optional(_) => Intersect::new(constraints)
+        };
+
+        // filtering inside an optional group would only drop the group
+        if scope == Scope::Required {
+            if let Some(f) = self.filters.remove(var) {
+                node = Filter::new(node, f);
+            }
+        }
+
+        Save::new(vec![var.to_string()], Some(node))
+    }
+
+    // the nodes of the variable that the pattern links to
+    fn edge(&mut self, p: &Pattern, var: &str, scope: Scope) -> Rc<RefCell<dyn Shape>> {
+        let mut dir = None;
+        let mut filters = Vec::new();
+        for (d, t) in p.terms() {
+            if dir.is_none() && t.var() == Some(var) {
+                dir = Some(d);
+                continue;
+            }
+            filters.push(QuadFilter::new_struct(d, Some(self.term(t, scope))));
+        }
+        NodesFrom::new(dir.unwrap(), Rc::new(RefCell::new(Quads(filters))))
+    }
+
+    fn term(&mut self, t: &Term, scope: Scope) -> Rc<RefCell<dyn Shape>> {
+        match t {
+            Term::Value(v) => Lookup::new(vec![v.clone()]),
+            Term::Var(v) if !self.bound.contains(v) => self.node(v, scope),
+            Term::Var(v) => {
+                let tag = format!("{}{}", ALIAS, self.aliases.len());
+                let group = match scope {
+                    Scope::Optional(g) => {
+                        self.groups[g].push(tag.clone());
+                        Some(g)
+                    },
+                    Scope::Required => None
+                };
+                self.aliases.push(Alias {
+                    tag: tag.clone(),
+                    var: v.clone(),
+                    group
+                });
+                Save::new(vec![tag], Some(AllNodes::new()))
+            }
+        }
+    }
+
+    // a pattern without variables only checks that the quad exists
+    fn ground(&mut self, p: &Pattern) -> Rc<RefCell<dyn Shape>> {
+        let filters = p.terms().iter().map(|(d, t)| {
+            QuadFilter::new_struct(d.clone(), Some(self.term(t, Scope::Required)))
+        }).collect();
+        NodesFrom::new(Direction::Subject, Rc::new(RefCell::new(Quads(filters))))
+    }
+
+    fn attach(&mut self, g: usize) -> Result<(), String> {
+        loop {
+            let group = &self.bgp.optional[g];
+            let next = group.iter().enumerate().find(|(i, _)| !self.opt_used[g][*i]);
+            let p = match next {
+                Some((_, p)) => p,
+                None => return Ok(())
+            };
+            let anchor = match p.vars().into_iter().find(|v| self.nodes.contains_key(*v)) {
+                Some(v) => v.to_string(),
+                None => return Err("optional patterns must share a variable with the required patterns".to_string())
+            };
+
+            let scope = Scope::Optional(g);
+            let mut constraints = Vec::new();
+            for p in self.take(&anchor, scope) {
+                constraints.push(self.edge(p, &anchor, scope));
+            }
+            self.nodes[&anchor].borrow_mut().add_optional(vec![Intersect::new(constraints)]);
+        }
+    }
+}
+
+
+// True if the variables of the patterns are linked in a cycle, like x -> y -> z -> x.
+// A tree of shapes only checks the closing link after joining the others.
+fn is_cyclic(patterns: &[Pattern]) -> bool {
+    let mut sets: Vec<Vec<&str>> = Vec::new();
+    for p in patterns {
+        let vars = p.vars();
+        for pair in vars.windows(2) {
+            let a = sets.iter().position(|s| s.contains(&pair[0]));
+            let b = sets.iter().position(|s| s.contains(&pair[1]));
+            match (a, b) {
+                (Some(a), Some(b)) if a == b => return true,
+                (Some(a), Some(b)) => {
+                    let moved = sets.remove(a.max(b));
+                    sets[a.min(b)].extend(moved);
+                },
+                (Some(a), None) => sets[a].push(pair[1]),
+                (None, Some(b)) => sets[b].push(pair[0]),
+                (None, None) => sets.push(vec![pair[0], pair[1]])
+            }
+        }
+    }
+    false
+}
+
+// The order a triejoin binds the variables in: from the root, then always the
+// variable linked to most of the ones already bound.
+fn join_order(patterns: &[&Pattern], root: String) -> Vec<String> {
+    let mut all: Vec<&str> = Vec::new();
+    for p in patterns {
+        for v in p.vars() {
+            if !all.contains(&v) {
+                all.push(v);
+            }
+        }
+    }
+
+    let mut order = vec![root];
+    while order.len() < all.len() {
+        let mut best: Option<(&str, usize, usize)> = None;
+        for v in &all {
+            if order.iter().any(|o| o == v) {
+                continue;
+            }
+            let uses: Vec<&&Pattern> = patterns.iter().filter(|p| p.mentions(v)).collect();
+            let linked = uses.iter().filter(|p| p.vars().iter().any(|x| order.iter().any(|o| o == x))).count();
+            if best.is_none_or(|(_, l, u)| linked > l || (linked == l && uses.len() > u)) {
+                best = Some((v, linked, uses.len()));
+            }
+        }
+        order.push(best.unwrap().0.to_string());
+    }
+    order
+}
+
+// Compiles the pattern into trees of shapes. Starts from the given variable
+// or, if there is none, from the one that appears in most patterns.
+// A cyclic pattern without optional groups or filters is compiled into
+// a single triejoin instead.
+pub fn compile(bgp: &Bgp, root: Option<&str>) -> Result<Plan, String> {
+    if bgp.patterns.is_empty() {
+        return Err("pattern is empty".to_string())
+    }
+    if let Some(r) = root {
+        if !bgp.patterns.iter().any(|p| p.mentions(r)) {
+            return Err(format!("variable {} is not in the pattern", r))
+        }
+    }
+
+    let mut c = Compiler::new(bgp);
+    let mut components = Vec::new();
+
+    if bgp.optional.is_empty() && bgp.filters.is_empty() && is_cyclic(&bgp.patterns) {
+        let root = root.map(|r| r.to_string()).or_else(|| c.pick_root()).unwrap();
+        let linked: Vec<&Pattern> = bgp.patterns.iter().filter(|p| !p.vars().is_empty()).collect();
+        let vars = join_order(&linked, root.clone());
+        let patterns = linked.iter().map(|p| {
+            p.terms().into_iter().map(|(d, t)| (d, t.clone())).collect()
+        }).collect();
+        components.push(Component {
+            shape: TrieJoin::new(vars, patterns),
+            root: Some(root)
+        });
+        for (i, p) in bgp.patterns.iter().enumerate() {
+            if p.vars().is_empty() {
+                c.used[i] = true;
+                let shape = c.ground(p);
+                components.push(Component {
+                    shape,
+                    root: None
+                });
+            }
+        }
+        return Ok(Plan {
+            components,
+            aliases: Vec::new(),
+            groups: Vec::new()
+        })
+    }
+
+    let mut next = root.map(|r| r.to_string());
+    while let Some(var) = next.take().or_else(|| c.pick_root()) {
+        let shape = c.node(&var, Scope::Required);
+        components.push(Component {
+            shape,
+            root: Some(var)
+        });
+    }
+
+    for i in 0..bgp.patterns.len() {
+        if !c.used[i] {
+            c.used[i] = true;
+            let shape = c.ground(&bgp.patterns[i]);
+            components.push(Component {
+                shape,
+                root: None
+            });
+        }
+    }
+
+    for g in 0..bgp.optional.len() {
+        c.attach(g)?;
+    }
+
+    if let Some(var) = c.filters.keys().next() {
+        return Err(format!("cannot filter variable {}, it is not bound by a required pattern", var))
+    }
+
+    Ok(Plan {
+        components,
+        aliases: c.aliases,
+        groups: c.groups
+    })
+}
+
+
+// The number of quads the pattern can match: the smallest index of its constants.
+fn estimate(qs: &dyn QuadStore, p: &Pattern, quads: i64) -> i64 {
+    let mut size = quads;
+    for (d, t) in p.terms() {
+        if let Term::Value(v) = t {
+            let n = match qs.value_of(v) {
+                Some(r) => qs.quad_iterator_size(&d, &r).map(|s| s.value).unwrap_or(quads),
+                // a value that isn't stored matches nothing
+                None => 0
+            };
+            size = size.min(n);
+        }
+    }
+    size
+}
+
+// Picks the variable of the most selective required pattern to start from,
+// using the index sizes of the store. Ties go to the variable in most patterns.
+pub fn pick_root(qs: Rc<RefCell<dyn QuadStore>>, bgp: &Bgp) -> Option<String> {
+    let qs = qs.borrow();
+    let quads = qs.stats(false).map(|s| s.quads.value).unwrap_or(i64::MAX);
+
+    let mut best: Option<(String, i64, usize)> = None;
+    for p in &bgp.patterns {
+        let size = estimate(&*qs, p, quads);
+        for v in p.vars() {
+            let count = bgp.patterns.iter().filter(|x| x.mentions(v)).count();
+            let better = match &best {
+                None => true,
+                Some((_, s, c)) => size < *s || (size == *s && count > *c)
+            };
+            if better {
+                best = Some((v.to_string(), size, count));
+            }
+        }
+    }
+    best.map(|(v, _, _)| v)
+}
+
+
+// Merges plans that start from the same variable into one plan over a Union shape.
+// Returns None if a plan has several parts or checks a variable twice.
+pub fn union(plans: &[Plan]) -> Option<Plan> {
+    let root = plans.first()?.root()?.to_string();
+    let mut shapes = Vec::new();
+    for p in plans {
+        if p.components.len() != 1 || !p.aliases.is_empty() || p.root() != Some(root.as_str()) {
+            return None
+        }
+        shapes.push(p.components[0].shape.clone());
+    }
+    Some(Plan {
+        components: vec![Component {
+            shape: Rc::new(RefCell::new(Union(shapes))),
+            root: Some(root)
+        }],
+        aliases: Vec::new(),
+        groups: Vec::new()
+    })
+}
+
+
+// resolves the tags of the results of a shape, stops after the first one if asked to
+fn tag_rows(qs: Rc<RefCell<dyn QuadStore>>, shape: Rc<RefCell<dyn Shape>>, first: bool) -> Result<Vec<HashMap<String, Value>>, String> {
+    let it = shape::build_iterator(qs.clone(), shape);
+    let mut each = iterator::iterate::TagEachIterator::new(it, true, true);
+    let mut rows = Vec::new();
+
+    for tags in each.by_ref() {
+        let mut row = HashMap::new();
+        for (k, r) in &tags {
+            match qs.borrow().name_of(r) {
+                Some(v) => { row.insert(k.clone(), v); },
+                None => return Err(format!("could not resolve value of tag {} for {:?}", k, r))
+            }
+        }
+        rows.push(row);
+        if first {
+            break;
+        }
+    }
+
+    match each.err() {
+        Some(e) => Err(e),
+        None => Ok(rows)
+    }
+}
+
+// Runs a shape and returns the values of its tags, one row per result.
+pub fn shape_rows(qs: Rc<RefCell<dyn QuadStore>>, shape: Rc<RefCell<dyn Shape>>) -> Result<Vec<HashMap<String, Value>>, String> {
+    tag_rows(qs, shape, false)
+}
+
+fn component_rows(qs: Rc<RefCell<dyn QuadStore>>, c: &Component) -> Result<Vec<HashMap<String, Value>>, String> {
+    tag_rows(qs, c.shape.clone(), c.root.is_none())
+}
+
+// Runs the plan and returns one row of variable bindings per match.
+pub fn run(qs: Rc<RefCell<dyn QuadStore>>, plan: &Plan) -> Result<Vec<HashMap<String, Value>>, String> {
+    let mut rows = vec![HashMap::new()];
+
+    for c in &plan.components {
+        let found = component_rows(qs.clone(), c)?;
+        let mut joined = Vec::new();
+        for row in &rows {
+            for f in &found {
+                let mut r = row.clone();
+                r.extend(f.iter().map(|(k, v)| (k.clone(), v.clone())));
+                joined.push(r);
+            }
+        }
+        rows = joined;
+        if rows.is_empty() {
+            break;
+        }
+    }
+
+    Ok(rows.into_iter().filter_map(|r| plan.resolve(r)).collect())
+}
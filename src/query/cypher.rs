@@ -0,0 +1,910 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use super::bgp::{self, Bgp, Pattern, Term};
+use super::expr::{Expr, error_at, check_regex, holds, order_values, conjuncts, push_down};
+use super::path::{Path, Via, Recursion};
+use super::shape::{Lookup, Recursive, Save};
+use super::super::graph::quad::QuadStore;
+use super::super::graph::value::Value;
+
+
+// prefix of the variables made up for anonymous nodes and relationships
+const HIDDEN: &str = "__";
+
+
+// The result of a query: the returned columns and one row per match,
+// a column missing from a row is null.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Results {
+    pub columns: Vec<String>,
+    pub rows: Vec<HashMap<String, Value>>
+}
+
+
+/////////////////////////////////////////////// lexer
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Name(String),
+    Iri(String),
+    Str(String),
+    Integer(i64),
+    Decimal(f64),
+    Punct(&'static str),
+    Eof
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    pos: usize
+}
+
+const PUNCT: [&str; 23] = [
+    "->", "<-", "..", "<>", "<=", ">=", "=~",
+    "-", "(", ")", "[", "]", "{", "}", ":", ",", "*", "|", "=", "<", ">", ".", ";"
+];
+
+// an IRI has no spaces and doesn't start like an arrow or an operator
+fn iri_end(chars: &[(usize, char)], from: usize) -> Option<usize> {
+    match chars.get(from) {
+        Some((_, c)) if *c == '-' || *c == '=' || *c == '>' => return None,
+        _ => {}
+    }
+    for (i, (_, c)) in chars.iter().enumerate().skip(from) {
+        match c {
+            '>' => return Some(i),
+            '<' | '"' => return None,
+            c if c.is_whitespace() => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn lex(src: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<(usize, char)> = src.char_indices().collect();
+    let at = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let pos_of = |i: usize| chars.get(i).map(|(p, _)| *p).unwrap_or(src.len());
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while let Some(c) = at(i) {
+        let start = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '/' && at(i + 1) == Some('/') {
+            while at(i).is_some_and(|c| c != '\n') {
+                i += 1;
+            }
+            continue;
+        }
+
+        let tok = if c == '<' && iri_end(&chars, i + 1).is_some() {
+            let end = iri_end(&chars, i + 1).unwrap();
+            let iri: String = chars[i + 1..end].iter().map(|(_, c)| *c).collect();
+            i = end + 1;
+            Tok::Iri(iri)
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            let mut s = String::new();
+            loop {
+                match at(i) {
+                    None => return Err(error_at(src, pos_of(start), "unterminated string")),
+                    Some(q) if q == c => { i += 1; break },
+                    Some('\\') => {
+                        let e = match at(i + 1) {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some(e) => e,
+                            None => return Err(error_at(src, pos_of(start), "unterminated string"))
+                        };
+                        s.push(e);
+                        i += 2;
+                    },
+                    Some(ch) => { s.push(ch); i += 1 }
+                }
+            }
+            Tok::Str(s)
+        } else if c.is_ascii_digit() {
+            while at(i).is_some_and(|c| c.is_ascii_digit()) {
+                i += 1;
+            }
+            // a dot followed by a dot is a range
+            let decimal = at(i) == Some('.') && at(i + 1).is_some_and(|c| c.is_ascii_digit());
+            if decimal {
+                i += 1;
+                while at(i).is_some_and(|c| c.is_ascii_digit()) {
+                    i += 1;
+                }
+            }
+            let text = &src[pos_of(start)..pos_of(i)];
+            match (decimal, text.parse::<i64>(), text.parse::<f64>()) {
+                (false, Ok(n), _) => Tok::Integer(n),
+                (true, _, Ok(f)) => Tok::Decimal(f),
+                _ => return Err(error_at(src, pos_of(start), "invalid number"))
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(c) = at(i).filter(|c| c.is_alphanumeric() || *c == '_') {
+                name.push(c);
+                i += 1;
+            }
+            Tok::Name(name)
+        } else {
+            let rest = &src[pos_of(i)..];
+            match PUNCT.iter().find(|p| rest.starts_with(*p)) {
+                Some(p) => {
+                    i += p.len();
+                    Tok::Punct(p)
+                },
+                None => return Err(error_at(src, pos_of(start), &format!("unexpected character {:?}", c)))
+            }
+        };
+
+        tokens.push(Token {
+            tok,
+            pos: pos_of(start)
+        });
+    }
+
+    tokens.push(Token {
+        tok: Tok::Eof,
+        pos: src.len()
+    });
+    Ok(tokens)
+}
+
+
+/////////////////////////////////////////////// syntax
+
+
+// A variable-length relationship, followed from `from` to `to`.
+#[derive(Debug, Clone)]
+struct VarEdge {
+    from: Term,
+    to: Term,
+    types: Vec<Value>,
+    min: usize,
+    max: Option<usize>,
+    // followed from `from`, or in reverse from `to`
+    forward: bool,
+    pos: usize
+}
+
+#[derive(Debug, Clone)]
+struct Rel {
+    var: Option<String>,
+    types: Vec<Value>,
+    range: Option<(usize, Option<usize>)>
+}
+
+// A parsed query: the patterns of MATCH, one group per OPTIONAL MATCH,
+// the variable-length relationships and the WHERE conditions.
+pub struct Query {
+    patterns: Vec<Pattern>,
+    optional: Vec<Vec<Pattern>>,
+    edges: Vec<VarEdge>,
+    filters: Vec<Expr>,
+    // all variables in order of appearance, for RETURN *
+    vars: Vec<String>,
+    distinct: bool,
+    // returned variables and their column names, None for RETURN *
+    returns: Option<Vec<(String, String)>>,
+    order: Vec<(String, bool)>,
+    skip: usize,
+    limit: Option<usize>
+}
+
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    i: usize,
+    hidden: usize
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.i].tok
+    }
+
+    fn next(&mut self) -> Tok {
+        let t = self.tokens[self.i].tok.clone();
+        if self.i < self.tokens.len() - 1 {
+            self.i += 1;
+        }
+        t
+    }
+
+    fn pos(&self) -> usize {
+        self.tokens[self.i].pos
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(error_at(self.src, self.pos(), msg))
+    }
+
+    fn is_word(&self, kw: &str) -> bool {
+        matches!(self.peek(), Tok::Name(w) if w.eq_ignore_ascii_case(kw))
+    }
+
+    fn eat_word(&mut self, kw: &str) -> bool {
+        if self.is_word(kw) {
+            self.next();
+            return true
+        }
+        false
+    }
+
+    fn expect_word(&mut self, kw: &str) -> Result<(), String> {
+        if self.eat_word(kw) {
+            return Ok(())
+        }
+        self.error(&format!("expected {}", kw))
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Tok::Punct(x) if *x == p)
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.next();
+            return true
+        }
+        false
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<(), String> {
+        if self.eat_punct(p) {
+            return Ok(())
+        }
+        self.error(&format!("expected '{}'", p))
+    }
+
+    fn hidden_var(&mut self, kind: &str) -> String {
+        self.hidden += 1;
+        format!("{}{}_{}", HIDDEN, kind, self.hidden)
+    }
+
+    fn query(&mut self) -> Result<Query, String> {
+        let mut q = Query {
+            patterns: Vec::new(),
+            optional: Vec::new(),
+            edges: Vec::new(),
+            filters: Vec::new(),
+            vars: Vec::new(),
+            distinct: false,
+            returns: None,
+            order: Vec::new(),
+            skip: 0,
+            limit: None
+        };
+
+        loop {
+            let optional = self.eat_word("OPTIONAL");
+            if optional {
+                self.expect_word("MATCH")?;
+            } else if !self.eat_word("MATCH") {
+                break;
+            }
+
+            let mut patterns = Vec::new();
+            loop {
+                self.path(&mut q, &mut patterns, optional)?;
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+
+            if self.is_word("WHERE") {
+                if optional {
+                    return self.error("WHERE is not supported after OPTIONAL MATCH")
+                }
+                self.next();
+                let e = self.expr()?;
+                conjuncts(&e, &mut q.filters);
+            }
+
+            if optional {
+                q.optional.push(patterns);
+            } else {
+                q.patterns.extend(patterns);
+            }
+        }
+
+        if q.patterns.is_empty() && q.edges.is_empty() {
+            return self.error("expected MATCH")
+        }
+
+        self.order_edges(&mut q)?;
+
+        self.expect_word("RETURN")?;
+        q.distinct = self.eat_word("DISTINCT");
+        if !self.eat_punct("*") {
+            let mut returns = Vec::new();
+            loop {
+                let var = self.known_var(&q)?;
+                let column = if self.eat_word("AS") { self.name()? } else { var.clone() };
+                if returns.iter().any(|(_, c)| *c == column) {
+                    return self.error(&format!("column {} is returned twice", column))
+                }
+                returns.push((var, column));
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            q.returns = Some(returns);
+        }
+
+        if self.eat_word("ORDER") {
+            self.expect_word("BY")?;
+            loop {
+                // a column name or a matched variable
+                let at = self.i;
+                let name = self.name()?;
+                let var = match &q.returns {
+                    Some(r) => r.iter().find(|(_, c)| *c == name).map(|(v, _)| v.clone()),
+                    None => None
+                };
+                let var = match var {
+                    Some(v) => v,
+                    None if q.vars.contains(&name) => name,
+                    None => {
+                        self.i = at;
+                        return self.error(&format!("unknown variable {}", name))
+                    }
+                };
+                let desc = self.eat_word("DESC") || self.eat_word("DESCENDING");
+                if !desc && !self.eat_word("ASC") {
+                    self.eat_word("ASCENDING");
+                }
+                q.order.push((var, desc));
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+        }
+        if self.eat_word("SKIP") {
+            q.skip = self.count()?;
+        }
+        if self.eat_word("LIMIT") {
+            q.limit = Some(self.count()?);
+        }
+
+        self.eat_punct(";");
+        if *self.peek() != Tok::Eof {
+            return self.error("unexpected input after the query")
+        }
+        Ok(q)
+    }
+
+    // orders the variable-length relationships so each one starts from a node
+    // that MATCH or an earlier relationship already bound
+    fn order_edges(&self, q: &mut Query) -> Result<(), String> {
+        let mut bound: HashSet<String> = Bgp::new(q.patterns.clone()).vars().into_iter().collect();
+        let mut todo = std::mem::take(&mut q.edges);
+
+        while !todo.is_empty() {
+            let i = match todo.iter().position(|e| is_bound(&e.from, &bound) || is_bound(&e.to, &bound)) {
+                Some(i) => i,
+                None => return Err(error_at(self.src, todo[0].pos, "a variable-length relationship needs a matched node at one end"))
+            };
+            let mut edge = todo.remove(i);
+            edge.forward = is_bound(&edge.from, &bound);
+            for t in [&edge.from, &edge.to] {
+                if let Term::Var(v) = t {
+                    bound.insert(v.clone());
+                }
+            }
+            q.edges.push(edge);
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.peek().clone() {
+            Tok::Name(n) => {
+                self.next();
+                Ok(n)
+            },
+            _ => self.error("expected a name")
+        }
+    }
+
+    fn known_var(&mut self, q: &Query) -> Result<String, String> {
+        let at = self.i;
+        let name = self.name()?;
+        if !q.vars.contains(&name) {
+            self.i = at;
+            return self.error(&format!("unknown variable {}", name))
+        }
+        Ok(name)
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        match self.peek() {
+            Tok::Integer(n) if *n >= 0 => {
+                let n = *n as usize;
+                self.next();
+                Ok(n)
+            },
+            _ => self.error("expected a non-negative integer")
+        }
+    }
+
+    fn add_var(q: &mut Query, t: &Term) {
+        if let Some(v) = t.var() {
+            if !q.vars.iter().any(|x| x == v) {
+                q.vars.push(v.to_string());
+            }
+        }
+    }
+
+    // a chain of nodes and relationships, fixed-length relationships become patterns
+    fn path(&mut self, q: &mut Query, out: &mut Vec<Pattern>, optional: bool) -> Result<(), String> {
+        let start = self.pos();
+        let before = out.len();
+        let mut left = self.node(q, out)?;
+        let mut chained = false;
+
+        while self.is_punct("-") || self.is_punct("<-") {
+            let at = self.pos();
+            let incoming = self.eat_punct("<-");
+            if !incoming {
+                self.next();
+            }
+            let rel = if self.eat_punct("[") {
+                let rel = self.rel()?;
+                self.expect_punct("]")?;
+                rel
+            } else {
+                Rel {
+                    var: None,
+                    types: Vec::new(),
+                    range: None
+                }
+            };
+            let outgoing = if self.eat_punct("->") {
+                true
+            } else if self.eat_punct("-") {
+                false
+            } else {
+                return self.error("expected '-' or '->'")
+            };
+            if incoming == outgoing {
+                return Err(error_at(self.src, at, "relationships need exactly one direction"))
+            }
+
+            let right = self.node(q, out)?;
+            let (from, to) = if outgoing {
+                (left.clone(), right.clone())
+            } else {
+                (right.clone(), left.clone())
+            };
+
+            match rel.range {
+                Some((min, max)) => {
+                    if optional {
+                        return Err(error_at(self.src, at, "variable-length relationships are not supported in OPTIONAL MATCH"))
+                    }
+                    q.edges.push(VarEdge {
+                        from,
+                        to,
+                        types: rel.types,
+                        min,
+                        max,
+                        forward: true,
+                        pos: at
+                    });
+                },
+                None => {
+                    let pred = match (&rel.var, rel.types.as_slice()) {
+                        (None, []) => Term::Var(self.hidden_var("rel")),
+                        (None, [t]) => Term::Value(t.clone()),
+                        (Some(v), _) => Term::Var(v.clone()),
+                        (None, _) => Term::Var(self.hidden_var("rel"))
+                    };
+                    if let (Term::Var(v), false) = (&pred, rel.types.is_empty()) {
+                        q.filters.push(type_filter(v, &rel.types, optional));
+                    }
+                    Parser::add_var(q, &pred);
+                    out.push(Pattern::new(from, pred, to));
+                }
+            }
+
+            left = right;
+            chained = true;
+        }
+
+        if !chained && out.len() == before {
+            return Err(error_at(self.src, start, "a node needs a relationship or a property to match"))
+        }
+        Ok(())
+    }
+
+    // (var {key: value}), (<iri>) or ()
+    fn node(&mut self, q: &mut Query, out: &mut Vec<Pattern>) -> Result<Term, String> {
+        self.expect_punct("(")?;
+        let term = match self.peek().clone() {
+            Tok::Name(n) => {
+                self.next();
+                Term::Var(n)
+            },
+            Tok::Iri(iri) => {
+                self.next();
+                Term::Value(Value::IRI(iri))
+            },
+            _ => Term::Var(self.hidden_var("node"))
+        };
+        if self.is_punct(":") {
+            return self.error("node labels are not supported, use a property")
+        }
+        Parser::add_var(q, &term);
+
+        if self.eat_punct("{") {
+            while !self.eat_punct("}") {
+                let key = self.key()?;
+                self.expect_punct(":")?;
+                let value = self.value()?;
+                out.push(Pattern::new(term.clone(), Term::Value(key), Term::Value(value)));
+                if !self.eat_punct(",") {
+                    self.expect_punct("}")?;
+                    break;
+                }
+            }
+        }
+
+        self.expect_punct(")")?;
+        Ok(term)
+    }
+
+    // [var:type|type*min..max]
+    fn rel(&mut self) -> Result<Rel, String> {
+        let mut rel = Rel {
+            var: None,
+            types: Vec::new(),
+            range: None
+        };
+        if let Tok::Name(n) = self.peek().clone() {
+            self.next();
+            rel.var = Some(n);
+        }
+        if self.eat_punct(":") {
+            loop {
+                rel.types.push(self.key()?);
+                if !self.eat_punct("|") {
+                    break;
+                }
+                self.eat_punct(":");
+            }
+        }
+        if self.is_punct("*") {
+            if rel.var.is_some() {
+                return self.error("variable-length relationships can't be bound to a variable")
+            }
+            self.next();
+            let min = match self.peek() {
+                Tok::Integer(_) => Some(self.count()?),
+                _ => None
+            };
+            let max = if self.eat_punct("..") {
+                match self.peek() {
+                    Tok::Integer(_) => Some(self.count()?),
+                    _ => None
+                }
+            } else {
+                min
+            };
+            let min = min.unwrap_or(1);
+            if max.is_some_and(|m| m < min) {
+                return self.error("the maximum length is lower than the minimum")
+            }
+            rel.range = Some((min, max));
+        }
+        if self.is_punct("{") {
+            return self.error("relationship properties are not supported")
+        }
+        Ok(rel)
+    }
+
+    // property keys and relationship types are predicates
+    fn key(&mut self) -> Result<Value, String> {
+        let v = match self.peek() {
+            Tok::Name(n) => Value::IRI(n.clone()),
+            Tok::Iri(iri) => Value::IRI(iri.clone()),
+            _ => return self.error("expected a name or an IRI")
+        };
+        self.next();
+        Ok(v)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        let negative = self.eat_punct("-");
+        let v = match self.peek() {
+            Tok::Integer(n) => Value::from(if negative { -*n } else { *n }),
+            Tok::Decimal(f) => Value::from(if negative { -*f } else { *f }),
+            _ if negative => return self.error("expected a number"),
+            Tok::Iri(iri) => Value::IRI(iri.clone()),
+            Tok::Str(s) => Value::String(s.clone()),
+            Tok::Name(n) if n.eq_ignore_ascii_case("true") => Value::Bool(true),
+            Tok::Name(n) if n.eq_ignore_ascii_case("false") => Value::Bool(false),
+            _ => return self.error("expected a value")
+        };
+        self.next();
+        Ok(v)
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut e = self.and_expr()?;
+        while self.eat_word("OR") {
+            e = Expr::Or(Box::new(e), Box::new(self.and_expr()?));
+        }
+        Ok(e)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, String> {
+        let mut e = self.not_expr()?;
+        while self.eat_word("AND") {
+            e = Expr::And(Box::new(e), Box::new(self.not_expr()?));
+        }
+        Ok(e)
+    }
+
+    fn not_expr(&mut self) -> Result<Expr, String> {
+        if self.eat_word("NOT") {
+            return Ok(Expr::Not(Box::new(self.not_expr()?)))
+        }
+        self.rel_expr()
+    }
+
+    fn rel_expr(&mut self) -> Result<Expr, String> {
+        let e = self.atom()?;
+
+        if self.eat_word("IS") {
+            let negated = self.eat_word("NOT");
+            self.expect_word("NULL")?;
+            let var = match e {
+                Expr::Var(v) => v,
+                _ => return self.error("IS NULL needs a variable")
+            };
+            return Ok(if negated { Expr::Bound(var) } else { Expr::Not(Box::new(Expr::Bound(var))) })
+        }
+
+        if self.is_punct("=~") {
+            let at = self.pos();
+            self.next();
+            let pattern = match self.peek().clone() {
+                Tok::Str(s) => s,
+                _ => return self.error("expected a string")
+            };
+            if cfg!(not(feature = "regex")) {
+                return Err(error_at(self.src, at, "=~ needs the regex feature"))
+            }
+            // the whole string has to match
+            let pattern = format!("^(?:{})$", pattern);
+            check_regex(&pattern).map_err(|e| error_at(self.src, self.pos(), &e))?;
+            self.next();
+            return Ok(Expr::Regex(Box::new(e), pattern, String::new()))
+        }
+
+        for (p, op) in [("=", "="), ("<>", "!="), ("<", "<"), ("<=", "<="), (">", ">"), (">=", ">=")] {
+            if self.eat_punct(p) {
+                return Ok(Expr::Cmp(op, Box::new(e), Box::new(self.atom()?)))
+            }
+        }
+        Ok(e)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        if self.eat_punct("(") {
+            let e = self.expr()?;
+            self.expect_punct(")")?;
+            return Ok(e)
+        }
+        match self.peek() {
+            Tok::Name(n) if !["true", "false"].iter().any(|b| n.eq_ignore_ascii_case(b)) => {
+                let n = n.clone();
+                self.next();
+                Ok(Expr::Var(n))
+            },
+            _ => Ok(Expr::Const(self.value()?))
+        }
+    }
+}
+
+
+// keeps the rows where the relationship has one of the types,
+// in OPTIONAL MATCH also the rows where it didn't match
+fn type_filter(var: &str, types: &[Value], optional: bool) -> Expr {
+    let is_type = |t: &Value| Expr::Cmp("=", Box::new(Expr::Var(var.to_string())), Box::new(Expr::Const(t.clone())));
+    let mut e = is_type(&types[0]);
+    for t in &types[1..] {
+        e = Expr::Or(Box::new(e), Box::new(is_type(t)));
+    }
+    if optional {
+        e = Expr::Or(Box::new(Expr::Not(Box::new(Expr::Bound(var.to_string())))), Box::new(e));
+    }
+    e
+}
+
+
+// Parses a MATCH ... RETURN query. Errors are prefixed with line:column.
+pub fn parse(src: &str) -> Result<Query, String> {
+    let mut p = Parser {
+        src,
+        tokens: lex(src)?,
+        i: 0,
+        hidden: 0
+    };
+    p.query()
+}
+
+
+/////////////////////////////////////////////// execution
+
+
+type Row = HashMap<String, Value>;
+
+
+fn is_bound(t: &Term, bound: &HashSet<String>) -> bool {
+    match t {
+        Term::Value(_) => true,
+        Term::Var(v) => bound.contains(v)
+    }
+}
+
+fn term_value<'a>(t: &'a Term, row: &'a Row) -> Option<&'a Value> {
+    match t {
+        Term::Value(v) => Some(v),
+        Term::Var(v) => row.get(v)
+    }
+}
+
+// The nodes reached from the start and the length of the shortest path to them.
+fn reach(qs: Rc<RefCell<dyn QuadStore>>, start: &Value, edge: &VarEdge) -> Result<Vec<(Value, usize)>, String> {
+    let mut found = Vec::new();
+    if edge.min == 0 {
+        found.push((start.clone(), 0));
+    }
+    if edge.max == Some(0) {
+        return Ok(found)
+    }
+
+    let via = if edge.types.is_empty() { Via::None } else { Via::Values(edge.types.clone()) };
+    let mut path = Path::start_morphism(vec![]);
+    if edge.forward {
+        path.out(via);
+    } else {
+        path.r#in(via);
+    }
+    let max = edge.max.map_or(0, |m| m as i32);
+    let recursive = Recursive::new(path, Lookup::new(vec![start.clone()]), &Recursion::new().with_max_depth(max), vec!["__depth".to_string()]);
+    let shape = Save::new(vec!["__end".to_string()], Some(recursive));
+
+    for row in bgp::shape_rows(qs, shape)? {
+        let depth = match row.get("__depth") {
+            Some(Value::Number(n)) => n.as_i64().unwrap_or(0) as usize,
+            _ => continue
+        };
+        if let Some(end) = row.get("__end") {
+            if depth >= edge.min && !(edge.min == 0 && end == start) {
+                found.push((end.clone(), depth));
+            }
+        }
+    }
+    Ok(found)
+}
+
+// Joins the rows with the nodes reached over the variable-length relationships.
+fn follow_edges(qs: Rc<RefCell<dyn QuadStore>>, edges: &[VarEdge], mut rows: Vec<Row>) -> Result<Vec<Row>, String> {
+    for edge in edges {
+        let (start, end) = if edge.forward { (&edge.from, &edge.to) } else { (&edge.to, &edge.from) };
+
+        let mut cache: HashMap<Value, Vec<(Value, usize)>> = HashMap::new();
+        let mut joined = Vec::new();
+        for row in rows {
+            let s = match term_value(start, &row) {
+                Some(s) => s.clone(),
+                None => continue
+            };
+            if !cache.contains_key(&s) {
+                cache.insert(s.clone(), reach(qs.clone(), &s, edge)?);
+            }
+            for (node, _) in &cache[&s] {
+                match (end, term_value(end, &row)) {
+                    (_, Some(v)) => {
+                        if v == node {
+                            joined.push(row.clone());
+                        }
+                    },
+                    (Term::Var(var), None) => {
+                        let mut r = row.clone();
+                        r.insert(var.clone(), node.clone());
+                        joined.push(r);
+                    },
+                    (Term::Value(_), None) => {}
+                }
+            }
+        }
+        rows = joined;
+    }
+
+    Ok(rows)
+}
+
+
+impl Query {
+    pub fn run(&self, qs: Rc<RefCell<dyn QuadStore>>) -> Result<Results, String> {
+        let mut bgp = Bgp::new(self.patterns.clone());
+        bgp.optional = self.optional.clone();
+        let required: HashSet<String> = bgp.vars().into_iter().collect();
+
+        let mut rest = Vec::new();
+        for f in &self.filters {
+            match push_down(f, &required) {
+                Some(f) => bgp.filters.push(f),
+                None => rest.push(f.clone())
+            }
+        }
+
+        let rows = if bgp.patterns.is_empty() {
+            if !bgp.optional.is_empty() {
+                return Err("OPTIONAL MATCH needs a MATCH pattern to attach to".to_string())
+            }
+            vec![Row::new()]
+        } else {
+            bgp::run(qs.clone(), &bgp::compile(&bgp, None)?)?
+        };
+
+        let rows = follow_edges(qs, &self.edges, rows)?;
+        let mut rows: Vec<Row> = rows.into_iter().filter(|r| rest.iter().all(|f| holds(f, r))).collect();
+
+        rows.sort_by(|a, b| {
+            for (var, desc) in &self.order {
+                let o = order_values(a.get(var), b.get(var));
+                let o = if *desc { o.reverse() } else { o };
+                if o != std::cmp::Ordering::Equal {
+                    return o
+                }
+            }
+            std::cmp::Ordering::Equal
+        });
+
+        let returns = match &self.returns {
+            Some(r) => r.clone(),
+            None => self.vars.iter().filter(|v| !v.starts_with(HIDDEN)).map(|v| (v.clone(), v.clone())).collect()
+        };
+
+        let mut rows: Vec<Row> = rows.into_iter().map(|r| {
+            returns.iter().filter_map(|(var, column)| r.get(var).map(|v| (column.clone(), v.clone()))).collect()
+        }).collect();
+
+        let columns: Vec<String> = returns.into_iter().map(|(_, c)| c).collect();
+        if self.distinct {
+            let mut seen = HashSet::new();
+            rows.retain(|r| {
+                let key: Vec<Option<Value>> = columns.iter().map(|c| r.get(c).cloned()).collect();
+                seen.insert(key)
+            });
+        }
+
+        let rows = rows.into_iter().skip(self.skip).take(self.limit.unwrap_or(usize::MAX)).collect();
+        Ok(Results { columns, rows })
+    }
+}
+
+
+// Runs a Cypher-like query. MATCH and OPTIONAL MATCH take node and relationship
+// patterns, relationship types and property keys are predicates and nodes can
+// be IRIs. A variable-length relationship like -[:<follows>*1..3]-> matches a
+// node at the length of the shortest path to it.
+pub fn query(qs: Rc<RefCell<dyn QuadStore>>, src: &str) -> Result<Results, String> {
+    parse(src)?.run(qs)
+}
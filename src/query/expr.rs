@@ -0,0 +1,330 @@
+use std::rc::Rc;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use super::shape::{self, ValueFilter};
+use super::super::graph::iterator::value_filter::Operator;
+use super::super::graph::value::Value;
+use super::super::graph::number::Number;
+
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+
+// prefixes a parse error with the line and column of the position
+pub(crate) fn error_at(src: &str, pos: usize, msg: &str) -> String {
+    let before = &src[..pos.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    format!("{}:{}: {}", line, column, msg)
+}
+
+// A filter expression, evaluated on rows of variable bindings.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Var(String),
+    Const(Value),
+    Cmp(&'static str, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Regex(Box<Expr>, String, String),
+    Bound(String),
+    Str(Box<Expr>)
+}
+
+#[cfg(feature = "regex")]
+pub(crate) fn check_regex(pattern: &str) -> Result<(), String> {
+    Regex::new(pattern).map(|_| ()).map_err(|e| format!("invalid regex: {}", e))
+}
+
+#[cfg(not(feature = "regex"))]
+pub(crate) fn check_regex(_pattern: &str) -> Result<(), String> {
+    Ok(())
+}
+
+// dates and datetimes are a kind of their own before other strings, so a
+// string is ordered the same way whatever it is compared with
+fn rank(v: &Value) -> u8 {
+    match v {
+        Value::None | Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(s) if datetime(s).is_some() => 3,
+        Value::String(_) => 4,
+        Value::IRI(_) => 5
+    }
+}
+
+// orders values of the same kind, None if they can't be compared
+pub(crate) fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64()?.partial_cmp(&y.as_f64()?),
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+        (Value::IRI(x), Value::IRI(y)) => Some(x.cmp(y)),
+        _ => None
+    }
+}
+
+// total order used by ORDER BY, order_by and the min and max of group_by:
+// unbound first, then by kind and value. Numbers compare exactly, dates and
+// datetimes by their instant and then as text.
+pub(crate) fn order_values(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    let (x, y) = match (a, b) {
+        (None, None) => return Ordering::Equal,
+        (None, Some(_)) => return Ordering::Less,
+        (Some(_), None) => return Ordering::Greater,
+        (Some(x), Some(y)) => (x, y)
+    };
+    rank(x).cmp(&rank(y)).then_with(|| match (x, y) {
+        (Value::Number(x), Value::Number(y)) => compare_numbers(x, y),
+        (Value::String(x), Value::String(y)) => match (datetime(x), datetime(y)) {
+            (Some(a), Some(b)) => a.cmp(&b).then_with(|| x.cmp(y)),
+            _ => x.cmp(y)
+        },
+        _ => compare(x, y).unwrap_or(Ordering::Equal)
+    })
+}
+
+fn compare_numbers(x: &Number, y: &Number) -> Ordering {
+    let int = |n: &Number| n.as_i64().map(i128::from).or(n.as_u64().map(i128::from));
+    match (int(x), int(y)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(a), None) => compare_int_float(a, y.as_f64().unwrap()),
+        (None, Some(b)) => compare_int_float(b, x.as_f64().unwrap()).reverse(),
+        // floats are always finite
+        (None, None) => x.as_f64().partial_cmp(&y.as_f64()).unwrap_or(Ordering::Equal)
+    }
+}
+
+// without rounding the integer, which a float may not hold exactly
+fn compare_int_float(i: i128, f: f64) -> Ordering {
+    let t = f.trunc();
+    i.cmp(&(t as i128)).then(t.partial_cmp(&f).unwrap_or(Ordering::Equal))
+}
+
+fn digits(s: &str, from: usize, to: usize) -> Option<i64> {
+    let d = s.get(from..to)?;
+    if !d.bytes().all(|c| c.is_ascii_digit()) {
+        return None
+    }
+    d.parse().ok()
+}
+
+// The nanoseconds since the epoch of an ISO 8601 date, or datetime with an
+// optional fraction and time zone, UTC when it has none.
+fn datetime(s: &str) -> Option<i128> {
+    if s.get(4..5) != Some("-") || s.get(7..8) != Some("-") {
+        return None
+    }
+    let (y, m, d) = (digits(s, 0, 4)?, digits(s, 5, 7)?, digits(s, 8, 10)?);
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None
+    }
+
+    // days from the civil date, counting years from march
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+
+    let mut secs = days * 86400;
+    let mut nanos: i128 = 0;
+    let rest = &s[10..];
+    if rest.is_empty() {
+        return Some(secs as i128 * 1_000_000_000)
+    }
+    let t = rest.strip_prefix('T').or(rest.strip_prefix(' '))?;
+    if t.get(2..3)? != ":" {
+        return None
+    }
+    secs += digits(t, 0, 2)? * 3600 + digits(t, 3, 5)? * 60;
+    let mut rest = &t[5..];
+    if let Some(t) = rest.strip_prefix(':') {
+        secs += digits(t, 0, 2)?;
+        rest = &t[2..];
+        if let Some(t) = rest.strip_prefix('.') {
+            let n = t.bytes().take_while(|c| c.is_ascii_digit()).count();
+            if n == 0 {
+                return None
+            }
+            let frac = &t[..n.min(9)];
+            nanos = frac.parse::<i128>().ok()? * 10i128.pow(9 - frac.len() as u32);
+            rest = &t[n..];
+        }
+    }
+    let offset = match rest {
+        "" | "Z" => 0,
+        _ => {
+            let sign = match rest.get(..1) {
+                Some("+") => 1,
+                Some("-") => -1,
+                _ => return None
+            };
+            let zone = rest[1..].replace(':', "");
+            if zone.len() != 4 {
+                return None
+            }
+            sign * (digits(&zone, 0, 2)? * 3600 + digits(&zone, 2, 4)? * 60)
+        }
+    };
+    Some((secs - offset) as i128 * 1_000_000_000 + nanos)
+}
+
+// evaluates an expression on a row, None is an evaluation error
+pub(crate) fn eval(e: &Expr, row: &HashMap<String, Value>) -> Option<Value> {
+    match e {
+        Expr::Var(v) => row.get(v).cloned(),
+        Expr::Const(v) => Some(v.clone()),
+        Expr::Bound(v) => Some(Value::Bool(row.contains_key(v))),
+        Expr::Str(e) => match eval(e, row)? {
+            Value::IRI(s) | Value::String(s) => Some(Value::String(s)),
+            Value::None | Value::Null => None,
+            v => Some(Value::String(v.to_string()))
+        },
+        Expr::Not(e) => Some(Value::Bool(!truth(&eval(e, row)?)?)),
+        Expr::And(a, b) => {
+            let a = eval(a, row).and_then(|v| truth(&v));
+            let b = eval(b, row).and_then(|v| truth(&v));
+            match (a, b) {
+                (Some(false), _) | (_, Some(false)) => Some(Value::Bool(false)),
+                (Some(true), Some(true)) => Some(Value::Bool(true)),
+                _ => None
+            }
+        },
+        Expr::Or(a, b) => {
+            let a = eval(a, row).and_then(|v| truth(&v));
+            let b = eval(b, row).and_then(|v| truth(&v));
+            match (a, b) {
+                (Some(true), _) | (_, Some(true)) => Some(Value::Bool(true)),
+                (Some(false), Some(false)) => Some(Value::Bool(false)),
+                _ => None
+            }
+        },
+        Expr::Cmp(op, a, b) => {
+            let a = eval(a, row)?;
+            let b = eval(b, row)?;
+            let res = match *op {
+                "=" => compare(&a, &b) == Some(Ordering::Equal),
+                "!=" => compare(&a, &b) != Some(Ordering::Equal),
+                _ => {
+                    if let (Value::IRI(_), Value::IRI(_)) = (&a, &b) {
+                        return None
+                    }
+                    let o = compare(&a, &b)?;
+                    match *op {
+                        "<" => o == Ordering::Less,
+                        "<=" => o != Ordering::Greater,
+                        ">" => o == Ordering::Greater,
+                        _ => o != Ordering::Less
+                    }
+                }
+            };
+            Some(Value::Bool(res))
+        },
+        Expr::Regex(text, pattern, flags) => match eval(text, row)? {
+            Value::String(s) => regex_match(pattern, flags, &s).map(Value::Bool),
+            _ => None
+        }
+    }
+}
+
+#[cfg(feature = "regex")]
+fn regex_match(pattern: &str, flags: &str, s: &str) -> Option<bool> {
+    let pattern = if flags.contains('i') { format!("(?i){}", pattern) } else { pattern.to_string() };
+    Regex::new(&pattern).ok().map(|re| re.is_match(s))
+}
+
+#[cfg(not(feature = "regex"))]
+fn regex_match(_pattern: &str, _flags: &str, _s: &str) -> Option<bool> {
+    None
+}
+
+// effective boolean value
+fn truth(v: &Value) -> Option<bool> {
+    match v {
+        Value::Bool(b) => Some(*b),
+        Value::String(s) => Some(!s.is_empty()),
+        Value::Number(n) => n.as_f64().map(|f| f != 0.0 && !f.is_nan()),
+        _ => None
+    }
+}
+
+pub(crate) fn holds(e: &Expr, row: &HashMap<String, Value>) -> bool {
+    eval(e, row).and_then(|v| truth(&v)).unwrap_or(false)
+}
+
+// the conjuncts of a filter
+pub(crate) fn conjuncts(e: &Expr, out: &mut Vec<Expr>) {
+    match e {
+        Expr::And(a, b) => {
+            conjuncts(a, out);
+            conjuncts(b, out);
+        },
+        e => out.push(e.clone())
+    }
+}
+
+// A filter on one variable that the iterators can apply. Only string
+// comparisons and regexes are pushed down, numbers are compared on rows
+// since value filters don't compare integers with decimals.
+pub(crate) fn push_down(e: &Expr, required: &HashSet<String>) -> Option<(String, Rc<dyn ValueFilter>)> {
+    match e {
+        Expr::Cmp(op, a, b) => {
+            let (var, val, op) = match (&**a, &**b) {
+                (Expr::Var(v), Expr::Const(c)) => (v, c, *op),
+                (Expr::Const(c), Expr::Var(v)) => (v, c, flip(op)),
+                _ => return None
+            };
+            if !required.contains(var) {
+                return None
+            }
+            let op = match op {
+                "<" => Operator::LT,
+                "<=" => Operator::LTE,
+                ">" => Operator::GT,
+                ">=" => Operator::GTE,
+                _ => return None
+            };
+            match val {
+                Value::String(_) => Some((var.clone(), Rc::new(shape::Comparison::new(op, val.clone())))),
+                _ => None
+            }
+        },
+        Expr::Regex(text, pattern, flags) => regex_filter(text, pattern, flags, required),
+        _ => None
+    }
+}
+
+#[cfg(feature = "regex")]
+fn regex_filter(text: &Expr, pattern: &str, flags: &str, required: &HashSet<String>) -> Option<(String, Rc<dyn ValueFilter>)> {
+    let (var, iri) = match text {
+        Expr::Var(v) => (v, false),
+        Expr::Str(e) => match &**e {
+            Expr::Var(v) => (v, true),
+            _ => return None
+        },
+        _ => return None
+    };
+    if !required.contains(var) {
+        return None
+    }
+    let pattern = if flags.contains('i') { format!("(?i){}", pattern) } else { pattern.to_string() };
+    Some((var.clone(), Rc::new(shape::Regexp::new(pattern, iri))))
+}
+
+#[cfg(not(feature = "regex"))]
+fn regex_filter(_text: &Expr, _pattern: &str, _flags: &str, _required: &HashSet<String>) -> Option<(String, Rc<dyn ValueFilter>)> {
+    None
+}
+
+fn flip(op: &'static str) -> &'static str {
+    match op {
+        "<" => ">",
+        "<=" => ">=",
+        ">" => "<",
+        ">=" => "<=",
+        op => op
+    }
+}
@@ -1,15 +1,22 @@
 use super::path;
 use super::shape;
+use super::bgp;
 use super::shape::Shape;
 use std::rc::Rc;
 use std::cell::RefCell;
-use crate::graph::quad::{QuadStore, QuadWriter, IgnoreOptions, Quad};
+use crate::graph::quad::{QuadStore, QuadWriter, IgnoreOptions, Quad, Direction};
 use crate::graph::rocksdb;
 use crate::graph::graphmock;
 use crate::graph::memstore;
 use crate::graph::value::Value;
 use crate::graph::iterator;
-use std::collections::HashMap;
+use crate::graph::iterator::weighted_path::{Weight, Heuristic};
+use crate::graph::iterator::sort::{SortKey, SortOrder};
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::cmp::Ordering;
+use super::expr::order_values;
 use crate::graph::refs::Ref;
 
 pub fn new_rocksdb_graph(path: &str) -> GraphWrapper {
@@ -72,6 +79,10 @@ impl GraphWrapper {
     pub fn delete(&self, quads: Vec<Quad>) {
         self.session.borrow().delete(quads)
     }
+
+    pub fn quad_store(&self) -> Rc<RefCell<dyn QuadStore>> {
+        self.session.borrow().qs.clone()
+    }
 }
 
 
@@ -145,6 +156,261 @@ impl Graph {
     pub fn m(&self) -> Path {
         Path::new(self.session.clone(), false, path::Path::start_morphism(Vec::new()))
     }
+
+    // rows of variable bindings for the quad patterns, starting from the
+    // variable of the most selective pattern
+    pub fn match_patterns(&self, patterns: Vec<bgp::Pattern>) -> Result<Vec<HashMap<String, Value>>, String> {
+        let qs = self.session.borrow().qs.clone();
+        let bgp = bgp::Bgp::new(patterns);
+        let root = bgp::pick_root(qs.clone(), &bgp);
+        let plan = bgp::compile(&bgp, root.as_deref())?;
+        bgp::run(qs, &plan)
+    }
+
+    // Every path from a node of `from` to a node of `to` that doesn't visit
+    // a node twice, following links through the given predicates, or any
+    // predicate for None, in at most max_depth steps (50 by default). The
+    // shortest come first, and no more than limit are found when it is given.
+    pub fn all_paths<F: Into<Values>, T: Into<Values>, V: Into<path::Via>>(&self, from: F, to: T, via: V, max_depth: Option<i32>, limit: Option<usize>) -> impl Iterator<Item = Vec<Quad>> {
+        let qs = self.session.borrow().qs.clone();
+        let nodes = |v: Vec<Value>| shape::build_iterator(qs.clone(), Rc::new(RefCell::new(shape::Lookup(v))));
+        let via = match via.into() {
+            path::Via::None => None,
+            v => Some(shape::build_iterator(qs.clone(), v.as_shape()))
+        };
+        let paths = iterator::all_paths::AllPaths::new(qs.clone(), nodes(from.into().to_vec()), nodes(to.into().to_vec()), via, max_depth.unwrap_or(50), limit.unwrap_or(0));
+        paths.map(move |p| p.iter().filter_map(|q| qs.borrow().quad(q)).collect())
+    }
+}
+
+
+// a quad pattern for match_patterns, "?x" is a variable
+pub fn pat<S: Into<bgp::Term>, P: Into<bgp::Term>, O: Into<bgp::Term>>(subject: S, predicate: P, object: O) -> bgp::Pattern {
+    bgp::Pattern::new(subject.into(), predicate.into(), object.into())
+}
+
+
+// A path found by shortest_path_to or weighted_path_to: the nodes from the
+// start to the target, and the predicate of the link from each node to the
+// next, or the edge node for Weight::Edge. Weighted paths have their cost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub nodes: Vec<Value>,
+    pub predicates: Vec<Value>,
+    pub cost: Option<f64>
+}
+
+impl Route {
+    // reads the path tags of a result, None if it has none
+    pub fn from_tags(tags: &HashMap<String, Value>) -> Option<Route> {
+        use iterator::shortest_path::{node_tag, via_tag};
+
+        let mut nodes = Vec::new();
+        let mut predicates = Vec::new();
+        while let Some(n) = tags.get(&node_tag(nodes.len())) {
+            if !nodes.is_empty() {
+                predicates.push(tags.get(&via_tag(nodes.len()))?.clone());
+            }
+            nodes.push(n.clone());
+        }
+        if nodes.is_empty() {
+            return None
+        }
+        let cost = match tags.get(iterator::weighted_path::COST_TAG) {
+            Some(Value::Number(n)) => n.as_f64(),
+            _ => None
+        };
+        Some(Route {
+            nodes,
+            predicates,
+            cost
+        })
+    }
+}
+
+
+// Which way a hop followed its link: out from the subject to the object,
+// or in from the object to the subject.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HopDirection {
+    In,
+    Out
+}
+
+// A link followed by a query, see iter_paths.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub from: Value,
+    pub predicate: Value,
+    pub to: Value,
+    pub label: Option<Value>,
+    pub direction: HopDirection
+}
+
+impl Hop {
+    // the hops saved in the tags of a result, in the order they were followed
+    fn from_tags(tags: &HashMap<String, Ref>, qs: &dyn QuadStore) -> Vec<Hop> {
+        let mut hops: Vec<(usize, Hop)> = tags.iter().filter_map(|(k, r)| {
+            let (i, r#in) = path::parse_hop_tag(k)?;
+            let q = qs.quad(r)?;
+            let (from, to, direction) = if r#in {
+                (q.object, q.subject, HopDirection::In)
+            } else {
+                (q.subject, q.object, HopDirection::Out)
+            };
+            let label = match q.label {
+                Value::None => None,
+                l => Some(l)
+            };
+            Some((i, Hop {
+                from,
+                predicate: q.predicate,
+                to,
+                label,
+                direction
+            }))
+        }).collect();
+        hops.sort_by_key(|h| h.0);
+        hops.into_iter().map(|h| h.1).collect()
+    }
+}
+
+
+// What to compute over the rows of a group, see GroupBy::aggregate. Sum
+// and Avg only take the numbers of their tag, Min and Max compare values
+// of the same kind as ORDER BY does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregate {
+    Count,
+    Sum(String),
+    Min(String),
+    Max(String),
+    Avg(String),
+    Collect(String)
+}
+
+// A row of group_by: the values of the grouping tags and of every aggregate
+// under its name, but for the ones of Collect that are in collected. A tag
+// missing from the rows, or an aggregate over no values, isn't set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub values: HashMap<String, Value>,
+    pub collected: HashMap<String, Vec<Value>>
+}
+
+// the running state of an aggregate over the rows of a group
+#[derive(Default)]
+struct Accumulator {
+    count: i64,
+    int_sum: Option<i64>,
+    sum: f64,
+    numbers: i64,
+    best: Option<Value>,
+    values: Vec<Value>
+}
+
+impl Accumulator {
+    fn add(&mut self, aggregate: &Aggregate, row: &HashMap<String, Value>) {
+        let (tag, keep) = match aggregate {
+            Aggregate::Count => {
+                self.count += 1;
+                return
+            },
+            Aggregate::Sum(t) | Aggregate::Avg(t) => (t, None),
+            Aggregate::Min(t) => (t, Some(Ordering::Less)),
+            Aggregate::Max(t) => (t, Some(Ordering::Greater)),
+            Aggregate::Collect(t) => {
+                self.values.extend(row.get(t).cloned());
+                return
+            }
+        };
+        let v = match row.get(tag) {
+            Some(v) => v,
+            None => return
+        };
+        if let Some(keep) = keep {
+            if self.best.is_none() || order_values(Some(v), self.best.as_ref()) == keep {
+                self.best = Some(v.clone());
+            }
+            return
+        }
+        if let Value::Number(n) = v {
+            // the sum stays an integer for as long as it can
+            self.int_sum = match (self.numbers, self.int_sum, n.as_i64()) {
+                (0, _, Some(i)) => Some(i),
+                (_, Some(s), Some(i)) => s.checked_add(i),
+                _ => None
+            };
+            self.sum += n.as_f64().unwrap_or(0f64);
+            self.numbers += 1;
+        }
+    }
+
+    fn finish(self, aggregate: &Aggregate) -> Option<Value> {
+        match aggregate {
+            Aggregate::Count => Some(Value::from(self.count)),
+            Aggregate::Sum(_) if self.numbers > 0 => Some(self.int_sum.map_or(Value::from(self.sum), Value::from)),
+            Aggregate::Avg(_) if self.numbers > 0 => Some(Value::from(self.sum / self.numbers as f64)),
+            Aggregate::Min(_) | Aggregate::Max(_) => self.best,
+            _ => None
+        }
+    }
+}
+
+// The rows of a path grouped by the values of some of their tags, see
+// Path::group_by.
+pub struct GroupBy {
+    path: Path,
+    tags: Vec<String>
+}
+
+impl GroupBy {
+    // One group for every distinct value of the grouping tags, in the order
+    // they first come in, with the aggregates named by the first of each pair.
+    // A tag that can't be resolved or an error from the iterators is an error.
+    pub fn aggregate(&self, aggregates: Vec<(&str, Aggregate)>) -> Result<Vec<Group>, String> {
+        let mut keys: Vec<Vec<Option<Value>>> = Vec::new();
+        let mut accs: Vec<Vec<Accumulator>> = Vec::new();
+        let mut index: HashMap<u64, Vec<usize>> = HashMap::new();
+
+        for row in self.path.try_iter_tags() {
+            let row = row?;
+            let key: Vec<Option<Value>> = self.tags.iter().map(|t| row.get(t).cloned()).collect();
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            let bucket = index.entry(hasher.finish()).or_default();
+            let i = match bucket.iter().find(|i| keys[**i] == key) {
+                Some(i) => *i,
+                None => {
+                    bucket.push(keys.len());
+                    keys.push(key);
+                    accs.push(aggregates.iter().map(|_| Accumulator::default()).collect());
+                    keys.len() - 1
+                }
+            };
+            for (acc, (_, aggregate)) in accs[i].iter_mut().zip(&aggregates) {
+                acc.add(aggregate, &row);
+            }
+        }
+
+        Ok(keys.into_iter().zip(accs).map(|(key, accs)| {
+            let mut values: HashMap<String, Value> = self.tags.iter().cloned().zip(key)
+                .filter_map(|(t, v)| Some((t, v?)))
+                .collect();
+            let mut collected = HashMap::new();
+            for (acc, (name, aggregate)) in accs.into_iter().zip(&aggregates) {
+                if let Aggregate::Collect(_) = aggregate {
+                    collected.insert(name.to_string(), acc.values);
+                } else if let Some(v) = acc.finish(aggregate) {
+                    values.insert(name.to_string(), v);
+                }
+            }
+            Group {
+                values,
+                collected
+            }
+        }).collect())
+    }
 }
 
 
@@ -152,7 +418,9 @@ impl Graph {
 pub struct Path {
     pub session: Rc<RefCell<Session>>,
     finals: bool,
-    pub path: path::Path
+    pub path: path::Path,
+    context: Option<iterator::context::QueryContext>,
+    workers: Option<usize>
 }
 
 impl Path {
@@ -161,14 +429,28 @@ impl Path {
         Path {
             session,
             finals,
-            path
+            path,
+            context: None,
+            workers: None
         }
     }
 
-    fn build_iterator_tree(&self) -> Rc<RefCell<dyn iterator::Shape>> {
-        // let s = self.session.borrow();
+    // The iterators built from the path, before they are optimized
+    fn build_plan(&self) -> Rc<RefCell<dyn iterator::Shape>> {
         let qs = self.session.borrow().qs.clone();
-        self.path.build_iterator_on(qs)
+        match self.workers {
+            Some(n) => self.path.build_parallel_iterator_on(qs, n),
+            None => self.path.build_iterator_on(qs)
+        }
+    }
+
+    // The iterators the query runs, optimized and guarded by the context
+    fn build_iterator_tree(&self) -> Rc<RefCell<dyn iterator::Shape>> {
+        let it = iterator::optimized(self.build_plan());
+        match &self.context {
+            Some(ctx) => iterator::context::guard(it, ctx),
+            None => it
+        }
     }
 
 
@@ -189,11 +471,159 @@ impl Path {
         self.session.borrow_mut().run_each_iterator(it).filter_map(move |r| ref_to_value(&r, &*qs.borrow()))
     }
 
+    // The results of a path that ends on quads, with out_e, in_e or both_e,
+    // an error for a path that ends on nodes. Results that aren't quads of
+    // the store are skipped, see try_iter_quads.
+    pub fn iter_quads(&self) -> Result<impl Iterator<Item = Quad>, String> {
+        if !shape::is_quads(&self.path.shape()) {
+            return Err("path does not end on quads, use out_e, in_e or both_e".to_string())
+        }
+        let it = self.build_iterator_tree();
+        let qs = self.session.borrow().qs.clone();
+        Ok(self.session.borrow_mut().run_each_iterator(it).filter_map(move |r| qs.borrow().quad(&r)))
+    }
+
+    // Like iter_quads, but a result that isn't a quad of the store and an
+    // error from the iterators are errors
+    pub fn try_iter_quads(&self) -> Result<Vec<Quad>, String> {
+        if !shape::is_quads(&self.path.shape()) {
+            return Err("path does not end on quads, use out_e, in_e or both_e".to_string())
+        }
+        let it = self.build_iterator_tree();
+        let qs = self.session.borrow().qs.clone();
+        let mut each = self.session.borrow_mut().run_each_iterator(it);
+        let quads: Result<Vec<Quad>, String> = each.by_ref()
+            .map(|r| qs.borrow().quad(&r).ok_or_else(|| format!("could not resolve quad for {:?}", r)))
+            .collect();
+        match each.err() {
+            Some(e) => Err(e),
+            None => quads
+        }
+    }
+
+    // Copies the quads between the nodes of the path, all of them whatever
+    // their predicate, into a new memory graph
+    pub fn extract_subgraph(&self) -> GraphWrapper {
+        let it = self.build_iterator_tree();
+        let qs = self.session.borrow().qs.clone();
+        let mut keys = HashSet::new();
+        let nodes: Vec<Ref> = self.session.borrow_mut().run_each_iterator(it)
+            .filter(|n| n.key().is_some_and(|k| keys.insert(k)))
+            .collect();
+
+        let qs = qs.borrow();
+        let mut quads = Vec::new();
+        for n in &nodes {
+            let it = qs.quad_iterator(&Direction::Subject, n).borrow().iterate();
+            while it.borrow_mut().next() {
+                let q = it.borrow().result().unwrap();
+                let o = qs.quad_direction(&q, &Direction::Object).and_then(|o| o.key());
+                if o.is_some_and(|o| keys.contains(&o)) {
+                    quads.extend(qs.quad(&q));
+                }
+            }
+            let _ = it.borrow_mut().close();
+        }
+
+        let graph = new_memory_graph();
+        graph.write(quads);
+        graph
+    }
+
+    // For every result, the links the query followed to get to it with in,
+    // out, both and follow, from the first to the last.
+    pub fn iter_paths(&self) -> impl Iterator<Item = Vec<Hop>> {
+        let mut tracked = self.clone();
+        tracked.path = self.path.track_hops();
+        let it = tracked.build_iterator_tree();
+        let qs = self.session.borrow().qs.clone();
+        self.session.borrow_mut().run_tag_each_iterator(it).map(move |r| Hop::from_tags(&r, &*qs.borrow()))
+    }
+
+    // The path to every result of a shortest_path_to
+    pub fn iter_routes(&self) -> impl Iterator<Item = Route> {
+        self.iter_tags().filter_map(|t| Route::from_tags(&t))
+    }
+
+    // Like iter_tags, but a tag that can't be resolved is an error and 
+    // an error from the iterators is returned as the last item
+    pub fn try_iter_tags(&self) -> impl Iterator<Item = Result<HashMap<String, Value>, String>> {
+        let it = self.build_iterator_tree();
+        let it = iterator::save::tag(&it, &"id");
+        let qs = self.session.borrow().qs.clone();
+        let mut each = self.session.borrow_mut().run_tag_each_iterator(it);
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None
+            }
+            match each.next() {
+                Some(r) => Some(tags_to_value_map_strict(&r, &*qs.borrow())),
+                None => {
+                    done = true;
+                    each.err().map(Err)
+                }
+            }
+        })
+    }
+
+    // Like iter_values, but a value that can't be resolved is an error and 
+    // an error from the iterators is returned as the last item
+    pub fn try_iter_values(&self) -> impl Iterator<Item = Result<Value, String>> {
+        let it = self.build_iterator_tree();
+        let qs = self.session.borrow().qs.clone();
+        let mut each = self.session.borrow_mut().run_each_iterator(it);
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None
+            }
+            match each.next() {
+                Some(r) => Some(ref_to_value_strict(&r, &*qs.borrow())),
+                None => {
+                    done = true;
+                    each.err().map(Err)
+                }
+            }
+        })
+    }
+
     pub fn count(&mut self) -> i64 {
         let it = self.build_iterator_tree();
         self.session.borrow_mut().run_each_iterator(it).count() as i64
     }
 
+    // Groups the rows of iter_tags by the values of the given tags, to be
+    // aggregated with GroupBy::aggregate
+    pub fn group_by<T: Into<Tags>>(&self, tags: T) -> GroupBy {
+        GroupBy {
+            path: self.clone(),
+            tags: tags.into().to_vec()
+        }
+    }
+
+    // The iterators the query runs, as build_iterator_tree optimizes them
+    pub fn explain(&self) -> iterator::explain::Explanation {
+        iterator::explain::explain(self.build_plan())
+    }
+
+    // Runs the query with every iterator instrumented and returns the tag
+    // rows, or the first error as try_iter_tags would, together with what
+    // each iterator did
+    pub fn profile(&self) -> (Result<Vec<HashMap<String, Value>>, String>, iterator::profile::Report) {
+        let it = iterator::profile::instrument(self.build_iterator_tree());
+        let tagged = iterator::save::tag(&it, &"id");
+        let qs = self.session.borrow().qs.clone();
+        let mut each = self.session.borrow_mut().run_tag_each_iterator(tagged);
+        let rows: Result<Vec<_>, String> = each.by_ref().map(|r| tags_to_value_map_strict(&r, &*qs.borrow())).collect();
+        let rows = rows.and_then(|rows| match each.err() {
+            Some(e) => Err(e),
+            None => Ok(rows)
+        });
+        let report = iterator::profile::report(&it).remove(0);
+        (rows, report)
+    }
+
 
     ///////////////
     // Traversals
@@ -225,6 +655,57 @@ impl Path {
 
     ///////////////////////////
     // Both(values: String[], tags: String[]) *
+    ///////////////////////////
+    // OutE(values: String[], tags: String[])
+    ///////////////////////////
+    // Like out, but moves to the quads followed rather than the nodes they
+    // lead to. See iter_quads, and subject, predicate, object and label to
+    // go on to a node.
+    pub fn out_e<V: Into<path::Via>, T: Into<Tags>>(&mut self, via: V, tags: T) -> Path {
+        self.path.out_e(tags.into().to_vec(), via.into());
+        self.clone()
+    }
+
+    ///////////////////////////
+    // InE(values: String[], tags: String[])
+    ///////////////////////////
+    pub fn in_e<V: Into<path::Via>, T: Into<Tags>>(&mut self, via: V, tags: T) -> Path {
+        self.path.in_e(tags.into().to_vec(), via.into());
+        self.clone()
+    }
+
+    ///////////////////////////
+    // BothE(values: String[], tags: String[])
+    ///////////////////////////
+    pub fn both_e<V: Into<path::Via>, T: Into<Tags>>(&mut self, via: V, tags: T) -> Path {
+        self.path.both_e(tags.into().to_vec(), via.into());
+        self.clone()
+    }
+
+    ///////////////////////////
+    // Subject(), Predicate(), Object(), Label()
+    ///////////////////////////
+    // From quads back to one of their nodes
+    pub fn subject(&mut self) -> Path {
+        self.path.edge_nodes(Direction::Subject);
+        self.clone()
+    }
+
+    pub fn predicate(&mut self) -> Path {
+        self.path.edge_nodes(Direction::Predicate);
+        self.clone()
+    }
+
+    pub fn object(&mut self) -> Path {
+        self.path.edge_nodes(Direction::Object);
+        self.clone()
+    }
+
+    pub fn label(&mut self) -> Path {
+        self.path.edge_nodes(Direction::Label);
+        self.clone()
+    }
+
     ///////////////////////////
     pub fn both<V: Into<path::Via>, T: Into<Tags>>(&mut self, via: V, tags: T) -> Path {
         self.path.both_with_tags(tags.into().to_vec(), via.into());
@@ -253,14 +734,106 @@ impl Path {
     // FollowRecursive(path: Path, maxDepth: int, tags: Stringp[]) -
     ///////////////////////////
     pub fn follow_recursive<T: Into<Tags>, V: Into<path::Via>>(&mut self, via: V, tags: T, max_depth: Option<i32>) -> Path {
+        let recursion = path::Recursion::new().with_max_depth(max_depth.unwrap_or(0));
+        self.follow_recursive_with(via, tags, recursion)
+    }
+
+
+    ///////////////////////////
+    // FollowRecursiveWith(path: Path, tags: String[], recursion: Recursion)
+    ///////////////////////////
+    // Like follow_recursive, with the depths, the nodes returned, the order
+    // and where to stop set by recursion, see path::Recursion.
+    pub fn follow_recursive_with<T: Into<Tags>, V: Into<path::Via>>(&mut self, via: V, tags: T, recursion: path::Recursion) -> Path {
         let via = via.into();
         match &via {
             path::Via::None => panic!("expected predicate list"),
             path::Via::Values(v) => if v.len() != 1 { panic!("expected one predicate or path for recursive follow") } 
             _ => ()
         }
-        let max_depth = match max_depth { Some(d) => d, None => 50 };
-        self.path.follow_recursive(via, max_depth, tags.into().to_vec());
+        self.path.follow_recursive(via, recursion, tags.into().to_vec());
+        self.clone()
+    }
+
+
+    ///////////////////////////
+    // Neighborhood(k: int, via: Path|predicates, tags: String[])
+    ///////////////////////////
+    // Moves to every node at most k links away from the current nodes, in
+    // either direction and through the given predicates, or any for None.
+    // The current nodes are kept, and each node comes once with its distance
+    // saved in tags. With k below 1 only the current nodes are kept, at 0.
+    pub fn neighborhood<V: Into<path::Via>, T: Into<Tags>>(&mut self, k: i32, via: V, tags: T) -> Path {
+        self.path.neighborhood(k, via.into(), tags.into().to_vec());
+        self.clone()
+    }
+
+
+    ///////////////////////////
+    // CountAs(path: Path, tag: String)
+    ///////////////////////////
+    // Saves the number of results of path from every current node as tag,
+    // nodes it has no results from are kept with 0.
+    pub fn count_as<S: Into<String>>(&mut self, ep: &Path, tag: S) -> Path {
+        self.path.count_as(ep.path.clone(), tag.into());
+        self.clone()
+    }
+
+
+    ///////////////////////////
+    // Degree(via: Path|predicates, dir: Direction, op: Operator, n: int)
+    ///////////////////////////
+    // Keeps the nodes whose number of links through the given predicates,
+    // or any for None, with the node as dir compares to n as op says.
+    // Counting stops as soon as the outcome is known.
+    pub fn degree<V: Into<path::Via>>(&mut self, via: V, dir: Direction, op: iterator::value_filter::Operator, n: i64) -> Path {
+        self.path.degree(via.into(), vec![dir], op, n);
+        self.clone()
+    }
+
+    // The degree filters below count the links in both directions
+    pub fn degree_gt<V: Into<path::Via>>(&mut self, via: V, n: i64) -> Path {
+        self.path.degree(via.into(), vec![Direction::Subject, Direction::Object], iterator::value_filter::Operator::GT, n);
+        self.clone()
+    }
+
+    pub fn degree_gte<V: Into<path::Via>>(&mut self, via: V, n: i64) -> Path {
+        self.path.degree(via.into(), vec![Direction::Subject, Direction::Object], iterator::value_filter::Operator::GTE, n);
+        self.clone()
+    }
+
+    pub fn degree_lt<V: Into<path::Via>>(&mut self, via: V, n: i64) -> Path {
+        self.path.degree(via.into(), vec![Direction::Subject, Direction::Object], iterator::value_filter::Operator::LT, n);
+        self.clone()
+    }
+
+    pub fn degree_lte<V: Into<path::Via>>(&mut self, via: V, n: i64) -> Path {
+        self.path.degree(via.into(), vec![Direction::Subject, Direction::Object], iterator::value_filter::Operator::LTE, n);
+        self.clone()
+    }
+
+
+    ///////////////////////////
+    // ShortestPathTo(target: Path, via: Path|predicates, maxDepth: int)
+    ///////////////////////////
+    // Moves to the nodes of target that can be reached from the current
+    // nodes through the given predicates, or any predicate for None, in at
+    // most max_depth steps (50 by default). Each is tagged with the shortest
+    // path to it, see Route and iter_routes.
+    pub fn shortest_path_to<V: Into<path::Via>>(&mut self, target: &Path, via: V, max_depth: Option<i32>) -> Path {
+        self.path.shortest_path_to(target.path.clone(), via.into(), max_depth.unwrap_or(50));
+        self.clone()
+    }
+
+
+    ///////////////////////////
+    // WeightedPathTo(target: Path, via: Path|predicates, weight: Weight, heuristic?: fn)
+    ///////////////////////////
+    // Like shortest_path_to, but finds the path of least total cost, with the
+    // cost of each link read as weight says. With a heuristic estimating the
+    // cost left from a node to the target, the search is an A* search.
+    pub fn weighted_path_to<V: Into<path::Via>>(&mut self, target: &Path, via: V, weight: Weight, heuristic: Option<Heuristic>) -> Path {
+        self.path.weighted_path_to(target.path.clone(), via.into(), weight, heuristic);
         self.clone()
     }
 
@@ -517,6 +1090,41 @@ impl Path {
         self.path.order();
         self.clone()
     }
+
+    ///////////////////////////
+    // OrderBy(keys: (String, SortOrder)[])
+    ///////////////////////////
+    // Sorts on the values saved under each tag in turn, numbers and dates
+    // by value, or on the nodes themselves for an order alone.
+    pub fn order_by<K: Into<SortKeys>>(&mut self, keys: K) -> Path {
+        self.path.order_by(keys.into().0);
+        self.clone()
+    }
+
+    ///////////////////////////
+    // WithContext(ctx: QueryContext)
+    ///////////////////////////
+    // Runs the query under the context's deadline, cancellation and limits.
+    // When one of them ends iteration the error is left in ctx.err().
+    // iter_values, iter_tags and count just stop there, try_iter_values and
+    // try_iter_tags return the error as their last item.
+    pub fn with_context(&mut self, ctx: iterator::context::QueryContext) -> Path {
+        self.context = Some(ctx);
+        self.clone()
+    }
+
+    ///////////////////////////
+    // Parallel(workers: usize)
+    ///////////////////////////
+    // Runs the branches of a union, or parts of a large set of starting
+    // nodes, on `workers` threads, one per core for 0. Results come branch
+    // by branch or part by part, in order. Stores that can't be read from
+    // other threads run the query as usual. The workers stop on the
+    // deadline, cancellation and limits of a context given with_context.
+    pub fn parallel(&mut self, workers: usize) -> Path {
+        self.workers = Some(workers);
+        self.clone()
+    }
 }
 
 fn save_validate(via: &SaveVia, tag: &Tag) -> String {
@@ -542,6 +1150,23 @@ fn ref_to_value(r: &Ref, qs: &dyn QuadStore) -> Option<Value> {
     qs.name_of(r) 
 }
 
+fn ref_to_value_strict(r: &Ref, qs: &dyn QuadStore) -> Result<Value, String> {
+    qs.name_of(r).ok_or_else(|| format!("could not resolve value for {:?}", r))
+}
+
+fn tags_to_value_map_strict(m: &HashMap<String, Ref>, qs: &dyn QuadStore) -> Result<HashMap<String, Value>, String> {
+    let mut output_map = HashMap::new();
+
+    for (key, value) in m {
+        match qs.name_of(value) {
+            Some(v) => { output_map.insert(key.clone(), v); },
+            None => return Err(format!("could not resolve value of tag {} for {:?}", key, value))
+        };
+    }
+
+    Ok(output_map)
+}
+
 fn tags_to_value_map(m: &HashMap<String, Ref>, qs: &dyn QuadStore) -> Option<HashMap<String, Value>> {
     let mut output_map = HashMap::new();
 
@@ -603,6 +1228,27 @@ impl From<&str> for Tags {
 }
 
 
+pub struct SortKeys(Vec<SortKey>);
+
+impl From<SortOrder> for SortKeys {
+    fn from(order: SortOrder) -> Self {
+        SortKeys(vec![SortKey { tag: None, order }])
+    }
+}
+
+impl From<(&str, SortOrder)> for SortKeys {
+    fn from((tag, order): (&str, SortOrder)) -> Self {
+        SortKeys(vec![SortKey { tag: Some(tag.to_string()), order }])
+    }
+}
+
+impl From<Vec<(&str, SortOrder)>> for SortKeys {
+    fn from(v: Vec<(&str, SortOrder)>) -> Self {
+        SortKeys(v.into_iter().map(|(tag, order)| SortKey { tag: Some(tag.to_string()), order }).collect())
+    }
+}
+
+
 pub enum Values {
     None,
     Some(Vec<Value>)
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use serde_json::{Map, Value as Json};
+use super::gizmo::{Graph, Path};
+use super::mql::value_to_json;
+use super::expr::error_at;
+use super::super::graph::value::Value;
+
+
+/////////////////////////////////////////////// lexer
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Name(String),
+    Iri(String),
+    Str(String),
+    Integer(i64),
+    Decimal(f64),
+    Punct(char),
+    Eof
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    pos: usize
+}
+
+fn lex(src: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<(usize, char)> = src.char_indices().collect();
+    let at = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let pos_of = |i: usize| chars.get(i).map(|(p, _)| *p).unwrap_or(src.len());
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while let Some(c) = at(i) {
+        let start = i;
+
+        if c.is_whitespace() || c == ',' {
+            i += 1;
+            continue;
+        }
+        if c == '#' {
+            while at(i).is_some_and(|c| c != '\n') {
+                i += 1;
+            }
+            continue;
+        }
+
+        let tok = if c == '<' {
+            i += 1;
+            let mut iri = String::new();
+            loop {
+                match at(i) {
+                    Some('>') => { i += 1; break },
+                    Some(c) if !c.is_whitespace() => { iri.push(c); i += 1 },
+                    _ => return Err(error_at(src, pos_of(start), "unterminated IRI"))
+                }
+            }
+            Tok::Iri(iri)
+        } else if c == '"' {
+            i += 1;
+            let mut s = String::new();
+            loop {
+                match at(i) {
+                    Some('"') => { i += 1; break },
+                    Some('\\') if at(i + 1).is_some() => { s.push(at(i + 1).unwrap()); i += 2 },
+                    Some(c) => { s.push(c); i += 1 },
+                    None => return Err(error_at(src, pos_of(start), "unterminated string"))
+                }
+            }
+            Tok::Str(s)
+        } else if c.is_ascii_digit() || (c == '-' && at(i + 1).is_some_and(|c| c.is_ascii_digit())) {
+            i += 1;
+            while at(i).is_some_and(|c| c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E') {
+                i += 1;
+            }
+            let text = &src[pos_of(start)..pos_of(i)];
+            if let Ok(n) = text.parse::<i64>() {
+                Tok::Integer(n)
+            } else if let Ok(f) = text.parse::<f64>() {
+                Tok::Decimal(f)
+            } else {
+                return Err(error_at(src, pos_of(start), "invalid number"))
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(c) = at(i).filter(|c| c.is_alphanumeric() || *c == '_') {
+                name.push(c);
+                i += 1;
+            }
+            Tok::Name(name)
+        } else if "{}()[]:@".contains(c) {
+            i += 1;
+            Tok::Punct(c)
+        } else {
+            return Err(error_at(src, pos_of(start), &format!("unexpected character {:?}", c)))
+        };
+
+        tokens.push(Token {
+            tok,
+            pos: pos_of(start)
+        });
+    }
+
+    tokens.push(Token {
+        tok: Tok::Eof,
+        pos: src.len()
+    });
+    Ok(tokens)
+}
+
+
+/////////////////////////////////////////////// syntax
+
+
+#[derive(Debug, Clone)]
+struct Field {
+    // the key of the field in the result
+    key: String,
+    pred: Value,
+    args: Vec<(Value, Vec<Value>)>,
+    reverse: bool,
+    optional: bool,
+    // None for fields that return values
+    selection: Option<Vec<Field>>
+}
+
+impl Field {
+    fn is_id(&self) -> bool {
+        self.pred == Value::IRI("id".to_string()) && self.selection.is_none()
+    }
+}
+
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    i: usize
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.i].tok
+    }
+
+    fn next(&mut self) -> Tok {
+        let t = self.tokens[self.i].tok.clone();
+        if self.i < self.tokens.len() - 1 {
+            self.i += 1;
+        }
+        t
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(error_at(self.src, self.tokens[self.i].pos, msg))
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if *self.peek() == Tok::Punct(c) {
+            self.next();
+            return true
+        }
+        false
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            return Ok(())
+        }
+        self.error(&format!("expected '{}'", c))
+    }
+
+    fn document(&mut self) -> Result<Vec<Field>, String> {
+        // query keyword and operation name
+        if *self.peek() == Tok::Name("query".to_string()) {
+            self.next();
+            if let Tok::Name(_) = self.peek() {
+                self.next();
+            }
+        }
+        let fields = self.selection()?;
+        if *self.peek() != Tok::Eof {
+            return self.error("unexpected input after the query")
+        }
+        for f in &fields {
+            if f.selection.is_none() {
+                return Err(format!("top level field {} needs a selection", f.key))
+            }
+        }
+        Ok(fields)
+    }
+
+    fn selection(&mut self) -> Result<Vec<Field>, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        while !self.eat('}') {
+            fields.push(self.field()?);
+        }
+        if fields.is_empty() {
+            return self.error("empty selection")
+        }
+        Ok(fields)
+    }
+
+    fn name(&mut self) -> Result<(String, Value), String> {
+        let res = match self.peek() {
+            Tok::Name(n) => (n.clone(), Value::IRI(n.clone())),
+            Tok::Iri(iri) => (format!("<{}>", iri), Value::IRI(iri.clone())),
+            _ => return self.error("expected a field name")
+        };
+        self.next();
+        Ok(res)
+    }
+
+    fn field(&mut self) -> Result<Field, String> {
+        let (key, mut pred) = self.name()?;
+        if self.eat(':') {
+            let (_, p) = self.name()?;
+            pred = p;
+        }
+
+        let mut args = Vec::new();
+        if self.eat('(') {
+            while !self.eat(')') {
+                let (_, name) = self.name()?;
+                self.expect(':')?;
+                let values = if self.eat('[') {
+                    let mut v = Vec::new();
+                    while !self.eat(']') {
+                        v.push(self.value()?);
+                    }
+                    v
+                } else {
+                    vec![self.value()?]
+                };
+                args.push((name, values));
+            }
+        }
+
+        let mut reverse = false;
+        let mut optional = false;
+        while self.eat('@') {
+            match self.peek() {
+                Tok::Name(d) if d == "rev" || d == "reverse" => reverse = true,
+                Tok::Name(d) if d == "opt" || d == "optional" => optional = true,
+                _ => return self.error("unknown directive")
+            }
+            self.next();
+        }
+
+        let selection = if *self.peek() == Tok::Punct('{') {
+            Some(self.selection()?)
+        } else {
+            None
+        };
+
+        if selection.is_none() && !args.is_empty() {
+            return self.error("arguments need a selection")
+        }
+
+        Ok(Field {
+            key,
+            pred,
+            args,
+            reverse,
+            optional,
+            selection
+        })
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        let v = match self.peek() {
+            Tok::Iri(iri) => Value::IRI(iri.clone()),
+            Tok::Str(s) => Value::from(s.clone()),
+            Tok::Integer(n) => Value::from(*n),
+            Tok::Decimal(f) => Value::from(*f),
+            Tok::Name(n) if n == "true" || n == "false" => Value::Bool(n == "true"),
+            _ => return self.error("expected a value")
+        };
+        self.next();
+        Ok(v)
+    }
+}
+
+
+/////////////////////////////////////////////// execution
+
+
+fn count_arg(values: &[Value], name: &str) -> Result<usize, String> {
+    match values {
+        [Value::Number(n)] => match n.as_i64() {
+            Some(i) if i >= 0 => Ok(i as usize),
+            _ => Err(format!("{} must be a non-negative integer", name))
+        },
+        _ => Err(format!("{} must be a non-negative integer", name))
+    }
+}
+
+// Runs the selection on the nodes of the path, one object per node.
+fn select(g: &Graph, mut path: Path, field: &Field) -> Result<Vec<Json>, String> {
+    let mut first = None;
+    let mut offset = 0;
+
+    for (name, values) in &field.args {
+        match name {
+            Value::IRI(n) if n == "id" => { path = path.is(values.clone()); },
+            Value::IRI(n) if n == "first" => first = Some(count_arg(values, "first")?),
+            Value::IRI(n) if n == "offset" => offset = count_arg(values, "offset")?,
+            pred => { path = path.has(pred.clone(), values.clone()); }
+        }
+    }
+
+    let fields = field.selection.as_ref().unwrap();
+
+    // values are saved on the path, nested objects are followed per node
+    let mut tags = Vec::new();
+    for (i, f) in fields.iter().enumerate() {
+        if f.selection.is_some() || f.is_id() {
+            continue;
+        }
+        let tag = format!("__field_{}", i);
+        path = match (f.reverse, f.optional) {
+            (false, false) => path.save(f.pred.clone(), tag.as_str()),
+            (true, false) => path.save_r(f.pred.clone(), tag.as_str()),
+            (false, true) => path.save_opt(f.pred.clone(), tag.as_str()),
+            (true, true) => path.save_opt_r(f.pred.clone(), tag.as_str())
+        };
+        tags.push((i, tag));
+    }
+
+    // rows of the same node are merged, values of a field are kept in order
+    let mut nodes: Vec<(Value, HashMap<usize, Vec<Value>>)> = Vec::new();
+    let mut index: HashMap<Value, usize> = HashMap::new();
+    for row in path.try_iter_tags() {
+        let row = row?;
+        let id = match row.get("id") {
+            Some(id) => id.clone(),
+            None => continue
+        };
+        let idx = *index.entry(id.clone()).or_insert_with(|| {
+            nodes.push((id, HashMap::new()));
+            nodes.len() - 1
+        });
+        for (i, tag) in &tags {
+            if let Some(v) = row.get(tag) {
+                let values = nodes[idx].1.entry(*i).or_default();
+                if !values.contains(v) {
+                    values.push(v.clone());
+                }
+            }
+        }
+    }
+
+    let mut results = Vec::new();
+    'nodes: for (id, values) in nodes {
+        let mut obj = Map::new();
+        for (i, f) in fields.iter().enumerate() {
+            let res = if f.is_id() {
+                value_to_json(&id)
+            } else if f.selection.is_some() {
+                let mut sub = g.v(id.clone());
+                let sub = if f.reverse {
+                    sub.r#in(f.pred.clone(), None)
+                } else {
+                    sub.out(f.pred.clone(), None)
+                };
+                let children = select(g, sub, f)?;
+                if children.is_empty() && !f.optional {
+                    continue 'nodes;
+                }
+                Json::Array(children)
+            } else {
+                match values.get(&i).map(|v| v.as_slice()) {
+                    None | Some([]) => Json::Null,
+                    Some([v]) => value_to_json(v),
+                    Some(v) => Json::Array(v.iter().map(value_to_json).collect())
+                }
+            };
+            obj.insert(f.key.clone(), res);
+        }
+        results.push(Json::Object(obj));
+    }
+
+    Ok(results.into_iter().skip(offset).take(first.unwrap_or(usize::MAX)).collect())
+}
+
+
+// Runs a GraphQL query. Fields are predicates and id is the node itself,
+// @rev follows a predicate in reverse and @opt keeps nodes that lack it.
+// Arguments filter nodes by id or predicate values, first and offset paginate.
+pub fn query(g: &Graph, src: &str) -> Result<Json, String> {
+    let mut p = Parser {
+        src,
+        tokens: lex(src)?,
+        i: 0
+    };
+    let fields = p.document()?;
+
+    let mut data = Map::new();
+    for f in &fields {
+        let start = g.v(None);
+        data.insert(f.key.clone(), Json::Array(select(g, start, f)?));
+    }
+    Ok(Json::Object(data))
+}
@@ -2,6 +2,19 @@
 #[cfg(feature = "standalone")]
 pub mod gizmo;
 
+#[cfg(feature = "standalone")]
+pub mod parser;
+
+#[cfg(feature = "standalone")]
+pub mod graphql;
+
 pub mod path;
 pub mod shape;
+pub mod rewrite;
+pub mod parallel;
+pub mod bgp;
+pub(crate) mod expr;
+pub mod sparql;
+pub mod cypher;
+pub mod mql;
 mod morphism;
\ No newline at end of file
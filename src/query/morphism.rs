@@ -1,10 +1,14 @@
 
 use crate::graph::value::Value;
+use crate::graph::quad::Direction;
 use std::cell::RefCell;
 use std::rc::Rc;
-use super::path::PathContext;
+use super::path::{PathContext, hop_tag};
 use crate::query::shape::*;
-use crate::query::path::{Via, Path};
+use crate::query::path::{Via, Path, Recursion};
+use crate::graph::iterator::weighted_path::{Weight, Heuristic};
+use crate::graph::iterator::value_filter::Operator;
+use crate::graph::iterator::sort::SortKey;
 
 
 fn join(its: Vec<Rc<RefCell<dyn Shape>>>) -> Rc<RefCell<dyn Shape>> {
@@ -20,6 +24,17 @@ fn join(its: Vec<Rc<RefCell<dyn Shape>>>) -> Rc<RefCell<dyn Shape>> {
 }
 
 
+// Saves the quads of a traversal under the tag of a hop, if it is tracked.
+fn track_hop(s: Rc<RefCell<dyn Shape>>, hop: Option<usize>, r#in: bool) -> Rc<RefCell<dyn Shape>> {
+    if let Some(i) = hop {
+        if let ShapeType::NodesFrom(n) = s.borrow_mut().shape_type() {
+            n.quads = Save::new(vec![hop_tag(i, r#in)], Some(n.quads.clone()));
+        }
+    }
+    s
+}
+
+
 //////////////////////////////////////////////////////////
 
 pub trait Morphism {
@@ -81,7 +96,8 @@ impl Morphism for InMorphism {
     }
 
     fn apply(&self, shape: Rc<RefCell<dyn Shape>>, ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
-        return (new_in_out(shape, self.via.as_shape(), ctx.label_set.clone(), self.tags.clone(), true), None)
+        let s = new_in_out(shape, self.via.as_shape(), ctx.label_set.clone(), self.tags.clone(), true);
+        return (track_hop(s, ctx.next_hop(), true), None)
     }
 
     fn tags(&self) -> Option<Vec<String>> { 
@@ -111,7 +127,8 @@ impl Morphism for OutMorphism {
     }
 
     fn apply(&self, shape: Rc<RefCell<dyn Shape>>, ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
-        return (new_in_out(shape, self.via.as_shape(), ctx.label_set.clone(), self.tags.clone(), false), None)
+        let s = new_in_out(shape, self.via.as_shape(), ctx.label_set.clone(), self.tags.clone(), false);
+        return (track_hop(s, ctx.next_hop(), false), None)
     }
 
     fn tags(&self) -> Option<Vec<String>> { 
@@ -142,9 +159,10 @@ impl Morphism for BothMorphism {
 
     fn apply(&self, shape: Rc<RefCell<dyn Shape>>, ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
         let via = self.via.as_shape();
+        let hop = ctx.next_hop();
         return (Rc::new(RefCell::new(Union(vec![
-            new_in_out(shape.clone(), via.clone(), ctx.label_set.clone(), self.tags.clone(), true),
-            new_in_out(shape.clone(), via.clone(), ctx.label_set.clone(), self.tags.clone(), false)
+            track_hop(new_in_out(shape.clone(), via.clone(), ctx.label_set.clone(), self.tags.clone(), true), hop, true),
+            track_hop(new_in_out(shape.clone(), via.clone(), ctx.label_set.clone(), self.tags.clone(), false), hop, false)
         ]))), None)
     }
 
@@ -155,6 +173,87 @@ impl Morphism for BothMorphism {
 
 //////////////////////////////////////////////////////////
 
+// From nodes to the quads that have them in one of `dirs`.
+pub struct EdgesMorphism {
+    tags: Option<Vec<String>>,
+    via: Via,
+    dirs: Vec<Direction>
+}
+
+impl EdgesMorphism {
+    pub fn new(tags: Option<Vec<String>>, via: Via, dirs: Vec<Direction>) -> Rc<dyn Morphism> {
+        Rc::new(EdgesMorphism {
+            tags,
+            via,
+            dirs
+        })
+    }
+}
+
+impl Morphism for EdgesMorphism {
+    fn reversal(&self, _ctx: &mut PathContext) -> (Rc<dyn Morphism>, Option<PathContext>) {
+        (EdgeNodesMorphism::new(self.via.clone(), self.dirs.clone()), None)
+    }
+
+    fn apply(&self, shape: Rc<RefCell<dyn Shape>>, ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
+        let via = self.via.as_shape();
+        let mut edges: Vec<Rc<RefCell<dyn Shape>>> = self.dirs.iter().map(|d| {
+            new_edges(shape.clone(), via.clone(), ctx.label_set.clone(), self.tags.clone(), d.clone())
+        }).collect();
+        if edges.len() == 1 {
+            return (edges.remove(0), None)
+        }
+        (Rc::new(RefCell::new(Union(edges))), None)
+    }
+
+    fn tags(&self) -> Option<Vec<String>> {
+        self.tags.clone()
+    }
+}
+
+//////////////////////////////////////////////////////////
+
+// From quads to their nodes in one of `dirs`, for the quads with a
+// predicate of `via` when it is given.
+pub struct EdgeNodesMorphism {
+    via: Via,
+    dirs: Vec<Direction>
+}
+
+impl EdgeNodesMorphism {
+    pub fn new(via: Via, dirs: Vec<Direction>) -> Rc<dyn Morphism> {
+        Rc::new(EdgeNodesMorphism {
+            via,
+            dirs
+        })
+    }
+}
+
+impl Morphism for EdgeNodesMorphism {
+    fn reversal(&self, _ctx: &mut PathContext) -> (Rc<dyn Morphism>, Option<PathContext>) {
+        (EdgesMorphism::new(None, self.via.clone(), self.dirs.clone()), None)
+    }
+
+    fn apply(&self, shape: Rc<RefCell<dyn Shape>>, _ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
+        let quads = match &self.via {
+            Via::None => shape,
+            via => Intersect::new(vec![
+                shape,
+                Rc::new(RefCell::new(Quads(vec![QuadFilter::new_struct(Direction::Predicate, Some(via.as_shape()))])))
+            ])
+        };
+        let mut nodes: Vec<Rc<RefCell<dyn Shape>>> = self.dirs.iter().map(|d| {
+            NodesFrom::new(d.clone(), quads.clone()) as Rc<RefCell<dyn Shape>>
+        }).collect();
+        if nodes.len() == 1 {
+            return (nodes.remove(0), None)
+        }
+        (Rc::new(RefCell::new(Union(nodes))), None)
+    }
+}
+
+//////////////////////////////////////////////////////////
+
 pub struct FollowMorphism {
     path: Path
 }
@@ -172,8 +271,11 @@ impl Morphism for FollowMorphism {
         (FollowMorphism::new(self.path.clone().reverse()), None)
     }
 
-    fn apply(&self, shape: Rc<RefCell<dyn Shape>>, _ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
-        (self.path.clone().shape_from(shape), None)
+    fn apply(&self, shape: Rc<RefCell<dyn Shape>>, ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
+        // the hops of the followed path go on from the ones before it
+        let mut path = self.path.clone();
+        path.base_context.hops = ctx.hops.clone();
+        (path.shape_from(shape), None)
     }
 }
 
@@ -181,16 +283,16 @@ impl Morphism for FollowMorphism {
 
 pub struct FollowRecursiveMorphism {
     path: Path,
-    max_depth: i32,
+    recursion: Recursion,
     depth_tags: Vec<String>,
 
 }
 
 impl FollowRecursiveMorphism {
-    pub fn new(path: Path, max_depth: i32, depth_tags: Vec<String>) -> Rc<dyn Morphism> {
+    pub fn new(path: Path, recursion: Recursion, depth_tags: Vec<String>) -> Rc<dyn Morphism> {
         Rc::new(FollowRecursiveMorphism {
             path,
-            max_depth,
+            recursion,
             depth_tags
         })
     }
@@ -198,7 +300,7 @@ impl FollowRecursiveMorphism {
 
 impl Morphism for FollowRecursiveMorphism {
     fn reversal(&self, _ctx: &mut PathContext) -> (Rc<dyn Morphism>, Option<PathContext>) {
-        (FollowRecursiveMorphism::new(self.path.clone().reverse(), self.max_depth, self.depth_tags.clone()), None)
+        (FollowRecursiveMorphism::new(self.path.clone().reverse(), self.recursion.clone(), self.depth_tags.clone()), None)
     }
 
     fn apply(&self, shape: Rc<RefCell<dyn Shape>>, _ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
@@ -206,7 +308,7 @@ impl Morphism for FollowRecursiveMorphism {
             Recursive::new(
                 self.path.clone(),
                 shape,
-                self.max_depth, 
+                &self.recursion,
                 self.depth_tags.clone()
             ),
             None
@@ -216,6 +318,105 @@ impl Morphism for FollowRecursiveMorphism {
 
 //////////////////////////////////////////////////////////
 
+// Counts the results of a path from every node, see CountPer.
+pub struct CountMorphism {
+    path: Path,
+    tag: Option<String>,
+    filter: Option<(Operator, i64)>
+}
+
+impl CountMorphism {
+    pub fn new(path: Path, tag: Option<String>, filter: Option<(Operator, i64)>) -> Rc<dyn Morphism> {
+        Rc::new(CountMorphism {
+            path,
+            tag,
+            filter
+        })
+    }
+}
+
+impl Morphism for CountMorphism {
+    fn reversal(&self, _ctx: &mut PathContext) -> (Rc<dyn Morphism>, Option<PathContext>) {
+        (CountMorphism::new(self.path.clone(), self.tag.clone(), self.filter.clone()), None)
+    }
+
+    fn apply(&self, shape: Rc<RefCell<dyn Shape>>, _ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
+        (CountPer::new(shape, self.path.clone(), self.tag.clone(), self.filter.clone()), None)
+    }
+}
+
+//////////////////////////////////////////////////////////
+
+pub struct ShortestPathMorphism {
+    target: Path,
+    via: Via,
+    max_depth: i32,
+    rev: bool
+}
+
+impl ShortestPathMorphism {
+    pub fn new(target: Path, via: Via, max_depth: i32, rev: bool) -> Rc<dyn Morphism> {
+        Rc::new(ShortestPathMorphism {
+            target,
+            via,
+            max_depth,
+            rev
+        })
+    }
+}
+
+impl Morphism for ShortestPathMorphism {
+    fn reversal(&self, _ctx: &mut PathContext) -> (Rc<dyn Morphism>, Option<PathContext>) {
+        (ShortestPathMorphism::new(self.target.clone(), self.via.clone(), self.max_depth, !self.rev), None)
+    }
+
+    fn apply(&self, shape: Rc<RefCell<dyn Shape>>, _ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
+        let via = match &self.via {
+            Via::None => None,
+            v => Some(v.as_shape())
+        };
+        (ShortestPath::new(shape, self.target.shape(), via, self.max_depth, self.rev), None)
+    }
+}
+
+//////////////////////////////////////////////////////////
+
+pub struct WeightedPathMorphism {
+    target: Path,
+    via: Via,
+    weight: Weight,
+    heuristic: Option<Heuristic>,
+    rev: bool
+}
+
+impl WeightedPathMorphism {
+    pub fn new(target: Path, via: Via, weight: Weight, heuristic: Option<Heuristic>, rev: bool) -> Rc<dyn Morphism> {
+        Rc::new(WeightedPathMorphism {
+            target,
+            via,
+            weight,
+            heuristic,
+            rev
+        })
+    }
+}
+
+impl Morphism for WeightedPathMorphism {
+    fn reversal(&self, _ctx: &mut PathContext) -> (Rc<dyn Morphism>, Option<PathContext>) {
+        (WeightedPathMorphism::new(self.target.clone(), self.via.clone(), self.weight.clone(), self.heuristic.clone(), !self.rev), None)
+    }
+
+    fn apply(&self, shape: Rc<RefCell<dyn Shape>>, _ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
+        let via = match &self.via {
+            Via::None => None,
+            v => Some(v.as_shape())
+        };
+        (WeightedPath::new(shape, self.target.shape(), via, self.weight.clone(), self.heuristic.clone(), self.rev), None)
+    }
+}
+
+//////////////////////////////////////////////////////////
+
 pub struct AndMorphism {
     path: Path
 }
@@ -493,22 +694,22 @@ impl Morphism for SkipMorphism {
 
 //////////////////////////////////////////////////////////
 
-pub struct OrderMorphism ();
+pub struct OrderMorphism (Vec<SortKey>);
 
 impl OrderMorphism {
-    pub fn new() -> Rc<dyn Morphism> {
-        Rc::new(OrderMorphism())
+    pub fn new(keys: Vec<SortKey>) -> Rc<dyn Morphism> {
+        Rc::new(OrderMorphism(keys))
     }
 }
 
 impl Morphism for OrderMorphism {
     fn reversal(&self, _ctx: &mut PathContext) -> (Rc<dyn Morphism>, Option<PathContext>) {
-        (OrderMorphism::new(), None)
+        (OrderMorphism::new(self.0.clone()), None)
     }
 
     fn apply(&self, shape: Rc<RefCell<dyn Shape>>, _ctx: &mut PathContext) -> (Rc<RefCell<dyn Shape>>, Option<PathContext>) {
         ( 
-            Rc::new(RefCell::new(Sort{from: shape})), 
+            Rc::new(RefCell::new(Sort{from: shape, keys: self.0.clone(), limit: 0})), 
             None
         )
     }
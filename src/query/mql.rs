@@ -0,0 +1,271 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use serde_json::{Map, Value as Json};
+use super::shape::{self, Shape, AllNodes, Lookup, Intersect, IntersectOpt, NodesFrom, QuadFilter, Quads, Save};
+use super::super::graph::iterator;
+use super::super::graph::quad::{QuadStore, Direction};
+use super::super::graph::value::Value;
+use super::super::graph::number::Number;
+
+
+type Row = HashMap<String, Value>;
+
+
+enum Target {
+    // null or a constant, returned as a value
+    Value(Option<Value>),
+    Node(Node)
+}
+
+struct Field {
+    // the key as written in the template, results use it too
+    key: String,
+    tag: String,
+    pred: Value,
+    reverse: bool,
+    optional: bool,
+    list: bool,
+    target: Target
+}
+
+struct Node {
+    tag: String,
+    // the template asked for the id of the node
+    has_id: bool,
+    id: Option<Value>,
+    fields: Vec<Field>
+}
+
+
+fn json_to_value(j: &Json) -> Result<Value, String> {
+    match j {
+        Json::String(s) => Ok(Value::from(s.clone())),
+        Json::Bool(b) => Ok(Value::Bool(*b)),
+        Json::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Ok(Value::from(i))
+            } else if let Some(f) = n.as_f64() {
+                Ok(Value::from(f))
+            } else {
+                Err(format!("unsupported number {}", n))
+            }
+        },
+        j => Err(format!("expected a value, got {}", j))
+    }
+}
+
+fn number_to_json(n: &Number) -> Json {
+    if let Some(i) = n.as_i64() {
+        Json::from(i)
+    } else if let Some(u) = n.as_u64() {
+        Json::from(u)
+    } else {
+        n.as_f64().map(Json::from).unwrap_or(Json::Null)
+    }
+}
+
+// IRIs keep their brackets so they can be fed back into a template
+pub fn value_to_json(v: &Value) -> Json {
+    match v {
+        Value::None | Value::Null => Json::Null,
+        Value::Bool(b) => Json::Bool(*b),
+        Value::Number(n) => number_to_json(n),
+        Value::IRI(_) => Json::String(v.to_string()),
+        Value::String(s) => Json::String(s.clone())
+    }
+}
+
+
+fn parse_node(template: &Map<String, Json>, tag: String) -> Result<Node, String> {
+    let mut node = Node {
+        tag,
+        has_id: false,
+        id: None,
+        fields: Vec::new()
+    };
+
+    for (i, (key, sub)) in template.iter().enumerate() {
+        if key == "id" {
+            node.has_id = true;
+            node.id = match sub {
+                Json::Null => None,
+                j => Some(json_to_value(j).map_err(|e| format!("id: {}", e))?)
+            };
+            continue;
+        }
+
+        let mut pred = key.as_str();
+        let reverse = pred.starts_with('!');
+        if reverse {
+            pred = &pred[1..];
+        }
+        let optional = pred.ends_with('?');
+        if optional {
+            pred = &pred[..pred.len() - 1];
+        }
+        if pred.is_empty() {
+            return Err(format!("{}: missing predicate", key))
+        }
+
+        let tag = format!("{}.{}", node.tag, i);
+        let (list, sub) = match sub {
+            Json::Array(a) if a.len() > 1 => return Err(format!("{}: a list holds at most one template", key)),
+            Json::Array(a) => (true, a.first().unwrap_or(&Json::Null)),
+            j => (false, j)
+        };
+        let target = match sub {
+            Json::Null => Target::Value(None),
+            Json::Object(o) => Target::Node(parse_node(o, tag.clone())?),
+            j => Target::Value(Some(json_to_value(j).map_err(|e| format!("{}: {}", key, e))?))
+        };
+
+        node.fields.push(Field {
+            key: key.clone(),
+            tag,
+            pred: Value::from(pred.to_string()),
+            reverse,
+            optional,
+            list,
+            target
+        });
+    }
+
+    Ok(node)
+}
+
+
+fn build_shape(node: &Node) -> Rc<RefCell<dyn Shape>> {
+    let mut required: Vec<Rc<RefCell<dyn Shape>>> = Vec::new();
+    let mut optional: Vec<Rc<RefCell<dyn Shape>>> = Vec::new();
+
+    if let Some(id) = &node.id {
+        required.push(Lookup::new(vec![id.clone()]));
+    }
+
+    for f in &node.fields {
+        let target: Rc<RefCell<dyn Shape>> = match &f.target {
+            Target::Value(Some(v)) => Save::new(vec![f.tag.clone()], Some(Lookup::new(vec![v.clone()]))),
+            Target::Value(None) => Save::new(vec![f.tag.clone()], Some(AllNodes::new())),
+            Target::Node(n) => build_shape(n)
+        };
+        let (from, to) = if f.reverse {
+            (Direction::Object, Direction::Subject)
+        } else {
+            (Direction::Subject, Direction::Object)
+        };
+        let quads = Quads(vec![
+            QuadFilter::new_struct(Direction::Predicate, Some(Lookup::new(vec![f.pred.clone()]))),
+            QuadFilter::new_struct(to, Some(target))
+        ]);
+        let link = NodesFrom::new(from, Rc::new(RefCell::new(quads)));
+
+        if f.optional {
+            optional.push(link);
+        } else {
+            required.push(link);
+        }
+    }
+
+    if required.is_empty() {
+        required.push(AllNodes::new());
+    }
+
+    let nodes = Rc::new(RefCell::new(IntersectOpt {
+        sub: Intersect(required),
+        opt: optional
+    }));
+    Save::new(vec![node.tag.clone()], Some(nodes))
+}
+
+
+// distinct values of the tag, in the order the rows came in
+fn distinct<'a>(rows: &[&'a Row], tag: &str) -> Vec<&'a Value> {
+    let mut res: Vec<&Value> = Vec::new();
+    for r in rows {
+        if let Some(v) = r.get(tag) {
+            if !res.contains(&v) {
+                res.push(v);
+            }
+        }
+    }
+    res
+}
+
+// groups the rows by the node they matched and builds one object per node
+fn assemble(node: &Node, rows: &[&Row]) -> Vec<Json> {
+    let mut results = Vec::new();
+
+    for id in distinct(rows, &node.tag) {
+        let group: Vec<&Row> = rows.iter().filter(|r| r.get(&node.tag) == Some(id)).cloned().collect();
+        let mut obj = Map::new();
+        if node.has_id {
+            obj.insert("id".to_string(), value_to_json(id));
+        }
+
+        for f in &node.fields {
+            let values = match &f.target {
+                Target::Value(_) => distinct(&group, &f.tag).into_iter().map(value_to_json).collect(),
+                Target::Node(n) => assemble(n, &group)
+            };
+            let res = if f.list {
+                Json::Array(values)
+            } else {
+                values.into_iter().next().unwrap_or(Json::Null)
+            };
+            obj.insert(f.key.clone(), res);
+        }
+
+        results.push(Json::Object(obj));
+    }
+
+    results
+}
+
+
+// Runs a JSON template. A template in a list returns every match,
+// a bare object only the first one, or null if nothing matched.
+// Keys are predicates, "!" in front follows the link in reverse and
+// "?" at the end makes the link optional.
+pub fn query(qs: Rc<RefCell<dyn QuadStore>>, template: &Json) -> Result<Json, String> {
+    let (list, root) = match template {
+        Json::Array(a) if a.len() == 1 => (true, &a[0]),
+        Json::Array(_) => return Err("the query must be a list with a single template".to_string()),
+        j => (false, j)
+    };
+    let root = match root {
+        Json::Object(o) => parse_node(o, "$".to_string())?,
+        _ => return Err("the query must be an object".to_string())
+    };
+
+    let it = shape::build_iterator(qs.clone(), build_shape(&root));
+    let mut each = iterator::iterate::TagEachIterator::new(it, true, true);
+    let mut rows = Vec::new();
+    for tags in each.by_ref() {
+        let mut row = Row::new();
+        for (k, r) in &tags {
+            match qs.borrow().name_of(r) {
+                Some(v) => { row.insert(k.clone(), v); },
+                None => return Err(format!("could not resolve value of tag {} for {:?}", k, r))
+            }
+        }
+        rows.push(row);
+    }
+    if let Some(e) = each.err() {
+        return Err(e)
+    }
+
+    let rows: Vec<&Row> = rows.iter().collect();
+    let results = assemble(&root, &rows);
+
+    if list {
+        Ok(Json::Array(results))
+    } else {
+        Ok(results.into_iter().next().unwrap_or(Json::Null))
+    }
+}
+
+pub fn query_str(qs: Rc<RefCell<dyn QuadStore>>, template: &str) -> Result<Json, String> {
+    let template: Json = serde_json::from_str(template).map_err(|e| format!("invalid template: {}", e))?;
+    query(qs, &template)
+}
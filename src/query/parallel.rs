@@ -0,0 +1,235 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::sync::Arc;
+use super::shape::{Shape, ShapeType, Null, AllNodes, Fixed, Lookup, Intersect, IntersectOpt, Union, NodesFrom, QuadFilter, Quads, Save, Unique, Sort, Page, Except, IteratorShape};
+use super::rewrite;
+use super::super::graph::iterator;
+use super::super::graph::iterator::parallel::{Parallel, Branch};
+use super::super::graph::quad::{QuadStore, SharedQuadStore, Direction};
+use super::super::graph::refs::Ref;
+use super::super::graph::value::Value;
+
+
+// the fewest seeds worth giving to a worker
+const MIN_PART: usize = 16;
+
+
+// A copy of a query shape that can be sent to another thread and built
+// there. Only shapes made of values have one, filters, recursion and
+// iterators hold state that stays on the thread that made them.
+#[derive(Clone, Debug)]
+pub enum Plan {
+    Null,
+    AllNodes,
+    Fixed(Vec<Ref>),
+    Lookup(Vec<Value>),
+    Intersect(Vec<Plan>),
+    IntersectOpt(Vec<Plan>, Vec<Plan>),
+    Union(Vec<Plan>),
+    NodesFrom(Direction, Box<Plan>),
+    Quads(Vec<(Direction, Option<Plan>)>),
+    Save(Vec<String>, Option<Box<Plan>>),
+    Unique(Box<Plan>),
+    Sort(Box<Plan>, Vec<iterator::sort::SortKey>, i64),
+    Page(Box<Plan>, i64, i64),
+    Except(Option<Box<Plan>>, Option<Box<Plan>>)
+}
+
+fn detach_all(v: &[Rc<RefCell<dyn Shape>>]) -> Option<Vec<Plan>> {
+    v.iter().map(detach).collect()
+}
+
+fn detach_opt(s: &Option<Rc<RefCell<dyn Shape>>>) -> Option<Option<Box<Plan>>> {
+    match s {
+        Some(s) => Some(Some(Box::new(detach(s)?))),
+        None => Some(None)
+    }
+}
+
+pub fn detach(s: &Rc<RefCell<dyn Shape>>) -> Option<Plan> {
+    Some(match s.borrow_mut().shape_type() {
+        ShapeType::Null => Plan::Null,
+        ShapeType::AllNodes => Plan::AllNodes,
+        ShapeType::Fixed(f) => Plan::Fixed(f.0.clone()),
+        ShapeType::Lookup(l) => Plan::Lookup(l.0.clone()),
+        ShapeType::Intersect(i) => Plan::Intersect(detach_all(&i.0)?),
+        ShapeType::IntersectOpt(io) => Plan::IntersectOpt(detach_all(&io.sub.0)?, detach_all(&io.opt)?),
+        ShapeType::Union(u) => Plan::Union(detach_all(&u.0)?),
+        ShapeType::NodesFrom(n) => Plan::NodesFrom(n.dir.clone(), Box::new(detach(&n.quads)?)),
+        ShapeType::Quads(q) => {
+            let filters: Option<Vec<_>> = q.0.iter().map(|f| Some((f.dir.clone(), detach_opt(&f.values)?.map(|p| *p)))).collect();
+            Plan::Quads(filters?)
+        },
+        ShapeType::Save(s) => Plan::Save(s.tags.clone(), detach_opt(&s.from)?),
+        ShapeType::Unique(u) => Plan::Unique(Box::new(detach(&u.from)?)),
+        ShapeType::Sort(s) => Plan::Sort(Box::new(detach(&s.from)?), s.keys.clone(), s.limit),
+        ShapeType::Page(p) => Plan::Page(Box::new(detach(&p.from)?), p.skip, p.limit),
+        ShapeType::Except(e) => Plan::Except(detach_opt(&e.exclude)?, detach_opt(&e.from)?),
+        _ => return None
+    })
+}
+
+impl Plan {
+    // the query shape again, on the calling thread
+    pub fn attach(&self) -> Rc<RefCell<dyn Shape>> {
+        let all = |v: &[Plan]| -> Vec<Rc<RefCell<dyn Shape>>> { v.iter().map(|p| p.attach()).collect() };
+        let opt = |p: &Option<Box<Plan>>| p.as_ref().map(|p| p.attach());
+        match self {
+            Plan::Null => Null::new(),
+            Plan::AllNodes => AllNodes::new(),
+            Plan::Fixed(refs) => Fixed::new(refs.clone()),
+            Plan::Lookup(values) => Lookup::new(values.clone()),
+            Plan::Intersect(v) => Intersect::new(all(v)),
+            Plan::IntersectOpt(sub, o) => Rc::new(RefCell::new(IntersectOpt {
+                sub: Intersect(all(sub)),
+                opt: all(o)
+            })),
+            Plan::Union(v) => Rc::new(RefCell::new(Union(all(v)))),
+            Plan::NodesFrom(dir, quads) => NodesFrom::new(dir.clone(), quads.attach()),
+            Plan::Quads(filters) => Rc::new(RefCell::new(Quads(filters.iter().map(|(dir, values)| {
+                QuadFilter::new_struct(dir.clone(), values.as_ref().map(|v| v.attach()))
+            }).collect()))),
+            Plan::Save(tags, from) => Save::new(tags.clone(), opt(from)),
+            Plan::Unique(from) => Rc::new(RefCell::new(Unique { from: from.attach() })),
+            Plan::Sort(from, keys, limit) => Rc::new(RefCell::new(Sort {
+                from: from.attach(),
+                keys: keys.clone(),
+                limit: *limit
+            })),
+            Plan::Page(from, skip, limit) => Rc::new(RefCell::new(Page {
+                from: from.attach(),
+                skip: *skip,
+                limit: *limit
+            })),
+            Plan::Except(exclude, from) => Rc::new(RefCell::new(Except {
+                exclude: opt(exclude),
+                from: opt(from)
+            }))
+        }
+    }
+
+    // The largest seed set the results are built from, as the path of
+    // children leading to it. It is only looked for under shapes that return
+    // the results of every part of their input, so parts of the seeds can be
+    // run on their own and their results put together.
+    fn seeds(&self) -> Option<(usize, Vec<usize>)> {
+        let child = |i: usize, p: &Plan| p.seeds().map(|(n, mut path)| {
+            path.insert(0, i);
+            (n, path)
+        });
+        match self {
+            Plan::Fixed(refs) => Some((refs.len(), Vec::new())),
+            Plan::Intersect(v) | Plan::IntersectOpt(v, _) => {
+                v.iter().enumerate().filter_map(|(i, p)| child(i, p)).max_by_key(|(n, _)| *n)
+            },
+            Plan::Quads(filters) => {
+                filters.iter().enumerate().filter_map(|(i, (_, p))| child(i, p.as_ref()?)).max_by_key(|(n, _)| *n)
+            },
+            Plan::NodesFrom(_, p) | Plan::Save(_, Some(p)) | Plan::Except(_, Some(p)) => child(0, p),
+            _ => None
+        }
+    }
+
+    fn seeds_mut(&mut self, path: &[usize]) -> Option<&mut Vec<Ref>> {
+        let (i, rest) = match path.split_first() {
+            Some((i, rest)) => (*i, rest),
+            None => return match self {
+                Plan::Fixed(refs) => Some(refs),
+                _ => None
+            }
+        };
+        match self {
+            Plan::Intersect(v) | Plan::IntersectOpt(v, _) => v.get_mut(i)?.seeds_mut(rest),
+            Plan::Quads(filters) => filters.get_mut(i)?.1.as_mut()?.seeds_mut(rest),
+            Plan::NodesFrom(_, p) | Plan::Save(_, Some(p)) | Plan::Except(_, Some(p)) => p.seeds_mut(rest),
+            _ => None
+        }
+    }
+
+    // The plans to run in parallel: the branches of a union, or the plan
+    // over parts of its seeds. None if it can't be split in two or more.
+    pub fn split(&self, parts: usize) -> Option<Vec<Plan>> {
+        if let Plan::Union(v) = self {
+            if v.len() > 1 {
+                return Some(v.clone())
+            }
+        }
+        let (n, path) = self.seeds()?;
+        let parts = parts.min(n / MIN_PART);
+        if parts < 2 {
+            return None
+        }
+        let mut plan = self.clone();
+        let seeds = plan.seeds_mut(&path)?.clone();
+        let mut res = Vec::new();
+        for part in seeds.chunks(n.div_ceil(parts)) {
+            *plan.seeds_mut(&path)? = part.to_vec();
+            res.push(plan.clone());
+        }
+        Some(res)
+    }
+}
+
+
+// A part of the seeds can change which shape is the most selective, so
+// every branch is optimized again by the worker that builds it.
+fn branch(plan: Plan) -> Branch {
+    Arc::new(move |qs: Rc<RefCell<dyn QuadStore>>| {
+        let s = plan.attach();
+        let n = s.borrow_mut().optimize(Some(&rewrite::Rules::new(qs.clone())));
+        let s = n.unwrap_or(s);
+        let it = s.borrow_mut().build_iterator(qs);
+        it
+    })
+}
+
+// The iterators of a shape, with the part that can be split run on the
+// workers. Uniques, sorts and pages over it still run on this thread.
+fn build_parallel(store: &Arc<dyn SharedQuadStore>, qs: &Rc<RefCell<dyn QuadStore>>, s: &Rc<RefCell<dyn Shape>>, workers: usize) -> Option<Rc<RefCell<dyn iterator::Shape>>> {
+    type Wrap = Box<dyn Fn(Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>>>;
+    let wrapper: Option<(Rc<RefCell<dyn Shape>>, Wrap)> = match s.borrow_mut().shape_type() {
+        ShapeType::Unique(u) => Some((u.from.clone(), Box::new(|from| Rc::new(RefCell::new(Unique { from }))))),
+        ShapeType::Sort(so) => {
+            let (keys, limit) = (so.keys.clone(), so.limit);
+            Some((so.from.clone(), Box::new(move |from| Rc::new(RefCell::new(Sort { from, keys: keys.clone(), limit })))))
+        },
+        ShapeType::Page(p) => {
+            let (skip, limit) = (p.skip, p.limit);
+            Some((p.from.clone(), Box::new(move |from| Rc::new(RefCell::new(Page { from, skip, limit })))))
+        },
+        _ => None
+    };
+    if let Some((from, wrap)) = wrapper {
+        let it = build_parallel(store, qs, &from, workers)?;
+        let s = wrap(Rc::new(RefCell::new(IteratorShape { it: Some(it) })));
+        let it = s.borrow_mut().build_iterator(qs.clone());
+        return Some(it)
+    }
+
+    let plans = detach(s)?.split(workers)?;
+    Some(Parallel::new(store.clone(), plans.into_iter().map(branch).collect(), workers))
+}
+
+// Builds the iterators of a shape to run on `workers` threads, one per core
+// for 0: the branches of a union, or parts of the seed set its results come
+// from. Shapes that can't be split, or stores that can't be read from other
+// threads, get the usual iterators.
+pub fn build_iterator(qs: Rc<RefCell<dyn QuadStore>>, shape: Rc<RefCell<dyn Shape>>, workers: usize) -> Rc<RefCell<dyn iterator::Shape>> {
+    let s = shape.borrow_mut().optimize(Some(&rewrite::Rules::new(qs.clone())));
+    let s = s.unwrap_or(shape);
+    let workers = if workers > 0 {
+        workers
+    } else {
+        std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    };
+    let store = qs.borrow().shared();
+    if let Some(store) = store {
+        if workers > 1 {
+            if let Some(it) = build_parallel(&store, &qs, &s, workers) {
+                return it
+            }
+        }
+    }
+    let it = s.borrow_mut().build_iterator(qs);
+    it
+}
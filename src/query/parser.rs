@@ -0,0 +1,787 @@
+use super::gizmo::{self, Graph, Path};
+use super::path::Via;
+use super::shape::ValueFilter;
+use crate::graph::value::Value;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::fmt;
+
+
+// Parses Gizmo query text, the JavaScript-like syntax used by Cayley:
+//
+//   var follows = g.M().Out("<follows>");
+//   g.V("<alice>").Follow(follows).Has("<status>", "cool_person").All()
+//
+// Steps are matched case-insensitively, so g.V("<alice>").out("<follows>") works too.
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    // byte offset into the source
+    pub pos: usize,
+    pub line: usize,
+    pub column: usize,
+    pub message: String
+}
+
+impl ParseError {
+    fn new<S: Into<String>>(src: &str, pos: usize, message: S) -> ParseError {
+        let before = &src[..pos.min(src.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        ParseError {
+            pos,
+            line,
+            column,
+            message: message.into()
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+
+// The call that ends a query and decides what it returns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Final {
+    All,
+    GetLimit(i64),
+    ToArray,
+    ToValue,
+    TagArray,
+    TagValue,
+    Count
+}
+
+pub enum Output {
+    Tags(Vec<HashMap<String, Value>>),
+    Values(Vec<Value>),
+    Count(i64)
+}
+
+pub struct Query {
+    pub path: Path,
+    pub final_step: Option<Final>
+}
+
+impl Query {
+    // Runs the query, a query without a final step runs as All()
+    pub fn run(&self) -> Result<Output, String> {
+        let mut path = self.path.clone();
+        match self.final_step.as_ref().unwrap_or(&Final::All) {
+            Final::All | Final::TagArray => Ok(Output::Tags(path.try_iter_tags().collect::<Result<_, _>>()?)),
+            Final::GetLimit(n) => Ok(Output::Tags(path.try_iter_tags().take(*n as usize).collect::<Result<_, _>>()?)),
+            Final::TagValue => Ok(Output::Tags(path.try_iter_tags().take(1).collect::<Result<_, _>>()?)),
+            Final::ToArray => Ok(Output::Values(path.try_iter_values().collect::<Result<_, _>>()?)),
+            Final::ToValue => Ok(Output::Values(path.try_iter_values().take(1).collect::<Result<_, _>>()?)),
+            Final::Count => Ok(Output::Count(path.count()))
+        }
+    }
+}
+
+
+pub fn parse(g: &Graph, src: &str) -> Result<Query, ParseError> {
+    let tokens = lex(src)?;
+    Parser {
+        src,
+        g,
+        tokens,
+        i: 0,
+        vars: HashMap::new()
+    }.program()
+}
+
+
+
+/////////////////////
+// Lexer
+/////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Str(String),
+    Num(String),
+    Punct(char),
+    End
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    pos: usize
+}
+
+fn lex(src: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<(usize, char)> = src.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1].1 == '/' {
+            while i < chars.len() && chars[i].1 != '\n' {
+                i += 1;
+            }
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let mut s = String::new();
+            while i < chars.len() && (chars[i].1.is_alphanumeric() || chars[i].1 == '_' || chars[i].1 == '$') {
+                s.push(chars[i].1);
+                i += 1;
+            }
+            tokens.push(Token { tok: Tok::Ident(s), pos });
+        } else if c.is_ascii_digit() || (c == '-' && i + 1 < chars.len() && chars[i + 1].1.is_ascii_digit()) {
+            let mut s = c.to_string();
+            i += 1;
+            while i < chars.len() && (chars[i].1.is_ascii_digit() || chars[i].1 == '.' || chars[i].1 == 'e' || chars[i].1 == 'E'
+                || ((chars[i].1 == '-' || chars[i].1 == '+') && (s.ends_with('e') || s.ends_with('E')))) {
+                s.push(chars[i].1);
+                i += 1;
+            }
+            tokens.push(Token { tok: Tok::Num(s), pos });
+        } else if c == '"' || c == '\'' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                if i >= chars.len() {
+                    return Err(ParseError::new(src, pos, "unterminated string"))
+                }
+                let ch = chars[i].1;
+                if ch == c {
+                    i += 1;
+                    break
+                }
+                if ch == '\\' {
+                    if i + 1 >= chars.len() {
+                        return Err(ParseError::new(src, pos, "unterminated string"))
+                    }
+                    let e = chars[i + 1].1;
+                    i += 2;
+                    match e {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        'r' => s.push('\r'),
+                        'u' => {
+                            let hex: String = chars[i..(i + 4).min(chars.len())].iter().map(|x| x.1).collect();
+                            let code = u32::from_str_radix(&hex, 16).ok().and_then(std::char::from_u32);
+                            match code {
+                                Some(ch) => s.push(ch),
+                                None => return Err(ParseError::new(src, chars[i - 2].0, "invalid unicode escape"))
+                            }
+                            i += 4;
+                        },
+                        other => s.push(other)
+                    }
+                    continue
+                }
+                s.push(ch);
+                i += 1;
+            }
+            tokens.push(Token { tok: Tok::Str(s), pos });
+        } else if "().,[];=".contains(c) {
+            tokens.push(Token { tok: Tok::Punct(c), pos });
+            i += 1;
+        } else {
+            return Err(ParseError::new(src, pos, format!("unexpected character '{}'", c)))
+        }
+    }
+
+    tokens.push(Token { tok: Tok::End, pos: src.len() });
+    Ok(tokens)
+}
+
+
+
+/////////////////////
+// Parser
+/////////////////////
+
+// A value while evaluating the query, with the position it came from.
+#[derive(Clone)]
+enum Arg {
+    Graph,
+    Value(Value),
+    Null,
+    List(Vec<(Arg, usize)>),
+    Path(Path),
+    Filter(Rc<dyn ValueFilter>)
+}
+
+impl Arg {
+    fn describe(&self) -> &'static str {
+        match self {
+            Arg::Graph => "the graph",
+            Arg::Value(_) => "a value",
+            Arg::Null => "null",
+            Arg::List(_) => "a list",
+            Arg::Path(_) => "a path",
+            Arg::Filter(_) => "a filter"
+        }
+    }
+}
+
+// The evaluated arguments of a call, with the position of the call.
+struct Args {
+    pos: usize,
+    name: String,
+    list: Vec<(Arg, usize)>
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    g: &'a Graph,
+    tokens: Vec<Token>,
+    i: usize,
+    vars: HashMap<String, Arg>
+}
+
+impl<'a> Parser<'a> {
+    fn err<T, S: Into<String>>(&self, pos: usize, message: S) -> Result<T, ParseError> {
+        Err(ParseError::new(self.src, pos, message))
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.i]
+    }
+
+    fn bump(&mut self) -> Token {
+        let t = self.tokens[self.i].clone();
+        if t.tok != Tok::End {
+            self.i += 1;
+        }
+        t
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek().tok == Tok::Punct(c) {
+            self.i += 1;
+            return true
+        }
+        false
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat(c) {
+            return Ok(())
+        }
+        let pos = self.peek().pos;
+        self.err(pos, format!("expected '{}', found {}", c, describe_tok(&self.peek().tok)))
+    }
+
+    fn program(&mut self) -> Result<Query, ParseError> {
+        let mut query = None;
+
+        loop {
+            while self.eat(';') {}
+            if self.peek().tok == Tok::End {
+                break
+            }
+
+            if let Some(pos) = query.as_ref().map(|(_, pos): &(Query, usize)| *pos) {
+                return self.err(pos, "only the last statement can be a query")
+            }
+
+            let is_decl = matches!(&self.peek().tok, Tok::Ident(k) if k == "var" || k == "let" || k == "const");
+            if is_decl {
+                self.bump();
+                let t = self.bump();
+                let name = match t.tok {
+                    Tok::Ident(n) => n,
+                    other => return self.err(t.pos, format!("expected a variable name, found {}", describe_tok(&other)))
+                };
+                self.expect('=')?;
+                let (value, _, final_step) = self.expr()?;
+                if final_step.is_some() {
+                    return self.err(t.pos, "a variable can't hold a finished query")
+                }
+                self.vars.insert(name, value);
+                continue
+            }
+
+            let pos = self.peek().pos;
+            let (value, _, final_step) = self.expr()?;
+            let path = match value {
+                Arg::Path(p) => p,
+                other => return self.err(pos, format!("expected a query, found {}", other.describe()))
+            };
+            query = Some((Query { path, final_step }, pos));
+        }
+
+        match query {
+            Some((q, _)) => Ok(q),
+            None => self.err(self.src.len(), "expected a query")
+        }
+    }
+
+    // a primary followed by any number of .Step(args) calls
+    fn expr(&mut self) -> Result<(Arg, usize, Option<Final>), ParseError> {
+        let pos = self.peek().pos;
+        let mut value = self.primary()?;
+        let mut final_step = None;
+
+        while self.eat('.') {
+            let t = self.bump();
+            let name = match t.tok {
+                Tok::Ident(n) => n,
+                other => return self.err(t.pos, format!("expected a step name, found {}", describe_tok(&other)))
+            };
+            if final_step.is_some() {
+                return self.err(t.pos, format!("'{}' follows the end of the query", name))
+            }
+            let args = self.args(t.pos, name)?;
+            value = match value {
+                Arg::Graph => self.graph_call(args)?,
+                Arg::Path(p) => {
+                    if let Some(f) = self.final_call(&args)? {
+                        final_step = Some(f);
+                        Arg::Path(p)
+                    } else {
+                        Arg::Path(self.step(p, args)?)
+                    }
+                },
+                other => return self.err(t.pos, format!("{} has no step '{}'", other.describe(), args.name))
+            };
+        }
+
+        Ok((value, pos, final_step))
+    }
+
+    fn args(&mut self, pos: usize, name: String) -> Result<Args, ParseError> {
+        self.expect('(')?;
+        let mut list = Vec::new();
+        if !self.eat(')') {
+            loop {
+                let (value, p, final_step) = self.expr()?;
+                if final_step.is_some() {
+                    return self.err(p, "a finished query can't be used as an argument")
+                }
+                list.push((value, p));
+                if self.eat(')') {
+                    break
+                }
+                self.expect(',')?;
+            }
+        }
+        Ok(Args { pos, name, list })
+    }
+
+    fn primary(&mut self) -> Result<Arg, ParseError> {
+        let t = self.bump();
+        match t.tok {
+            Tok::Str(s) => Ok(Arg::Value(Value::from(s))),
+            Tok::Num(n) => {
+                if let Ok(i) = n.parse::<i64>() {
+                    return Ok(Arg::Value(Value::from(i)))
+                }
+                match n.parse::<f64>() {
+                    Ok(f) => Ok(Arg::Value(Value::from(f))),
+                    Err(_) => self.err(t.pos, format!("invalid number '{}'", n))
+                }
+            },
+            Tok::Punct('[') => {
+                let mut list = Vec::new();
+                if !self.eat(']') {
+                    loop {
+                        let (value, p, _) = self.expr()?;
+                        list.push((value, p));
+                        if self.eat(']') {
+                            break
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Arg::List(list))
+            },
+            Tok::Punct('(') => {
+                let (value, _, _) = self.expr()?;
+                self.expect(')')?;
+                Ok(value)
+            },
+            Tok::Ident(name) => {
+                match name.as_str() {
+                    "g" | "graph" => return Ok(Arg::Graph),
+                    "true" => return Ok(Arg::Value(Value::Bool(true))),
+                    "false" => return Ok(Arg::Value(Value::Bool(false))),
+                    "null" | "undefined" => return Ok(Arg::Null),
+                    _ => {}
+                }
+                if self.peek().tok == Tok::Punct('(') {
+                    let args = self.args(t.pos, name)?;
+                    return self.filter_call(args)
+                }
+                match self.vars.get(&name) {
+                    Some(v) => Ok(v.clone()),
+                    None => self.err(t.pos, format!("unknown variable '{}'", name))
+                }
+            },
+            other => self.err(t.pos, format!("expected a value, found {}", describe_tok(&other)))
+        }
+    }
+
+    fn graph_call(&mut self, args: Args) -> Result<Arg, ParseError> {
+        match args.name.to_lowercase().as_str() {
+            "v" | "vertex" => {
+                let values = self.values(&args, 0)?;
+                Ok(Arg::Path(self.g.v(values)))
+            },
+            "m" | "morphism" => {
+                self.arity(&args, 0, 0)?;
+                Ok(Arg::Path(self.g.m()))
+            },
+            _ => self.err(args.pos, format!("unknown graph call '{}'", args.name))
+        }
+    }
+
+    fn final_call(&self, args: &Args) -> Result<Option<Final>, ParseError> {
+        let f = match args.name.to_lowercase().as_str() {
+            "getlimit" => {
+                self.arity(args, 1, 1)?;
+                return Ok(Some(Final::GetLimit(self.int(args, 0)?)))
+            },
+            "all" => Final::All,
+            "toarray" => Final::ToArray,
+            "tovalue" => Final::ToValue,
+            "tagarray" => Final::TagArray,
+            "tagvalue" => Final::TagValue,
+            "count" => Final::Count,
+            _ => return Ok(None)
+        };
+        self.arity(args, 0, 0)?;
+        Ok(Some(f))
+    }
+
+    fn step(&self, mut p: Path, args: Args) -> Result<Path, ParseError> {
+        let name = args.name.to_lowercase();
+        let p = match name.as_str() {
+            "is" => {
+                let values = self.values(&args, 0)?;
+                p.is(values)
+            },
+            "in" | "out" | "both" => {
+                self.arity(&args, 0, 2)?;
+                let via = self.via(&args, 0)?;
+                let tags = self.tags(&args, 1)?;
+                match name.as_str() {
+                    "in" => p.r#in(via, tags),
+                    "out" => p.out(via, tags),
+                    _ => p.both(via, tags)
+                }
+            },
+            "follow" | "followr" => {
+                self.arity(&args, 1, 1)?;
+                let other = self.path(&args, 0)?;
+                if name == "follow" { p.follow(&other) } else { p.follow_r(&other) }
+            },
+            "followrecursive" => {
+                self.arity(&args, 1, 3)?;
+                let via = self.via(&args, 0)?;
+                match &via {
+                    Via::None => return self.err(args.list[0].1, "expected a predicate or a path"),
+                    Via::Values(v) if v.len() != 1 => return self.err(args.list[0].1, "expected one predicate or a path"),
+                    _ => {}
+                }
+                // Cayley takes (path, maxDepth, tags) but either may be left out
+                let mut max_depth = None;
+                let mut tags = gizmo::Tags::None;
+                for i in 1..args.list.len() {
+                    match &args.list[i].0 {
+                        Arg::Value(Value::Number(_)) => max_depth = Some(self.int(&args, i)? as i32),
+                        _ => tags = self.tags(&args, i)?
+                    }
+                }
+                p.follow_recursive(via, tags, max_depth)
+            },
+            "and" | "intersect" | "or" | "union" | "except" | "difference" => {
+                self.arity(&args, 1, 1)?;
+                let other = self.path(&args, 0)?;
+                match name.as_str() {
+                    "and" | "intersect" => p.intersect(&other),
+                    "or" | "union" => p.union(&other),
+                    _ => p.except(&other)
+                }
+            },
+            "back" => {
+                self.arity(&args, 1, 1)?;
+                let tag = self.string(&args, 0)?;
+                p.back(tag)
+            },
+            "tag" | "as" => {
+                let tags = self.tag_list(&args)?;
+                p.tag(tags)
+            },
+            "has" | "hasr" => {
+                self.arity(&args, 1, 2)?;
+                let via = self.via(&args, 0)?;
+                let object = self.has_object(&args, 1)?;
+                if name == "has" { p.has(via, object) } else { p.has_r(via, object) }
+            },
+            "save" | "saver" | "saveopt" | "saveoptr" => {
+                self.arity(&args, 2, 2)?;
+                let via = match &args.list[0].0 {
+                    Arg::Path(other) => gizmo::SaveVia::from(other),
+                    Arg::Value(v) if *v != Value::None => gizmo::SaveVia::Value(v.clone()),
+                    _ => return self.err(args.list[0].1, "expected a predicate or a path")
+                };
+                let tag = self.string(&args, 1)?;
+                match name.as_str() {
+                    "save" => p.save(via, tag),
+                    "saver" => p.save_r(via, tag),
+                    "saveopt" => p.save_opt(via, tag),
+                    _ => p.save_opt_r(via, tag)
+                }
+            },
+            "unique" => {
+                self.arity(&args, 0, 0)?;
+                p.unique()
+            },
+            "labels" => {
+                self.arity(&args, 0, 0)?;
+                p.labels()
+            },
+            "inpredicates" | "outpredicates" => {
+                self.arity(&args, 0, 0)?;
+                if name == "inpredicates" { p.in_predicates() } else { p.out_predicates() }
+            },
+            "saveinpredicates" | "saveoutpredicates" => {
+                self.arity(&args, 1, 1)?;
+                let tag = self.string(&args, 0)?;
+                if name == "saveinpredicates" { p.save_in_predicates(tag) } else { p.save_out_predicates(tag) }
+            },
+            "labelcontext" => {
+                self.arity(&args, 0, 2)?;
+                let via = self.via(&args, 0)?;
+                let tags = self.tags(&args, 1)?;
+                p.label_context(via, tags)
+            },
+            "filter" => {
+                self.arity(&args, 1, usize::MAX)?;
+                let mut filters = Vec::new();
+                for i in 0..args.list.len() {
+                    filters.extend(self.filters(&args, i)?);
+                }
+                p.filter(filters)
+            },
+            "limit" => {
+                self.arity(&args, 1, 1)?;
+                let n = self.int(&args, 0)?;
+                p.limit(n)
+            },
+            "skip" => {
+                self.arity(&args, 1, 1)?;
+                let n = self.int(&args, 0)?;
+                p.skip(n)
+            },
+            "order" => {
+                self.arity(&args, 0, 0)?;
+                p.order()
+            },
+            _ => return self.err(args.pos, format!("unknown step '{}'", args.name))
+        };
+        Ok(p)
+    }
+
+    fn filter_call(&self, args: Args) -> Result<Arg, ParseError> {
+        let f = match args.name.as_str() {
+            "lt" | "lte" | "gt" | "gte" => {
+                self.arity(&args, 1, 1)?;
+                let v = self.value(&args, 0)?;
+                match args.name.as_str() {
+                    "lt" => gizmo::lt(v),
+                    "lte" => gizmo::lte(v),
+                    "gt" => gizmo::gt(v),
+                    _ => gizmo::gte(v)
+                }
+            },
+            "regex" => {
+                self.arity(&args, 1, 2)?;
+                let pattern = self.string(&args, 0)?;
+                let iri = match args.list.get(1) {
+                    Some((Arg::Value(Value::Bool(b)), _)) => *b,
+                    Some((_, p)) => return self.err(*p, "expected true or false"),
+                    None => false
+                };
+                self.regex(args.list[0].1, pattern, iri)?
+            },
+            "like" => {
+                self.arity(&args, 1, 1)?;
+                gizmo::like(self.string(&args, 0)?)
+            },
+            _ => return self.err(args.pos, format!("unknown function '{}'", args.name))
+        };
+        Ok(Arg::Filter(f))
+    }
+
+    #[cfg(feature = "regex")]
+    fn regex(&self, pos: usize, pattern: String, iri: bool) -> Result<Rc<dyn ValueFilter>, ParseError> {
+        if let Err(e) = regex::Regex::new(&pattern) {
+            return self.err(pos, format!("invalid regular expression: {}", e))
+        }
+        Ok(gizmo::regex(pattern, iri))
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[allow(unused)]
+    fn regex(&self, pos: usize, pattern: String, iri: bool) -> Result<Rc<dyn ValueFilter>, ParseError> {
+        self.err(pos, "regular expressions are not supported in this build")
+    }
+
+
+    /////////////////////
+    // Argument helpers
+    /////////////////////
+
+    fn arity(&self, args: &Args, min: usize, max: usize) -> Result<(), ParseError> {
+        let n = args.list.len();
+        if n < min {
+            return self.err(args.pos, format!("'{}' expects at least {} argument(s), got {}", args.name, min, n))
+        }
+        if n > max {
+            return self.err(args.list[max].1, format!("'{}' expects at most {} argument(s), got {}", args.name, max, n))
+        }
+        Ok(())
+    }
+
+    fn value(&self, args: &Args, i: usize) -> Result<Value, ParseError> {
+        match &args.list[i] {
+            (Arg::Value(v), _) => Ok(v.clone()),
+            (other, pos) => self.err(*pos, format!("expected a value, found {}", other.describe()))
+        }
+    }
+
+    fn string(&self, args: &Args, i: usize) -> Result<String, ParseError> {
+        match &args.list[i] {
+            (Arg::Value(Value::String(s)), _) => Ok(s.clone()),
+            (Arg::Value(Value::IRI(s)), _) => Ok(format!("<{}>", s)),
+            (other, pos) => self.err(*pos, format!("expected a string, found {}", other.describe()))
+        }
+    }
+
+    fn int(&self, args: &Args, i: usize) -> Result<i64, ParseError> {
+        if let (Arg::Value(Value::Number(n)), _) = &args.list[i] {
+            if let Some(n) = n.as_i64() {
+                return Ok(n)
+            }
+        }
+        self.err(args.list[i].1, "expected an integer")
+    }
+
+    fn path(&self, args: &Args, i: usize) -> Result<Path, ParseError> {
+        match &args.list[i] {
+            (Arg::Path(p), _) => Ok(p.clone()),
+            (other, pos) => self.err(*pos, format!("expected a path, found {}", other.describe()))
+        }
+    }
+
+    // a value or a list of values
+    fn value_list(&self, arg: &Arg, pos: usize) -> Result<Vec<Value>, ParseError> {
+        match arg {
+            Arg::Value(v) => Ok(vec![v.clone()]),
+            Arg::Null => Ok(Vec::new()),
+            Arg::List(l) => {
+                let mut out = Vec::new();
+                for (a, p) in l {
+                    match a {
+                        Arg::Value(v) => out.push(v.clone()),
+                        other => return self.err(*p, format!("expected a value, found {}", other.describe()))
+                    }
+                }
+                Ok(out)
+            },
+            other => self.err(pos, format!("expected a value, found {}", other.describe()))
+        }
+    }
+
+    // every argument from i on is a value or a list of values
+    fn values(&self, args: &Args, from: usize) -> Result<Vec<Value>, ParseError> {
+        let mut out = Vec::new();
+        for (arg, pos) in args.list.iter().skip(from) {
+            out.extend(self.value_list(arg, *pos)?);
+        }
+        Ok(out)
+    }
+
+    fn via(&self, args: &Args, i: usize) -> Result<Via, ParseError> {
+        match args.list.get(i) {
+            None => Ok(Via::None),
+            Some((Arg::Path(p), _)) => Ok(Via::from(p)),
+            Some((arg, pos)) => Ok(Via::from(self.value_list(arg, *pos)?))
+        }
+    }
+
+    fn strings(&self, arg: &Arg, pos: usize) -> Result<Vec<String>, ParseError> {
+        match arg {
+            Arg::Value(Value::String(s)) => Ok(vec![s.clone()]),
+            Arg::Value(Value::IRI(s)) => Ok(vec![format!("<{}>", s)]),
+            Arg::List(l) => {
+                let mut out = Vec::new();
+                for (a, p) in l {
+                    out.extend(self.strings(a, *p)?);
+                }
+                Ok(out)
+            },
+            other => self.err(pos, format!("expected a tag name, found {}", other.describe()))
+        }
+    }
+
+    fn tags(&self, args: &Args, i: usize) -> Result<gizmo::Tags, ParseError> {
+        match args.list.get(i) {
+            None | Some((Arg::Null, _)) => Ok(gizmo::Tags::None),
+            Some((arg, pos)) => Ok(gizmo::Tags::Some(self.strings(arg, *pos)?))
+        }
+    }
+
+    fn tag_list(&self, args: &Args) -> Result<gizmo::Tags, ParseError> {
+        let mut out = Vec::new();
+        for (arg, pos) in &args.list {
+            out.extend(self.strings(arg, *pos)?);
+        }
+        if out.is_empty() {
+            return self.err(args.pos, format!("'{}' expects at least one tag", args.name))
+        }
+        Ok(gizmo::Tags::Some(out))
+    }
+
+    fn filters(&self, args: &Args, i: usize) -> Result<Vec<Rc<dyn ValueFilter>>, ParseError> {
+        match &args.list[i] {
+            (Arg::Filter(f), _) => Ok(vec![f.clone()]),
+            (Arg::List(l), _) => {
+                let mut out = Vec::new();
+                for (a, p) in l {
+                    match a {
+                        Arg::Filter(f) => out.push(f.clone()),
+                        other => return self.err(*p, format!("expected a filter, found {}", other.describe()))
+                    }
+                }
+                Ok(out)
+            },
+            (other, pos) => self.err(*pos, format!("expected a filter, found {}", other.describe()))
+        }
+    }
+
+    fn has_object(&self, args: &Args, i: usize) -> Result<gizmo::HasObject, ParseError> {
+        match args.list.get(i) {
+            None | Some((Arg::Null, _)) => Ok(gizmo::HasObject::Values(gizmo::Values::None)),
+            Some((Arg::Filter(_), _)) => Ok(gizmo::HasObject::from(self.filters(args, i)?)),
+            Some((Arg::List(l), _)) if l.iter().any(|(a, _)| matches!(a, Arg::Filter(_))) => Ok(gizmo::HasObject::from(self.filters(args, i)?)),
+            Some((arg, pos)) => Ok(gizmo::HasObject::from(self.value_list(arg, *pos)?))
+        }
+    }
+}
+
+
+fn describe_tok(t: &Tok) -> String {
+    match t {
+        Tok::Ident(s) => format!("'{}'", s),
+        Tok::Str(s) => format!("\"{}\"", s),
+        Tok::Num(n) => n.clone(),
+        Tok::Punct(c) => format!("'{}'", c),
+        Tok::End => "end of input".to_string()
+    }
+}
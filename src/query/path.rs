@@ -1,11 +1,16 @@
 use crate::graph::value::Value;
 use crate::graph::iterator;
+use crate::graph::iterator::weighted_path::{Weight, Heuristic};
+use crate::graph::iterator::recursive::{Emit, Order};
+use crate::graph::iterator::value_filter::Operator;
+use crate::graph::iterator::sort::SortKey;
 use crate::graph::quad::{Direction, QuadStore};
 //use crate::graph::iterator::Shape;
-use crate::query::shape::{Shape, AllNodes, Lookup, IteratorShape, build_iterator, ValueFilter};
+use crate::query::shape::{Shape, AllNodes, Lookup, Fixed, Save, IteratorShape, build_iterator, ValueFilter};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use super::morphism;
+use super::parallel;
 
 #[cfg(feature = "standalone")]
 use crate::query::gizmo;
@@ -14,7 +19,47 @@ use crate::query::gizmo;
 
 #[derive(Clone)]
 pub struct PathContext {
-    pub label_set: Option<Rc<RefCell<dyn Shape>>>
+    pub label_set: Option<Rc<RefCell<dyn Shape>>>,
+    // numbers the links followed when the path tracks its hops
+    pub hops: Option<Rc<Cell<usize>>>
+}
+
+impl PathContext {
+    // the number of the next link followed, None if hops are not tracked
+    pub fn next_hop(&self) -> Option<usize> {
+        self.hops.as_ref().map(|h| {
+            let n = h.get();
+            h.set(n + 1);
+            n
+        })
+    }
+}
+
+
+// The tag of the quad of a tracked hop: "hop:<i>:in" or "hop:<i>:out", where
+// hops are numbered in the order they are followed.
+pub const HOP_TAG: &str = "hop";
+
+pub fn hop_tag(i: usize, r#in: bool) -> String {
+    format!("{}:{}:{}", HOP_TAG, i, if r#in { "in" } else { "out" })
+}
+
+// the number of a hop and whether it was followed in, from its tag
+pub fn parse_hop_tag(tag: &str) -> Option<(usize, bool)> {
+    let mut parts = tag.split(':');
+    if parts.next()? != HOP_TAG {
+        return None
+    }
+    let i = parts.next()?.parse().ok()?;
+    let r#in = match parts.next()? {
+        "in" => true,
+        "out" => false,
+        _ => return None
+    };
+    if parts.next().is_some() {
+        return None
+    }
+    Some((i, r#in))
 }
 
 
@@ -39,7 +84,7 @@ impl Path {
         Path {
             stack,
             qs,
-            base_context: PathContext{ label_set: None }
+            base_context: PathContext{ label_set: None, hops: None }
         }   
     }
 
@@ -73,6 +118,22 @@ impl Path {
         self .stack.push(morphism::BothMorphism::new(Some(tags), via));
     }
 
+    pub fn out_e(&mut self, tags: Vec<String>, via: Via) {
+        self.stack.push(morphism::EdgesMorphism::new(Some(tags), via, vec![Direction::Subject]));
+    }
+
+    pub fn in_e(&mut self, tags: Vec<String>, via: Via) {
+        self.stack.push(morphism::EdgesMorphism::new(Some(tags), via, vec![Direction::Object]));
+    }
+
+    pub fn both_e(&mut self, tags: Vec<String>, via: Via) {
+        self.stack.push(morphism::EdgesMorphism::new(Some(tags), via, vec![Direction::Subject, Direction::Object]));
+    }
+
+    pub fn edge_nodes(&mut self, dir: Direction) {
+        self.stack.push(morphism::EdgeNodesMorphism::new(Via::None, vec![dir]));
+    }
+
     pub fn follow(&mut self, path: Path) {
         self.stack.push(morphism::FollowMorphism::new(path));
     }
@@ -81,7 +142,7 @@ impl Path {
         self.stack.push(morphism::FollowMorphism::new(path.reverse()));
     }
 
-    pub fn follow_recursive(&mut self, via: Via, max_depth: i32, tags: Vec<String>) {
+    pub fn follow_recursive(&mut self, via: Via, recursion: Recursion, tags: Vec<String>) {
         let path = match via {
             Via::Values(_) => {
                 let mut path = Path::start_morphism(Vec::new());
@@ -91,7 +152,24 @@ impl Path {
             Via::Path(p) => p,
             Via::None => panic!("did not pass a predicate or a Path to FollowRecursive"),
         };
-        self.stack.push(morphism::FollowRecursiveMorphism::new(path, max_depth, tags));
+        self.stack.push(morphism::FollowRecursiveMorphism::new(path, recursion, tags));
+    }
+
+    // the nodes at most k links away in either direction, each once, at its
+    // distance saved in tags
+    // the nodes at most k links away, for k below 1 the nodes themselves at 0
+    pub fn neighborhood(&mut self, k: i32, via: Via, tags: Vec<String>) {
+        let mut path = Path::start_morphism(Vec::new());
+        path.both(if k < 1 { Via::Values(Vec::new()) } else { via });
+        self.follow_recursive(Via::Path(path), Recursion::new().with_max_depth(k.max(1)).with_min_depth(0), tags);
+    }
+
+    pub fn shortest_path_to(&mut self, target: Path, via: Via, max_depth: i32) {
+        self.stack.push(morphism::ShortestPathMorphism::new(target, via, max_depth, false));
+    }
+
+    pub fn weighted_path_to(&mut self, target: Path, via: Via, weight: Weight, heuristic: Option<Heuristic>) {
+        self.stack.push(morphism::WeightedPathMorphism::new(target, via, weight, heuristic, false));
     }
 
     pub fn and(&mut self, path: Path) {
@@ -161,12 +239,25 @@ impl Path {
     }
 
     pub fn order(&mut self) {
-        self.stack.push(morphism::OrderMorphism::new());
+        self.stack.push(morphism::OrderMorphism::new(Vec::new()));
     }
 
-    // pub fn count(&mut self) {
-    //     self.stack.push(morphism::CountMorphism::new());
-    // }
+    pub fn order_by(&mut self, keys: Vec<SortKey>) {
+        self.stack.push(morphism::OrderMorphism::new(keys));
+    }
+
+    // tags every node with the number of results of path from it
+    pub fn count_as(&mut self, path: Path, tag: String) {
+        self.stack.push(morphism::CountMorphism::new(path, Some(tag), None));
+    }
+
+    // keeps the nodes whose number of links through via, with the node in
+    // one of dirs, compares to n as op says
+    pub fn degree(&mut self, via: Via, dirs: Vec<Direction>, op: Operator, n: i64) {
+        let mut path = Path::start_morphism(Vec::new());
+        path.stack.push(morphism::EdgesMorphism::new(None, via, dirs));
+        self.stack.push(morphism::CountMorphism::new(path, None, Some((op, n))));
+    }
 
     pub fn save(&mut self, via: Via, tag: String, rev: bool, opt: bool) {
         self.stack.push(morphism::SaveMorphism::new(via, tag, rev, opt));
@@ -204,6 +295,14 @@ impl Path {
     }
 
 
+    // The same path, with the quad of every link followed by an in, out or
+    // both, also inside a follow, saved under its hop_tag.
+    pub fn track_hops(&self) -> Path {
+        let mut p = self.clone();
+        p.base_context.hops = Some(Rc::new(Cell::new(0)));
+        p
+    }
+
     pub fn shape(&self) -> Rc<RefCell<dyn Shape>> {
         return self.shape_from(Rc::new(RefCell::new(AllNodes())))
     }
@@ -227,6 +326,12 @@ impl Path {
         build_iterator(qs, s)
     }
 
+    // Like build_iterator_on, but runs the branches of a union or parts of
+    // a large seed set on `workers` threads, one per core for 0.
+    pub fn build_parallel_iterator_on(&self, qs: Rc<RefCell<dyn QuadStore>>, workers: usize) -> Rc<RefCell<dyn iterator::Shape>> {
+        parallel::build_iterator(qs, self.shape(), workers)
+    }
+
     // pub fn morphism_for(&self, qs: Rc<RefCell<dyn QuadStore>>) -> Rc<dyn iterator::Morphism> {
     //     self.shape_from(Rc::new(RefCell::new(IteratorShape {
     //         it: shape,
@@ -273,15 +378,92 @@ impl MorphismForPath {
     }
 }
 
+// Fixed nodes, as recursion and counting pass, maybe saved under tags, as
+// a shape that is built anew for every branch that uses it, as both does.
+fn fixed_shape(shape: &Rc<RefCell<dyn iterator::Shape>>) -> Option<Rc<RefCell<dyn Shape>>> {
+    match shape.borrow_mut().shape_type() {
+        iterator::ShapeType::Fixed(f) => Some(Rc::new(RefCell::new(Fixed(f.values.borrow().clone())))),
+        iterator::ShapeType::Save(s) => {
+            let (from, tags) = s.saved();
+            if tags.len() != s.tag_names().len() {
+                return None
+            }
+            Some(Save::new(tags, Some(fixed_shape(&from)?)))
+        },
+        _ => None
+    }
+}
+
 impl iterator::Morphism for MorphismForPath {
     fn morph(&self, shape: Rc<RefCell<dyn iterator::Shape>>) -> Rc<RefCell<dyn iterator::Shape>> {
-        return self.path.clone().shape_from(Rc::new(RefCell::new(IteratorShape{it: Some(shape)}))).borrow_mut().build_iterator(self.qs.clone())
+        let from = fixed_shape(&shape).unwrap_or_else(|| Rc::new(RefCell::new(IteratorShape{it: Some(shape.clone())})));
+        return self.path.clone().shape_from(from).borrow_mut().build_iterator(self.qs.clone())
     }
 }
 
 
 
 
+// How a recursive follow repeats its path, much like gremlin's
+// repeat().times().until().emit(). By default it goes breadth first, as deep
+// as the default depth, and returns every node it reaches.
+#[derive(Clone)]
+pub struct Recursion {
+    pub max_depth: i32,
+    pub min_depth: i32,
+    pub emit: Emit,
+    pub order: Order,
+    pub until: Via
+}
+
+impl Recursion {
+    pub fn new() -> Recursion {
+        Recursion {
+            max_depth: 0,
+            min_depth: 1,
+            emit: Emit::All,
+            order: Order::BreadthFirst,
+            until: Via::None
+        }
+    }
+
+    // 0 for the default depth, below 0 for no limit
+    pub fn with_max_depth(mut self, max_depth: i32) -> Recursion {
+        self.max_depth = max_depth;
+        self
+    }
+
+    // nodes reached in fewer steps are followed but not returned, with 0 the
+    // start nodes are returned too
+    pub fn with_min_depth(mut self, min_depth: i32) -> Recursion {
+        self.min_depth = min_depth;
+        self
+    }
+
+    pub fn with_emit(mut self, emit: Emit) -> Recursion {
+        self.emit = emit;
+        self
+    }
+
+    pub fn with_order(mut self, order: Order) -> Recursion {
+        self.order = order;
+        self
+    }
+
+    // the nodes, or the nodes of a path, not to go on from
+    pub fn with_until<V: Into<Via>>(mut self, until: V) -> Recursion {
+        self.until = until.into();
+        self
+    }
+}
+
+impl Default for Recursion {
+    fn default() -> Self {
+        Recursion::new()
+    }
+}
+
+
 #[derive(Clone)]
 pub enum Via {
     None,
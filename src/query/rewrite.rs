@@ -0,0 +1,565 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use super::shape::{Shape, ShapeType, Optimizer, ResolveValues, QuadFilter, Null, AllNodes, Intersect, Union, Unique, Sort, Save, Filter, TrieJoin};
+use super::bgp::Term;
+use super::super::graph::quad::{QuadStore, Direction};
+use super::super::graph::value::Value;
+
+
+// Rewrite rules over query shapes. Every rule is an Optimizer that looks at
+// one node, whose sub-shapes are already optimized, and either rewrites it in
+// place, replaces it, or leaves it alone. Rules run one after another on every
+// node, a replaced node is optimized again until no rule applies.
+pub struct Rules {
+    qs: Option<Rc<RefCell<dyn QuadStore>>>,
+    rules: Vec<Box<dyn Optimizer>>
+}
+
+impl Rules {
+    // the rules applied before building iterators
+    pub fn new(qs: Rc<RefCell<dyn QuadStore>>) -> Rules {
+        Rules {
+            qs: Some(qs.clone()),
+            rules: vec![
+                Box::new(ResolveValues { qs: qs.clone() }),
+                Box::new(FoldEmpty),
+                Box::new(CommonSubexpressions),
+                Box::new(TrieJoins { qs: qs.clone() }),
+                Box::new(OrderBySelectivity { qs }),
+                Box::new(PushDownFilters),
+                Box::new(TopK)
+            ]
+        }
+    }
+
+    pub fn with(qs: Option<Rc<RefCell<dyn QuadStore>>>, rules: Vec<Box<dyn Optimizer>>) -> Rules {
+        Rules {
+            qs,
+            rules
+        }
+    }
+}
+
+impl Optimizer for Rules {
+    fn optimize_shape(&self, shape: &mut dyn Shape) -> Option<Rc<RefCell<dyn Shape>>> {
+        for r in &self.rules {
+            if let Some(n) = r.optimize_shape(shape) {
+                let again = n.borrow_mut().optimize(Some(self));
+                return Some(again.unwrap_or(n))
+            }
+        }
+        None
+    }
+
+    fn quad_store(&self) -> Option<Rc<RefCell<dyn QuadStore>>> {
+        self.qs.clone()
+    }
+}
+
+
+fn is_null(s: &Rc<RefCell<dyn Shape>>) -> bool {
+    matches!(s.borrow_mut().shape_type(), ShapeType::Null)
+}
+
+fn is_all(s: &Rc<RefCell<dyn Shape>>) -> bool {
+    matches!(s.borrow_mut().shape_type(), ShapeType::AllNodes)
+}
+
+fn null() -> Option<Rc<RefCell<dyn Shape>>> {
+    Some(Null::new())
+}
+
+
+// Constant folding: an empty set makes an intersection, a traversal or a
+// wrapper around it empty, is dropped from unions and optional parts, and
+// removes nothing from an Except.
+pub struct FoldEmpty;
+
+impl Optimizer for FoldEmpty {
+    fn optimize_shape(&self, shape: &mut dyn Shape) -> Option<Rc<RefCell<dyn Shape>>> {
+        match shape.shape_type() {
+            ShapeType::Fixed(f) if f.0.is_empty() => null(),
+            ShapeType::Lookup(l) if l.0.is_empty() => null(),
+            ShapeType::Intersect(i) => {
+                if i.0.is_empty() || i.0.iter().any(is_null) {
+                    return null()
+                }
+                // every node is in AllNodes, it only matters alone
+                if i.0.len() > 1 && i.0.iter().any(is_all) {
+                    let rest: Vec<_> = i.0.iter().filter(|s| !is_all(s)).cloned().collect();
+                    i.0 = if rest.is_empty() { vec![AllNodes::new() as Rc<RefCell<dyn Shape>>] } else { rest };
+                }
+                if i.0.len() == 1 {
+                    return Some(i.0[0].clone())
+                }
+                None
+            },
+            ShapeType::IntersectOpt(io) => {
+                if io.sub.0.iter().any(is_null) {
+                    return null()
+                }
+                let before = io.opt.len();
+                io.opt.retain(|s| !is_null(s));
+                if io.opt.is_empty() && before > 0 && !io.sub.0.is_empty() {
+                    return Some(Intersect::new(io.sub.0.clone()))
+                }
+                None
+            },
+            ShapeType::Union(u) => {
+                let before = u.0.len();
+                u.0.retain(|s| !is_null(s));
+                match u.0.len() {
+                    0 => null(),
+                    1 if before > 1 => Some(u.0[0].clone()),
+                    _ => None
+                }
+            },
+            ShapeType::NodesFrom(n) if is_null(&n.quads) => null(),
+            ShapeType::Quads(q) => {
+                if q.0.is_empty() || q.0.iter().any(|f| f.values.as_ref().is_none_or(is_null)) {
+                    return null()
+                }
+                None
+            },
+            ShapeType::Save(s) if s.from.as_ref().is_none_or(is_null) => null(),
+            ShapeType::Unique(u) if is_null(&u.from) => null(),
+            ShapeType::Sort(s) if is_null(&s.from) => null(),
+            ShapeType::Page(p) if is_null(&p.from) => null(),
+            ShapeType::Filter(f) if is_null(&f.from) => null(),
+            ShapeType::Recursive(r) if is_null(&r.r#in) => null(),
+            ShapeType::CountPer(c) if is_null(&c.from) => null(),
+            ShapeType::ShortestPath(p) if is_null(&p.from) || is_null(&p.to) => null(),
+            ShapeType::WeightedPath(p) if is_null(&p.from) || is_null(&p.to) => null(),
+            ShapeType::Except(e) => {
+                if e.from.as_ref().is_some_and(is_null) {
+                    return null()
+                }
+                if e.exclude.as_ref().is_some_and(is_null) {
+                    return Some(e.from.clone().unwrap_or_else(|| AllNodes::new() as Rc<RefCell<dyn Shape>>))
+                }
+                None
+            },
+            _ => None
+        }
+    }
+
+    fn quad_store(&self) -> Option<Rc<RefCell<dyn QuadStore>>> {
+        None
+    }
+}
+
+
+// A structural description of a shape, equal for shapes that return the
+// same results. Shapes that save tags, filter values or wrap iterators have
+// none, so they are never merged.
+pub fn key(s: &Rc<RefCell<dyn Shape>>) -> Option<String> {
+    let list = |v: &Vec<Rc<RefCell<dyn Shape>>>| -> Option<String> {
+        let keys: Option<Vec<String>> = v.iter().map(key).collect();
+        Some(keys?.join(","))
+    };
+    match s.borrow_mut().shape_type() {
+        ShapeType::Null => Some("null".to_string()),
+        ShapeType::AllNodes => Some("all".to_string()),
+        ShapeType::Fixed(f) => Some(format!("fixed({:?})", f.0.iter().map(|r| r.key()).collect::<Vec<_>>())),
+        ShapeType::Lookup(l) => Some(format!("lookup({:?})", l.0)),
+        ShapeType::NodesFrom(n) => Some(format!("from({:?},{})", n.dir, key(&n.quads)?)),
+        ShapeType::Quads(q) => {
+            let filters: Option<Vec<String>> = q.0.iter().map(filter_key).collect();
+            Some(format!("quads({})", filters?.join(",")))
+        },
+        ShapeType::Intersect(i) => Some(format!("and({})", list(&i.0)?)),
+        ShapeType::Union(u) => Some(format!("or({})", list(&u.0)?)),
+        ShapeType::Unique(u) => Some(format!("unique({})", key(&u.from)?)),
+        _ => None
+    }
+}
+
+fn filter_key(f: &QuadFilter) -> Option<String> {
+    Some(format!("{:?}={}", f.dir, key(f.values.as_ref()?)?))
+}
+
+
+// Whether every result of the shape comes with a single path. Intersecting
+// with such a shape again changes nothing, while intersecting with a shape
+// that has several paths to a node returns the node once for each of them.
+fn is_set(s: &Rc<RefCell<dyn Shape>>) -> bool {
+    match s.borrow_mut().shape_type() {
+        ShapeType::Null | ShapeType::AllNodes | ShapeType::Fixed(_) | ShapeType::Lookup(_) | ShapeType::Unique(_) => true,
+        ShapeType::Intersect(i) => i.0.iter().all(is_set),
+        _ => false
+    }
+}
+
+// Drops the sub-shapes of an intersection that repeat an earlier one,
+// all of them or only those that are sets.
+fn dedup(shapes: &mut Vec<Rc<RefCell<dyn Shape>>>, all: bool) {
+    let mut seen = HashSet::new();
+    shapes.retain(|s| !(all || is_set(s)) || key(s).is_none_or(|k| seen.insert(k)));
+}
+
+// Common subexpressions: a set intersected with itself is the set, so an
+// intersection computes every distinct sub-shape that is a set once, and a
+// quad pattern checks every distinct direction filter on a set once. Under
+// a unique every repeated sub-shape goes, as paths no longer count. This is
+// common after follow and back, which repeat the path they came from.
+pub struct CommonSubexpressions;
+
+impl Optimizer for CommonSubexpressions {
+    fn optimize_shape(&self, shape: &mut dyn Shape) -> Option<Rc<RefCell<dyn Shape>>> {
+        match shape.shape_type() {
+            ShapeType::Intersect(i) => {
+                dedup(&mut i.0, false);
+                if i.0.len() == 1 {
+                    return Some(i.0[0].clone())
+                }
+                None
+            },
+            ShapeType::Unique(u) => {
+                let single = match u.from.borrow_mut().shape_type() {
+                    ShapeType::Intersect(i) => {
+                        dedup(&mut i.0, true);
+                        if i.0.len() == 1 { Some(i.0[0].clone()) } else { None }
+                    },
+                    _ => None
+                };
+                if let Some(s) = single {
+                    u.from = s;
+                }
+                None
+            },
+            ShapeType::Quads(q) => {
+                let mut seen = HashSet::new();
+                q.0.retain(|f| !f.values.as_ref().is_some_and(is_set) || filter_key(f).is_none_or(|k| seen.insert(k)));
+                None
+            },
+            _ => None
+        }
+    }
+
+    fn quad_store(&self) -> Option<Rc<RefCell<dyn QuadStore>>> {
+        None
+    }
+}
+
+
+// the most links a traversal from one node is assumed to have
+const FANOUT: i64 = 20;
+
+// An estimate of the number of results of a shape, from the store statistics.
+pub fn estimate(qs: &dyn QuadStore, s: &Rc<RefCell<dyn Shape>>) -> i64 {
+    let quads = qs.stats(false).map(|s| s.quads.value).unwrap_or(i64::MAX);
+    match s.borrow_mut().shape_type() {
+        ShapeType::Null => 0,
+        ShapeType::Fixed(f) => f.0.len() as i64,
+        ShapeType::Lookup(l) => l.0.len() as i64,
+        ShapeType::AllNodes => qs.stats(false).map(|s| s.nodes.value).unwrap_or(quads),
+        ShapeType::NodesFrom(n) => estimate(qs, &n.quads),
+        ShapeType::Quads(q) => q.0.iter().map(|f| filter_estimate(qs, f, quads)).min().unwrap_or(0),
+        ShapeType::Intersect(i) => i.0.iter().map(|c| estimate(qs, c)).min().unwrap_or(0),
+        ShapeType::IntersectOpt(io) => io.sub.0.iter().map(|c| estimate(qs, c)).min().unwrap_or(0),
+        ShapeType::Union(u) => u.0.iter().map(|c| estimate(qs, c)).fold(0i64, |a, b| a.saturating_add(b)),
+        ShapeType::Save(s) => s.from.as_ref().map(|f| estimate(qs, f)).unwrap_or(0),
+        ShapeType::Unique(u) => estimate(qs, &u.from),
+        ShapeType::Sort(s) => estimate(qs, &s.from),
+        ShapeType::Filter(f) => estimate(qs, &f.from),
+        ShapeType::CountPer(c) => estimate(qs, &c.from),
+        ShapeType::ShortestPath(p) => estimate(qs, &p.to),
+        ShapeType::WeightedPath(p) => estimate(qs, &p.to),
+        ShapeType::Page(p) => {
+            let e = estimate(qs, &p.from);
+            if p.limit > 0 { e.min(p.limit) } else { e }
+        },
+        ShapeType::Except(e) => match &e.from {
+            Some(f) => estimate(qs, f),
+            None => qs.stats(false).map(|s| s.nodes.value).unwrap_or(quads)
+        },
+        _ => quads
+    }
+}
+
+// the quads matching a direction filter, exact for fixed nodes
+fn filter_estimate(qs: &dyn QuadStore, f: &QuadFilter, quads: i64) -> i64 {
+    let values = match &f.values {
+        Some(v) => v,
+        None => return 0
+    };
+    if let ShapeType::Fixed(fixed) = values.borrow_mut().shape_type() {
+        return fixed.0.iter().map(|r| qs.quad_iterator_size(&f.dir, r).map(|s| s.value).unwrap_or(quads)).sum()
+    }
+    estimate(qs, values).saturating_mul(FANOUT).min(quads / 2 + 1)
+}
+
+
+// Orders intersections and quad patterns so the one with the fewest results
+// comes first, it drives the iteration and the others are only checked.
+pub struct OrderBySelectivity {
+    pub qs: Rc<RefCell<dyn QuadStore>>
+}
+
+impl Optimizer for OrderBySelectivity {
+    fn optimize_shape(&self, shape: &mut dyn Shape) -> Option<Rc<RefCell<dyn Shape>>> {
+        let qs = self.qs.borrow();
+        match shape.shape_type() {
+            ShapeType::Intersect(i) => {
+                i.0.sort_by_cached_key(|s| estimate(&*qs, s));
+            },
+            ShapeType::IntersectOpt(io) => {
+                io.sub.0.sort_by_cached_key(|s| estimate(&*qs, s));
+            },
+            ShapeType::Quads(q) => {
+                let quads = qs.stats(false).map(|s| s.quads.value).unwrap_or(i64::MAX);
+                q.0.sort_by_cached_key(|f| filter_estimate(&*qs, f, quads));
+            },
+            _ => {}
+        }
+        None
+    }
+
+    fn quad_store(&self) -> Option<Rc<RefCell<dyn QuadStore>>> {
+        Some(self.qs.clone())
+    }
+}
+
+
+// The quad patterns of an intersection, with a variable for every node the
+// patterns meet at. The first variable is the node of the intersection.
+struct Patterns {
+    qs: Rc<RefCell<dyn QuadStore>>,
+    tags: Vec<Vec<String>>,
+    patterns: Vec<Vec<(Direction, Term)>>,
+    // the variables of every pattern
+    links: Vec<Vec<usize>>,
+    values: Vec<(String, Vec<Value>)>
+}
+
+fn var_name(v: usize) -> String {
+    format!("_{}", v)
+}
+
+impl Patterns {
+    fn var(&mut self) -> usize {
+        self.tags.push(Vec::new());
+        self.tags.len() - 1
+    }
+
+    // the value of a shape that is a single node
+    fn single(&self, s: &Rc<RefCell<dyn Shape>>) -> Option<Value> {
+        match s.borrow_mut().shape_type() {
+            ShapeType::Fixed(f) if f.0.len() == 1 => self.qs.borrow().name_of(&f.0[0]),
+            ShapeType::Lookup(l) if l.0.len() == 1 => Some(l.0[0].clone()),
+            _ => None
+        }
+    }
+
+    // Restricts the variable to the nodes of the shape, false if the shape
+    // is more than quad patterns, sets of nodes and tags.
+    fn nodes(&mut self, s: &Rc<RefCell<dyn Shape>>, var: usize) -> bool {
+        match s.borrow_mut().shape_type() {
+            ShapeType::AllNodes => true,
+            ShapeType::Fixed(f) => {
+                let values: Option<Vec<Value>> = f.0.iter().map(|r| self.qs.borrow().name_of(r)).collect();
+                match values {
+                    Some(v) => {
+                        self.values.push((var_name(var), v));
+                        true
+                    },
+                    None => false
+                }
+            },
+            ShapeType::Lookup(l) => {
+                self.values.push((var_name(var), l.0.clone()));
+                true
+            },
+            ShapeType::Save(sv) => match &sv.from {
+                Some(f) => {
+                    self.tags[var].extend(sv.tags.iter().cloned());
+                    self.nodes(f, var)
+                },
+                None => false
+            },
+            ShapeType::Intersect(i) => i.0.iter().all(|c| self.nodes(c, var)),
+            ShapeType::NodesFrom(n) => {
+                let mut pattern = vec![(n.dir.clone(), Term::Var(var_name(var)))];
+                let mut links = vec![var];
+                match n.quads.borrow_mut().shape_type() {
+                    ShapeType::Quads(q) => {
+                        for f in &q.0 {
+                            let values = match &f.values {
+                                Some(v) => v,
+                                None => return false
+                            };
+                            if let Some(v) = self.single(values) {
+                                pattern.push((f.dir.clone(), Term::Value(v)));
+                                continue;
+                            }
+                            let w = self.var();
+                            pattern.push((f.dir.clone(), Term::Var(var_name(w))));
+                            links.push(w);
+                            if !self.nodes(values, w) {
+                                return false
+                            }
+                        }
+                    },
+                    _ => return false
+                }
+                self.patterns.push(pattern);
+                self.links.push(links);
+                true
+            },
+            _ => false
+        }
+    }
+
+    // the order a triejoin binds the variables in: the node of the intersection,
+    // then always the variable linked to most of the ones already bound
+    fn order(&self) -> Vec<usize> {
+        let mut order = vec![0];
+        while order.len() < self.tags.len() {
+            let mut best: Option<(usize, usize, usize)> = None;
+            for v in 0..self.tags.len() {
+                if order.contains(&v) {
+                    continue;
+                }
+                let uses: Vec<&Vec<usize>> = self.links.iter().filter(|l| l.contains(&v)).collect();
+                let linked = uses.iter().filter(|l| l.iter().any(|x| order.contains(x))).count();
+                if best.is_none_or(|(_, l, u)| linked > l || (linked == l && uses.len() > u)) {
+                    best = Some((v, linked, uses.len()));
+                }
+            }
+            order.push(best.unwrap().0);
+        }
+        order
+    }
+
+    // the quads and nodes the triejoin reads into its tries, a pattern
+    // without a stored node reads every quad
+    fn reads(&self) -> i64 {
+        let qs = self.qs.borrow();
+        let quads = qs.stats(false).map(|s| s.quads.value).unwrap_or(i64::MAX);
+        let patterns = self.patterns.iter().map(|p| {
+            p.iter().filter_map(|(d, t)| match t {
+                Term::Value(v) => Some(qs.value_of(v).map(|r| qs.quad_iterator_size(d, &r).map(|s| s.value).unwrap_or(quads)).unwrap_or(0)),
+                Term::Var(_) => None
+            }).min().unwrap_or(quads)
+        });
+        let nodes = self.values.iter().map(|(_, v)| v.len() as i64);
+        patterns.chain(nodes).fold(0i64, |a, b| a.saturating_add(b))
+    }
+}
+
+// An intersection of quad patterns that meet at more than one node is joined
+// by a leapfrog triejoin, which binds all the nodes at once instead of
+// checking the patterns of every branch one result at a time. Reading the
+// patterns has to cost no more than following links from the results of the
+// most selective branch, or a small intersection would read the whole store.
+pub struct TrieJoins {
+    pub qs: Rc<RefCell<dyn QuadStore>>
+}
+
+impl Optimizer for TrieJoins {
+    fn optimize_shape(&self, shape: &mut dyn Shape) -> Option<Rc<RefCell<dyn Shape>>> {
+        let i = match shape.shape_type() {
+            ShapeType::Intersect(i) => i,
+            _ => return None
+        };
+        let mut p = Patterns {
+            qs: self.qs.clone(),
+            tags: vec![Vec::new()],
+            patterns: Vec::new(),
+            links: Vec::new(),
+            values: Vec::new()
+        };
+        for c in &i.0 {
+            if !p.nodes(c, 0) {
+                return None
+            }
+        }
+
+        let shared = (0..p.tags.len()).filter(|v| p.links.iter().filter(|l| l.contains(v)).count() > 1).count();
+        if shared < 2 {
+            return None
+        }
+        let driver = i.0.iter().map(|c| estimate(&*self.qs.borrow(), c)).min().unwrap_or(0);
+        if p.reads() > driver.saturating_mul(FANOUT) {
+            return None
+        }
+
+        let order = p.order();
+        let tags = order.iter().map(|v| p.tags[*v].clone()).collect();
+        Some(TrieJoin::with(order.iter().map(|v| var_name(*v)).collect(), p.patterns, p.values, tags))
+    }
+
+    fn quad_store(&self) -> Option<Rc<RefCell<dyn QuadStore>>> {
+        Some(self.qs.clone())
+    }
+}
+
+
+// Moves value filters below the shapes that don't change node values: into
+// the first shape of an intersection, which drives it, into every branch of
+// a union, and under saves, uniques and sorts. Filters stay above pages,
+// which count the results they skip, and sorts that keep only the first.
+pub struct PushDownFilters;
+
+impl Optimizer for PushDownFilters {
+    fn optimize_shape(&self, shape: &mut dyn Shape) -> Option<Rc<RefCell<dyn Shape>>> {
+        let (from, filters) = match shape.shape_type() {
+            ShapeType::Filter(f) => (f.from.clone(), f.filters.clone()),
+            _ => return None
+        };
+        let filter = |s: &Rc<RefCell<dyn Shape>>| Filter::new(s.clone(), filters.clone());
+
+        let mut from_mut = from.borrow_mut();
+        match from_mut.shape_type() {
+            ShapeType::Intersect(i) if !i.0.is_empty() => {
+                let mut sub = i.0.clone();
+                sub[0] = filter(&sub[0]);
+                Some(Intersect::new(sub))
+            },
+            ShapeType::Union(u) => {
+                Some(Rc::new(RefCell::new(Union(u.0.iter().map(filter).collect()))))
+            },
+            ShapeType::Save(s) if s.from.is_some() => {
+                Some(Save::new(s.tags.clone(), s.from.as_ref().map(filter)))
+            },
+            ShapeType::Unique(u) => {
+                Some(Rc::new(RefCell::new(Unique { from: filter(&u.from) })))
+            },
+            ShapeType::Sort(s) if s.limit == 0 => {
+                Some(Rc::new(RefCell::new(Sort { from: filter(&s.from), keys: s.keys.clone(), limit: 0 })))
+            },
+            _ => None
+        }
+    }
+
+    fn quad_store(&self) -> Option<Rc<RefCell<dyn QuadStore>>> {
+        None
+    }
+}
+
+
+// A sort under a page with a limit only has to keep the results the page
+// skips and returns, which it does in a bounded heap instead of sorting all.
+pub struct TopK;
+
+impl Optimizer for TopK {
+    fn optimize_shape(&self, shape: &mut dyn Shape) -> Option<Rc<RefCell<dyn Shape>>> {
+        if let ShapeType::Page(p) = shape.shape_type() {
+            if p.limit <= 0 {
+                return None
+            }
+            let k = p.skip.max(0) + p.limit;
+            if let ShapeType::Sort(s) = p.from.borrow_mut().shape_type() {
+                if s.limit == 0 || s.limit > k {
+                    s.limit = k;
+                }
+            }
+        }
+        None
+    }
+
+    fn quad_store(&self) -> Option<Rc<RefCell<dyn QuadStore>>> {
+        None
+    }
+}
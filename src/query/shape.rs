@@ -1,6 +1,9 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::fmt;
 use super::path;
+use super::bgp::Term;
+use super::rewrite;
 use super::super::graph::iterator;
 use super::super::graph::hasa::HasA;
 use super::super::graph::value::Value;
@@ -18,46 +21,56 @@ pub enum ShapeType<'a> {
     Null,
     Fixed(&'a mut Fixed),
     AllNodes,
+    AllQuads,
     Intersect(&'a mut Intersect),
     IntersectOpt(&'a mut IntersectOpt),
-    NodesFrom,
+    NodesFrom(&'a mut NodesFrom),
     QuadFilter,
-    Quads,
-    Save,
-    Union,
-    Recursive,
+    Quads(&'a mut Quads),
+    Save(&'a mut Save),
+    Union(&'a mut Union),
+    Recursive(&'a mut Recursive),
+    CountPer(&'a mut CountPer),
     IteratorShape,
     Filter(&'a mut Filter),
-    Except,
-    Unique,
-    Page,
-    Sort
-}
-
-// impl<'a> fmt::Display for ShapeType<'a> {
-//     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-//         match self {
-//             ShapeType::Lookup(_) => write!(f, "Lookup"),
-//             ShapeType::Null => write!(f, "Null"),
-//             ShapeType::Fixed(_) => write!(f, "Fixed"),
-//             ShapeType::AllNodes => write!(f, "AllNodes"),
-//             ShapeType::Intersect(_) => write!(f, "Intersect"),
-//             ShapeType::IntersectOpt(_) => write!(f, "IntersectOpt"),
-//             ShapeType::NodesFrom => write!(f, "NodesFrom"),
-//             ShapeType::QuadFilter => write!(f, "QuadFilter"),
-//             ShapeType::Quads => write!(f, "Quads"),
-//             ShapeType::Save => write!(f, "Save"),
-//             ShapeType::Union => write!(f, "Union"),
-//             ShapeType::Recursive => write!(f, "Recursive"),
-//             ShapeType::IteratorShape => write!(f, "IteratorShape"),
-//             ShapeType::Filter(_) => write!(f, "Filter"),
-//             ShapeType::Except => write!(f, "Except"),
-//             ShapeType::Unique => write!(f, "Unique"),
-//             ShapeType::Page => write!(f, "Page"),
-//             ShapeType::Sort => write!(f, "Sort")
-//         }
-//     }
-// }
+    Except(&'a mut Except),
+    Unique(&'a mut Unique),
+    Page(&'a mut Page),
+    Sort(&'a mut Sort),
+    TrieJoin,
+    ShortestPath(&'a mut ShortestPath),
+    WeightedPath(&'a mut WeightedPath)
+}
+
+impl<'a> fmt::Display for ShapeType<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShapeType::Lookup(_) => write!(f, "Lookup"),
+            ShapeType::Null => write!(f, "Null"),
+            ShapeType::Fixed(_) => write!(f, "Fixed"),
+            ShapeType::AllNodes => write!(f, "AllNodes"),
+            ShapeType::AllQuads => write!(f, "AllQuads"),
+            ShapeType::Intersect(_) => write!(f, "Intersect"),
+            ShapeType::IntersectOpt(_) => write!(f, "IntersectOpt"),
+            ShapeType::NodesFrom(_) => write!(f, "NodesFrom"),
+            ShapeType::QuadFilter => write!(f, "QuadFilter"),
+            ShapeType::Quads(_) => write!(f, "Quads"),
+            ShapeType::Save(_) => write!(f, "Save"),
+            ShapeType::Union(_) => write!(f, "Union"),
+            ShapeType::Recursive(_) => write!(f, "Recursive"),
+            ShapeType::CountPer(_) => write!(f, "CountPer"),
+            ShapeType::IteratorShape => write!(f, "IteratorShape"),
+            ShapeType::Filter(_) => write!(f, "Filter"),
+            ShapeType::Except(_) => write!(f, "Except"),
+            ShapeType::Unique(_) => write!(f, "Unique"),
+            ShapeType::Page(_) => write!(f, "Page"),
+            ShapeType::Sort(_) => write!(f, "Sort"),
+            ShapeType::TrieJoin => write!(f, "TrieJoin"),
+            ShapeType::ShortestPath(_) => write!(f, "ShortestPath"),
+            ShapeType::WeightedPath(_) => write!(f, "WeightedPath")
+        }
+    }
+}
 
 
 pub trait Shape {
@@ -71,6 +84,16 @@ pub trait Optimizer{
     fn quad_store(&self) -> Option<Rc<RefCell<dyn QuadStore>>>;
 }
 
+// Optimizes a sub-shape, replacing it if the optimizer rewrote it.
+// Composite shapes optimize their sub-shapes before themselves, so an
+// optimizer always sees a node with optimized children.
+pub fn optimize_sub(s: &mut Rc<RefCell<dyn Shape>>, r: Option<&dyn Optimizer>) {
+    let n = s.borrow_mut().optimize(r);
+    if let Some(n) = n {
+        *s = n;
+    }
+}
+
 // pub trait Composite {
 //     fn simplify(&self) -> Rc<RefCell<dyn Shape>>;
 // }
@@ -80,7 +103,7 @@ pub trait Optimizer{
 // }
 
 
-struct ResolveValues {
+pub struct ResolveValues {
     pub qs: Rc<RefCell<dyn QuadStore>>
 }
 
@@ -197,9 +220,6 @@ impl Shape for Fixed {
     }
 
     fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        if self.0.is_empty() {
-            return None
-        }
         if let Some(o) = r {
             return o.optimize_shape(self)
         }
@@ -271,6 +291,36 @@ impl Shape for AllNodes {
 }
 
 
+///////////////////////////////////////////////
+
+
+pub struct AllQuads ();
+
+impl AllQuads {
+    pub fn new() -> Rc<RefCell<AllQuads>> {
+        Rc::new(RefCell::new(AllQuads()))
+    }
+}
+
+
+impl Shape for AllQuads {
+    fn build_iterator(&mut self, qs: Rc<RefCell<dyn QuadStore>>) -> Rc<RefCell<dyn iterator::Shape>> {
+        qs.borrow().quads_all_iterator()
+    }
+
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>>  {
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
+        return None
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::AllQuads
+    }
+}
+
+
 ///////////////////////////////////////////////
 
 #[derive(Clone)]
@@ -299,25 +349,12 @@ impl Shape for Intersect {
     }
 
     fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        if self.0.is_empty() {
-            return None
+        for c in self.0.iter_mut() {
+            optimize_sub(c, r);
         }
-
-        for i in 0..self.0.len() {
-            let c = &self.0[i];
-            if let ShapeType::Null = c.borrow_mut().shape_type() {
-                return None
-            }
-            let v = c.borrow_mut().optimize(r);
-            if v.is_none() {
-                continue;
-            }
-            if let ShapeType::Null = v.as_ref().unwrap().borrow_mut().shape_type() {
-                return None
-            }
-            self.0[i] = v.unwrap();
+        if let Some(o) = r {
+            return o.optimize_shape(self)
         }
-
         return None
     }
 
@@ -331,8 +368,8 @@ impl Shape for Intersect {
 
 
 pub struct NodesFrom {
-    dir: Direction,
-    quads: Rc<RefCell<dyn Shape>>
+    pub dir: Direction,
+    pub quads: Rc<RefCell<dyn Shape>>
 }
 
 impl NodesFrom {
@@ -354,13 +391,16 @@ impl Shape for NodesFrom {
         return HasA::new(qs.clone(), sub, self.dir.clone())
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_sub(&mut self.quads, r);
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::NodesFrom
+        ShapeType::NodesFrom(self)
     }
 }
 
@@ -369,8 +409,8 @@ impl Shape for NodesFrom {
 
 
 pub struct QuadFilter {
-    dir: Direction,
-    values: Option<Rc<RefCell<dyn Shape>>>
+    pub dir: Direction,
+    pub values: Option<Rc<RefCell<dyn Shape>>>
 }
 
 impl QuadFilter {
@@ -405,8 +445,10 @@ impl Shape for QuadFilter {
         LinksTo::new(qs.clone(), sub, self.dir.clone())
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        if let Some(v) = self.values.as_mut() {
+            optimize_sub(v, r);
+        }
         return None
     }
 
@@ -451,13 +493,18 @@ impl Shape for Quads {
         return iterator::and::And::new(its)
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        for f in self.0.iter_mut() {
+            f.optimize(r);
+        }
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Quads
+        ShapeType::Quads(self)
     }
 }
 
@@ -465,8 +512,8 @@ impl Shape for Quads {
 ///////////////////////////////////////////////
 
 pub struct Save {
-    tags: Vec<String>,
-    from: Option<Rc<RefCell<dyn Shape>>>
+    pub tags: Vec<String>,
+    pub from: Option<Rc<RefCell<dyn Shape>>>
 }
 
 impl Save {
@@ -491,13 +538,18 @@ impl Shape for Save {
         return it
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        if let Some(f) = self.from.as_mut() {
+            optimize_sub(f, r);
+        }
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Save
+        ShapeType::Save(self)
     }
 }
 
@@ -525,13 +577,18 @@ impl Shape for Union {
         return iterator::or::Or::new(sub)
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        for c in self.0.iter_mut() {
+            optimize_sub(c, r);
+        }
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Union
+        ShapeType::Union(self)
     }
 }
 
@@ -548,13 +605,16 @@ impl Shape for Unique {
        return iterator::unique::Unique::new(it);
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_sub(&mut self.from, r);
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Unique
+        ShapeType::Unique(self)
     }
 }
 
@@ -564,17 +624,28 @@ impl Shape for Unique {
 
 pub struct Recursive {
     path: path::Path,
-    r#in: Rc<RefCell<dyn Shape>>,
+    pub r#in: Rc<RefCell<dyn Shape>>,
     max_depth: i32, 
+    min_depth: i32,
+    emit: iterator::recursive::Emit,
+    order: iterator::recursive::Order,
+    pub until: Option<Rc<RefCell<dyn Shape>>>,
     tags: Vec<String>
 }
 
 impl Recursive {
-    pub fn new(path: path::Path, r#in: Rc<RefCell<dyn Shape>>, max_depth: i32, tags: Vec<String>) -> Rc<RefCell<Recursive>> {
+    pub fn new(path: path::Path, r#in: Rc<RefCell<dyn Shape>>, recursion: &path::Recursion, tags: Vec<String>) -> Rc<RefCell<Recursive>> {
         Rc::new(RefCell::new(Recursive {
             path,
             r#in,
-            max_depth, 
+            max_depth: recursion.max_depth, 
+            min_depth: recursion.min_depth,
+            emit: recursion.emit,
+            order: recursion.order,
+            until: match &recursion.until {
+                path::Via::None => None,
+                v => Some(v.as_shape())
+            },
             tags
         }))
     }
@@ -587,18 +658,250 @@ impl Shape for Recursive {
         for s in &self.tags {
             it.borrow_mut().add_depth_tag(s.clone());
         }
+        it.borrow_mut().set_min_depth(self.min_depth);
+        it.borrow_mut().set_emit(self.emit);
+        it.borrow_mut().set_order(self.order);
+        if let Some(until) = self.until.as_ref() {
+            it.borrow_mut().set_until(until.borrow_mut().build_iterator(qs.clone()));
+        }
         return it
     }
 
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_sub(&mut self.r#in, r);
+        if let Some(u) = self.until.as_mut() {
+            optimize_sub(u, r);
+        }
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
+        return None
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::Recursive(self)
+    }
+}
+
+///////////////////////////////////////////////
+
+
+// The nodes of from with the number of results of path from each, saved as
+// tag, or only those whose number passes filter.
+pub struct CountPer {
+    pub from: Rc<RefCell<dyn Shape>>,
+    path: path::Path,
+    tag: Option<String>,
+    filter: Option<(iterator::value_filter::Operator, i64)>
+}
+
+impl CountPer {
+    pub fn new(from: Rc<RefCell<dyn Shape>>, path: path::Path, tag: Option<String>, filter: Option<(iterator::value_filter::Operator, i64)>) -> Rc<RefCell<CountPer>> {
+        Rc::new(RefCell::new(CountPer {
+            from,
+            path,
+            tag,
+            filter
+        }))
+    }
+}
+
+impl Shape for CountPer {
+    fn build_iterator(&mut self, qs: Rc<RefCell<dyn QuadStore>>) -> Rc<RefCell<dyn iterator::Shape>> {
+        let from = self.from.borrow_mut().build_iterator(qs.clone());
+        iterator::count::CountPer::new(from, path::MorphismForPath::new(self.path.clone(), qs), self.tag.clone(), self.filter.clone())
+    }
+
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_sub(&mut self.from, r);
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
+        return None
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::CountPer(self)
+    }
+}
+
+///////////////////////////////////////////////
+
+
+// Joins quad patterns with a leapfrog triejoin, binding the variables in the
+// given order, some of them only to the nodes of a set of values. Every
+// variable is saved under its tags, the nodes are those of the first.
+pub struct TrieJoin {
+    vars: Vec<String>,
+    patterns: Vec<Vec<(Direction, Term)>>,
+    values: Vec<(String, Vec<Value>)>,
+    tags: Vec<Vec<String>>
+}
+
+impl TrieJoin {
+    // a join that saves every variable under its name
+    pub fn new(vars: Vec<String>, patterns: Vec<Vec<(Direction, Term)>>) -> Rc<RefCell<TrieJoin>> {
+        let tags = vars.iter().map(|v| vec![v.clone()]).collect();
+        TrieJoin::with(vars, patterns, Vec::new(), tags)
+    }
+
+    pub fn with(vars: Vec<String>, patterns: Vec<Vec<(Direction, Term)>>, values: Vec<(String, Vec<Value>)>, tags: Vec<Vec<String>>) -> Rc<RefCell<TrieJoin>> {
+        Rc::new(RefCell::new(TrieJoin {
+            vars,
+            patterns,
+            values,
+            tags
+        }))
+    }
+}
+
+impl Shape for TrieJoin {
+    fn build_iterator(&mut self, qs: Rc<RefCell<dyn QuadStore>>) -> Rc<RefCell<dyn iterator::Shape>> {
+        let var = |v: &String| self.vars.iter().position(|x| x == v);
+        let mut atoms = Vec::new();
+        for p in &self.patterns {
+            let mut slots = Vec::new();
+            for (d, t) in p {
+                let slot = match t {
+                    Term::Var(v) => match var(v) {
+                        Some(i) => iterator::triejoin::Slot::Var(i),
+                        None => return iterator::Null::new()
+                    },
+                    // a value that isn't stored matches nothing
+                    Term::Value(v) => match qs.borrow().value_of(v) {
+                        Some(r) => iterator::triejoin::Slot::Fixed(r),
+                        None => return iterator::Null::new()
+                    }
+                };
+                slots.push((d.clone(), slot));
+            }
+            atoms.push(iterator::triejoin::Atom::new(slots));
+        }
+        for (v, values) in &self.values {
+            let i = match var(v) {
+                Some(i) => i,
+                None => return iterator::Null::new()
+            };
+            let nodes = values.iter().filter_map(|x| qs.borrow().value_of(x)).collect();
+            atoms.push(iterator::triejoin::Atom::nodes(i, nodes));
+        }
+        iterator::triejoin::TrieJoin::with_tags(qs, self.vars.clone(), self.tags.clone(), atoms)
+    }
+
     fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        None
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::TrieJoin
+    }
+}
+
+
+///////////////////////////////////////////////
+
+
+// The nodes of `to` that can be reached from `from` through links with a
+// predicate of `via`, each tagged with the shortest path to it.
+pub struct ShortestPath {
+    pub from: Rc<RefCell<dyn Shape>>,
+    pub to: Rc<RefCell<dyn Shape>>,
+    pub via: Option<Rc<RefCell<dyn Shape>>>,
+    max_depth: i32,
+    rev: bool
+}
+
+impl ShortestPath {
+    pub fn new(from: Rc<RefCell<dyn Shape>>, to: Rc<RefCell<dyn Shape>>, via: Option<Rc<RefCell<dyn Shape>>>, max_depth: i32, rev: bool) -> Rc<RefCell<ShortestPath>> {
+        Rc::new(RefCell::new(ShortestPath {
+            from,
+            to,
+            via,
+            max_depth,
+            rev
+        }))
+    }
+}
+
+impl Shape for ShortestPath {
+    fn build_iterator(&mut self, qs: Rc<RefCell<dyn QuadStore>>) -> Rc<RefCell<dyn iterator::Shape>> {
+        let from = self.from.borrow_mut().build_iterator(qs.clone());
+        let to = self.to.borrow_mut().build_iterator(qs.clone());
+        let via = self.via.as_ref().map(|v| v.borrow_mut().build_iterator(qs.clone()));
+        iterator::shortest_path::ShortestPath::new(qs, from, to, via, self.max_depth, self.rev)
+    }
+
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_sub(&mut self.from, r);
+        optimize_sub(&mut self.to, r);
+        if let Some(v) = self.via.as_mut() {
+            optimize_sub(v, r);
+        }
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
+        return None
+    }
+
+    fn shape_type(&mut self) -> ShapeType {
+        ShapeType::ShortestPath(self)
+    }
+}
+
+
+///////////////////////////////////////////////
+
+
+// The nodes of `to` that can be reached from `from` through links with a
+// predicate of `via`, each tagged with the path of least cost to it.
+pub struct WeightedPath {
+    pub from: Rc<RefCell<dyn Shape>>,
+    pub to: Rc<RefCell<dyn Shape>>,
+    pub via: Option<Rc<RefCell<dyn Shape>>>,
+    weight: iterator::weighted_path::Weight,
+    heuristic: Option<iterator::weighted_path::Heuristic>,
+    rev: bool
+}
+
+impl WeightedPath {
+    pub fn new(from: Rc<RefCell<dyn Shape>>, to: Rc<RefCell<dyn Shape>>, via: Option<Rc<RefCell<dyn Shape>>>, weight: iterator::weighted_path::Weight, heuristic: Option<iterator::weighted_path::Heuristic>, rev: bool) -> Rc<RefCell<WeightedPath>> {
+        Rc::new(RefCell::new(WeightedPath {
+            from,
+            to,
+            via,
+            weight,
+            heuristic,
+            rev
+        }))
+    }
+}
+
+impl Shape for WeightedPath {
+    fn build_iterator(&mut self, qs: Rc<RefCell<dyn QuadStore>>) -> Rc<RefCell<dyn iterator::Shape>> {
+        let from = self.from.borrow_mut().build_iterator(qs.clone());
+        let to = self.to.borrow_mut().build_iterator(qs.clone());
+        let via = self.via.as_ref().map(|v| v.borrow_mut().build_iterator(qs.clone()));
+        iterator::weighted_path::WeightedPath::new(qs, from, to, via, self.weight.clone(), self.heuristic.clone(), self.rev)
+    }
+
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_sub(&mut self.from, r);
+        optimize_sub(&mut self.to, r);
+        if let Some(v) = self.via.as_mut() {
+            optimize_sub(v, r);
+        }
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Recursive
+        ShapeType::WeightedPath(self)
     }
 }
 
+
 ///////////////////////////////////////////////
 #[derive(Clone)]
 pub struct IteratorShape {
@@ -669,8 +972,13 @@ impl Shape for IntersectOpt {
         return it
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        for c in self.sub.0.iter_mut().chain(self.opt.iter_mut()) {
+            optimize_sub(c, r);
+        }
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
@@ -702,13 +1010,18 @@ impl Shape for Except {
         }
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        for c in self.exclude.iter_mut().chain(self.from.iter_mut()) {
+            optimize_sub(c, r);
+        }
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Except
+        ShapeType::Except(self)
     }
 }
 
@@ -740,22 +1053,29 @@ impl Shape for Page {
         return it
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_sub(&mut self.from, r);
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Page
+        ShapeType::Page(self)
     }
 }
 
 ///////////////////////////////////////////////
 
 
+// Sorts on the keys, or on the node values without any. A limit keeps only
+// that many of the first results, see rewrite::TopK.
 #[derive(Clone)]
 pub struct Sort {
     pub from: Rc<RefCell<dyn Shape>>,
+    pub keys: Vec<iterator::sort::SortKey>,
+    pub limit: i64
 }
 
 impl Shape for Sort {
@@ -766,16 +1086,19 @@ impl Shape for Sort {
 
         let it = self.from.borrow_mut().build_iterator(qs.clone());
 
-        return iterator::sort::Sort::new(qs.clone(), it)
+        return iterator::sort::Sort::new_with_keys(qs.clone(), it, self.keys.clone(), self.limit.max(0) as usize)
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
-        // TODO: Implement
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_sub(&mut self.from, r);
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
     fn shape_type(&mut self) -> ShapeType {
-        ShapeType::Sort
+        ShapeType::Sort(self)
     }
 }
 
@@ -897,8 +1220,8 @@ impl ValueFilter for Comparison {
 
 
 pub struct Filter {
-    from: Rc<RefCell<dyn Shape>>,
-    filters: Vec<Rc<dyn ValueFilter>>
+    pub from: Rc<RefCell<dyn Shape>>,
+    pub filters: Vec<Rc<dyn ValueFilter>>
 }
 
 impl Filter {
@@ -931,7 +1254,11 @@ impl Shape for Filter {
         return it
     }
 
-    fn optimize(&mut self, _r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+    fn optimize(&mut self, r: Option<&dyn Optimizer>) -> Option<Rc<RefCell<dyn Shape>>> {
+        optimize_sub(&mut self.from, r);
+        if let Some(o) = r {
+            return o.optimize_shape(self)
+        }
         return None
     }
 
@@ -1005,7 +1332,7 @@ pub fn intersect_optional(main: Rc<RefCell<dyn Shape>>, opt: Rc<RefCell<dyn Shap
 
 
 fn optimize(qs: Rc<RefCell<dyn QuadStore>>, shape:Rc<RefCell<dyn Shape>>) -> Option<Rc<RefCell<dyn Shape>>> {
-    shape.borrow_mut().optimize(Some(&ResolveValues{qs}))
+    shape.borrow_mut().optimize(Some(&rewrite::Rules::new(qs)))
 }
 
 pub fn build_iterator(qs: Rc<RefCell<dyn QuadStore>>, shape:Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn iterator::Shape>>{
@@ -1015,11 +1342,9 @@ pub fn build_iterator(qs: Rc<RefCell<dyn QuadStore>>, shape:Rc<RefCell<dyn Shape
     a.build_iterator(qs.clone())
 }
 
-// buildOut() from query/shape/path.go
-pub fn new_in_out(from:Rc<RefCell<dyn Shape>>, mut via:Rc<RefCell<dyn Shape>>, labels:Option<Rc<RefCell<dyn Shape>>>, tags:Option<Vec<String>>, r#in: bool) -> Rc<RefCell<dyn Shape>> {
-
-    let start = if r#in { Direction::Object } else { Direction::Subject };
-    let goal = if r#in { Direction::Subject } else { Direction::Object };
+// The quads with a node of `from` in direction `start`, with a predicate of
+// `via` and a label of `labels`, and the predicates saved under `tags`.
+fn quads_from(from:Rc<RefCell<dyn Shape>>, mut via:Rc<RefCell<dyn Shape>>, labels:Option<Rc<RefCell<dyn Shape>>>, tags:Option<Vec<String>>, start: Direction) -> Rc<RefCell<Quads>> {
 
     if let Some(t) = tags {
         if !t.is_empty() {
@@ -1066,12 +1391,46 @@ pub fn new_in_out(from:Rc<RefCell<dyn Shape>>, mut via:Rc<RefCell<dyn Shape>>, l
         };
     }
 
+    quads
+}
+
+// buildOut() from query/shape/path.go
+pub fn new_in_out(from:Rc<RefCell<dyn Shape>>, via:Rc<RefCell<dyn Shape>>, labels:Option<Rc<RefCell<dyn Shape>>>, tags:Option<Vec<String>>, r#in: bool) -> Rc<RefCell<dyn Shape>> {
+
+    let start = if r#in { Direction::Object } else { Direction::Subject };
+    let goal = if r#in { Direction::Subject } else { Direction::Object };
+
     Rc::new(RefCell::new(NodesFrom {
-        quads,
+        quads: quads_from(from, via, labels, tags, start),
         dir: goal
     }))
 }
 
+// The quads themselves rather than the nodes at their other end, all of
+// them when nothing filters them.
+pub fn new_edges(from:Rc<RefCell<dyn Shape>>, via:Rc<RefCell<dyn Shape>>, labels:Option<Rc<RefCell<dyn Shape>>>, tags:Option<Vec<String>>, start: Direction) -> Rc<RefCell<dyn Shape>> {
+    let quads = quads_from(from, via, labels, tags, start);
+    if quads.borrow().0.is_empty() {
+        return AllQuads::new()
+    }
+    quads
+}
+
+// Whether the results of a shape are quads rather than nodes, as after new_edges.
+pub fn is_quads(s: &Rc<RefCell<dyn Shape>>) -> bool {
+    match s.borrow_mut().shape_type() {
+        ShapeType::Quads(_) | ShapeType::AllQuads => true,
+        ShapeType::Union(u) => !u.0.is_empty() && u.0.iter().all(is_quads),
+        ShapeType::Intersect(i) => i.0.iter().any(is_quads),
+        ShapeType::Save(s) => s.from.as_ref().is_some_and(is_quads),
+        ShapeType::Unique(u) => is_quads(&u.from),
+        ShapeType::Page(p) => is_quads(&p.from),
+        ShapeType::Sort(s) => is_quads(&s.from),
+        ShapeType::Filter(f) => is_quads(&f.from),
+        _ => false
+    }
+}
+
 // pub fn new_in(from:Rc<RefCell<dyn Shape>>, via:Rc<RefCell<dyn Shape>>, labels:Option<Rc<RefCell<dyn Shape>>>, tags:Vec<String>) -> Rc<RefCell<dyn Shape>> {
 //     new_in_out(from, via, labels, tags, true)
 // }
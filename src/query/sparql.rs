@@ -0,0 +1,927 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use super::bgp::{self, Bgp, Pattern, Term, Plan};
+use super::expr::{Expr, error_at, check_regex, eval, holds, order_values, conjuncts, push_down};
+use super::super::graph::quad::QuadStore;
+use super::super::graph::value::Value;
+
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+
+// The result of a query: variable bindings for SELECT, a boolean for ASK.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Results {
+    Bindings {
+        vars: Vec<String>,
+        rows: Vec<HashMap<String, Value>>
+    },
+    Boolean(bool)
+}
+
+
+/////////////////////////////////////////////// lexer
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Iri(String),
+    PName(String, String),
+    Var(String),
+    BNode(String),
+    Str(String),
+    LangTag(String),
+    Integer(i64),
+    Decimal(f64),
+    Word(String),
+    Punct(&'static str),
+    Eof
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    pos: usize
+}
+
+const PUNCT: [&str; 18] = ["^^", "&&", "||", "<=", ">=", "!=", "{", "}", "(", ")", ".", ";", ",", "*", "=", "<", ">", "!"];
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+
+fn lex(src: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<(usize, char)> = src.char_indices().collect();
+    let at = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let pos_of = |i: usize| chars.get(i).map(|(p, _)| *p).unwrap_or(src.len());
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while let Some(c) = at(i) {
+        let start = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '#' {
+            while at(i).is_some_and(|c| c != '\n') {
+                i += 1;
+            }
+            continue;
+        }
+
+        let tok = if c == '<' && iri_end(&chars, i + 1).is_some() {
+            let end = iri_end(&chars, i + 1).unwrap();
+            let iri: String = chars[i + 1..end].iter().map(|(_, c)| *c).collect();
+            i = end + 1;
+            Tok::Iri(iri)
+        } else if (c == '?' || c == '$') && at(i + 1).is_some_and(is_name_char) {
+            i += 1;
+            let name = take_while(&chars, &mut i, is_name_char);
+            Tok::Var(name)
+        } else if c == '_' && at(i + 1) == Some(':') {
+            i += 2;
+            let name = take_while(&chars, &mut i, is_name_char);
+            Tok::BNode(name)
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            let mut s = String::new();
+            loop {
+                match at(i) {
+                    None => return Err(error_at(src, pos_of(start), "unterminated string")),
+                    Some(q) if q == c => { i += 1; break },
+                    Some('\\') => {
+                        let e = match at(i + 1) {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some(e) => e,
+                            None => return Err(error_at(src, pos_of(start), "unterminated string"))
+                        };
+                        s.push(e);
+                        i += 2;
+                    },
+                    Some(ch) => { s.push(ch); i += 1 }
+                }
+            }
+            Tok::Str(s)
+        } else if c == '@' {
+            i += 1;
+            Tok::LangTag(take_while(&chars, &mut i, |c| c.is_alphanumeric() || c == '-'))
+        } else if c.is_ascii_digit() || ((c == '-' || c == '+') && at(i + 1).is_some_and(|c| c.is_ascii_digit())) {
+            i += 1;
+            take_while(&chars, &mut i, |c| c.is_ascii_digit());
+            let mut decimal = false;
+            if at(i) == Some('.') && at(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+                decimal = true;
+                i += 1;
+                take_while(&chars, &mut i, |c| c.is_ascii_digit());
+            }
+            if at(i) == Some('e') || at(i) == Some('E') {
+                decimal = true;
+                i += 1;
+                if at(i) == Some('-') || at(i) == Some('+') {
+                    i += 1;
+                }
+                take_while(&chars, &mut i, |c| c.is_ascii_digit());
+            }
+            let text = &src[pos_of(start)..pos_of(i)];
+            let text = text.trim_start_matches('+');
+            if decimal {
+                match text.parse() {
+                    Ok(f) => Tok::Decimal(f),
+                    Err(_) => return Err(error_at(src, pos_of(start), "invalid number"))
+                }
+            } else {
+                match text.parse() {
+                    Ok(n) => Tok::Integer(n),
+                    Err(_) => return Err(error_at(src, pos_of(start), "invalid number"))
+                }
+            }
+        } else if c.is_alphabetic() || c == ':' {
+            let name = take_while(&chars, &mut i, is_name_char);
+            if at(i) == Some(':') {
+                i += 1;
+                let mut local = take_while(&chars, &mut i, |c| is_name_char(c) || c == '.');
+                // a trailing dot ends the triple
+                while local.ends_with('.') {
+                    local.pop();
+                    i -= 1;
+                }
+                Tok::PName(name, local)
+            } else {
+                Tok::Word(name)
+            }
+        } else {
+            let rest = &src[pos_of(i)..];
+            match PUNCT.iter().find(|p| rest.starts_with(*p)) {
+                Some(p) => {
+                    i += p.len();
+                    Tok::Punct(p)
+                },
+                None => return Err(error_at(src, pos_of(start), &format!("unexpected character {:?}", c)))
+            }
+        };
+
+        tokens.push(Token {
+            tok,
+            pos: pos_of(start)
+        });
+    }
+
+    tokens.push(Token {
+        tok: Tok::Eof,
+        pos: src.len()
+    });
+    Ok(tokens)
+}
+
+// an IRI reference has no spaces, which tells it apart from the < operator
+fn iri_end(chars: &[(usize, char)], from: usize) -> Option<usize> {
+    for (i, (_, c)) in chars.iter().enumerate().skip(from) {
+        match c {
+            '>' => return Some(i),
+            '<' | '"' | '{' | '}' | '|' | '\\' | '^' | '`' => return None,
+            c if c.is_whitespace() => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn take_while(chars: &[(usize, char)], i: &mut usize, f: impl Fn(char) -> bool) -> String {
+    let mut s = String::new();
+    while let Some((_, c)) = chars.get(*i) {
+        if !f(*c) {
+            break;
+        }
+        s.push(*c);
+        *i += 1;
+    }
+    s
+}
+
+
+/////////////////////////////////////////////// syntax
+
+
+
+#[derive(Debug, Clone)]
+enum Element {
+    Triple(Pattern),
+    Filter(Expr),
+    Optional(Vec<Element>),
+    Union(Vec<Vec<Element>>),
+    Graph(Term, Vec<Element>)
+}
+
+#[derive(Debug, Clone)]
+enum Form {
+    Select {
+        distinct: bool,
+        // None for SELECT *
+        vars: Option<Vec<String>>
+    },
+    Ask
+}
+
+
+// A parsed query, ready to run against a quad store.
+#[derive(Debug, Clone)]
+pub struct Query {
+    form: Form,
+    pattern: Vec<Element>,
+    order: Vec<(Expr, bool)>,
+    limit: Option<usize>,
+    offset: usize
+}
+
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    i: usize,
+    prefixes: HashMap<String, String>
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.i].tok
+    }
+
+    fn next(&mut self) -> Tok {
+        let t = self.tokens[self.i].tok.clone();
+        if self.i < self.tokens.len() - 1 {
+            self.i += 1;
+        }
+        t
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(error_at(self.src, self.tokens[self.i].pos, msg))
+    }
+
+    // consumes the next token if it is what the caller expects
+    fn expect<T>(&mut self, f: impl Fn(&Tok) -> Option<T>, msg: &str) -> Result<T, String> {
+        match f(self.peek()) {
+            Some(v) => {
+                self.next();
+                Ok(v)
+            },
+            None => self.error(msg)
+        }
+    }
+
+    fn is_word(&self, kw: &str) -> bool {
+        matches!(self.peek(), Tok::Word(w) if w.eq_ignore_ascii_case(kw))
+    }
+
+    fn eat_word(&mut self, kw: &str) -> bool {
+        if self.is_word(kw) {
+            self.next();
+            return true
+        }
+        false
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Tok::Punct(x) if *x == p)
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.next();
+            return true
+        }
+        false
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<(), String> {
+        if self.eat_punct(p) {
+            return Ok(())
+        }
+        self.error(&format!("expected '{}'", p))
+    }
+
+    fn query(&mut self) -> Result<Query, String> {
+        loop {
+            if self.eat_word("PREFIX") {
+                let prefix = self.expect(|t| match t {
+                    Tok::PName(p, l) if l.is_empty() => Some(p.clone()),
+                    _ => None
+                }, "expected a prefix name")?;
+                let iri = self.expect(|t| match t {
+                    Tok::Iri(iri) => Some(iri.clone()),
+                    _ => None
+                }, "expected an IRI")?;
+                self.prefixes.insert(prefix, iri);
+            } else if self.eat_word("BASE") {
+                return self.error("BASE is not supported")
+            } else {
+                break;
+            }
+        }
+
+        let form = if self.eat_word("SELECT") {
+            let distinct = self.eat_word("DISTINCT") || self.eat_word("REDUCED");
+            let vars = if self.eat_punct("*") {
+                None
+            } else {
+                let mut vars = Vec::new();
+                while let Tok::Var(v) = self.peek() {
+                    vars.push(v.clone());
+                    self.next();
+                }
+                if vars.is_empty() {
+                    return self.error("expected variables or '*'")
+                }
+                Some(vars)
+            };
+            Form::Select { distinct, vars }
+        } else if self.eat_word("ASK") {
+            Form::Ask
+        } else {
+            return self.error("expected SELECT or ASK")
+        };
+
+        self.eat_word("WHERE");
+        let pattern = self.group()?;
+
+        let mut order = Vec::new();
+        if self.eat_word("ORDER") {
+            if !self.eat_word("BY") {
+                return self.error("expected BY")
+            }
+            loop {
+                if self.is_word("ASC") || self.is_word("DESC") {
+                    let desc = self.is_word("DESC");
+                    self.next();
+                    self.expect_punct("(")?;
+                    let e = self.expr()?;
+                    self.expect_punct(")")?;
+                    order.push((e, desc));
+                } else if let Tok::Var(v) = self.peek() {
+                    order.push((Expr::Var(v.clone()), false));
+                    self.next();
+                } else if self.eat_punct("(") {
+                    let e = self.expr()?;
+                    self.expect_punct(")")?;
+                    order.push((e, false));
+                } else {
+                    break;
+                }
+            }
+            if order.is_empty() {
+                return self.error("expected an order condition")
+            }
+        }
+
+        let mut limit = None;
+        let mut offset = 0;
+        loop {
+            if self.eat_word("LIMIT") {
+                limit = Some(self.count()?);
+            } else if self.eat_word("OFFSET") {
+                offset = self.count()?;
+            } else {
+                break;
+            }
+        }
+
+        if *self.peek() != Tok::Eof {
+            return self.error("unexpected input after the query")
+        }
+
+        Ok(Query {
+            form,
+            pattern,
+            order,
+            limit,
+            offset
+        })
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        self.expect(|t| match t {
+            Tok::Integer(n) if *n >= 0 => Some(*n as usize),
+            _ => None
+        }, "expected a non-negative integer")
+    }
+
+    fn group(&mut self) -> Result<Vec<Element>, String> {
+        self.expect_punct("{")?;
+        let mut elements = Vec::new();
+
+        loop {
+            if self.eat_punct("}") {
+                return Ok(elements)
+            } else if self.eat_punct(".") {
+                continue;
+            } else if self.eat_word("FILTER") {
+                elements.push(Element::Filter(self.constraint()?));
+            } else if self.eat_word("OPTIONAL") {
+                elements.push(Element::Optional(self.group()?));
+            } else if self.eat_word("GRAPH") {
+                let t = self.term()?;
+                if let Term::Value(Value::String(_)) | Term::Value(Value::Number(_)) | Term::Value(Value::Bool(_)) = t {
+                    return self.error("expected an IRI or a variable after GRAPH")
+                }
+                elements.push(Element::Graph(t, self.group()?));
+            } else if self.is_punct("{") {
+                let mut alts = vec![self.group()?];
+                while self.eat_word("UNION") {
+                    alts.push(self.group()?);
+                }
+                elements.push(Element::Union(alts));
+            } else if *self.peek() == Tok::Eof {
+                return self.error("expected '}'")
+            } else {
+                self.triples(&mut elements)?;
+            }
+        }
+    }
+
+    // subject predicate object, with ';' and ',' lists
+    fn triples(&mut self, out: &mut Vec<Element>) -> Result<(), String> {
+        let subject = self.term()?;
+        loop {
+            let predicate = if self.eat_word("a") {
+                Term::Value(Value::IRI(RDF_TYPE.to_string()))
+            } else {
+                self.term()?
+            };
+            loop {
+                let object = self.term()?;
+                out.push(Element::Triple(Pattern::new(subject.clone(), predicate.clone(), object)));
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            if !self.eat_punct(";") {
+                return Ok(())
+            }
+            // a trailing ';' is allowed
+            if self.is_punct(".") || self.is_punct("}") {
+                return Ok(())
+            }
+        }
+    }
+
+    fn iri(&mut self) -> Result<Option<String>, String> {
+        match self.peek().clone() {
+            Tok::Iri(iri) => {
+                self.next();
+                Ok(Some(iri))
+            },
+            Tok::PName(p, l) => {
+                match self.prefixes.get(&p) {
+                    Some(ns) => {
+                        let iri = format!("{}{}", ns, l);
+                        self.next();
+                        Ok(Some(iri))
+                    },
+                    None => self.error(&format!("unknown prefix '{}'", p))
+                }
+            },
+            _ => Ok(None)
+        }
+    }
+
+    fn literal(&mut self) -> Result<Option<Value>, String> {
+        match self.peek().clone() {
+            Tok::Str(s) => {
+                self.next();
+                match self.peek() {
+                    // language tags are not kept
+                    Tok::LangTag(_) => { self.next(); },
+                    Tok::Punct("^^") => {
+                        self.next();
+                        let dt = match self.iri()? {
+                            Some(dt) => dt,
+                            None => return self.error("expected a datatype IRI")
+                        };
+                        return self.typed(s, &dt).map(Some)
+                    },
+                    _ => {}
+                }
+                Ok(Some(Value::String(s)))
+            },
+            Tok::Integer(n) => { self.next(); Ok(Some(Value::from(n))) },
+            Tok::Decimal(f) => { self.next(); Ok(Some(Value::from(f))) },
+            Tok::Word(w) if w == "true" || w == "false" => {
+                self.next();
+                Ok(Some(Value::Bool(w == "true")))
+            },
+            _ => Ok(None)
+        }
+    }
+
+    fn typed(&self, s: String, dt: &str) -> Result<Value, String> {
+        let local = match dt.strip_prefix(XSD) {
+            Some(l) => l,
+            None => return Ok(Value::String(s))
+        };
+        let parsed = match local {
+            "integer" | "int" | "long" | "short" | "byte" | "nonNegativeInteger" | "positiveInteger" | "negativeInteger" | "nonPositiveInteger" => {
+                s.trim().parse::<i64>().ok().map(Value::from)
+            },
+            "decimal" | "double" | "float" => s.trim().parse::<f64>().ok().map(Value::from),
+            "boolean" => match s.trim() {
+                "true" | "1" => Some(Value::Bool(true)),
+                "false" | "0" => Some(Value::Bool(false)),
+                _ => None
+            },
+            _ => Some(Value::String(s.clone()))
+        };
+        match parsed {
+            Some(v) => Ok(v),
+            None => self.error(&format!("invalid literal \"{}\" for xsd:{}", s, local))
+        }
+    }
+
+    fn term(&mut self) -> Result<Term, String> {
+        if let Tok::Var(v) = self.peek().clone() {
+            self.next();
+            return Ok(Term::Var(v))
+        }
+        if let Tok::BNode(b) = self.peek().clone() {
+            self.next();
+            // blank nodes match like variables that are never returned
+            return Ok(Term::Var(format!("_:{}", b)))
+        }
+        if let Some(iri) = self.iri()? {
+            return Ok(Term::Value(Value::IRI(iri)))
+        }
+        if let Some(v) = self.literal()? {
+            return Ok(Term::Value(v))
+        }
+        self.error("expected a variable, an IRI or a literal")
+    }
+
+    fn constraint(&mut self) -> Result<Expr, String> {
+        if self.eat_punct("(") {
+            let e = self.expr()?;
+            self.expect_punct(")")?;
+            return Ok(e)
+        }
+        match self.peek() {
+            Tok::Word(_) => self.call(),
+            _ => self.error("expected '(' or a function call after FILTER")
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut e = self.and_expr()?;
+        while self.eat_punct("||") {
+            e = Expr::Or(Box::new(e), Box::new(self.and_expr()?));
+        }
+        Ok(e)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, String> {
+        let mut e = self.rel_expr()?;
+        while self.eat_punct("&&") {
+            e = Expr::And(Box::new(e), Box::new(self.rel_expr()?));
+        }
+        Ok(e)
+    }
+
+    fn rel_expr(&mut self) -> Result<Expr, String> {
+        let e = self.unary()?;
+        for op in ["=", "!=", "<", "<=", ">", ">="] {
+            if self.eat_punct(op) {
+                return Ok(Expr::Cmp(op, Box::new(e), Box::new(self.unary()?)))
+            }
+        }
+        Ok(e)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat_punct("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)))
+        }
+        if self.eat_punct("(") {
+            let e = self.expr()?;
+            self.expect_punct(")")?;
+            return Ok(e)
+        }
+        if let Tok::Var(v) = self.peek().clone() {
+            self.next();
+            return Ok(Expr::Var(v))
+        }
+        if let Some(iri) = self.iri()? {
+            return Ok(Expr::Const(Value::IRI(iri)))
+        }
+        if let Some(v) = self.literal()? {
+            return Ok(Expr::Const(v))
+        }
+        if let Tok::Word(_) = self.peek() {
+            return self.call()
+        }
+        self.error("expected an expression")
+    }
+
+    fn call(&mut self) -> Result<Expr, String> {
+        let name = match self.peek() {
+            Tok::Word(w) => w.to_lowercase(),
+            _ => return self.error("expected a function name")
+        };
+        let at = self.i;
+        self.next();
+        self.expect_punct("(")?;
+
+        let e = match name.as_str() {
+            "regex" => {
+                let text = self.expr()?;
+                self.expect_punct(",")?;
+                let pattern = self.string_arg()?;
+                let flags = if self.eat_punct(",") { self.string_arg()? } else { String::new() };
+                if flags.chars().any(|c| c != 'i') {
+                    return self.error("only the 'i' regex flag is supported")
+                }
+                if cfg!(not(feature = "regex")) {
+                    self.i = at;
+                    return self.error("regex needs the regex feature")
+                }
+                check_regex(&pattern).map_err(|e| error_at(self.src, self.tokens[at].pos, &e))?;
+                Expr::Regex(Box::new(text), pattern, flags)
+            },
+            "bound" => Expr::Bound(self.expect(|t| match t {
+                Tok::Var(v) => Some(v.clone()),
+                _ => None
+            }, "expected a variable")?),
+            "str" => Expr::Str(Box::new(self.expr()?)),
+            _ => {
+                self.i = at;
+                return self.error(&format!("unknown function {}", name))
+            }
+        };
+
+        self.expect_punct(")")?;
+        Ok(e)
+    }
+
+    fn string_arg(&mut self) -> Result<String, String> {
+        self.expect(|t| match t {
+            Tok::Str(s) => Some(s.clone()),
+            _ => None
+        }, "expected a string")
+    }
+}
+
+
+
+
+// Parses a SELECT or ASK query. Errors are prefixed with line:column.
+pub fn parse(src: &str) -> Result<Query, String> {
+    let tokens = lex(src)?;
+    let mut p = Parser {
+        src,
+        tokens,
+        i: 0,
+        prefixes: HashMap::new()
+    };
+    p.query()
+}
+
+
+/////////////////////////////////////////////// compilation
+
+
+// One alternative of the pattern once UNIONs are expanded.
+#[derive(Debug, Clone, Default)]
+struct Branch {
+    patterns: Vec<Pattern>,
+    optional: Vec<Vec<Pattern>>,
+    filters: Vec<Expr>
+}
+
+impl Branch {
+    fn merge(&self, other: &Branch) -> Branch {
+        let mut b = self.clone();
+        b.patterns.extend(other.patterns.iter().cloned());
+        b.optional.extend(other.optional.iter().cloned());
+        b.filters.extend(other.filters.iter().cloned());
+        b
+    }
+}
+
+fn flatten(elements: &[Element], label: Option<&Term>) -> Result<Vec<Branch>, String> {
+    let mut branches = vec![Branch::default()];
+
+    for e in elements {
+        match e {
+            Element::Triple(p) => {
+                let p = match label {
+                    Some(l) => p.clone().with_label(l.clone()),
+                    None => p.clone()
+                };
+                branches.iter_mut().for_each(|b| b.patterns.push(p.clone()));
+            },
+            Element::Filter(f) => {
+                branches.iter_mut().for_each(|b| b.filters.push(f.clone()));
+            },
+            Element::Optional(group) => {
+                let inner = flatten(group, label)?;
+                if inner.len() != 1 || !inner[0].optional.is_empty() || !inner[0].filters.is_empty() {
+                    return Err("only triple patterns are supported inside OPTIONAL".to_string())
+                }
+                let patterns = inner[0].patterns.clone();
+                branches.iter_mut().for_each(|b| b.optional.push(patterns.clone()));
+            },
+            Element::Union(alts) => {
+                let mut expanded = Vec::new();
+                for alt in alts {
+                    expanded.extend(flatten(alt, label)?);
+                }
+                branches = branches.iter().flat_map(|b| expanded.iter().map(move |x| b.merge(x))).collect();
+            },
+            Element::Graph(l, group) => {
+                let inner = flatten(group, Some(l))?;
+                branches = branches.iter().flat_map(|b| inner.iter().map(move |x| b.merge(x))).collect();
+            }
+        }
+    }
+
+    Ok(branches)
+}
+
+
+
+
+
+
+// splits the branch into a pattern with pushed down filters and the filters left for rows
+fn to_bgp(b: &Branch) -> (Bgp, Vec<Expr>) {
+    let mut bgp = Bgp::new(b.patterns.clone());
+    bgp.optional = b.optional.clone();
+
+    let required: HashSet<String> = bgp.vars().into_iter().collect();
+    let mut rest = Vec::new();
+    for f in &b.filters {
+        let mut parts = Vec::new();
+        conjuncts(f, &mut parts);
+        for p in parts {
+            match push_down(&p, &required) {
+                Some(f) => bgp.filters.push(f),
+                None => rest.push(p)
+            }
+        }
+    }
+    (bgp, rest)
+}
+
+fn run_plan(qs: Rc<RefCell<dyn QuadStore>>, plan: Option<&Plan>, filters: &[Expr]) -> Result<Vec<HashMap<String, Value>>, String> {
+    let rows = match plan {
+        Some(p) => bgp::run(qs, p)?,
+        // an empty pattern has a single empty solution
+        None => vec![HashMap::new()]
+    };
+    Ok(rows.into_iter().filter(|r| filters.iter().all(|f| holds(f, r))).collect())
+}
+
+fn compile(b: &Branch, root: Option<&str>) -> Result<(Option<Plan>, Vec<Expr>), String> {
+    let (bgp, rest) = to_bgp(b);
+    if bgp.patterns.is_empty() {
+        if !bgp.optional.is_empty() {
+            return Err("OPTIONAL needs required patterns to attach to".to_string())
+        }
+        return Ok((None, rest))
+    }
+    Ok((Some(bgp::compile(&bgp, root)?), rest))
+}
+
+// a variable bound by the required patterns of every branch
+fn common_root(branches: &[Branch]) -> Option<String> {
+    let first = Bgp::new(branches.first()?.patterns.clone()).vars();
+    first.into_iter().find(|v| {
+        branches.iter().all(|b| b.patterns.iter().any(|p| p.vars().contains(&v.as_str())))
+    })
+}
+
+fn solutions(qs: Rc<RefCell<dyn QuadStore>>, branches: &[Branch]) -> Result<Vec<HashMap<String, Value>>, String> {
+    // branches that start from the same variable and filter rows the same way run as one Union
+    if branches.len() > 1 {
+        if let Some(root) = common_root(branches) {
+            let mut plans = Vec::new();
+            let mut filters = None;
+            for b in branches {
+                let (plan, rest) = compile(b, Some(&root))?;
+                if *filters.get_or_insert_with(|| rest.clone()) != rest {
+                    break;
+                }
+                if let Some(p) = plan {
+                    plans.push(p);
+                }
+            }
+            if plans.len() == branches.len() {
+                if let Some(plan) = bgp::union(&plans) {
+                    return run_plan(qs, Some(&plan), &filters.unwrap_or_default())
+                }
+            }
+        }
+    }
+
+    let mut rows = Vec::new();
+    for b in branches {
+        let (plan, rest) = compile(b, None)?;
+        rows.extend(run_plan(qs.clone(), plan.as_ref(), &rest)?);
+    }
+    Ok(rows)
+}
+
+fn add_visible(t: &Term, out: &mut Vec<String>) {
+    if let Some(v) = t.var() {
+        if !v.starts_with("_:") && !out.iter().any(|x| x == v) {
+            out.push(v.to_string());
+        }
+    }
+}
+
+fn visible_vars(elements: &[Element], out: &mut Vec<String>) {
+    for e in elements {
+        match e {
+            Element::Triple(p) => {
+                add_visible(&p.subject, out);
+                add_visible(&p.predicate, out);
+                add_visible(&p.object, out);
+            },
+            Element::Graph(l, group) => {
+                add_visible(l, out);
+                visible_vars(group, out);
+            },
+            Element::Optional(group) => visible_vars(group, out),
+            Element::Union(alts) => alts.iter().for_each(|a| visible_vars(a, out)),
+            Element::Filter(_) => {}
+        }
+    }
+}
+
+
+impl Query {
+    pub fn run(&self, qs: Rc<RefCell<dyn QuadStore>>) -> Result<Results, String> {
+        let branches = flatten(&self.pattern, None)?;
+        let mut rows = solutions(qs, &branches)?;
+
+        let (distinct, vars) = match &self.form {
+            Form::Ask => return Ok(Results::Boolean(!rows.is_empty())),
+            Form::Select { distinct, vars } => (*distinct, vars.clone())
+        };
+
+        if !self.order.is_empty() {
+            let keys: Vec<Vec<Option<Value>>> = rows.iter().map(|r| {
+                self.order.iter().map(|(e, _)| eval(e, r)).collect()
+            }).collect();
+            let mut idx: Vec<usize> = (0..rows.len()).collect();
+            idx.sort_by(|a, b| {
+                for (i, (_, desc)) in self.order.iter().enumerate() {
+                    let o = order_values(keys[*a][i].as_ref(), keys[*b][i].as_ref());
+                    let o = if *desc { o.reverse() } else { o };
+                    if o != Ordering::Equal {
+                        return o
+                    }
+                }
+                Ordering::Equal
+            });
+            let mut sorted: Vec<Option<HashMap<String, Value>>> = rows.into_iter().map(Some).collect();
+            rows = idx.iter().map(|i| sorted[*i].take().unwrap()).collect();
+        }
+
+        let vars = match vars {
+            Some(v) => v,
+            None => {
+                let mut v = Vec::new();
+                visible_vars(&self.pattern, &mut v);
+                v
+            }
+        };
+
+        let mut rows: Vec<HashMap<String, Value>> = rows.into_iter().map(|mut r| {
+            r.retain(|k, _| vars.contains(k));
+            r
+        }).collect();
+
+        if distinct {
+            let mut seen = HashSet::new();
+            rows.retain(|r| {
+                let key: Vec<Option<Value>> = vars.iter().map(|v| r.get(v).cloned()).collect();
+                seen.insert(key)
+            });
+        }
+
+        let rows = rows.into_iter().skip(self.offset).take(self.limit.unwrap_or(usize::MAX)).collect();
+
+        Ok(Results::Bindings { vars, rows })
+    }
+}
+
+
+// Parses and runs a query.
+pub fn query(qs: Rc<RefCell<dyn QuadStore>>, src: &str) -> Result<Results, String> {
+    parse(src)?.run(qs)
+}
@@ -340,4 +340,35 @@ pub fn iterated(s: Rc<RefCell<dyn Shape>>) -> Vec<i64> {
     }
     let _ = it.borrow_mut().close();
     return res
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "standalone")]
+pub fn simple_memory_graph() -> gizmo_db::query::gizmo::GraphWrapper {
+    use gizmo_db::graph::quad::Quad;
+
+    let graph = gizmo_db::query::gizmo::new_memory_graph();
+
+    graph.write(vec![
+        Quad::new("<alice>", "<follows>", "<bob>", ()),
+        Quad::new("<bob>", "<follows>", "<fred>", ()),
+        Quad::new("<bob>", "<status>", "cool_person", ()),
+
+        Quad::new("<dani>", "<follows>", "<bob>", ()),
+        Quad::new("<charlie>", "<follows>", "<bob>", ()),
+        Quad::new("<charlie>", "<follows>", "<dani>", ()),
+
+        Quad::new("<dani>", "<follows>", "<greg>", ()),
+        Quad::new("<dani>", "<status>", "cool_person", ()),
+        Quad::new("<emily>", "<follows>", "<fred>", ()),
+
+        Quad::new("<fred>", "<follows>", "<greg>", ()),
+        Quad::new("<greg>", "<status>", "cool_person", ()),
+        Quad::new("<predicates>", "<are>", "<follows>", ()),
+
+        Quad::new("<predicates>", "<are>", "<status>", ()),
+        Quad::new("<emily>", "<status>", "smart_person", "<smart_graph>"),
+        Quad::new("<greg>", "<status>", "smart_person", "<smart_graph>")
+    ]);
+
+    graph
+}
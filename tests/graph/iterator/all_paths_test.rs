@@ -0,0 +1,62 @@
+use gizmo_db::graph::iterator::all_paths::{AllPaths};
+use gizmo_db::graph::iterator::fixed::{Fixed};
+use gizmo_db::graph::iterator::Shape;
+use gizmo_db::graph::memstore::quadstore::MemStore;
+use gizmo_db::graph::quad::{QuadStore, Quad, QuadWriter, IgnoreOptions};
+use gizmo_db::graph::value::Value;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+fn store() -> Rc<RefCell<dyn QuadStore>> {
+    let qs = Rc::new(RefCell::new(MemStore::new()));
+    let qw = QuadWriter::new(qs.clone(), IgnoreOptions{ignore_dup: true, ignore_missing: true});
+    // two ways from a to d, a cycle through a, and a dead end at x
+    for (s, p, o) in [("a", "next", "b"), ("b", "next", "d"), ("a", "next", "c"), ("c", "next", "b"),
+                      ("c", "jump", "d"), ("b", "next", "a"), ("a", "next", "x"), ("d", "next", "e")] {
+        let _ = qw.add_quad(Quad::new(s, p, o, ""));
+    }
+    qs
+}
+
+fn nodes(qs: &Rc<RefCell<dyn QuadStore>>, names: &[&str]) -> Rc<RefCell<dyn Shape>> {
+    Fixed::new(names.iter().map(|n| qs.borrow().value_of(&Value::from(*n)).unwrap()).collect())
+}
+
+// the nodes along every path, from its subjects and last object
+fn paths(qs: &Rc<RefCell<dyn QuadStore>>, it: AllPaths) -> Vec<String> {
+    it.map(|p| {
+        let quads: Vec<Quad> = p.iter().map(|q| qs.borrow().quad(q).unwrap()).collect();
+        let mut names: Vec<String> = quads.iter().map(|q| q.subject.to_string()).collect();
+        names.push(quads.last().unwrap().object.to_string());
+        names.join("")
+    }).collect()
+}
+
+
+#[test]
+fn test_all_paths() {
+    let qs = store();
+    let it = AllPaths::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["d"]), None, 0, 0);
+    let mut res = paths(&qs, it);
+    // the shortest first
+    assert_eq!(vec!["abd", "acd"], { let mut v = res[..2].to_vec(); v.sort(); v });
+    res.drain(..2);
+    assert_eq!(vec!["acbd"], res);
+
+    // only some predicates, and no longer than a depth
+    let it = AllPaths::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["d"]), Some(nodes(&qs, &["next"])), 0, 0);
+    assert_eq!(vec!["abd", "acbd"], paths(&qs, it));
+    let it = AllPaths::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["d"]), Some(nodes(&qs, &["next"])), 2, 0);
+    assert_eq!(vec!["abd"], paths(&qs, it));
+
+    // the cycle back to a is not simple
+    let it = AllPaths::new(qs.clone(), nodes(&qs, &["b"]), nodes(&qs, &["e"]), None, 0, 0);
+    assert_eq!(vec!["bde", "bacde"], paths(&qs, it));
+
+    // a limit, and several ends
+    let it = AllPaths::new(qs.clone(), nodes(&qs, &["a", "c"]), nodes(&qs, &["d", "x"]), None, 0, 3);
+    assert_eq!(3, paths(&qs, it).len());
+    let it = AllPaths::new(qs.clone(), nodes(&qs, &["e"]), nodes(&qs, &["a"]), None, 0, 0);
+    assert!(paths(&qs, it).is_empty());
+}
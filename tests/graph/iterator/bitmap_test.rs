@@ -0,0 +1,129 @@
+use gizmo_db::graph::iterator::bitmap::{Bitmap, BitmapSet};
+use gizmo_db::graph::iterator::and::{And};
+use gizmo_db::graph::iterator::or::{Or};
+use gizmo_db::graph::iterator::not::{Not};
+use gizmo_db::graph::iterator::save::{tag};
+use gizmo_db::graph::iterator::{Shape};
+use gizmo_db::graph::refs::{Ref, Content};
+use gizmo_db::graph::iterator::fixed::{Fixed};
+use gizmo_db::graph::iterator::unique::{Unique};
+use gizmo_db::graph::iterator::materialize::{Materialize};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+
+// store nodes, which carry only their key
+fn nodes(ids: impl Iterator<Item = u64>) -> Rc<RefCell<dyn Shape>> {
+    Fixed::new(ids.map(|k| Ref { k: Some(k), content: Content::None }).collect())
+}
+
+fn keys(it: &Rc<RefCell<dyn Shape>>) -> Vec<u64> {
+    let it = it.borrow().iterate();
+    let mut res = Vec::new();
+    while it.borrow_mut().next() {
+        res.push(it.borrow().result().unwrap().key().unwrap());
+    }
+    res
+}
+
+fn optimized(it: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>> {
+    let n = it.borrow_mut().optimize();
+    n.unwrap_or(it)
+}
+
+
+#[test]
+fn test_bitmap_set_algebra() {
+    // sparse values, a dense run that turns into a bitset and values past 32 bits
+    let a: BTreeSet<u64> = (1..200).map(|i| i * 7).chain(70000..80000).chain(vec![1 << 40, u64::MAX]).collect();
+    let b: BTreeSet<u64> = (1..300).map(|i| i * 5).chain((65536..90000).step_by(2)).chain(vec![1 << 40]).collect();
+    let ba: Bitmap = a.iter().copied().collect();
+    let bb: Bitmap = b.iter().copied().collect();
+
+    assert_eq!(a.len(), ba.len());
+    assert!(ba.contains(u64::MAX) && !ba.contains(8));
+    assert_eq!(a.iter().copied().collect::<Vec<_>>(), ba.iter().collect::<Vec<_>>());
+
+    assert_eq!(a.intersection(&b).copied().collect::<Vec<_>>(), ba.and(&bb).iter().collect::<Vec<_>>());
+    assert_eq!(a.union(&b).copied().collect::<Vec<_>>(), ba.or(&bb).iter().collect::<Vec<_>>());
+    assert_eq!(a.difference(&b).copied().collect::<Vec<_>>(), ba.and_not(&bb).iter().collect::<Vec<_>>());
+    assert_eq!(b.difference(&a).copied().collect::<Vec<_>>(), bb.and_not(&ba).iter().collect::<Vec<_>>());
+
+    assert_eq!(Some(70000), ba.seek(1396));
+    assert_eq!(None, Bitmap::new().seek(0));
+}
+
+
+#[test]
+fn test_and_bitmap() {
+    let a = nodes(1..200);
+    let b = nodes((100..400).rev());
+    let ands = optimized(And::new(vec![a, b]));
+
+    assert_eq!("Bitmap", ands.borrow_mut().shape_type().to_string());
+    assert_eq!((100..200).collect::<Vec<_>>(), keys(&ands));
+
+    let lookup = ands.borrow().lookup();
+    assert!(lookup.borrow_mut().contains(&Ref { k: Some(150), content: Content::None }));
+    assert!(!lookup.borrow_mut().contains(&Ref { k: Some(250), content: Content::None }));
+}
+
+
+#[test]
+fn test_and_not_bitmap() {
+    // the first hundred nodes except the even ones
+    let all = nodes(1..1000);
+    let excluded: Rc<RefCell<dyn Shape>> = Not::new(nodes((2..1000).step_by(2)), all);
+    let ands = optimized(And::new(vec![nodes(1..100), excluded]));
+
+    assert_eq!("Bitmap", ands.borrow_mut().shape_type().to_string());
+    assert_eq!((1..100).step_by(2).collect::<Vec<_>>(), keys(&ands));
+}
+
+
+#[test]
+fn test_or_bitmap() {
+    let ors = optimized(Or::new(vec![nodes(100..200), nodes(1..50)]));
+    assert_eq!("Bitmap", ors.borrow_mut().shape_type().to_string());
+    assert_eq!((1..50).chain(100..200).collect::<Vec<_>>(), keys(&ors));
+
+    // overlapping sets return the shared values twice
+    let ors = optimized(Or::new(vec![nodes(1..100), nodes(50..150)]));
+    assert_eq!("Or", ors.borrow_mut().shape_type().to_string());
+    assert_eq!(199, keys(&ors).len());
+
+    let ors = optimized(Or::new_short_circuit(vec![nodes(0..0), nodes(1..100), nodes(50..150)]));
+    assert_eq!((1..100).collect::<Vec<_>>(), keys(&ors));
+}
+
+
+#[test]
+fn test_bitmap_needs_plain_sets() {
+    // tags are kept per result, so tagged sets are intersected one at a time
+    let ands = optimized(And::new(vec![tag(&nodes(1..200), &"a"), nodes(100..400)]));
+    assert_eq!("And", ands.borrow_mut().shape_type().to_string());
+
+    // small sets aren't worth it
+    let ands = optimized(And::new(vec![nodes(1..10), nodes(5..20)]));
+    assert_eq!("And", ands.borrow_mut().shape_type().to_string());
+
+    // values that carry content would lose it
+    let values: Rc<RefCell<dyn Shape>> = Fixed::new((1..200).map(Ref::new_i64_node).collect());
+    assert!(values.borrow_mut().bitmap().is_none());
+
+    let set: Rc<RefCell<dyn Shape>> = BitmapSet::new(Rc::new((1..5).collect()));
+    assert_eq!(vec![1, 2, 3, 4], keys(&set));
+}
+
+
+#[test]
+fn test_bitmap_not_read_while_optimizing() {
+    // a set with no bitmap of its own is only read when the query runs
+    let set: Rc<RefCell<dyn Shape>> = Unique::new(Or::new(vec![nodes(1..100), nodes(50..150)]));
+    assert!(Materialize::new(set.clone()).borrow_mut().bitmap().is_none());
+
+    let excluded = optimized(Not::new(set, nodes(1..1000)));
+    assert_eq!("Not", excluded.borrow_mut().shape_type().to_string());
+    assert_eq!((150..1000).collect::<Vec<_>>(), keys(&excluded));
+}
@@ -0,0 +1,169 @@
+use gizmo_db::graph::iterator::join::{HashJoin, MergeJoin, JoinKind, choose, join};
+use gizmo_db::graph::iterator::save::{tag};
+use gizmo_db::graph::iterator::or::{Or};
+use gizmo_db::graph::iterator::{Shape, Costs};
+use gizmo_db::graph::refs::{Ref, Size};
+use gizmo_db::graph::iterator::fixed::{Fixed};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+
+fn fixed(ids: &[i64]) -> Rc<RefCell<dyn Shape>> {
+    Fixed::new(ids.iter().map(|i| Ref::new_i64_node(*i)).collect())
+}
+
+// node refs in ascending key order
+fn sorted_fixed(ids: &[i64]) -> Rc<RefCell<dyn Shape>> {
+    let mut refs: Vec<Ref> = ids.iter().map(|i| Ref::new_i64_node(*i)).collect();
+    refs.sort_by_key(|r| r.key());
+    Fixed::new(refs)
+}
+
+fn collect(it: &Rc<RefCell<dyn Shape>>) -> Vec<Ref> {
+    let it = it.borrow().iterate();
+    let mut res = Vec::new();
+    while it.borrow_mut().next() {
+        res.push(it.borrow().result().unwrap());
+    }
+    res
+}
+
+fn costs(size: i64, next_cost: i64, contains_cost: i64) -> Costs {
+    Costs {
+        contains_cost,
+        next_cost,
+        size: Size {
+            value: size,
+            exact: true
+        }
+    }
+}
+
+
+#[test]
+fn test_hash_join() {
+    let build = tag(&fixed(&[5, 3, 9]), &"b");
+    let probe = tag(&fixed(&[1, 9, 2, 3, 4]), &"p");
+    let j: Rc<RefCell<dyn Shape>> = HashJoin::new(build, probe);
+
+    assert_eq!(vec![Ref::new_i64_node(9), Ref::new_i64_node(3)], collect(&j));
+
+    let it = j.borrow().iterate();
+    assert!(it.borrow_mut().next());
+    let mut tags = HashMap::new();
+    it.borrow().tag_results(&mut tags);
+    assert_eq!(hashmap!{
+        "b".into() => Ref::new_i64_node(9),
+        "p".into() => Ref::new_i64_node(9),
+    }, tags);
+
+    let lookup = j.borrow().lookup();
+    assert!(lookup.borrow_mut().contains(&Ref::new_i64_node(3)));
+    assert!(!lookup.borrow_mut().contains(&Ref::new_i64_node(5)));
+    assert!(!lookup.borrow_mut().contains(&Ref::new_i64_node(4)));
+}
+
+
+#[test]
+fn test_hash_join_paths() {
+    // the build side returns 2 twice with different tags
+    let build: Rc<RefCell<dyn Shape>> = Or::new(vec![
+        tag(&fixed(&[2]), &"x"),
+        tag(&fixed(&[2]), &"y"),
+    ]);
+    let j: Rc<RefCell<dyn Shape>> = HashJoin::new(build, fixed(&[1, 2]));
+
+    let it = j.borrow().iterate();
+    assert!(it.borrow_mut().next());
+    assert_eq!(Ref::new_i64_node(2), it.borrow().result().unwrap());
+
+    let mut paths = Vec::new();
+    loop {
+        let mut tags = HashMap::new();
+        it.borrow().tag_results(&mut tags);
+        let mut names: Vec<String> = tags.into_keys().collect();
+        names.sort();
+        paths.push(names);
+        if !it.borrow_mut().next_path() {
+            break;
+        }
+    }
+    paths.sort();
+    assert_eq!(vec![vec!["x".to_string()], vec!["y".to_string()]], paths);
+    assert!(!it.borrow_mut().next());
+}
+
+
+#[test]
+fn test_merge_join() {
+    let left = tag(&sorted_fixed(&[1, 3, 4, 7, 9]), &"l");
+    let right = tag(&sorted_fixed(&[2, 3, 7, 8, 9, 10]), &"r");
+    assert!(left.borrow_mut().sorted() && right.borrow_mut().sorted());
+
+    let j: Rc<RefCell<dyn Shape>> = MergeJoin::new(left, right);
+    let mut expected = vec![
+        Ref::new_i64_node(3),
+        Ref::new_i64_node(7),
+        Ref::new_i64_node(9),
+    ];
+    expected.sort_by_key(|r| r.key());
+    assert_eq!(expected, collect(&j));
+
+    let it = j.borrow().iterate();
+    assert!(it.borrow_mut().next());
+    let mut tags = HashMap::new();
+    it.borrow().tag_results(&mut tags);
+    assert_eq!(hashmap!{
+        "l".into() => expected[0].clone(),
+        "r".into() => expected[0].clone(),
+    }, tags);
+
+    let st = j.borrow_mut().stats().unwrap();
+    assert_eq!(5, st.size.value);
+
+    let lookup = j.borrow().lookup();
+    assert!(lookup.borrow_mut().contains(&Ref::new_i64_node(9)));
+    assert!(!lookup.borrow_mut().contains(&Ref::new_i64_node(8)));
+}
+
+
+#[test]
+fn test_fixed_sorted() {
+    assert!(sorted_fixed(&[1, 2, 5]).borrow_mut().sorted());
+    assert!(!fixed(&[1, 1]).borrow_mut().sorted());
+
+    let mut refs = vec![Ref::new_i64_node(1), Ref::new_i64_node(2)];
+    refs.sort_by_key(|r| std::cmp::Reverse(r.key()));
+    let fix: Rc<RefCell<dyn Shape>> = Fixed::new(refs);
+    assert!(!fix.borrow_mut().sorted());
+}
+
+
+#[test]
+fn test_choose_join() {
+    // small inputs keep the nested loop
+    assert_eq!(JoinKind::NestedLoop, choose(&costs(10, 1, 500), &costs(10, 1, 500), true));
+    // cheap lookups keep the nested loop
+    assert_eq!(JoinKind::NestedLoop, choose(&costs(1000, 1, 1), &costs(2000, 1, 1), true));
+    // expensive lookups are replaced by scans
+    assert_eq!(JoinKind::Merge, choose(&costs(1000, 2, 50), &costs(2000, 2, 50), true));
+    assert_eq!(JoinKind::Hash, choose(&costs(1000, 2, 50), &costs(2000, 2, 50), false));
+}
+
+
+#[test]
+fn test_join_picks_build_side() {
+    let small = fixed(&(0..100).collect::<Vec<_>>());
+    let large = fixed(&(0..1000).collect::<Vec<_>>());
+
+    // fixed iterators are cheap to check, so the nested loop stays
+    assert!(join(&large, &small).is_none());
+
+    // a lookup through an Or checks every branch
+    let branches = (0..100).map(|_| fixed(&[5000])).chain(std::iter::once(small.clone())).collect();
+    let expensive: Rc<RefCell<dyn Shape>> = Or::new(branches);
+    let j = join(&large, &expensive).unwrap();
+    assert_eq!("HashJoin", j.borrow_mut().shape_type().to_string());
+    assert_eq!(100, collect(&j).len());
+}
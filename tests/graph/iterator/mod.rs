@@ -8,5 +8,12 @@ mod recursive_test;
 mod resolver_test;
 mod unique_test;
 mod skip_test;
+mod triejoin_test;
+mod join_test;
+mod bitmap_test;
+mod parallel_test;
+mod shortest_path_test;
+mod weighted_path_test;
+mod all_paths_test;
 
 use super::common;
\ No newline at end of file
@@ -0,0 +1,130 @@
+use gizmo_db::graph::iterator::parallel::{Parallel, Branch};
+use gizmo_db::graph::iterator::fixed::{Fixed};
+use gizmo_db::graph::iterator::save::{tag};
+use gizmo_db::graph::iterator::{Shape, Error};
+use gizmo_db::graph::iterator::context::{QueryContext, ContextError};
+use gizmo_db::graph::memstore::quadstore::MemStore;
+use gizmo_db::graph::quad::{QuadStore, Quad, QuadWriter, IgnoreOptions, Direction};
+use gizmo_db::graph::refs::{Ref};
+use gizmo_db::graph::value::Value;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+
+fn fixed(ids: std::ops::Range<i64>, name: &'static str) -> Branch {
+    Arc::new(move |_qs| tag(&(Fixed::new(ids.clone().map(Ref::new_i64_node).collect()) as Rc<RefCell<dyn Shape>>), &name))
+}
+
+fn collect(it: &Rc<RefCell<dyn Shape>>) -> Vec<(Ref, Vec<HashMap<String, Ref>>)> {
+    let it = it.borrow().iterate();
+    let mut res = Vec::new();
+    while it.borrow_mut().next() {
+        let mut paths = Vec::new();
+        loop {
+            let mut tags = HashMap::new();
+            it.borrow().tag_results(&mut tags);
+            paths.push(tags);
+            if !it.borrow_mut().next_path() {
+                break
+            }
+        }
+        res.push((it.borrow().result().unwrap(), paths));
+    }
+    assert_eq!(None, it.borrow().err());
+    res
+}
+
+fn store() -> Rc<RefCell<dyn QuadStore>> {
+    let qs = Rc::new(RefCell::new(MemStore::new()));
+    let qw = QuadWriter::new(qs.clone(), IgnoreOptions{ignore_dup: true, ignore_missing: true});
+    for i in 0..100 {
+        let _ = qw.add_quad(Quad::new(format!("<n{}>", i).as_str(), "<next>", format!("<n{}>", i + 1).as_str(), ()));
+    }
+    qs
+}
+
+
+#[test]
+fn test_parallel_keeps_branch_order() {
+    let qs = store();
+    let branches = vec![fixed(0..500, "a"), fixed(500..600, "b"), fixed(600..1000, "c")];
+    let par: Rc<RefCell<dyn Shape>> = Parallel::new(qs.borrow().shared().unwrap(), branches, 2);
+
+    let res = collect(&par);
+    assert_eq!((0..1000).map(Ref::new_i64_node).collect::<Vec<_>>(), res.iter().map(|r| r.0.clone()).collect::<Vec<_>>());
+    assert_eq!(hashmap!{"b".to_string() => Ref::new_i64_node(550)}, res[550].1[0]);
+
+    let st = par.borrow_mut().stats().unwrap();
+    assert_eq!(1000, st.size.value);
+
+    let lookup = par.borrow().lookup();
+    assert!(lookup.borrow_mut().contains(&Ref::new_i64_node(700)));
+    assert!(!lookup.borrow_mut().contains(&Ref::new_i64_node(1000)));
+}
+
+
+#[test]
+fn test_parallel_branches_use_store() {
+    let qs = store();
+    // every worker opens the store and reads the links of its nodes
+    let branches: Vec<Branch> = (0..10).map(|i| {
+        Arc::new(move |qs: Rc<RefCell<dyn QuadStore>>| {
+            let n = qs.borrow().value_of(&Value::from(format!("<n{}>", i * 10).as_str())).unwrap();
+            let it = qs.borrow().quad_iterator(&Direction::Subject, &n);
+            it
+        }) as Branch
+    }).collect();
+    let par: Rc<RefCell<dyn Shape>> = Parallel::new(qs.borrow().shared().unwrap(), branches, 4);
+
+    let quads: Vec<String> = collect(&par).iter().map(|r| qs.borrow().quad(&r.0).unwrap().subject.to_string()).collect();
+    assert_eq!((0..10).map(|i| format!("<n{}>", i * 10)).collect::<Vec<_>>(), quads);
+}
+
+
+#[test]
+fn test_parallel_error_and_early_close() {
+    let qs = store();
+    let failing: Branch = Arc::new(|_qs| Error::new("broken".to_string()));
+    let par: Rc<RefCell<dyn Shape>> = Parallel::new(qs.borrow().shared().unwrap(), vec![fixed(0..3, "a"), failing, fixed(3..5, "b")], 3);
+
+    let it = par.borrow().iterate();
+    let mut n = 0;
+    while it.borrow_mut().next() {
+        n += 1;
+    }
+    assert_eq!(3, n);
+    assert_eq!(Some("broken".to_string()), it.borrow().err());
+
+    // workers blocked on a full buffer stop when the reader goes away
+    let par: Rc<RefCell<dyn Shape>> = Parallel::new(qs.borrow().shared().unwrap(), vec![fixed(0..10000, "a"), fixed(0..10000, "b")], 2);
+    let it = par.borrow().iterate();
+    assert!(it.borrow_mut().next());
+    assert!(it.borrow_mut().close().is_ok());
+    assert!(!it.borrow_mut().next());
+}
+
+
+#[test]
+fn test_parallel_workers_stop_on_context() {
+    let qs = store();
+    let ctx = QueryContext::new();
+    let cancel = ctx.cancel_handle();
+    // the second branch cancels the query as its worker builds it
+    let branches = vec![fixed(0..500, "a"), Arc::new(move |qs: Rc<RefCell<dyn QuadStore>>| {
+        cancel.cancel();
+        fixed(500..1000, "b")(qs)
+    }) as Branch];
+    let par = Parallel::new(qs.borrow().shared().unwrap(), branches, 2);
+    par.borrow_mut().set_context(ctx.clone());
+
+    let it = par.borrow().iterate();
+    let mut n = 0;
+    while it.borrow_mut().next() {
+        n += 1;
+    }
+    assert!(n <= 500);
+    assert_eq!(Some("query cancelled".to_string()), it.borrow().err());
+    assert_eq!(Some(ContextError::Cancelled), ctx.err());
+}
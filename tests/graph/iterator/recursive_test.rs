@@ -1,7 +1,7 @@
 use gizmo_db::graph::iterator::fixed::{Fixed};
 use gizmo_db::graph::iterator::and::{And};
 use gizmo_db::graph::iterator::save::{tag};
-use gizmo_db::graph::iterator::recursive::{Recursive};
+use gizmo_db::graph::iterator::recursive::{Recursive, Emit, Order};
 use gizmo_db::graph::iterator::{Shape, Morphism};
 use gizmo_db::graph::refs::{pre_fetched, Namer};
 use gizmo_db::graph::value::{Value};
@@ -10,7 +10,7 @@ use gizmo_db::graph::hasa::{HasA};
 use gizmo_db::graph::graphmock::{Store};
 use gizmo_db::graph::quad::{Quad, QuadStore, Direction};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 
 
@@ -33,6 +33,20 @@ impl Morphism for SingleHop {
 }
 
 
+// counts the trees built, one for every level followed
+struct CountedHop {
+    hop: SingleHop,
+    morphs: Rc<Cell<i32>>
+}
+
+impl Morphism for CountedHop {
+    fn morph(&self, shape: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>> {
+        self.morphs.set(self.morphs.get() + 1);
+        self.hop.morph(shape)
+    }
+}
+
+
 fn rec_test_qs() -> Store {
     Store {
         data: vec![
@@ -133,4 +147,113 @@ fn test_recursive_next_path() {
     got.sort();
 
     assert_eq!(expected, got);
-}
\ No newline at end of file
+}
+
+
+fn rec_results(r: Rc<RefCell<Recursive>>) -> Vec<(String, Value)> {
+    r.borrow_mut().add_depth_tag("depth".to_string());
+    let it = r.borrow().iterate();
+    let mut got = Vec::new();
+    while it.borrow_mut().next() {
+        let mut res = HashMap::new();
+        it.borrow().tag_results(&mut res);
+        let re = it.borrow().result().unwrap();
+        got.push((re.unwrap_value().to_string(), res["depth"].unwrap_value().clone()));
+    }
+    got
+}
+
+
+#[test]
+fn test_recursive_min_depth() {
+    let qs = Rc::new(RefCell::new(rec_test_qs()));
+    let start = Fixed::new(vec![pre_fetched(Value::from("alice"))]);
+    let r = Recursive::new(start, Rc::new(SingleHop {qs: qs.clone(), pred: "parent".to_string()}), 0);
+    r.borrow_mut().set_min_depth(3);
+
+    assert_eq!(vec![
+        ("dani".to_string(), Value::from(3)),
+        ("emily".to_string(), Value::from(4))
+    ], rec_results(r));
+}
+
+
+#[test]
+fn test_recursive_leaves() {
+    let qs = Rc::new(RefCell::new(rec_test_qs()));
+    let start = Fixed::new(vec![pre_fetched(Value::from("alice"))]);
+    let r = Recursive::new(start.clone(), Rc::new(SingleHop {qs: qs.clone(), pred: "parent".to_string()}), 0);
+    r.borrow_mut().set_emit(Emit::Leaves);
+
+    // charlie goes back to bob, which was seen before
+    assert_eq!(vec![("emily".to_string(), Value::from(4))], rec_results(r));
+
+    let r = Recursive::new(start, Rc::new(SingleHop {qs: qs.clone(), pred: "parent".to_string()}), 2);
+    r.borrow_mut().set_emit(Emit::Leaves);
+
+    assert_eq!(vec![("charlie".to_string(), Value::from(2))], rec_results(r));
+}
+
+
+#[test]
+fn test_recursive_until() {
+    let qs = Rc::new(RefCell::new(rec_test_qs()));
+    let start = Fixed::new(vec![pre_fetched(Value::from("alice"))]);
+    let r = Recursive::new(start.clone(), Rc::new(SingleHop {qs: qs.clone(), pred: "parent".to_string()}), 0);
+    r.borrow_mut().set_until(Fixed::new(vec![pre_fetched(Value::from("charlie"))]));
+
+    assert_eq!(vec![
+        ("bob".to_string(), Value::from(1)),
+        ("charlie".to_string(), Value::from(2))
+    ], rec_results(r));
+
+    let r = Recursive::new(start, Rc::new(SingleHop {qs: qs.clone(), pred: "parent".to_string()}), 0);
+    r.borrow_mut().set_until(Fixed::new(vec![pre_fetched(Value::from("charlie"))]));
+    let it = r.borrow().lookup();
+
+    assert!(it.borrow_mut().contains(&pre_fetched(Value::from("charlie"))));
+    assert!(!it.borrow_mut().contains(&pre_fetched(Value::from("dani"))));
+}
+
+
+#[test]
+fn test_recursive_morphs_once_per_level() {
+    let qs = Rc::new(RefCell::new(rec_test_qs()));
+    let morphs = Rc::new(Cell::new(0));
+    let start = Fixed::new(vec![pre_fetched(Value::from("alice")), pre_fetched(Value::from("charlie"))]);
+    let r = Recursive::new(start, Rc::new(CountedHop {hop: SingleHop {qs: qs.clone(), pred: "parent".to_string()}, morphs: morphs.clone()}), 0);
+
+    // alice and charlie, then bob and dani, then charlie again and emily
+    let mut got: Vec<(String, Value)> = rec_results(r);
+    got.sort_by_key(|(n, _)| n.clone());
+    assert_eq!(vec![
+        ("bob".to_string(), Value::from(1)),
+        ("charlie".to_string(), Value::from(2)),
+        ("dani".to_string(), Value::from(1)),
+        ("emily".to_string(), Value::from(2))
+    ], got);
+    assert_eq!(3, morphs.get());
+}
+
+
+#[test]
+fn test_recursive_depth_first_follows_levels_as_needed() {
+    let qs = Rc::new(RefCell::new(rec_test_qs()));
+    let morphs = Rc::new(Cell::new(0));
+    let start = Fixed::new(vec![pre_fetched(Value::from("alice"))]);
+    let r = Recursive::new(start, Rc::new(CountedHop {hop: SingleHop {qs: qs.clone(), pred: "parent".to_string()}, morphs: morphs.clone()}), 0);
+    r.borrow_mut().set_order(Order::DepthFirst);
+
+    // bob is returned once the levels of alice and bob are followed
+    let it = r.borrow().iterate();
+    assert!(it.borrow_mut().next());
+    assert_eq!("bob", it.borrow().result().unwrap().unwrap_value().to_string());
+    assert_eq!(2, morphs.get());
+
+    let mut got = Vec::new();
+    while it.borrow_mut().next() {
+        got.push(it.borrow().result().unwrap().unwrap_value().to_string());
+    }
+    assert_eq!(vec!["charlie", "dani", "emily"], got);
+    assert_eq!(5, morphs.get());
+}
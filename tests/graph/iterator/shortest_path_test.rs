@@ -0,0 +1,112 @@
+use gizmo_db::graph::iterator::shortest_path::{ShortestPath, node_tag, via_tag};
+use gizmo_db::graph::iterator::fixed::{Fixed};
+use gizmo_db::graph::iterator::Shape;
+use gizmo_db::graph::iterator::context::{QueryContext, ContextError};
+use gizmo_db::graph::memstore::quadstore::MemStore;
+use gizmo_db::graph::quad::{QuadStore, Quad, QuadWriter, IgnoreOptions};
+use gizmo_db::graph::refs::Ref;
+use gizmo_db::graph::value::Value;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+
+fn store() -> Rc<RefCell<dyn QuadStore>> {
+    let qs = Rc::new(RefCell::new(MemStore::new()));
+    let qw = QuadWriter::new(qs.clone(), IgnoreOptions{ignore_dup: true, ignore_missing: true});
+    // a long way round from a to e, and a shortcut through x
+    for (s, p, o) in [("a", "next", "b"), ("b", "next", "c"), ("c", "next", "d"), ("d", "next", "e"),
+                      ("a", "jump", "x"), ("x", "next", "e"), ("c", "next", "a"), ("f", "next", "a")] {
+        let _ = qw.add_quad(Quad::new(s, p, o, ""));
+    }
+    qs
+}
+
+fn nodes(qs: &Rc<RefCell<dyn QuadStore>>, names: &[&str]) -> Rc<RefCell<dyn Shape>> {
+    Fixed::new(names.iter().map(|n| qs.borrow().value_of(&Value::from(*n)).unwrap()).collect())
+}
+
+// every target found, with the names of the nodes and predicates on its path
+fn routes(qs: &Rc<RefCell<dyn QuadStore>>, sp: &Rc<RefCell<dyn Shape>>) -> Vec<(Vec<String>, Vec<String>)> {
+    let name = |r: &Ref| qs.borrow().name_of(r).unwrap().to_string();
+    let it = sp.borrow().iterate();
+    let mut res = Vec::new();
+    while it.borrow_mut().next() {
+        let mut tags = HashMap::new();
+        it.borrow().tag_results(&mut tags);
+        let mut path = Vec::new();
+        let mut preds = Vec::new();
+        while let Some(n) = tags.get(&node_tag(path.len())) {
+            if let Some(p) = tags.get(&via_tag(path.len())) {
+                preds.push(name(p));
+            }
+            path.push(name(n));
+        }
+        assert_eq!(Some(path.last().unwrap().clone()), it.borrow().result().map(|r| name(&r)));
+        res.push((path, preds));
+    }
+    assert_eq!(None, it.borrow().err());
+    res
+}
+
+
+#[test]
+fn test_shortest_path() {
+    let qs = store();
+    let sp: Rc<RefCell<dyn Shape>> = ShortestPath::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["e", "d", "a", "f"]), None, 0, false);
+    assert_eq!(vec![
+        (vec!["a".to_string(), "x".into(), "e".into()], vec!["jump".to_string(), "next".into()]),
+        (vec!["a".to_string(), "b".into(), "c".into(), "d".into()], vec!["next".to_string(), "next".into(), "next".into()]),
+        (vec!["a".to_string()], vec![])
+    ], routes(&qs, &sp));
+
+    // only following some predicates
+    let sp: Rc<RefCell<dyn Shape>> = ShortestPath::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["e"]), Some(nodes(&qs, &["next"])), 0, false);
+    assert_eq!(vec!["a", "b", "c", "d", "e"], routes(&qs, &sp)[0].0);
+
+    // too far
+    let sp: Rc<RefCell<dyn Shape>> = ShortestPath::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["e"]), Some(nodes(&qs, &["next"])), 3, false);
+    assert!(routes(&qs, &sp).is_empty());
+
+    // against the direction of the links
+    let sp: Rc<RefCell<dyn Shape>> = ShortestPath::new(qs.clone(), nodes(&qs, &["e"]), nodes(&qs, &["f", "b"]), None, 0, true);
+    assert_eq!(vec![
+        (vec!["e".to_string(), "x".into(), "a".into(), "f".into()], vec!["next".to_string(), "jump".into(), "next".into()]),
+        (vec!["e".to_string(), "d".into(), "c".into(), "b".into()], vec!["next".to_string(), "next".into(), "next".into()])
+    ], routes(&qs, &sp));
+}
+
+
+#[test]
+fn test_shortest_path_contains() {
+    let qs = store();
+    let sp = ShortestPath::new(qs.clone(), nodes(&qs, &["b", "x"]), nodes(&qs, &["a", "e", "f"]), None, 0, false);
+    let it = sp.borrow().lookup();
+
+    let e = qs.borrow().value_of(&Value::from("e")).unwrap();
+    assert!(it.borrow_mut().contains(&e));
+    let mut tags = HashMap::new();
+    it.borrow().tag_results(&mut tags);
+    assert_eq!(Some(&qs.borrow().value_of(&Value::from("x")).unwrap()), tags.get(&node_tag(0)));
+    assert_eq!(Some(&e), tags.get(&node_tag(1)));
+
+    // nothing leads to f, and b is not a target
+    assert!(!it.borrow_mut().contains(&qs.borrow().value_of(&Value::from("f")).unwrap()));
+    assert!(!it.borrow_mut().contains(&qs.borrow().value_of(&Value::from("b")).unwrap()));
+    assert!(it.borrow_mut().contains(&qs.borrow().value_of(&Value::from("a")).unwrap()));
+}
+
+
+#[test]
+fn test_shortest_path_stops_on_context() {
+    let qs = store();
+    let sp = ShortestPath::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["e", "d"]), None, 0, false);
+    let ctx = QueryContext::new();
+    sp.borrow_mut().set_context(ctx.clone());
+    ctx.cancel_handle().cancel();
+
+    // no level is expanded once the query is cancelled
+    let it = sp.borrow().iterate();
+    assert!(!it.borrow_mut().next());
+    assert_eq!(Some(ContextError::Cancelled), ctx.err());
+}
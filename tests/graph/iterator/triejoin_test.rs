@@ -0,0 +1,127 @@
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::triejoin::{TrieJoin, Atom, Slot};
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::{Shape, Null};
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::context::{self, QueryContext};
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::{Quad, Direction, QuadStore};
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+#[cfg(feature = "standalone")]
+use std::rc::Rc;
+#[cfg(feature = "standalone")]
+use std::cell::RefCell;
+#[cfg(feature = "standalone")]
+use std::collections::HashMap;
+#[cfg(feature = "standalone")]
+use super::common;
+
+
+// x follows y, y follows z, z follows x
+#[cfg(feature = "standalone")]
+fn triangle(qs: &Rc<RefCell<dyn QuadStore>>) -> Rc<RefCell<TrieJoin>> {
+    let follows = qs.borrow().value_of(&Value::from("<follows>")).unwrap();
+    let link = |from: usize, to: usize| Atom::new(vec![
+        (Direction::Subject, Slot::Var(from)),
+        (Direction::Predicate, Slot::Fixed(follows.clone())),
+        (Direction::Object, Slot::Var(to))
+    ]);
+    TrieJoin::new(
+        qs.clone(),
+        vec!["x".to_string(), "y".to_string(), "z".to_string()],
+        vec![link(0, 1), link(1, 2), link(2, 0)]
+    )
+}
+
+#[cfg(feature = "standalone")]
+fn names(qs: &Rc<RefCell<dyn QuadStore>>, tags: &HashMap<String, gizmo_db::graph::refs::Ref>) -> Vec<String> {
+    ["x", "y", "z"].iter().map(|t| qs.borrow().name_of(&tags[*t]).unwrap().to_string()).collect()
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn test_triejoin_triangles() {
+    let graph = common::simple_memory_graph();
+    graph.write(vec![Quad::new("<greg>", "<follows>", "<charlie>", ())]);
+    let qs = graph.quad_store();
+
+    let it = triangle(&qs).borrow().iterate();
+    let mut rows = Vec::new();
+    while it.borrow_mut().next() {
+        let mut tags = HashMap::new();
+        it.borrow().tag_results(&mut tags);
+        assert_eq!(it.borrow().result(), Some(tags["x"].clone()));
+        rows.push(names(&qs, &tags));
+    }
+    rows.sort();
+
+    assert_eq!(rows, vec![
+        vec!["<charlie>", "<dani>", "<greg>"],
+        vec!["<dani>", "<greg>", "<charlie>"],
+        vec!["<greg>", "<charlie>", "<dani>"]
+    ]);
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn test_triejoin_contains() {
+    let graph = common::simple_memory_graph();
+    graph.write(vec![Quad::new("<greg>", "<follows>", "<charlie>", ())]);
+    let qs = graph.quad_store();
+    let join = triangle(&qs);
+
+    let dani = qs.borrow().value_of(&Value::from("<dani>")).unwrap();
+    let alice = qs.borrow().value_of(&Value::from("<alice>")).unwrap();
+
+    let index = join.borrow().lookup();
+    assert!(index.borrow_mut().contains(&dani));
+    let mut tags = HashMap::new();
+    index.borrow().tag_results(&mut tags);
+    assert_eq!(names(&qs, &tags), vec!["<dani>", "<greg>", "<charlie>"]);
+    assert!(!index.borrow_mut().next_path());
+
+    assert!(!index.borrow_mut().contains(&alice));
+
+    // no triangle without the closing link
+    let qs = common::simple_memory_graph().quad_store();
+    let it = triangle(&qs).borrow().iterate();
+    assert!(!it.borrow_mut().next());
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn test_triejoin_reads_sub_iterators() {
+    let graph = common::simple_memory_graph();
+    graph.write(vec![Quad::new("<greg>", "<follows>", "<charlie>", ())]);
+    let qs = graph.quad_store();
+    let join = triangle(&qs);
+    let subs = join.borrow().sub_iterators().unwrap();
+    assert_eq!(3, subs.len());
+
+    // a pattern read as empty closes no triangle
+    join.borrow_mut().map_sub_iterators(&|s| if Rc::ptr_eq(&s, &subs[0]) { Null::new() } else { s });
+    let it = join.borrow().iterate();
+    assert!(!it.borrow_mut().next());
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn test_triejoin_stops_on_context() {
+    let graph = common::simple_memory_graph();
+    graph.write(vec![Quad::new("<greg>", "<follows>", "<charlie>", ())]);
+    let qs = graph.quad_store();
+    let join = triangle(&qs);
+    let ctx = QueryContext::new();
+    join.borrow_mut().map_sub_iterators(&|s| context::guard_branch(s, &ctx));
+    ctx.cancel_handle().cancel();
+
+    // the patterns stop being read, so no triangle is found
+    let it = join.borrow().iterate();
+    assert!(!it.borrow_mut().next());
+    assert_eq!(Some("query cancelled".to_string()), it.borrow().err());
+}
@@ -0,0 +1,164 @@
+use gizmo_db::graph::iterator::weighted_path::{WeightedPath, Weight, Heuristic, COST_TAG};
+use gizmo_db::graph::iterator::shortest_path::{node_tag, via_tag};
+use gizmo_db::graph::iterator::fixed::{Fixed};
+use gizmo_db::graph::iterator::Shape;
+use gizmo_db::graph::iterator::context::{QueryContext, ContextError};
+use gizmo_db::graph::memstore::quadstore::MemStore;
+use gizmo_db::graph::quad::{QuadStore, Quad, QuadWriter, IgnoreOptions};
+use gizmo_db::graph::refs::Ref;
+use gizmo_db::graph::value::Value;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Instant;
+
+
+fn store(quads: Vec<Quad>) -> Rc<RefCell<dyn QuadStore>> {
+    let qs = Rc::new(RefCell::new(MemStore::new()));
+    let qw = QuadWriter::new(qs.clone(), IgnoreOptions{ignore_dup: true, ignore_missing: true});
+    for q in quads {
+        let _ = qw.add_quad(q);
+    }
+    qs
+}
+
+// roads with their length in the label: a-b-c-d is longer in steps but
+// shorter in total than a-d
+fn roads() -> Rc<RefCell<dyn QuadStore>> {
+    store(vec![
+        Quad::new("a", "road", "b", 1),
+        Quad::new("b", "road", "c", 2),
+        Quad::new("c", "road", "d", 1.5),
+        Quad::new("a", "road", "d", 10),
+        Quad::new("a", "rail", "d", 1),
+        Quad::new("d", "road", "e", "unknown"),
+    ])
+}
+
+fn nodes(qs: &Rc<RefCell<dyn QuadStore>>, names: &[&str]) -> Rc<RefCell<dyn Shape>> {
+    Fixed::new(names.iter().map(|n| qs.borrow().value_of(&Value::from(*n)).unwrap()).collect())
+}
+
+// every target found, with the nodes and ways on its path and its cost
+fn routes(qs: &Rc<RefCell<dyn QuadStore>>, sp: &Rc<RefCell<dyn Shape>>) -> Vec<(Vec<Value>, Vec<Value>, Value)> {
+    let name = |r: &Ref| qs.borrow().name_of(r).unwrap();
+    let it = sp.borrow().iterate();
+    let mut res = Vec::new();
+    while it.borrow_mut().next() {
+        let mut tags = HashMap::new();
+        it.borrow().tag_results(&mut tags);
+        let mut path = Vec::new();
+        let mut ways = Vec::new();
+        while let Some(n) = tags.get(&node_tag(path.len())) {
+            if let Some(v) = tags.get(&via_tag(path.len())) {
+                ways.push(name(v));
+            }
+            path.push(name(n));
+        }
+        res.push((path, ways, name(&tags[COST_TAG])));
+    }
+    assert_eq!(None, it.borrow().err());
+    res
+}
+
+fn values(v: &[&str]) -> Vec<Value> {
+    v.iter().map(|s| Value::from(*s)).collect()
+}
+
+
+#[test]
+fn test_weighted_path_label() {
+    let qs = roads();
+    let wp: Rc<RefCell<dyn Shape>> = WeightedPath::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["d", "a", "e"]), Some(nodes(&qs, &["road"])), Weight::Label, None, false);
+    assert_eq!(vec![
+        (values(&["a", "b", "c", "d"]), values(&["road", "road", "road"]), Value::from(4.5)),
+        (values(&["a"]), vec![], Value::from(0.0))
+    ], routes(&qs, &wp));
+
+    // any predicate takes the rail
+    let wp: Rc<RefCell<dyn Shape>> = WeightedPath::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["d"]), None, Weight::Label, None, false);
+    assert_eq!(vec![(values(&["a", "d"]), values(&["rail"]), Value::from(1.0))], routes(&qs, &wp));
+
+    // against the roads
+    let wp: Rc<RefCell<dyn Shape>> = WeightedPath::new(qs.clone(), nodes(&qs, &["d"]), nodes(&qs, &["b"]), Some(nodes(&qs, &["road"])), Weight::Label, None, true);
+    assert_eq!(vec![(values(&["d", "c", "b"]), values(&["road", "road"]), Value::from(3.5))], routes(&qs, &wp));
+}
+
+
+#[test]
+fn test_weighted_path_edge_nodes() {
+    let qs = store(vec![
+        Quad::new("a", "link", "e1", ""),
+        Quad::new("e1", "to", "b", ""),
+        Quad::new("e1", "cost", 5, ""),
+        Quad::new("a", "link", "e2", ""),
+        Quad::new("e2", "to", "c", ""),
+        Quad::new("e2", "cost", 1, ""),
+        Quad::new("c", "link", "e3", ""),
+        Quad::new("e3", "to", "b", ""),
+        Quad::new("e3", "cost", 2, ""),
+    ]);
+    let weight = Weight::Edge { to: Value::from("to"), cost: Value::from("cost") };
+    let wp: Rc<RefCell<dyn Shape>> = WeightedPath::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["b"]), Some(nodes(&qs, &["link"])), weight.clone(), None, false);
+    assert_eq!(vec![(values(&["a", "c", "b"]), values(&["e2", "e3"]), Value::from(3.0))], routes(&qs, &wp));
+
+    let wp: Rc<RefCell<dyn Shape>> = WeightedPath::new(qs.clone(), nodes(&qs, &["b"]), nodes(&qs, &["a"]), Some(nodes(&qs, &["link"])), weight, None, true);
+    assert_eq!(vec![(values(&["b", "c", "a"]), values(&["e3", "e2"]), Value::from(3.0))], routes(&qs, &wp));
+}
+
+
+#[test]
+fn test_weighted_path_a_star() {
+    // a grid of points named by their coordinates, with links of length 1
+    let name = |x: i64, y: i64| format!("{},{}", x, y);
+    let mut quads = Vec::new();
+    for x in 0..10 {
+        for y in 0..10 {
+            if x < 9 {
+                quads.push(Quad::new(name(x, y).as_str(), "next", name(x + 1, y).as_str(), 1));
+            }
+            if y < 9 {
+                quads.push(Quad::new(name(x, y).as_str(), "next", name(x, y + 1).as_str(), 1));
+            }
+        }
+    }
+    let qs = store(quads);
+    let coords = |v: &Value| -> (f64, f64) {
+        let s = v.to_string();
+        let (x, y) = s.split_once(',').unwrap();
+        (x.parse().unwrap(), y.parse().unwrap())
+    };
+    let manhattan: Heuristic = Rc::new(move |n, t| {
+        let (a, b) = (coords(n), coords(t));
+        (a.0 - b.0).abs() + (a.1 - b.1).abs()
+    });
+
+    let wp: Rc<RefCell<dyn Shape>> = WeightedPath::new(qs.clone(), nodes(&qs, &["0,0"]), nodes(&qs, &["9,9", "3,4"]), None, Weight::Label, Some(manhattan), false);
+    let res = routes(&qs, &wp);
+    assert_eq!(2, res.len());
+    assert_eq!((19, Value::from(18.0)), (res[0].0.len(), res[0].2.clone()));
+    assert_eq!((8, Value::from(7.0)), (res[1].0.len(), res[1].2.clone()));
+}
+
+
+#[test]
+fn test_weighted_path_negative_cost() {
+    let qs = store(vec![Quad::new("a", "road", "b", -1)]);
+    let wp = WeightedPath::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["b"]), None, Weight::Label, None, false);
+    let it = wp.borrow().iterate();
+    assert!(!it.borrow_mut().next());
+    assert!(it.borrow().err().unwrap().contains("negative"));
+}
+
+
+#[test]
+fn test_weighted_path_stops_on_context() {
+    let qs = roads();
+    let wp = WeightedPath::new(qs.clone(), nodes(&qs, &["a"]), nodes(&qs, &["d"]), None, Weight::Label, None, false);
+    let ctx = QueryContext::new().with_deadline(Instant::now());
+    wp.borrow_mut().set_context(ctx.clone());
+
+    let it = wp.borrow().iterate();
+    assert!(!it.borrow_mut().next());
+    assert_eq!(Some(ContextError::Timeout), ctx.err());
+}
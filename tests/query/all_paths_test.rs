@@ -0,0 +1,22 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn all_paths_tests() {
+    let graph = common::simple_memory_graph();
+
+    let paths: Vec<Vec<Quad>> = graph.g().all_paths("<charlie>", "<greg>", "<follows>", None, None).collect();
+    assert_eq!(vec![
+        vec![Quad::new("<charlie>", "<follows>", "<dani>", ()), Quad::new("<dani>", "<follows>", "<greg>", ())],
+        vec![Quad::new("<charlie>", "<follows>", "<bob>", ()), Quad::new("<bob>", "<follows>", "<fred>", ()), Quad::new("<fred>", "<follows>", "<greg>", ())],
+        vec![Quad::new("<charlie>", "<follows>", "<dani>", ()), Quad::new("<dani>", "<follows>", "<bob>", ()), Quad::new("<bob>", "<follows>", "<fred>", ()), Quad::new("<fred>", "<follows>", "<greg>", ())]
+    ], paths);
+
+    assert_eq!(2, graph.g().all_paths("<charlie>", "<greg>", "<follows>", Some(3), None).count());
+    assert_eq!(1, graph.g().all_paths("<charlie>", "<greg>", "<follows>", None, Some(1)).count());
+    assert_eq!(0, graph.g().all_paths("<greg>", "<charlie>", None, None, None).count());
+}
@@ -0,0 +1,54 @@
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::new_memory_graph;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+
+
+#[cfg(feature = "standalone")]
+fn names(from: i64, to: i64) -> Vec<String> {
+    let mut v: Vec<String> = (from..to).map(|i| format!("<p{}>", i)).collect();
+    v.sort();
+    v
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn bitmap_tests() {
+    let graph = new_memory_graph();
+    let mut quads = Vec::new();
+    for i in 0..300 {
+        quads.push(Quad::new(format!("<p{}>", i).as_str(), "<a>", format!("<x{}>", i % 3).as_str(), ()));
+    }
+    graph.write(quads);
+    let g = graph.g();
+
+    let first = names(0, 100);
+    let second = names(50, 120);
+    let third = names(200, 250);
+    let first: Vec<&str> = first.iter().map(|s| s.as_str()).collect();
+    let second: Vec<&str> = second.iter().map(|s| s.as_str()).collect();
+    let third: Vec<&str> = third.iter().map(|s| s.as_str()).collect();
+    let run = |p: gizmo_db::query::gizmo::Path| {
+        assert!(p.explain().to_string().starts_with("Bitmap"));
+        let mut got: Vec<String> = p.iter_values().map(|v| v.to_string()).collect();
+        got.sort();
+        got
+    };
+
+    assert_eq!(names(50, 100), run(g.v(first.clone()).and(&g.v(second.clone()))));
+    assert_eq!(names(0, 50), run(g.v(first.clone()).except(&g.v(second.clone()))));
+    let mut both = names(0, 100);
+    both.extend(names(200, 250));
+    both.sort();
+    assert_eq!(both, run(g.v(first.clone()).union(&g.v(third.clone()))));
+
+    // every node but the excluded ones, the objects and predicate included
+    let mut rest = names(100, 300);
+    rest.extend(vec!["<a>".to_string(), "<x0>".to_string(), "<x1>".to_string(), "<x2>".to_string()]);
+    rest.sort();
+    assert_eq!(rest, run(g.v(None).except(&g.v(first.clone()))));
+
+    // overlapping branches return a node once for each
+    assert_eq!(170, g.v(first.clone()).union(&g.v(second.clone())).count());
+}
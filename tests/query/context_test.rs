@@ -0,0 +1,61 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::context::{QueryContext, ContextError};
+#[cfg(feature = "standalone")]
+use std::time::Duration;
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn context_tests() {
+    let g = common::simple_memory_graph().g();
+
+    let all = g.v(None).count();
+    assert!(all > 2);
+
+    // within the limits nothing changes
+    let ctx = QueryContext::new().with_max_rows(all).with_timeout(Duration::from_secs(60));
+    let n = g.v(None).with_context(ctx.clone()).iter_values().count() as i64;
+    assert_eq!(n, all);
+    assert_eq!(ctx.err(), None);
+
+    // too many rows ends iteration with an error
+    let ctx = QueryContext::new().with_max_rows(2);
+    let n = g.v(None).with_context(ctx.clone()).iter_values().count();
+    assert_eq!(n, 2);
+    assert_eq!(ctx.err(), Some(ContextError::RowLimit(2)));
+
+    // cancelled from another handle
+    let ctx = QueryContext::new();
+    let handle = ctx.cancel_handle();
+    std::thread::spawn(move || handle.cancel()).join().unwrap();
+    let n = g.v(None).with_context(ctx.clone()).iter_tags().count();
+    assert_eq!(n, 0);
+    assert_eq!(ctx.err(), Some(ContextError::Cancelled));
+
+    // deadline already passed
+    let ctx = QueryContext::new().with_timeout(Duration::from_secs(0));
+    let n = g.v("<alice>").follow_recursive("<follows>", None, None).with_context(ctx.clone()).iter_values().count();
+    assert_eq!(n, 0);
+    assert_eq!(ctx.err(), Some(ContextError::Timeout));
+
+    // sorting buffers every node before returning the first one
+    let ctx = QueryContext::new().with_max_materialized(3);
+    let n = g.v(None).order().with_context(ctx.clone()).iter_values().count();
+    assert_eq!(n, 0);
+    assert_eq!(ctx.err(), Some(ContextError::MaterializeLimit(3)));
+    assert_eq!(ctx.err().unwrap().to_string(), "query buffered more than 3 results");
+
+    // recursion keeps every node it has seen
+    let ctx = QueryContext::new().with_max_materialized(1);
+    g.v("<alice>").follow_recursive("<follows>", None, None).with_context(ctx.clone()).iter_values().count();
+    assert_eq!(ctx.err(), Some(ContextError::MaterializeLimit(1)));
+
+    // a node the limit cut the check short on is not known to be absent
+    let ctx = QueryContext::new().with_max_materialized(1);
+    let rows: Vec<Result<String, String>> = g.v(None).except(&g.v(None).out("<follows>", None))
+        .with_context(ctx.clone()).try_iter_values().map(|r| r.map(|v| v.to_string())).collect();
+    assert_eq!(rows, vec![Err("query buffered more than 1 results".to_string())]);
+    assert_eq!(ctx.err(), Some(ContextError::MaterializeLimit(1)));
+}
@@ -0,0 +1,60 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Direction;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::value_filter::Operator;
+
+
+#[cfg(feature = "standalone")]
+fn sorted(mut v: Vec<String>) -> Vec<String> {
+    v.sort();
+    v
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn count_as_tests() {
+    let graph = common::simple_memory_graph();
+    let g = graph.g();
+
+    let got: Vec<String> = g.v(vec!["<alice>", "<bob>", "<greg>"]).count_as(&g.v(None).r#in("<follows>", None), "followers")
+        .iter_tags().map(|t| format!("{} {}", t["id"], t["followers"])).collect();
+    assert_eq!(sorted(vec![
+        "<alice> 0".to_string(),
+        "<bob> 3".to_string(),
+        "<greg> 2".to_string()
+    ]), sorted(got));
+
+    // counted links can be filtered on further on
+    let got: Vec<String> = g.v(None).has("<status>", "cool_person").count_as(&g.v(None).out("<follows>", None), "n")
+        .iter_tags().filter(|t| t["n"].as_i64().unwrap() > 0).map(|t| t["id"].to_string()).collect();
+    assert_eq!(vec!["<bob>", "<dani>"], sorted(got));
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn degree_tests() {
+    let graph = common::simple_memory_graph();
+    let g = graph.g();
+
+    let got: Vec<String> = g.v(None).degree("<follows>", Direction::Object, Operator::GTE, 2)
+        .iter_values().map(|v| v.to_string()).collect();
+    assert_eq!(vec!["<bob>", "<fred>", "<greg>"], sorted(got));
+
+    let got: Vec<String> = g.v(None).degree("<follows>", Direction::Subject, Operator::GT, 1)
+        .iter_values().map(|v| v.to_string()).collect();
+    assert_eq!(vec!["<charlie>", "<dani>"], sorted(got));
+
+    // bob follows fred and has three followers
+    let got: Vec<String> = g.v(None).degree_gt("<follows>", 3).iter_values().map(|v| v.to_string()).collect();
+    assert_eq!(vec!["<bob>"], sorted(got));
+
+    let got: Vec<String> = g.v(vec!["<alice>", "<bob>", "<emily>"]).degree_lte("<follows>", 1).iter_values().map(|v| v.to_string()).collect();
+    assert_eq!(vec!["<alice>", "<emily>"], sorted(got));
+
+    assert!(g.v("<bob>").degree_gte(None, 5).is("<bob>").count() == 1);
+    assert!(g.v("<bob>").degree_lt(None, 5).count() == 0);
+}
@@ -0,0 +1,146 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::cypher;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::GraphWrapper;
+
+
+// rows as strings in the order of the columns, "-" for null
+#[cfg(feature = "standalone")]
+fn rows(graph: &GraphWrapper, q: &str) -> Vec<Vec<String>> {
+    let res = cypher::query(graph.quad_store(), q).unwrap();
+    res.rows.iter().map(|r| {
+        res.columns.iter().map(|c| r.get(c).map_or("-".to_string(), |x| x.to_string())).collect()
+    }).collect()
+}
+
+#[cfg(feature = "standalone")]
+fn sorted(mut rows: Vec<Vec<String>>) -> Vec<Vec<String>> {
+    rows.sort();
+    rows
+}
+
+#[cfg(feature = "standalone")]
+fn expect(r: &[&[&str]]) -> Vec<Vec<String>> {
+    r.iter().map(|x| x.iter().map(|s| s.to_string()).collect()).collect()
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn cypher_match_and_where() {
+    let graph = common::simple_memory_graph();
+
+    assert_eq!(
+        sorted(rows(&graph, r#"MATCH (a)-[:<follows>]->(b)-[:<status>]->(s) WHERE s = "cool_person" RETURN a, b"#)),
+        expect(&[
+            &["<alice>", "<bob>"],
+            &["<charlie>", "<bob>"],
+            &["<charlie>", "<dani>"],
+            &["<dani>", "<bob>"],
+            &["<dani>", "<greg>"],
+            &["<fred>", "<greg>"]
+        ])
+    );
+
+    // properties, IRI nodes and comma separated patterns
+    assert_eq!(
+        sorted(rows(&graph, r#"MATCH (x {<status>: "cool_person"})-[:<follows>]->(y), (<charlie>)-->(x) RETURN *"#)),
+        expect(&[&["<bob>", "<fred>"], &["<dani>", "<bob>"], &["<dani>", "<greg>"]])
+    );
+
+    assert_eq!(
+        rows(&graph, r#"MATCH (x)-[:<status>]->(s) WHERE s =~ "smart.*" AND NOT x = <emily> RETURN x"#),
+        expect(&[&["<greg>"]])
+    );
+
+    // relationship variables and several types
+    assert_eq!(
+        rows(&graph, "MATCH (<greg>)<-[r]-(x) RETURN DISTINCT r"),
+        expect(&[&["<follows>"]])
+    );
+    assert_eq!(
+        rows(&graph, "MATCH (<bob>)-[r:<follows>|<status>]->(x) RETURN r, x ORDER BY r"),
+        expect(&[&["<follows>", "<fred>"], &["<status>", "cool_person"]])
+    );
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn cypher_variable_length() {
+    let graph = common::simple_memory_graph();
+
+    assert_eq!(
+        sorted(rows(&graph, "MATCH (<alice>)-[:<follows>*1..2]->(x) RETURN x")),
+        expect(&[&["<bob>"], &["<fred>"]])
+    );
+    assert_eq!(
+        sorted(rows(&graph, "MATCH (<alice>)-[:<follows>*]->(x) RETURN x")),
+        expect(&[&["<bob>"], &["<fred>"], &["<greg>"]])
+    );
+    assert_eq!(
+        sorted(rows(&graph, "MATCH (<alice>)-[:<follows>*0..1]->(x) RETURN x")),
+        expect(&[&["<alice>"], &["<bob>"]])
+    );
+
+    // followed in reverse from the bound end
+    assert_eq!(
+        sorted(rows(&graph, "MATCH (x)-[:<follows>*2]->(<greg>) RETURN x")),
+        expect(&[&["<bob>"], &["<charlie>"], &["<emily>"]])
+    );
+
+    // joined with the rows of the fixed patterns
+    assert_eq!(
+        sorted(rows(&graph, r#"MATCH (a {<status>: "cool_person"})-[:<follows>*2..3]->(b) RETURN a, b"#)),
+        expect(&[&["<bob>", "<greg>"], &["<dani>", "<fred>"]])
+    );
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn cypher_optional_and_modifiers() {
+    let graph = common::simple_memory_graph();
+
+    assert_eq!(
+        rows(&graph, "MATCH (p)-[:<follows>]->(<bob>) OPTIONAL MATCH (p)-[:<status>]->(s) RETURN p, s ORDER BY p"),
+        expect(&[&["<alice>", "-"], &["<charlie>", "-"], &["<dani>", "cool_person"]])
+    );
+
+    let res = cypher::query(
+        graph.quad_store(),
+        r#"MATCH (x {<status>: "cool_person"})-[:<follows>]->(y) RETURN x AS who, y ORDER BY who DESC, y SKIP 1 LIMIT 1"#
+    ).unwrap();
+    assert_eq!(res.columns, vec!["who".to_string(), "y".to_string()]);
+    assert_eq!(res.rows.len(), 1);
+    assert_eq!(res.rows[0]["who"].to_string(), "<dani>");
+    assert_eq!(res.rows[0]["y"].to_string(), "<greg>");
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn cypher_errors() {
+    let qs = common::simple_memory_graph().quad_store();
+
+    assert_eq!(
+        cypher::query(qs.clone(), "MATCH (a:Person) RETURN a").unwrap_err(),
+        "1:9: node labels are not supported, use a property"
+    );
+    assert_eq!(
+        cypher::query(qs.clone(), "MATCH (a)-[:<follows>]-(b) RETURN a").unwrap_err(),
+        "1:10: relationships need exactly one direction"
+    );
+    assert_eq!(
+        cypher::query(qs.clone(), "MATCH (a)-[:<follows>*]->(b) RETURN a").unwrap_err(),
+        "1:10: a variable-length relationship needs a matched node at one end"
+    );
+    assert_eq!(
+        cypher::query(qs.clone(), "MATCH (a)-[:<f>]->(b)\nRETURN c").unwrap_err(),
+        "2:8: unknown variable c"
+    );
+    assert!(cypher::query(qs.clone(), "MATCH (a)-[:<f>*3..1]->(<b>) RETURN a").is_err());
+    assert!(cypher::query(qs, "MATCH (a) RETURN a").is_err());
+}
@@ -0,0 +1,77 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::context::QueryContext;
+
+
+#[cfg(feature = "standalone")]
+fn sorted<T: std::fmt::Debug>(mut v: Vec<T>) -> Vec<T> {
+    v.sort_by_key(|x| format!("{:?}", x));
+    v
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn edges_tests() {
+    let g = common::simple_memory_graph().g();
+
+    let quads: Vec<Quad> = g.v("<bob>").out_e("<follows>", None).iter_quads().unwrap().collect();
+    assert_eq!(vec![Quad::new("<bob>", "<follows>", "<fred>", ())], quads);
+
+    let quads = sorted(g.v("<bob>").in_e("<follows>", None).iter_quads().unwrap().collect());
+    assert_eq!(sorted(vec![
+        Quad::new("<alice>", "<follows>", "<bob>", ()),
+        Quad::new("<charlie>", "<follows>", "<bob>", ()),
+        Quad::new("<dani>", "<follows>", "<bob>", ())
+    ]), quads);
+
+    // every predicate, both ways, with the labels of the quads
+    let quads = sorted(g.v("<greg>").both_e(None, None).iter_quads().unwrap().collect());
+    assert_eq!(sorted(vec![
+        Quad::new("<dani>", "<follows>", "<greg>", ()),
+        Quad::new("<fred>", "<follows>", "<greg>", ()),
+        Quad::new("<greg>", "<status>", "cool_person", ()),
+        Quad::new("<greg>", "<status>", "smart_person", "<smart_graph>")
+    ]), quads);
+    let all: Vec<Quad> = g.v(None).out_e(None, None).iter_quads().unwrap().collect();
+    assert_eq!(15, all.len());
+
+    // back to the nodes
+    let values: Vec<Value> = g.v("<bob>").in_e("<follows>", None).subject().iter_values().collect();
+    assert_eq!(sorted(vec![Value::from("<alice>"), Value::from("<charlie>"), Value::from("<dani>")]), sorted(values));
+    let values: Vec<Value> = g.v("<bob>").out_e("<follows>", None).object().iter_values().collect();
+    assert_eq!(vec![Value::from("<fred>")], values);
+    let values: Vec<Value> = g.v("<greg>").out_e(None, None).predicate().unique().iter_values().collect();
+    assert_eq!(vec![Value::from("<status>")], values);
+    let values: Vec<Value> = g.v("<emily>").out_e("<status>", None).label().iter_values().collect();
+    assert_eq!(vec![Value::from("<smart_graph>")], values);
+
+    // steps after the edge
+    let values: Vec<Value> = g.v("<alice>").out_e("<follows>", None).object().out("<status>", None).iter_values().collect();
+    assert_eq!(vec![Value::from("cool_person")], values);
+
+    // as a morphism, forwards and reversed
+    let via_edges = g.m().out_e("<follows>", None).object();
+    let values: Vec<Value> = g.v("<alice>").follow(&via_edges).iter_values().collect();
+    assert_eq!(vec![Value::from("<bob>")], values);
+    let values: Vec<Value> = g.v("<fred>").follow_r(&via_edges).iter_values().collect();
+    assert_eq!(sorted(vec![Value::from("<bob>"), Value::from("<emily>")]), sorted(values));
+
+    // a path that ends on nodes has no quads to return
+    assert!(g.v("<alice>").out("<follows>", None).iter_quads().is_err());
+    let quads: Vec<Quad> = g.v("<alice>").out_e("<follows>", None).tag("e").limit(1).iter_quads().unwrap().collect();
+    assert_eq!(vec![Quad::new("<alice>", "<follows>", "<bob>", ())], quads);
+
+    // the same with every error returned
+    let quads = sorted(g.v("<bob>").in_e("<follows>", None).try_iter_quads().unwrap());
+    assert_eq!(3, quads.len());
+    assert!(g.v("<alice>").out("<follows>", None).try_iter_quads().is_err());
+    let ctx = QueryContext::new().with_max_rows(1);
+    let r = g.v("<bob>").in_e("<follows>", None).with_context(ctx).try_iter_quads();
+    assert_eq!(Err("query returned more than 1 rows".to_string()), r);
+}
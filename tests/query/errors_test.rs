@@ -0,0 +1,84 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::context::QueryContext;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::Morphism;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::iterate::{EachIterator, TagEachIterator};
+#[cfg(feature = "standalone")]
+use gizmo_db::query::path::MorphismForPath;
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn try_iter_tests() {
+    let g = common::simple_memory_graph().g();
+
+    let mut expected: Vec<Value> = g.v("<bob>").r#in("<follows>", None).iter_values().collect();
+    let mut r: Vec<Value> = g
+        .v("<bob>")
+        .r#in("<follows>", None)
+        .try_iter_values()
+        .collect::<Result<Vec<Value>, String>>()
+        .unwrap();
+    expected.sort_by_key(|v| v.to_string());
+    r.sort_by_key(|v| v.to_string());
+    assert_eq!(r, expected);
+
+    let rows: Result<Vec<_>, String> = g.v("<bob>").r#in("<follows>", "x").try_iter_tags().collect();
+    let rows = rows.unwrap();
+    assert_eq!(rows.len(), expected.len());
+    assert!(rows.iter().all(|row| row.contains_key("x") && row.contains_key("id")));
+
+    // an error that stops the iterators comes out as the last item
+    let ctx = QueryContext::new().with_max_rows(1);
+    let r: Vec<Result<Value, String>> = g.v(None).with_context(ctx).try_iter_values().collect();
+    assert_eq!(r.len(), 2);
+    assert!(r[0].is_ok());
+    assert_eq!(r[1], Err("query returned more than 1 rows".to_string()));
+
+    let ctx = QueryContext::new().with_max_rows(1);
+    let r: Vec<_> = g.v(None).with_context(ctx).try_iter_tags().collect();
+    assert_eq!(r.len(), 2);
+    assert!(r[1].is_err());
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn try_iter_unresolved_tests() {
+    let g = common::simple_memory_graph().g();
+
+    // a quad has no value: iter_values drops it, try_iter_values says so
+    let edges = g.v("<bob>").out_e("<follows>", None);
+    assert_eq!(edges.iter_values().count(), 0);
+    let r: Vec<Result<Value, String>> = edges.try_iter_values().collect();
+    assert_eq!(r.len(), 1);
+    assert!(r[0].as_ref().is_err_and(|e| e.starts_with("could not resolve")));
+
+    let r: Vec<_> = g.v("<bob>").out_e("<follows>", "e").try_iter_tags().collect();
+    assert_eq!(r.len(), 1);
+    assert!(r[0].as_ref().is_err_and(|e| e.starts_with("could not resolve value of tag")));
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn iterator_err_tests() {
+    let graph = common::simple_memory_graph();
+    let path = graph.g().m().out("<follows>", None).tag("x");
+
+    // an error below a path comes out of the runners try_iter_values and try_iter_tags use
+    let failing = common::Test::new(false, Some("read failed".to_string()));
+    let it = MorphismForPath::new(path.path.clone(), graph.quad_store()).morph(failing);
+    let mut each = EachIterator::new(it.clone(), false, true);
+    assert_eq!(each.next(), None);
+    assert_eq!(each.err(), Some("read failed".to_string()));
+
+    let mut each = TagEachIterator::new(it, false, true);
+    assert!(each.next().is_none());
+    assert_eq!(each.err(), Some("read failed".to_string()));
+}
@@ -0,0 +1,57 @@
+#[cfg(feature = "standalone")]
+use super::common;
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn explain_tests() {
+    let g = common::simple_memory_graph().g();
+
+    let explanation = g
+        .v("<bob>")
+        .r#in("<follows>", None)
+        .tag("follower")
+        .has("<status>", "cool_person")
+        .explain();
+
+    let text = explanation.to_string();
+    assert!(text.contains("HasA"));
+    // the nodes are resolved up front, so their quads come straight from the index
+    assert!(text.contains("QuadIds"));
+    assert!(text.contains("follower"));
+
+    // the Ands are rebuilt by the optimizer
+    assert_eq!(explanation.kind, "And");
+    assert!(any_replaced(&explanation));
+    assert!(explanation.costs.is_ok());
+
+    let json = explanation.to_json();
+    assert_eq!(json["kind"], "And");
+    assert_eq!(json["replaced"], true);
+    assert!(json["sub"].as_array().unwrap().len() > 1);
+    assert!(json["costs"]["size"].is_number());
+}
+
+
+#[cfg(feature = "standalone")]
+fn any_replaced(e: &gizmo_db::graph::iterator::explain::Explanation) -> bool {
+    e.replaced || e.sub.iter().any(any_replaced)
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn explain_page_tests() {
+    let g = common::simple_memory_graph().g();
+
+    let explanation = g.v("<bob>").r#in("<follows>", None).limit(2).explain();
+    assert_eq!(explanation.kind, "Limit");
+    assert_eq!(explanation.sub.len(), 1);
+
+    let explanation = g.v("<bob>").r#in("<follows>", None).skip(1).explain();
+    assert_eq!(explanation.kind, "Skip");
+
+    // the plan explained is the one that runs
+    assert_eq!(2, g.v("<bob>").r#in("<follows>", None).limit(2).count());
+    assert_eq!(2, g.v("<bob>").r#in("<follows>", None).skip(1).count());
+}
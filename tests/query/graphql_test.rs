@@ -0,0 +1,118 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::graphql;
+#[cfg(feature = "standalone")]
+use serde_json::{json, Value as Json};
+
+
+#[cfg(feature = "standalone")]
+fn normalize(j: Json) -> Json {
+    match j {
+        Json::Array(a) => {
+            let mut a: Vec<Json> = a.into_iter().map(normalize).collect();
+            a.sort_by_key(|x| x.to_string());
+            Json::Array(a)
+        },
+        Json::Object(o) => Json::Object(o.into_iter().map(|(k, v)| (k, normalize(v))).collect()),
+        j => j
+    }
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn graphql_fields_and_nesting() {
+    let g = common::simple_memory_graph().g();
+
+    let res = graphql::query(&g, r#"{
+        nodes(id: <charlie>) {
+            id
+            follows {
+                id
+                status
+            }
+        }
+    }"#).unwrap();
+    assert_eq!(normalize(res), normalize(json!({
+        "nodes": [{
+            "id": "<charlie>",
+            "follows": [
+                {"id": "<bob>", "status": "cool_person"},
+                {"id": "<dani>", "status": "cool_person"}
+            ]
+        }]
+    })));
+
+    // aliases, IRI field names and several values for one field
+    let res = graphql::query(&g, r#"query Q {
+        people(id: [<greg>, <emily>]) {
+            name: id
+            <status>
+        }
+    }"#).unwrap();
+    assert_eq!(normalize(res), normalize(json!({
+        "people": [
+            {"name": "<emily>", "<status>": "smart_person"},
+            {"name": "<greg>", "<status>": ["cool_person", "smart_person"]}
+        ]
+    })));
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn graphql_directives() {
+    let g = common::simple_memory_graph().g();
+
+    let res = graphql::query(&g, r#"{
+        nodes(id: <bob>) {
+            id
+            followers: follows @rev { id }
+        }
+    }"#).unwrap();
+    assert_eq!(normalize(res), normalize(json!({
+        "nodes": [{
+            "id": "<bob>",
+            "followers": [{"id": "<alice>"}, {"id": "<charlie>"}, {"id": "<dani>"}]
+        }]
+    })));
+
+    // without @opt, nodes missing the field are left out
+    let res = graphql::query(&g, r#"{ nodes(follows: <bob>) { id status } }"#).unwrap();
+    assert_eq!(normalize(res), json!({"nodes": [{"id": "<dani>", "status": "cool_person"}]}));
+
+    let res = graphql::query(&g, r#"{ nodes(follows: <bob>) { id status @opt } }"#).unwrap();
+    assert_eq!(normalize(res), normalize(json!({
+        "nodes": [
+            {"id": "<alice>", "status": null},
+            {"id": "<charlie>", "status": null},
+            {"id": "<dani>", "status": "cool_person"}
+        ]
+    })));
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn graphql_pagination_and_errors() {
+    let g = common::simple_memory_graph().g();
+
+    let all = graphql::query(&g, r#"{ nodes(status: "cool_person") { id } }"#).unwrap();
+    let all = all["nodes"].as_array().unwrap().clone();
+    assert_eq!(all.len(), 3);
+
+    let page = graphql::query(&g, r#"{ nodes(status: "cool_person", first: 1, offset: 1) { id } }"#).unwrap();
+    assert_eq!(page["nodes"], json!([all[1].clone()]));
+
+    let res = graphql::query(&g, r#"{ nodes(id: <dani>) { id follows(first: 1) { id } } }"#).unwrap();
+    assert_eq!(res["nodes"][0]["follows"].as_array().unwrap().len(), 1);
+
+    assert_eq!(
+        graphql::query(&g, "{\n  nodes(id: <bob>) { id @unknown }\n}").unwrap_err(),
+        "2:26: unknown directive"
+    );
+    assert_eq!(graphql::query(&g, "{ nodes { id }").unwrap_err(), "1:15: expected a field name");
+    assert!(graphql::query(&g, "{ id }").is_err());
+    assert!(graphql::query(&g, r#"{ nodes(first: "x") { id } }"#).is_err());
+}
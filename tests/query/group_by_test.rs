@@ -0,0 +1,90 @@
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::{new_memory_graph, Aggregate, Group};
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::context::QueryContext;
+#[cfg(feature = "standalone")]
+use std::collections::HashMap;
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn group_by_tests() {
+    let graph = new_memory_graph();
+    graph.write(vec![
+        Quad::new("<alice>", "<dept>", "<eng>", ()),
+        Quad::new("<alice>", "<age>", 30, ()),
+        Quad::new("<bob>", "<dept>", "<eng>", ()),
+        Quad::new("<bob>", "<age>", 40, ()),
+        Quad::new("<carol>", "<dept>", "<ops>", ()),
+        Quad::new("<carol>", "<age>", 25.5, ()),
+        Quad::new("<dan>", "<dept>", "<ops>", ()),
+        Quad::new("<erin>", "<dept>", "<hr>", ())
+    ]);
+    let g = graph.g();
+
+    let mut groups = g.v(None).save("<dept>", "dept").save_opt("<age>", "age").group_by("dept").aggregate(vec![
+        ("people", Aggregate::Count),
+        ("total", Aggregate::Sum("age".into())),
+        ("youngest", Aggregate::Min("age".into())),
+        ("oldest", Aggregate::Max("age".into())),
+        ("mean", Aggregate::Avg("age".into())),
+        ("who", Aggregate::Collect("id".into()))
+    ]).unwrap();
+    groups.sort_by_key(|g| g.values["dept"].to_string());
+    for g in groups.iter_mut() {
+        g.collected.get_mut("who").unwrap().sort_by_key(|v| v.to_string());
+    }
+
+    assert_eq!(vec![
+        Group {
+            values: vec![
+                ("dept", Value::from("<eng>")),
+                ("people", Value::from(2)),
+                ("total", Value::from(70)),
+                ("youngest", Value::from(30)),
+                ("oldest", Value::from(40)),
+                ("mean", Value::from(35.0))
+            ].into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+            collected: vec![("who".to_string(), vec![Value::from("<alice>"), Value::from("<bob>")])].into_iter().collect()
+        },
+        // erin has no age, so there is nothing to add up
+        Group {
+            values: vec![
+                ("dept", Value::from("<hr>")),
+                ("people", Value::from(1))
+            ].into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+            collected: vec![("who".to_string(), vec![Value::from("<erin>")])].into_iter().collect()
+        },
+        Group {
+            values: vec![
+                ("dept", Value::from("<ops>")),
+                ("people", Value::from(2)),
+                ("total", Value::from(25.5)),
+                ("youngest", Value::from(25.5)),
+                ("oldest", Value::from(25.5)),
+                ("mean", Value::from(25.5))
+            ].into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+            collected: vec![("who".to_string(), vec![Value::from("<carol>"), Value::from("<dan>")])].into_iter().collect()
+        }
+    ], groups);
+
+    // without grouping tags every row is in one group
+    let groups = g.v(None).out("<age>", None).group_by(None).aggregate(vec![("n", Aggregate::Count), ("total", Aggregate::Sum("id".into()))]).unwrap();
+    let expected: HashMap<String, Value> = vec![
+        ("n".to_string(), Value::from(3)),
+        ("total".to_string(), Value::from(95.5))
+    ].into_iter().collect();
+    assert_eq!(1, groups.len());
+    assert_eq!(expected, groups[0].values);
+
+    // rows that can't be resolved make the aggregate fail instead of shrinking it
+    let r = g.v("<carol>").out_e("<age>", None).group_by(None).aggregate(vec![("n", Aggregate::Count)]);
+    assert!(r.is_err_and(|e| e.starts_with("could not resolve")));
+    let ctx = QueryContext::new().with_max_rows(2);
+    let r = g.v(None).out("<age>", None).with_context(ctx).group_by(None).aggregate(vec![("n", Aggregate::Count)]);
+    assert_eq!(Err("query returned more than 2 rows".to_string()), r);
+}
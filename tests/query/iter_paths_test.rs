@@ -0,0 +1,65 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::{Hop, HopDirection};
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+
+
+#[cfg(feature = "standalone")]
+fn hop(from: &str, predicate: &str, to: &str, direction: HopDirection) -> Hop {
+    Hop {
+        from: Value::from(from),
+        predicate: Value::from(predicate),
+        to: Value::from(to),
+        label: None,
+        direction
+    }
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn iter_paths_tests() {
+    let g = common::simple_memory_graph().g();
+
+    let paths: Vec<Vec<Hop>> = g.v("<alice>").out("<follows>", None).out("<follows>", None).iter_paths().collect();
+    assert_eq!(vec![vec![
+        hop("<alice>", "<follows>", "<bob>", HopDirection::Out),
+        hop("<bob>", "<follows>", "<fred>", HopDirection::Out)
+    ]], paths);
+
+    // every way to a result is its own path
+    let mut paths: Vec<Vec<Hop>> = g.v("<fred>").r#in("<follows>", None).r#in("<follows>", None).iter_paths().collect();
+    paths.sort_by_key(|p| p[1].to.to_string());
+    assert_eq!(vec![
+        vec![hop("<fred>", "<follows>", "<bob>", HopDirection::In), hop("<bob>", "<follows>", "<alice>", HopDirection::In)],
+        vec![hop("<fred>", "<follows>", "<bob>", HopDirection::In), hop("<bob>", "<follows>", "<charlie>", HopDirection::In)],
+        vec![hop("<fred>", "<follows>", "<bob>", HopDirection::In), hop("<bob>", "<follows>", "<dani>", HopDirection::In)]
+    ], paths);
+
+    // the hops of a followed path, and of both, in the order they were taken
+    let friend = g.m().out("<follows>", None);
+    let paths: Vec<Vec<Hop>> = g.v("<charlie>").follow(&friend).has("<status>", "cool_person").both("<status>", None).iter_paths().collect();
+    assert!(!paths.is_empty());
+    for p in &paths {
+        assert_eq!(2, p.len());
+        assert_eq!(Value::from("<charlie>"), p[0].from);
+        assert_eq!(p[0].to, p[1].from);
+        assert_eq!(HopDirection::Out, p[1].direction);
+        assert_eq!(Value::from("<status>"), p[1].predicate);
+    }
+
+    // labels of the links
+    let paths: Vec<Vec<Hop>> = g.v("<emily>").out("<status>", None).iter_paths().collect();
+    assert_eq!(Some(Value::from("<smart_graph>")), paths[0][0].label);
+
+    // tracking is only for iter_paths
+    let q = g.v("<alice>").out("<follows>", None).tag("x").out("<follows>", None);
+    assert_eq!(vec!["id", "x"], {
+        let mut keys: Vec<String> = q.iter_tags().next().unwrap().into_keys().collect();
+        keys.sort();
+        keys
+    });
+    assert_eq!(q.iter_paths().count(), q.iter_values().count());
+}
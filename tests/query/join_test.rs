@@ -0,0 +1,103 @@
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::new_memory_graph;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+#[cfg(feature = "standalone")]
+use std::collections::HashMap;
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn join_tests() {
+    let graph = new_memory_graph();
+    let mut quads = Vec::new();
+    let mut a = HashMap::new();
+    let mut b = HashMap::new();
+    for i in 0..300 {
+        let (x, y) = (i % 150, (i * 3) % 400);
+        quads.push(Quad::new(format!("<p{}>", i).as_str(), "<a>", format!("<x{}>", x).as_str(), ()));
+        quads.push(Quad::new(format!("<p{}>", i).as_str(), "<b>", format!("<x{}>", y).as_str(), ()));
+        *a.entry(x).or_insert(0) += 1;
+        *b.entry(y).or_insert(0) += 1;
+    }
+    graph.write(quads);
+    let g = graph.g();
+
+    // both sides are large and costly to check, so they are joined
+    let both = g.v(None).out("<a>", None).and(&g.v(None).out("<b>", None));
+    assert!(both.explain().to_string().starts_with("HashJoin"));
+
+    let mut expected: Vec<String> = a.keys().filter(|x| b.contains_key(x)).map(|x| format!("<x{}>", x)).collect();
+    expected.sort();
+    let mut got: Vec<String> = both.clone().unique().iter_values().map(|v| v.to_string()).collect();
+    got.sort();
+    assert_eq!(expected, got);
+
+    // every pair of paths to a node is a result, as with a nested loop
+    let paths: i64 = a.iter().map(|(x, n)| n * b.get(x).unwrap_or(&0)).sum();
+    assert_eq!(paths, both.clone().count());
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn triejoin_tests() {
+    let graph = new_memory_graph();
+    let links = [(1, 2), (2, 3), (3, 1), (1, 3), (3, 4), (4, 2), (2, 5), (5, 3)];
+    let mut quads: Vec<Quad> = links.iter()
+        .map(|(s, o)| Quad::new(format!("<n{}>", s).as_str(), "<follows>", format!("<n{}>", o).as_str(), ()))
+        .collect();
+    // the same link in a graph is a second path through it
+    quads.push(Quad::new("<n2>", "<follows>", "<n3>", "<g>"));
+    for i in 0..200 {
+        quads.push(Quad::new(format!("<m{}>", i).as_str(), "<likes>", format!("<m{}>", i + 1).as_str(), ()));
+    }
+    graph.write(quads);
+    let g = graph.g();
+
+    let mut all = links.to_vec();
+    all.push((2, 3));
+
+    // two hops from a to c through b, where c is also followed by someone:
+    // the patterns meet at b and c, so they are joined at once
+    let two_hops = g.v(None).tag("a").out("<follows>", None).tag("b").out("<follows>", None).and(&g.v(None).out("<follows>", None));
+    assert!(two_hops.explain().to_string().starts_with("TrieJoin"));
+
+    let mut expected = Vec::new();
+    for (a, b) in &all {
+        for (b2, c) in &all {
+            if b == b2 {
+                for _ in all.iter().filter(|(_, x)| x == c) {
+                    expected.push(format!("<n{}> <n{}> <n{}>", a, b, c));
+                }
+            }
+        }
+    }
+    expected.sort();
+    let mut got: Vec<String> = two_hops.iter_tags().map(|t| format!("{} {} {}", t["a"], t["b"], t["id"])).collect();
+    got.sort();
+    assert_eq!(expected, got);
+    assert_eq!(expected.len() as i64, two_hops.clone().count());
+
+    // meeting at a single node is left to the other joins
+    let one = g.v(None).out("<follows>", None).and(&g.v(None).r#in("<follows>", None));
+    assert!(!one.explain().to_string().contains("TrieJoin"));
+
+    // a pattern without a fixed node would read every quad of the store for
+    // two starting nodes, so it is checked one result at a time
+    let preds = vec![Value::from("<follows>"), Value::from("<likes>")];
+    let few = g.v(vec!["<n1>", "<n2>"]).tag("a").out(preds, None).tag("b").out("<follows>", None).and(&g.v(None).out("<follows>", None));
+    assert!(!few.explain().to_string().contains("TrieJoin"));
+    let mut got: Vec<String> = few.iter_tags().map(|t| format!("{} {} {}", t["a"], t["b"], t["id"])).collect();
+    got.sort();
+    let mut expected: Vec<String> = expected.into_iter().filter(|r| r.starts_with("<n1> ") || r.starts_with("<n2> ")).collect();
+    expected.dedup();
+    got.dedup();
+    assert_eq!(expected, got);
+
+    // the patterns read by the join are its sub-iterators
+    let text = two_hops.explain().to_string();
+    assert_eq!(4, text.lines().count(), "{}", text);
+}
@@ -0,0 +1,97 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::pat;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::bgp::{self, Bgp};
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+#[cfg(feature = "standalone")]
+use std::collections::HashMap;
+
+
+#[cfg(feature = "standalone")]
+fn columns(rows: Vec<HashMap<String, Value>>, vars: &[&str]) -> Vec<Vec<String>> {
+    let mut res: Vec<Vec<String>> = rows.iter().map(|r| {
+        vars.iter().map(|v| r.get(*v).map_or("-".to_string(), |x| x.to_string())).collect()
+    }).collect();
+    res.sort();
+    res
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn match_patterns_cycle() {
+    let graph = common::simple_memory_graph();
+    graph.write(vec![Quad::new("<greg>", "<follows>", "<charlie>", ())]);
+    let g = graph.g();
+
+    let rows = g.match_patterns(vec![
+        pat("?x", "<follows>", "?y"),
+        pat("?y", "<follows>", "?z"),
+        pat("?z", "<follows>", "?x")
+    ]).unwrap();
+
+    assert_eq!(columns(rows, &["x", "y", "z"]), vec![
+        vec!["<charlie>", "<dani>", "<greg>"],
+        vec!["<dani>", "<greg>", "<charlie>"],
+        vec!["<greg>", "<charlie>", "<dani>"]
+    ]);
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn match_patterns_constants() {
+    let graph = common::simple_memory_graph();
+    let g = graph.g();
+
+    let rows = g.match_patterns(vec![
+        pat("?x", "<follows>", "?y"),
+        pat("?y", "<status>", "smart_person")
+    ]).unwrap();
+    assert_eq!(columns(rows, &["x", "y"]), vec![
+        vec!["<dani>", "<greg>"],
+        vec!["<fred>", "<greg>"]
+    ]);
+
+    // a pattern without variables matches once or not at all
+    assert_eq!(g.match_patterns(vec![pat("<alice>", "<follows>", "<bob>")]).unwrap().len(), 1);
+    assert!(g.match_patterns(vec![pat("<alice>", "<follows>", "<greg>")]).unwrap().is_empty());
+    assert!(g.match_patterns(vec![]).is_err());
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn match_patterns_root_from_stats() {
+    let qs = common::simple_memory_graph().quad_store();
+
+    // two quads have the object "smart_person", eight have the predicate <follows>
+    let b = Bgp::new(vec![pat("?x", "<follows>", "?y"), pat("?y", "<status>", "smart_person")]);
+    assert_eq!(bgp::pick_root(qs.clone(), &b), Some("y".to_string()));
+
+    let b = Bgp::new(vec![pat("?x", "<follows>", "?y"), pat("?x", "<status>", "?s")]);
+    assert_eq!(bgp::pick_root(qs.clone(), &b), Some("x".to_string()));
+
+    // a value missing from the store matches nothing, ties go to the shared variable
+    let b = Bgp::new(vec![pat("?x", "<follows>", "?y"), pat("?z", "<likes>", "?y")]);
+    assert_eq!(bgp::pick_root(qs, &b), Some("y".to_string()));
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn match_patterns_cyclic_plan() {
+    // cyclic patterns are joined by a triejoin, others by trees of shapes
+    let b = Bgp::new(vec![pat("?x", "<follows>", "?y"), pat("?y", "<follows>", "?z"), pat("?z", "<follows>", "?x")]);
+    let plan = bgp::compile(&b, None).unwrap();
+    assert_eq!(plan.shapes()[0].borrow_mut().shape_type().to_string(), "TrieJoin");
+
+    let b = Bgp::new(vec![pat("?x", "<follows>", "?y"), pat("?y", "<follows>", "?z")]);
+    let plan = bgp::compile(&b, None).unwrap();
+    assert_eq!(plan.shapes()[0].borrow_mut().shape_type().to_string(), "Save");
+}
@@ -1,4 +1,28 @@
 mod gizmo_test;
 mod path_test;
+mod explain_test;
+mod profile_test;
+mod context_test;
+mod errors_test;
+mod parser_test;
+mod sparql_test;
+mod mql_test;
+mod graphql_test;
+mod cypher_test;
+mod match_test;
+mod rewrite_test;
+mod parallel_test;
+mod shortest_path_test;
+mod weighted_path_test;
+mod all_paths_test;
+mod iter_paths_test;
+mod edges_test;
+mod recursive_test;
+mod neighborhood_test;
+mod group_by_test;
+mod count_as_test;
+mod order_by_test;
+mod join_test;
+mod bitmap_test;
 
 use super::common;
\ No newline at end of file
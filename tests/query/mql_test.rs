@@ -0,0 +1,110 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::mql;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::GraphWrapper;
+#[cfg(feature = "standalone")]
+use serde_json::{json, Value as Json};
+
+
+// sorts every list so results can be compared regardless of iteration order
+#[cfg(feature = "standalone")]
+fn normalize(j: Json) -> Json {
+    match j {
+        Json::Array(a) => {
+            let mut a: Vec<Json> = a.into_iter().map(normalize).collect();
+            a.sort_by_key(|x| x.to_string());
+            Json::Array(a)
+        },
+        Json::Object(o) => Json::Object(o.into_iter().map(|(k, v)| (k, normalize(v))).collect()),
+        j => j
+    }
+}
+
+#[cfg(feature = "standalone")]
+fn run(graph: &GraphWrapper, template: &str) -> Json {
+    normalize(mql::query_str(graph.quad_store(), template).unwrap())
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn mql_constraints() {
+    let graph = common::simple_memory_graph();
+
+    assert_eq!(
+        run(&graph, r#"[{"id": null, "<status>": "cool_person"}]"#),
+        normalize(json!([
+            {"id": "<bob>", "<status>": "cool_person"},
+            {"id": "<dani>", "<status>": "cool_person"},
+            {"id": "<greg>", "<status>": "cool_person"}
+        ]))
+    );
+
+    // a bare object returns the first match
+    assert_eq!(
+        run(&graph, r#"{"id": "<bob>", "!<follows>": []}"#),
+        normalize(json!({"id": "<bob>", "!<follows>": ["<alice>", "<charlie>", "<dani>"]}))
+    );
+
+    assert_eq!(
+        run(&graph, r#"{"id": "<alice>", "<status>": null}"#),
+        Json::Null
+    );
+
+    assert_eq!(
+        run(&graph, r#"[{"id": "<greg>", "<status>": []}]"#),
+        normalize(json!([{"id": "<greg>", "<status>": ["cool_person", "smart_person"]}]))
+    );
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn mql_nested() {
+    let graph = common::simple_memory_graph();
+
+    assert_eq!(
+        run(&graph, r#"[{"id": "<charlie>", "<follows>": [{"id": null, "<status>": null}]}]"#),
+        normalize(json!([{
+            "id": "<charlie>",
+            "<follows>": [
+                {"id": "<bob>", "<status>": "cool_person"},
+                {"id": "<dani>", "<status>": "cool_person"}
+            ]
+        }]))
+    );
+
+    // nodes without an id key are matched but not returned
+    assert_eq!(
+        run(&graph, r#"[{"id": null, "<follows>": {"<follows>": {"id": "<greg>"}}}]"#),
+        normalize(json!([
+            {"id": "<bob>", "<follows>": {"<follows>": {"id": "<greg>"}}},
+            {"id": "<charlie>", "<follows>": {"<follows>": {"id": "<greg>"}}},
+            {"id": "<emily>", "<follows>": {"<follows>": {"id": "<greg>"}}}
+        ]))
+    );
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn mql_optional_and_errors() {
+    let graph = common::simple_memory_graph();
+
+    assert_eq!(
+        run(&graph, r#"[{"id": null, "<follows>": "<bob>", "<status>?": null}]"#),
+        normalize(json!([
+            {"id": "<alice>", "<follows>": "<bob>", "<status>?": null},
+            {"id": "<charlie>", "<follows>": "<bob>", "<status>?": null},
+            {"id": "<dani>", "<follows>": "<bob>", "<status>?": "cool_person"}
+        ]))
+    );
+
+    let qs = graph.quad_store();
+    assert!(mql::query_str(qs.clone(), r#"[{"id": null}, {"id": null}]"#).is_err());
+    assert!(mql::query_str(qs.clone(), r#"[{"id": {}}]"#).is_err());
+    assert!(mql::query_str(qs.clone(), r#"[{"<follows>": [null, null]}]"#).is_err());
+    assert!(mql::query_str(qs, r#"[{"id": null"#).is_err());
+}
@@ -0,0 +1,80 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+
+
+#[cfg(feature = "standalone")]
+fn sorted<T: std::fmt::Debug>(mut v: Vec<T>) -> Vec<T> {
+    v.sort_by_key(|x| format!("{:?}", x));
+    v
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn neighborhood_tests() {
+    let graph = common::simple_memory_graph();
+    let g = graph.g();
+
+    let got: Vec<(String, String)> = g.v("<fred>").neighborhood(1, "<follows>", "hops")
+        .iter_tags().map(|t| (t["id"].to_string(), t["hops"].to_string())).collect();
+    assert_eq!(vec![
+        ("<bob>".to_string(), "1".to_string()),
+        ("<emily>".to_string(), "1".to_string()),
+        ("<fred>".to_string(), "0".to_string()),
+        ("<greg>".to_string(), "1".to_string())
+    ], sorted(got));
+
+    // fred is two links away from itself, but only comes once
+    let got: Vec<(String, String)> = g.v("<fred>").neighborhood(2, "<follows>", "hops")
+        .iter_tags().map(|t| (t["id"].to_string(), t["hops"].to_string())).collect();
+    assert_eq!(vec![
+        ("<alice>".to_string(), "2".to_string()),
+        ("<bob>".to_string(), "1".to_string()),
+        ("<charlie>".to_string(), "2".to_string()),
+        ("<dani>".to_string(), "2".to_string()),
+        ("<emily>".to_string(), "1".to_string()),
+        ("<fred>".to_string(), "0".to_string()),
+        ("<greg>".to_string(), "1".to_string())
+    ], sorted(got));
+
+    let p = g.v("<greg>").neighborhood(1, None, "hops");
+    let got: Vec<Value> = p.iter_values().collect();
+    assert_eq!(sorted(vec![
+        Value::from("<greg>"),
+        Value::from("<dani>"),
+        Value::from("<fred>"),
+        Value::from("cool_person"),
+        Value::from("smart_person")
+    ]), sorted(got));
+
+    // no hops keeps the start nodes alone, at 0
+    let got: Vec<(String, String)> = g.v(vec!["<fred>", "<greg>"]).neighborhood(0, "<follows>", "hops")
+        .iter_tags().map(|t| (t["id"].to_string(), t["hops"].to_string())).collect();
+    assert_eq!(vec![
+        ("<fred>".to_string(), "0".to_string()),
+        ("<greg>".to_string(), "0".to_string())
+    ], sorted(got));
+    assert_eq!(1, g.v("<fred>").neighborhood(-1, None, None).count());
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn extract_subgraph_tests() {
+    let graph = common::simple_memory_graph();
+    let g = graph.g();
+
+    let sub = g.v("<fred>").neighborhood(1, "<follows>", None).extract_subgraph();
+    let got: Vec<Quad> = sub.read(None, None, None, None).collect();
+    assert_eq!(sorted(vec![
+        Quad::new("<bob>", "<follows>", "<fred>", ()),
+        Quad::new("<emily>", "<follows>", "<fred>", ()),
+        Quad::new("<fred>", "<follows>", "<greg>", ())
+    ]), sorted(got));
+
+    assert_eq!(vec![Value::from("<greg>")], sub.g().v("<emily>").out("<follows>", None).out("<follows>", None).iter_values().collect::<Vec<Value>>());
+}
@@ -0,0 +1,127 @@
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::new_memory_graph;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::sort::SortOrder;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::Aggregate;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+
+
+#[cfg(feature = "standalone")]
+fn people() -> gizmo_db::query::gizmo::GraphWrapper {
+    let graph = new_memory_graph();
+    graph.write(vec![
+        Quad::new("<alice>", "<age>", 30, ()),
+        Quad::new("<alice>", "<dept>", "eng", ()),
+        Quad::new("<alice>", "<joined>", "2020-03-01T10:00:00+02:00", ()),
+        Quad::new("<bob>", "<age>", 9, ()),
+        Quad::new("<bob>", "<dept>", "ops", ()),
+        Quad::new("<bob>", "<joined>", "2020-03-01T09:00:00Z", ()),
+        Quad::new("<carol>", "<age>", 100, ()),
+        Quad::new("<carol>", "<dept>", "eng", ()),
+        Quad::new("<carol>", "<joined>", "2019-12-31", ()),
+        Quad::new("<dan>", "<age>", 30, ()),
+        Quad::new("<dan>", "<dept>", "ops", ()),
+        Quad::new("<erin>", "<dept>", "eng", ()),
+        Quad::new("<erin>", "<age>", 25.5, ())
+    ]);
+    graph
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn order_by_tests() {
+    let graph = people();
+    let g = graph.g();
+
+    // numbers sort by value, not by their text
+    let got: Vec<String> = g.v(None).save("<age>", "age").order_by(("age", SortOrder::Asc))
+        .iter_tags().map(|t| t["id"].to_string()).collect();
+    assert_eq!(vec!["<bob>", "<erin>", "<alice>", "<dan>", "<carol>"], got);
+
+    let got: Vec<String> = g.v(None).save("<age>", "age").order_by(("age", SortOrder::Desc))
+        .iter_tags().map(|t| t["id"].to_string()).collect();
+    assert_eq!(vec!["<carol>", "<alice>", "<dan>", "<erin>", "<bob>"], got);
+
+    // later keys break ties of the earlier ones
+    let got: Vec<String> = g.v(None).save("<dept>", "dept").save("<age>", "age")
+        .order_by(vec![("dept", SortOrder::Asc), ("age", SortOrder::Desc)])
+        .iter_tags().map(|t| t["id"].to_string()).collect();
+    assert_eq!(vec!["<carol>", "<alice>", "<erin>", "<dan>", "<bob>"], got);
+
+    // an order alone sorts the nodes themselves
+    let got: Vec<String> = g.v(vec!["<dan>", "<alice>", "<carol>"]).order_by(SortOrder::Desc)
+        .iter_values().map(|v| v.to_string()).collect();
+    assert_eq!(vec!["<dan>", "<carol>", "<alice>"], got);
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn order_by_datetime_tests() {
+    let graph = people();
+    let g = graph.g();
+
+    // datetimes compare in time across zones, and those without one go first
+    let got: Vec<String> = g.v(vec!["<alice>", "<bob>", "<carol>", "<dan>", "<erin>"]).save_opt("<joined>", "joined").order_by(("joined", SortOrder::Asc))
+        .iter_tags().map(|t| t["id"].to_string()).collect();
+    assert_eq!(vec!["<dan>", "<erin>", "<carol>", "<alice>", "<bob>"], got);
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn order_by_limit_tests() {
+    let graph = people();
+    let g = graph.g();
+
+    let got: Vec<String> = g.v(None).save("<age>", "age").order_by(("age", SortOrder::Desc)).limit(2)
+        .iter_tags().map(|t| t["id"].to_string()).collect();
+    assert_eq!(vec!["<carol>", "<alice>"], got);
+
+    let got: Vec<String> = g.v(None).save("<age>", "age").order_by(("age", SortOrder::Asc)).skip(1).limit(2)
+        .iter_tags().map(|t| t["id"].to_string()).collect();
+    assert_eq!(vec!["<erin>", "<alice>"], got);
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn order_by_total_order_tests() {
+    let times = ["2020-01-01T10:00:00+05:00", "2020-01-01T06:00:00Z", "2020-01-01T08"];
+    // dates sort before other strings, whichever they are compared with first
+    let expected = vec!["<e0>", "<e1>", "<e2>"];
+
+    for first in 0..3 {
+        let graph = new_memory_graph();
+        let order: Vec<usize> = (0..3).map(|i| (first + i) % 3).collect();
+        graph.write(order.iter().map(|&i| Quad::new(format!("<e{}>", i).as_str(), "<t>", times[i], ())).collect());
+        let g = graph.g();
+
+        let got: Vec<String> = g.v(None).save("<t>", "t").order_by(("t", SortOrder::Asc))
+            .iter_tags().map(|t| t["id"].to_string()).collect();
+        assert_eq!(expected, got);
+
+        // group_by takes its minimum and maximum in the same order
+        let groups = g.v(None).save("<t>", "t").group_by(Vec::<&str>::new())
+            .aggregate(vec![("min", Aggregate::Min("t".into())), ("max", Aggregate::Max("t".into()))]).unwrap();
+        assert_eq!(Value::from(times[0]), groups[0].values["min"]);
+        assert_eq!(Value::from(times[2]), groups[0].values["max"]);
+    }
+
+    // integers and floats compare by value
+    let graph = new_memory_graph();
+    graph.write(vec![
+        Quad::new("<a>", "<n>", 9007199254740993i64, ()),
+        Quad::new("<b>", "<n>", 9007199254740992.0, ()),
+        Quad::new("<c>", "<n>", 2.5, ()),
+        Quad::new("<d>", "<n>", 2, ())
+    ]);
+    let got: Vec<String> = graph.g().v(None).save("<n>", "n").order_by(("n", SortOrder::Desc))
+        .iter_tags().map(|t| t["id"].to_string()).collect();
+    assert_eq!(vec!["<a>", "<b>", "<c>", "<d>"], got);
+}
@@ -0,0 +1,114 @@
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::new_memory_graph;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::context::{QueryContext, ContextError};
+#[cfg(feature = "standalone")]
+use std::time::Duration;
+use gizmo_db::query::parallel::{Plan, detach};
+use gizmo_db::query::shape::{Shape, Fixed, Intersect, Lookup, Unique};
+use gizmo_db::graph::refs::Ref;
+use gizmo_db::graph::value::Value;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn parallel_tests() {
+    let graph = new_memory_graph();
+    let mut quads = Vec::new();
+    for i in 0..300 {
+        quads.push(Quad::new(format!("<p{}>", i).as_str(), "<follows>", format!("<p{}>", (i * 7) % 300).as_str(), ()));
+        quads.push(Quad::new(format!("<p{}>", i).as_str(), "<status>", if i % 3 == 0 { "cool" } else { "plain" }, ()));
+    }
+    graph.write(quads);
+    let g = graph.g();
+
+    let names: Vec<String> = (0..300).map(|i| format!("<p{}>", i)).collect();
+    let names: Vec<&str> = names.iter().map(|s| s.as_str()).collect();
+
+    // parts of the starting nodes on each worker
+    let sorted = |mut v: Vec<std::collections::HashMap<String, gizmo_db::graph::value::Value>>| {
+        v.sort_by_key(|t| format!("{:?}", t.get("x")));
+        v
+    };
+    let expected = sorted(g.v(names.clone()).out("<follows>", None).tag("x").out("<status>", None).iter_tags().collect());
+    let res = sorted(g.v(names.clone()).out("<follows>", None).tag("x").out("<status>", None).parallel(4).iter_tags().collect());
+    assert_eq!(300, res.len());
+    assert_eq!(expected, res);
+    let text = g.v(names.clone()).out("<follows>", None).parallel(4).explain().to_string();
+    assert!(text.starts_with("Parallel"));
+
+    // the branches of a union
+    let cool = g.v("cool").r#in("<status>", None);
+    let expected: Vec<_> = g.v("<p1>").out("<follows>", None).union(&cool).iter_values().collect();
+    let res: Vec<_> = g.v("<p1>").out("<follows>", None).union(&cool).parallel(2).iter_values().collect();
+    assert_eq!(101, res.len());
+    assert_eq!(expected, res);
+    assert!(g.v("<p1>").out("<follows>", None).union(&cool).parallel(2).explain().to_string().starts_with("Parallel"));
+
+    // a single node isn't split
+    assert!(!g.v("<p1>").out("<follows>", None).parallel(4).explain().to_string().contains("Parallel"));
+
+    // a unique over the parallel part runs on the reader
+    let res = g.v(names.clone()).out("<status>", None).unique().parallel(3).count();
+    assert_eq!(2, res);
+    assert!(g.v(names.clone()).out("<status>", None).unique().parallel(3).explain().to_string().contains("Parallel"));
+
+    // the limits of a context hold across the workers
+    let ctx = QueryContext::new().with_max_rows(10);
+    let res: Vec<_> = g.v(names.clone()).out("<follows>", None).with_context(ctx.clone()).parallel(4).try_iter_values().collect();
+    assert_eq!(11, res.len());
+    assert_eq!(Err("query returned more than 10 rows".to_string()), res[10]);
+    assert_eq!(Some(ContextError::RowLimit(10)), ctx.err());
+
+    let ctx = QueryContext::new().with_max_materialized(5);
+    g.v(names.clone()).out("<status>", None).unique().with_context(ctx.clone()).parallel(3).count();
+    assert_eq!(Some(ContextError::MaterializeLimit(5)), ctx.err());
+
+    let ctx = QueryContext::new().with_timeout(Duration::from_secs(0));
+    let res = g.v(names.clone()).out("<follows>", None).with_context(ctx.clone()).parallel(4).count();
+    assert_eq!(0, res);
+    assert_eq!(Some(ContextError::Timeout), ctx.err());
+
+    let ctx = QueryContext::new().with_max_rows(1000);
+    let res = g.v(names.clone()).out("<follows>", None).with_context(ctx.clone()).parallel(4).count();
+    assert_eq!(300, res);
+    assert_eq!(None, ctx.err());
+}
+
+
+#[test]
+fn plan_tests() {
+    let seeds: Vec<Ref> = (0..100).map(Ref::new_i64_node).collect();
+    let shape: Rc<RefCell<dyn Shape>> = Intersect::new(vec![Fixed::new(seeds.clone()), Lookup::new(vec![Value::from("a")])]);
+    let plan = detach(&shape).unwrap();
+
+    let parts = plan.split(3).unwrap();
+    assert_eq!(3, parts.len());
+    let mut split = Vec::new();
+    for p in &parts {
+        match p {
+            Plan::Intersect(v) => match &v[0] {
+                Plan::Fixed(refs) => split.extend(refs.clone()),
+                _ => panic!("seeds not split")
+            },
+            _ => panic!("not an intersection")
+        }
+    }
+    assert_eq!(seeds, split);
+
+    // too few seeds to be worth it
+    assert!(plan.split(10).map(|p| p.len()) == Some(6));
+    let small = detach(&Fixed::new(seeds[..20].to_vec())).unwrap();
+    assert!(small.split(4).is_none());
+
+    // uniques need every result
+    let unique: Rc<RefCell<dyn Shape>> = Rc::new(RefCell::new(Unique { from: shape.clone() }));
+    assert!(detach(&unique).unwrap().split(4).is_none());
+
+    let back = plan.attach();
+    assert_eq!("Intersect", back.borrow_mut().shape_type().to_string());
+}
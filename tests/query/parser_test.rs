@@ -0,0 +1,148 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::parser::{self, Final, Output};
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+
+
+#[cfg(feature = "standalone")]
+fn values(src: &str, g: &gizmo::Graph) -> Vec<String> {
+    let q = parser::parse(g, src).unwrap();
+    let mut r: Vec<String> = q.path.iter_values().map(|v| v.to_string()).collect();
+    r.sort();
+    r
+}
+
+#[cfg(feature = "standalone")]
+fn sorted(v: Vec<Value>) -> Vec<String> {
+    let mut r: Vec<String> = v.iter().map(|v| v.to_string()).collect();
+    r.sort();
+    r
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn parse_steps() {
+    let g = common::simple_memory_graph().g();
+
+    assert_eq!(
+        values(r#"g.V("<alice>").Out("<follows>").All()"#, &g),
+        sorted(g.v("<alice>").out("<follows>", None).iter_values().collect())
+    );
+
+    assert_eq!(
+        values(r#"g.V("<bob>").In("<follows>").Has("<status>", "cool_person").All()"#, &g),
+        sorted(g.v("<bob>").r#in("<follows>", None).has("<status>", "cool_person").iter_values().collect())
+    );
+
+    // lower case steps, single quotes, comments and morphisms held in variables
+    let src = r#"
+        // people followed by people alice follows
+        var follows = g.M().out('<follows>');
+        g.V('<alice>').follow(follows).follow(follows).all()
+    "#;
+    let follows = g.m().out("<follows>", None);
+    assert_eq!(values(src, &g), sorted(g.v("<alice>").follow(&follows).follow(&follows).iter_values().collect()));
+
+    assert_eq!(
+        values(r#"g.V("<alice>", "<bob>").Out(["<follows>", "<status>"]).Unique().All()"#, &g),
+        sorted(g.v(vec!["<alice>", "<bob>"]).out(vec![Value::from("<follows>"), Value::from("<status>")], None).unique().iter_values().collect())
+    );
+
+    assert_eq!(
+        values(r#"g.V().Has("<status>", "cool_person").Except(g.V("<bob>")).All()"#, &g),
+        sorted(g.v(None).has("<status>", "cool_person").except(&g.v("<bob>")).iter_values().collect())
+    );
+
+    assert_eq!(
+        values(r#"g.V("<alice>").FollowRecursive("<follows>", 2).All()"#, &g),
+        sorted(g.v("<alice>").follow_recursive("<follows>", None, Some(2)).iter_values().collect())
+    );
+
+    assert_eq!(
+        values(r#"g.V("<bob>").In("<follows>").Filter(like("al*"), lt("<d>")).All()"#, &g),
+        sorted(g.v("<bob>").r#in("<follows>", None).filter(vec![gizmo::like("al*"), gizmo::lt("<d>")]).iter_values().collect())
+    );
+
+    assert_eq!(
+        values(r#"g.V("<bob>").In("<follows>").Filter(regex("ar?li.*e")).All()"#, &g),
+        sorted(g.v("<bob>").r#in("<follows>", None).filter(gizmo::regex("ar?li.*e", false)).iter_values().collect())
+    );
+
+    // tags, back and save
+    let q = parser::parse(&g, r#"g.V("<bob>").Tag("target").In("<follows>").Save("<status>", "st").Back("target").All()"#).unwrap();
+    assert_eq!(q.final_step, Some(Final::All));
+    let rows: Vec<_> = q.path.iter_tags().collect();
+    assert!(!rows.is_empty());
+    assert!(rows.iter().all(|r| r["target"] == Value::from("<bob>") && r.contains_key("st")));
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn parse_finals() {
+    let g = common::simple_memory_graph().g();
+
+    let expected = g.v("<alice>").out("<follows>", None).count();
+
+    match parser::parse(&g, r#"g.V("<alice>").Out("<follows>").Count()"#).unwrap().run().unwrap() {
+        Output::Count(n) => assert_eq!(n, expected),
+        _ => panic!("expected a count")
+    }
+
+    match parser::parse(&g, r#"g.V().GetLimit(2)"#).unwrap().run().unwrap() {
+        Output::Tags(rows) => assert_eq!(rows.len(), 2),
+        _ => panic!("expected tags")
+    }
+
+    match parser::parse(&g, r#"g.V("<alice>").ToValue()"#).unwrap().run().unwrap() {
+        Output::Values(v) => assert_eq!(v, vec![Value::from("<alice>")]),
+        _ => panic!("expected values")
+    }
+
+    // no final runs like All()
+    match parser::parse(&g, r#"g.V("<alice>")"#).unwrap().run().unwrap() {
+        Output::Tags(rows) => assert_eq!(rows.len(), 1),
+        _ => panic!("expected tags")
+    }
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn parse_errors() {
+    let g = common::simple_memory_graph().g();
+
+    let e = parser::parse(&g, r#"g.V("<alice>").Outt("<follows>")"#).err().unwrap();
+    assert_eq!((e.line, e.column), (1, 16));
+    assert_eq!(e.message, "unknown step 'Outt'");
+    assert_eq!(e.to_string(), "1:16: unknown step 'Outt'");
+
+    let e = parser::parse(&g, "g.V(\"<alice>\")\n  .Out(\"<follows>\"").err().unwrap();
+    assert_eq!((e.line, e.column), (2, 19));
+
+    let e = parser::parse(&g, r#"g.V("<alice>).All()"#).err().unwrap();
+    assert_eq!(e.message, "unterminated string");
+    assert_eq!(e.pos, 4);
+
+    let e = parser::parse(&g, r#"g.V("<alice>").Limit("x")"#).err().unwrap();
+    assert_eq!(e.message, "expected an integer");
+    assert_eq!(e.column, 22);
+
+    let e = parser::parse(&g, r#"g.V().Follow(x)"#).err().unwrap();
+    assert_eq!(e.message, "unknown variable 'x'");
+
+    let e = parser::parse(&g, r#"g.V().All().Out("<follows>")"#).err().unwrap();
+    assert_eq!(e.message, "'Out' follows the end of the query");
+
+    let e = parser::parse(&g, r#"g.V().Filter(regex("("))"#).err().unwrap();
+    assert!(e.message.starts_with("invalid regular expression"));
+
+    assert!(parser::parse(&g, "var x = g.M();").is_err());
+    assert!(parser::parse(&g, r#"g.V().Has()"#).is_err());
+    assert!(parser::parse(&g, r#"g.V() g.V()"#).is_err());
+}
@@ -0,0 +1,49 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::context::QueryContext;
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn profile_tests() {
+    let g = common::simple_memory_graph().g();
+
+    let path = g
+        .v("<bob>")
+        .r#in("<follows>", None)
+        .tag("follower")
+        .has("<status>", "cool_person");
+
+    let expected: Vec<_> = path.iter_tags().collect();
+    let (rows, report) = path.profile();
+    let rows = rows.unwrap();
+
+    assert_eq!(rows, expected);
+
+    // every row came out of one successful next on the root
+    assert_eq!(report.actual.results, rows.len() as i64);
+    assert!(report.actual.next > report.actual.results);
+    assert!(report.estimated.is_ok());
+    assert!(!report.sub.is_empty());
+
+    // the And answers contains for its second branch
+    fn total_contains(r: &gizmo_db::graph::iterator::profile::Report) -> i64 {
+        r.actual.contains + r.sub.iter().map(total_contains).sum::<i64>()
+    }
+    assert!(total_contains(&report) > 0);
+
+    let text = report.to_string();
+    assert!(text.contains("HasA"));
+    assert!(text.contains("follower"));
+
+    let json = report.to_json();
+    assert_eq!(json["kind"], "And");
+    assert_eq!(json["actual"]["results"], rows.len() as i64);
+
+    // a run the context stops is an error, not fewer rows
+    let ctx = QueryContext::new().with_max_rows(1);
+    let (rows, report) = g.v(None).with_context(ctx).profile();
+    assert_eq!(rows, Err("query returned more than 1 rows".to_string()));
+    assert!(report.actual.next > 1);
+}
@@ -0,0 +1,126 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::recursive::{Emit, Order};
+#[cfg(feature = "standalone")]
+use gizmo_db::query::path::Recursion;
+
+
+#[cfg(feature = "standalone")]
+fn sorted(mut v: Vec<(String, Value)>) -> Vec<(String, Value)> {
+    v.sort_by_key(|x| x.0.clone());
+    v
+}
+
+
+#[cfg(feature = "standalone")]
+fn depths(p: &gizmo_db::query::gizmo::Path) -> Vec<(String, Value)> {
+    p.iter_tags().map(|t| (t["id"].to_string(), t["depth"].clone())).collect()
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn follow_recursive_with_tests() {
+    let graph = common::simple_memory_graph();
+    let g = graph.g();
+
+    let p = g.v("<charlie>").follow_recursive_with("<follows>", "depth", Recursion::new().with_min_depth(2));
+    assert_eq!(vec![
+        ("<fred>".to_string(), Value::from(2)),
+        ("<greg>".to_string(), Value::from(2))
+    ], sorted(depths(&p)));
+
+    // greg is the only one who follows nobody
+    let p = g.v("<charlie>").follow_recursive_with("<follows>", "depth", Recursion::new().with_emit(Emit::Leaves));
+    assert_eq!(vec![
+        ("<greg>".to_string(), Value::from(2))
+    ], sorted(depths(&p)));
+
+    let p = g.v("<charlie>").follow_recursive_with("<follows>", "depth", Recursion::new().with_until("<dani>"));
+    assert_eq!(vec![
+        ("<bob>".to_string(), Value::from(1)),
+        ("<dani>".to_string(), Value::from(1)),
+        ("<fred>".to_string(), Value::from(2)),
+        ("<greg>".to_string(), Value::from(3))
+    ], sorted(depths(&p)));
+
+    let p = g.v("<charlie>").follow_recursive_with("<follows>", "depth", Recursion::new()
+        .with_until(&g.v(None).has("<status>", "cool_person"))
+        .with_emit(Emit::Leaves));
+    assert_eq!(vec![
+        ("<bob>".to_string(), Value::from(1)),
+        ("<dani>".to_string(), Value::from(1))
+    ], sorted(depths(&p)));
+
+    let p = g.v("<charlie>").follow_recursive_with("<follows>", "depth", Recursion::new().with_max_depth(1).with_emit(Emit::Leaves));
+    assert_eq!(2, p.iter_values().count());
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn follow_recursive_order_tests() {
+    let graph = common::simple_memory_graph();
+    let g = graph.g();
+
+    let p = g.v("<charlie>").follow_recursive_with("<follows>", "depth", Recursion::new());
+    let d: Vec<Value> = depths(&p).into_iter().map(|x| x.1).collect();
+    assert_eq!(vec![Value::from(1), Value::from(1), Value::from(2), Value::from(2)], d);
+
+    // the first node is followed down before the other one at depth 1
+    let p = g.v("<charlie>").follow_recursive_with("<follows>", "depth", Recursion::new().with_order(Order::DepthFirst));
+    let d: Vec<Value> = depths(&p).into_iter().map(|x| x.1).collect();
+    assert_eq!(4, d.len());
+    assert_eq!(Value::from(1), d[0]);
+    assert_eq!(Value::from(2), d[1]);
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn follow_recursive_depth_first_tests() {
+    use gizmo_db::query::gizmo::new_memory_graph;
+    use gizmo_db::graph::quad::Quad;
+
+    let graph = new_memory_graph();
+    graph.write(vec![
+        Quad::new("<a>", "<to>", "<p>", ()),
+        Quad::new("<p>", "<to>", "<s>", ()),
+        Quad::new("<a>", "<to>", "<q>", ()),
+        Quad::new("<q>", "<to>", "<r>", ()),
+        Quad::new("<r>", "<to>", "<s>", ()),
+        Quad::new("<s>", "<to>", "<t>", ())
+    ]);
+    let g = graph.g();
+    let all = vec![
+        ("<p>".to_string(), Value::from(1)),
+        ("<q>".to_string(), Value::from(1)),
+        ("<r>".to_string(), Value::from(2)),
+        ("<s>".to_string(), Value::from(2)),
+        ("<t>".to_string(), Value::from(3))
+    ];
+
+    // depths are the fewest steps to a node whichever branch reaches it first
+    for order in [Order::BreadthFirst, Order::DepthFirst] {
+        let p = g.v("<a>").follow_recursive_with("<to>", "depth", Recursion::new().with_max_depth(3).with_order(order));
+        assert_eq!(all, sorted(depths(&p)));
+
+        let p = g.v("<a>").follow_recursive_with("<to>", "depth", Recursion::new().with_emit(Emit::Leaves).with_order(order));
+        assert_eq!(vec![("<t>".to_string(), Value::from(3))], depths(&p));
+
+        // where the traversal stops is a leaf too
+        let p = g.v("<a>").follow_recursive_with("<to>", "depth", Recursion::new().with_max_depth(2).with_emit(Emit::Leaves).with_order(order));
+        assert_eq!(vec![
+            ("<r>".to_string(), Value::from(2)),
+            ("<s>".to_string(), Value::from(2))
+        ], sorted(depths(&p)));
+    }
+
+    // one branch is returned whole before the next
+    let p = g.v("<a>").follow_recursive_with("<to>", "depth", Recursion::new().with_order(Order::DepthFirst));
+    let got: Vec<String> = depths(&p).into_iter().map(|x| x.0).collect();
+    assert_eq!(vec!["<p>", "<s>", "<t>", "<q>", "<r>"], got);
+}
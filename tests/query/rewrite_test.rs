@@ -0,0 +1,180 @@
+use gizmo_db::query::shape::{Shape, Lookup, Null, AllNodes, Intersect, Union, Save, Filter, Page, Sort, Unique, Quads, QuadFilter, NodesFrom, Wildcard, ValueFilter, Optimizer};
+use gizmo_db::query::rewrite::{Rules, FoldEmpty, CommonSubexpressions, OrderBySelectivity, PushDownFilters, TopK, key};
+use gizmo_db::graph::quad::{QuadStore, Quad, QuadWriter, IgnoreOptions, Direction};
+use gizmo_db::graph::memstore::quadstore::MemStore;
+use gizmo_db::graph::value::Value;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+
+fn lookup(names: &[&str]) -> Rc<RefCell<dyn Shape>> {
+    Lookup::new(names.iter().map(|n| Value::from(*n)).collect())
+}
+
+fn rewrite(rule: Box<dyn Optimizer>, s: Rc<RefCell<dyn Shape>>) -> Rc<RefCell<dyn Shape>> {
+    let rules = Rules::with(None, vec![rule]);
+    let n = s.borrow_mut().optimize(Some(&rules));
+    n.unwrap_or(s)
+}
+
+fn type_of(s: &Rc<RefCell<dyn Shape>>) -> String {
+    s.borrow_mut().shape_type().to_string()
+}
+
+fn children(s: &Rc<RefCell<dyn Shape>>) -> Vec<Rc<RefCell<dyn Shape>>> {
+    match s.borrow_mut().shape_type() {
+        gizmo_db::query::shape::ShapeType::Intersect(i) => i.0.clone(),
+        gizmo_db::query::shape::ShapeType::Union(u) => u.0.clone(),
+        _ => Vec::new()
+    }
+}
+
+fn wildcard() -> Vec<Rc<dyn ValueFilter>> {
+    vec![Rc::new(Wildcard::new("a%".to_string()))]
+}
+
+
+#[test]
+fn test_fold_empty() {
+    let s = rewrite(Box::new(FoldEmpty), Intersect::new(vec![lookup(&["a"]), lookup(&[])]));
+    assert_eq!("Null", type_of(&s));
+
+    // the empty branch of a union is dropped
+    let s = rewrite(Box::new(FoldEmpty), Rc::new(RefCell::new(Union(vec![lookup(&[]), lookup(&["a"])]))));
+    assert_eq!("Lookup", type_of(&s));
+
+    // a traversal from nothing finds nothing
+    let quads = Rc::new(RefCell::new(Quads(vec![QuadFilter::new_struct(Direction::Subject, Some(lookup(&[])))])));
+    let s = rewrite(Box::new(FoldEmpty), NodesFrom::new(Direction::Object, quads));
+    assert_eq!("Null", type_of(&s));
+
+    // all nodes only matter on their own
+    let s = rewrite(Box::new(FoldEmpty), Intersect::new(vec![AllNodes::new(), lookup(&["a", "b"])]));
+    assert_eq!("Lookup", type_of(&s));
+    let s = rewrite(Box::new(FoldEmpty), Intersect::new(vec![AllNodes::new(), AllNodes::new()]));
+    assert_eq!("AllNodes", type_of(&s));
+
+    let s = rewrite(Box::new(FoldEmpty), Save::new(vec!["x".into()], Some(Null::new())));
+    assert_eq!("Null", type_of(&s));
+}
+
+
+#[test]
+fn test_common_subexpressions() {
+    let s = rewrite(Box::new(CommonSubexpressions), Intersect::new(vec![lookup(&["a"]), lookup(&["b"]), lookup(&["a"])]));
+    assert_eq!("Intersect", type_of(&s));
+    assert_eq!(vec![key(&lookup(&["a"])), key(&lookup(&["b"]))], children(&s).iter().map(key).collect::<Vec<_>>());
+
+    let s = rewrite(Box::new(CommonSubexpressions), Intersect::new(vec![lookup(&["a"]), lookup(&["a"])]));
+    assert_eq!("Lookup", type_of(&s));
+
+    // saved tags are kept even when they save the same nodes
+    let saved = || Save::new(vec!["x".into()], Some(lookup(&["a"]))) as Rc<RefCell<dyn Shape>>;
+    let s = rewrite(Box::new(CommonSubexpressions), Intersect::new(vec![saved(), saved(), lookup(&["a"])]));
+    assert_eq!(3, children(&s).len());
+
+    // the same traversal reached two ways is checked once
+    let from = || NodesFrom::new(Direction::Object, Rc::new(RefCell::new(Quads(vec![
+        QuadFilter::new_struct(Direction::Subject, Some(lookup(&["a"]))),
+        QuadFilter::new_struct(Direction::Predicate, Some(lookup(&["p"]))),
+        QuadFilter::new_struct(Direction::Subject, Some(lookup(&["a"])))
+    ])))) as Rc<RefCell<dyn Shape>>;
+    let s = rewrite(Box::new(CommonSubexpressions), Rc::new(RefCell::new(Unique { from: Intersect::new(vec![from(), from()]) })));
+    let from_key = "from(Object,quads(Subject=lookup([String(\"a\")]),Predicate=lookup([String(\"p\")])))";
+    assert_eq!(Some(format!("unique({})", from_key)), key(&s));
+
+    // a traversal can reach a node several ways, and each of them is a result
+    let s = rewrite(Box::new(CommonSubexpressions), Intersect::new(vec![from(), from()]));
+    assert_eq!(vec![Some(from_key.to_string()); 2], children(&s).iter().map(key).collect::<Vec<_>>());
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn test_common_subexpressions_paths() {
+    let g = super::common::simple_memory_graph().g();
+
+    let follows = g.v(None).out("<follows>", None);
+    assert_eq!(18, follows.clone().and(&follows).count());
+
+    let follows = g.v(vec!["<dani>", "<fred>"]).out("<follows>", None);
+    let mut got: Vec<String> = follows.clone().and(&follows).iter_values().map(|v| v.to_string()).collect();
+    got.sort();
+    assert_eq!(vec!["<bob>", "<greg>", "<greg>", "<greg>", "<greg>"], got);
+
+    assert_eq!(2, follows.clone().and(&follows).unique().count());
+}
+
+
+#[test]
+fn test_push_down_filters() {
+    let s = rewrite(Box::new(PushDownFilters), Filter::new(Rc::new(RefCell::new(Union(vec![lookup(&["a"]), lookup(&["b"])]))), wildcard()));
+    assert_eq!("Union", type_of(&s));
+    assert_eq!(vec!["Filter", "Filter"], children(&s).iter().map(type_of).collect::<Vec<_>>());
+
+    // only the shape driving the intersection is filtered
+    let s = rewrite(Box::new(PushDownFilters), Filter::new(Intersect::new(vec![lookup(&["a"]), lookup(&["b"])]), wildcard()));
+    assert_eq!("Intersect", type_of(&s));
+    assert_eq!(vec!["Filter", "Lookup"], children(&s).iter().map(type_of).collect::<Vec<_>>());
+
+    let s = rewrite(Box::new(PushDownFilters), Filter::new(Save::new(vec!["x".into()], Some(lookup(&["a"]))), wildcard()));
+    assert_eq!("Save", type_of(&s));
+
+    // a page counts the results it skips, so it is filtered after
+    let page = Rc::new(RefCell::new(Page { from: lookup(&["a"]), skip: 1, limit: 2 }));
+    let s = rewrite(Box::new(PushDownFilters), Filter::new(page, wildcard()));
+    assert_eq!("Filter", type_of(&s));
+}
+
+
+#[test]
+fn test_top_k() {
+    let limit_of = |s: &Rc<RefCell<dyn Shape>>| match s.borrow_mut().shape_type() {
+        gizmo_db::query::shape::ShapeType::Page(p) => match p.from.borrow_mut().shape_type() {
+            gizmo_db::query::shape::ShapeType::Sort(so) => so.limit,
+            _ => -1
+        },
+        _ => -1
+    };
+    let sort = || Rc::new(RefCell::new(Sort { from: lookup(&["a", "b"]), keys: Vec::new(), limit: 0 }));
+
+    let s = rewrite(Box::new(TopK), Rc::new(RefCell::new(Page { from: sort(), skip: 1, limit: 2 })));
+    assert_eq!(3, limit_of(&s));
+
+    // a page without a limit still needs every result sorted
+    let s = rewrite(Box::new(TopK), Rc::new(RefCell::new(Page { from: sort(), skip: 1, limit: 0 })));
+    assert_eq!(0, limit_of(&s));
+}
+
+
+#[test]
+fn test_order_by_selectivity() {
+    let qs = Rc::new(RefCell::new(MemStore::new()));
+    let qw = QuadWriter::new(qs.clone(), IgnoreOptions{ignore_dup: true, ignore_missing: true});
+    for i in 0..10 {
+        qw.add_quad(Quad::new("<alice>", "<follows>", format!("<p{}>", i).as_str(), ()));
+    }
+    qw.add_quad(Quad::new("<bob>", "<likes>", "<alice>", ()));
+    let qs: Rc<RefCell<dyn QuadStore>> = qs;
+
+    let via = |pred: &str| NodesFrom::new(Direction::Subject, Rc::new(RefCell::new(Quads(vec![
+        QuadFilter::new_struct(Direction::Predicate, Some(lookup(&[pred])))
+    ])))) as Rc<RefCell<dyn Shape>>;
+
+    let rules = Rules::with(Some(qs.clone()), vec![
+        Box::new(gizmo_db::query::shape::ResolveValues { qs: qs.clone() }),
+        Box::new(OrderBySelectivity { qs: qs.clone() })
+    ]);
+    let s = Intersect::new(vec![AllNodes::new(), via("<follows>"), lookup(&["<alice>", "<bob>"]), via("<likes>")]);
+    let n = s.borrow_mut().optimize(Some(&rules));
+    let s = n.unwrap_or(s);
+
+    let types: Vec<String> = children(&s).iter().map(type_of).collect();
+    assert_eq!(vec!["NodesFrom", "Fixed", "NodesFrom", "AllNodes"], types);
+    let first = children(&s)[0].clone();
+    assert!(key(&first).unwrap().contains("Predicate=fixed"));
+
+    let follows = via("<follows>");
+    assert!(follows.borrow_mut().optimize(Some(&rules)).is_none());
+    assert_eq!(10, gizmo_db::query::rewrite::estimate(&*qs.borrow(), &follows));
+}
@@ -0,0 +1,51 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::Route;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+
+
+#[cfg(feature = "standalone")]
+fn route(nodes: &[&str], predicates: &[&str]) -> Route {
+    Route {
+        nodes: nodes.iter().map(|n| Value::from(*n)).collect(),
+        predicates: predicates.iter().map(|p| Value::from(*p)).collect(),
+        cost: None
+    }
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn shortest_path_tests() {
+    let g = common::simple_memory_graph().g();
+
+    let routes: Vec<Route> = g.v("<alice>").shortest_path_to(&g.v("<greg>"), "<follows>", None).iter_routes().collect();
+    assert_eq!(vec![route(&["<alice>", "<bob>", "<fred>", "<greg>"], &["<follows>", "<follows>", "<follows>"])], routes);
+
+    // charlie has a shorter way to greg, through dani
+    let routes: Vec<Route> = g.v("<charlie>").shortest_path_to(&g.v(vec!["<greg>", "<fred>"]), "<follows>", None).iter_routes().collect();
+    assert_eq!(vec![
+        route(&["<charlie>", "<dani>", "<greg>"], &["<follows>", "<follows>"]),
+        route(&["<charlie>", "<bob>", "<fred>"], &["<follows>", "<follows>"])
+    ], routes);
+
+    // the start is its own target
+    let routes: Vec<Route> = g.v("<alice>").shortest_path_to(&g.v("<alice>"), "<follows>", None).iter_routes().collect();
+    assert_eq!(vec![route(&["<alice>"], &[])], routes);
+
+    // greg is three steps away from alice
+    assert_eq!(0, g.v("<alice>").shortest_path_to(&g.v("<greg>"), "<follows>", Some(2)).iter_values().count());
+
+    // any predicate, but only along the links
+    let values: Vec<Value> = g.v("<greg>").shortest_path_to(&g.v(None).has("<status>", "smart_person"), None, None).iter_values().collect();
+    assert_eq!(vec![Value::from("<greg>")], values);
+    let routes: Vec<Route> = g.v("<greg>").shortest_path_to(&g.v("<alice>"), "<follows>", None).iter_routes().collect();
+    assert!(routes.is_empty());
+
+    // tags from before the step are kept
+    let tags: Vec<_> = g.v("<alice>").tag("start").shortest_path_to(&g.v("<fred>"), "<follows>", None).iter_tags().collect();
+    assert_eq!(Some(&Value::from("<alice>")), tags[0].get("start"));
+    assert_eq!(Some(&Value::from("<bob>")), tags[0].get("path:1"));
+}
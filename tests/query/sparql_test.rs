@@ -0,0 +1,165 @@
+#[cfg(feature = "standalone")]
+use super::common;
+#[cfg(feature = "standalone")]
+use gizmo_db::query::sparql::{self, Results};
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::GraphWrapper;
+
+
+// rows as strings in the order of the selected variables, "-" for unbound
+#[cfg(feature = "standalone")]
+fn select(graph: &GraphWrapper, q: &str) -> Vec<Vec<String>> {
+    match sparql::query(graph.quad_store(), q).unwrap() {
+        Results::Bindings { vars, rows } => {
+            rows.iter().map(|r| {
+                vars.iter().map(|v| r.get(v).map_or("-".to_string(), |x| x.to_string())).collect()
+            }).collect()
+        },
+        Results::Boolean(_) => panic!("expected bindings")
+    }
+}
+
+#[cfg(feature = "standalone")]
+fn sorted(mut rows: Vec<Vec<String>>) -> Vec<Vec<String>> {
+    rows.sort();
+    rows
+}
+
+#[cfg(feature = "standalone")]
+fn rows(r: &[&[&str]]) -> Vec<Vec<String>> {
+    r.iter().map(|x| x.iter().map(|s| s.to_string()).collect()).collect()
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn select_basic_graph_pattern() {
+    let graph = common::simple_memory_graph();
+    let g = graph.g();
+
+    let mut expected: Vec<Vec<String>> = g.v("<alice>").out("<follows>", None).iter_values().map(|v| vec![v.to_string()]).collect();
+    expected.sort();
+    assert_eq!(sorted(select(&graph, "SELECT ?x WHERE { <alice> <follows> ?x }")), expected);
+
+    assert_eq!(
+        sorted(select(&graph, r#"SELECT ?a ?b WHERE { ?a <follows> ?b . ?b <status> "cool_person" }"#)),
+        rows(&[
+            &["<alice>", "<bob>"],
+            &["<charlie>", "<bob>"],
+            &["<charlie>", "<dani>"],
+            &["<dani>", "<bob>"],
+            &["<dani>", "<greg>"],
+            &["<fred>", "<greg>"]
+        ])
+    );
+
+    // ; and , lists, prefixes and a variable reached twice
+    assert_eq!(
+        select(&graph, "PREFIX : <> SELECT * WHERE { ?a :follows ?b , ?c . ?b :follows ?c }"),
+        rows(&[&["<charlie>", "<dani>", "<bob>"]])
+    );
+
+    assert_eq!(
+        select(&graph, r#"SELECT ?x WHERE { ?x <follows> <fred> ; <status> "cool_person" . }"#),
+        rows(&[&["<bob>"]])
+    );
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn select_filters() {
+    let graph = common::simple_memory_graph();
+
+    assert_eq!(
+        sorted(select(&graph, r#"SELECT ?x WHERE { ?x <status> ?s FILTER regex(?s, "^SMART", "i") }"#)),
+        rows(&[&["<emily>"], &["<greg>"]])
+    );
+
+    assert_eq!(
+        sorted(select(&graph, r#"SELECT ?x ?s WHERE { ?x <status> ?s FILTER(?s >= "d" && ?x != <emily>) }"#)),
+        rows(&[&["<greg>", "smart_person"]])
+    );
+
+    assert_eq!(
+        sorted(select(&graph, r#"SELECT ?x WHERE { ?x <follows> ?y FILTER(regex(str(?y), "^gr") || ?y = <fred>) }"#)),
+        rows(&[&["<bob>"], &["<dani>"], &["<emily>"], &["<fred>"]])
+    );
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn select_optional_and_union() {
+    let graph = common::simple_memory_graph();
+
+    assert_eq!(
+        sorted(select(&graph, "SELECT ?p ?s WHERE { ?p <follows> <bob> OPTIONAL { ?p <status> ?s } }")),
+        rows(&[&["<alice>", "-"], &["<charlie>", "-"], &["<dani>", "cool_person"]])
+    );
+
+    assert_eq!(
+        sorted(select(&graph, "SELECT ?p WHERE { ?p <follows> <bob> OPTIONAL { ?p <status> ?s } FILTER(!bound(?s)) }")),
+        rows(&[&["<alice>"], &["<charlie>"]])
+    );
+
+    assert_eq!(
+        sorted(select(&graph, r#"SELECT DISTINCT ?x WHERE { { ?x <follows> <greg> } UNION { ?x <status> "smart_person" } }"#)),
+        rows(&[&["<dani>"], &["<emily>"], &["<fred>"], &["<greg>"]])
+    );
+
+    // branches without a shared variable
+    assert_eq!(
+        sorted(select(&graph, r#"SELECT ?x ?y WHERE { { <alice> <follows> ?x } UNION { ?y <follows> <fred> } }"#)),
+        rows(&[&["-", "<bob>"], &["-", "<emily>"], &["<bob>", "-"]])
+    );
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn select_graph_and_modifiers() {
+    let graph = common::simple_memory_graph();
+
+    assert_eq!(
+        sorted(select(&graph, "SELECT ?x WHERE { GRAPH <smart_graph> { ?x <status> ?s } }")),
+        rows(&[&["<emily>"], &["<greg>"]])
+    );
+
+    assert_eq!(
+        select(&graph, r#"SELECT ?g WHERE { GRAPH ?g { <emily> <status> "smart_person" } }"#),
+        rows(&[&["<smart_graph>"]])
+    );
+
+    assert_eq!(
+        select(&graph, "SELECT ?x WHERE { ?x <follows> ?y } ORDER BY DESC(?x) LIMIT 2 OFFSET 1"),
+        rows(&[&["<emily>"], &["<dani>"]])
+    );
+
+    assert_eq!(
+        select(&graph, "SELECT DISTINCT ?y WHERE { ?x <follows> ?y } ORDER BY ?y"),
+        rows(&[&["<bob>"], &["<dani>"], &["<fred>"], &["<greg>"]])
+    );
+}
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn ask_and_errors() {
+    let graph = common::simple_memory_graph();
+    let qs = graph.quad_store();
+
+    assert_eq!(sparql::query(qs.clone(), "ASK { <alice> <follows> <bob> }"), Ok(Results::Boolean(true)));
+    assert_eq!(sparql::query(qs.clone(), "ASK { <alice> <follows> <greg> }"), Ok(Results::Boolean(false)));
+    assert_eq!(sparql::query(qs.clone(), "ASK WHERE { ?x <follows> ?x }"), Ok(Results::Boolean(false)));
+
+    assert_eq!(
+        sparql::query(qs.clone(), "SELECT ?x WHERE {\n  ?x ex:p ?y }").unwrap_err(),
+        "2:6: unknown prefix 'ex'"
+    );
+    assert_eq!(
+        sparql::query(qs.clone(), "SELECT ?x WHERE { ?x <follows> ?y } LIMIT").unwrap_err(),
+        "1:42: expected a non-negative integer"
+    );
+    assert!(sparql::query(qs, "SELECT ?x WHERE { ?x <p> ?y OPTIONAL { { ?x <q> ?z } UNION { ?x <r> ?z } } }").is_err());
+}
@@ -0,0 +1,54 @@
+#[cfg(feature = "standalone")]
+use gizmo_db::query::gizmo::{new_memory_graph, Route};
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::iterator::weighted_path::{Weight, Heuristic};
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::quad::Quad;
+#[cfg(feature = "standalone")]
+use gizmo_db::graph::value::Value;
+#[cfg(feature = "standalone")]
+use std::rc::Rc;
+
+
+#[cfg(feature = "standalone")]
+#[test]
+fn weighted_path_tests() {
+    let graph = new_memory_graph();
+    graph.write(vec![
+        // costs in the label
+        Quad::new("<a>", "<depends_on>", "<b>", 3),
+        Quad::new("<b>", "<depends_on>", "<d>", 3),
+        Quad::new("<a>", "<depends_on>", "<c>", 1),
+        Quad::new("<c>", "<depends_on>", "<d>", 4),
+
+        // and on reified edges
+        Quad::new("<x>", "<route>", "_:r1", ()),
+        Quad::new("_:r1", "<to>", "<y>", ()),
+        Quad::new("_:r1", "<km>", 12, ()),
+        Quad::new("<x>", "<route>", "_:r2", ()),
+        Quad::new("_:r2", "<to>", "<y>", ()),
+        Quad::new("_:r2", "<km>", 7.5, ())
+    ]);
+    let g = graph.g();
+
+    let routes: Vec<Route> = g.v("<a>").weighted_path_to(&g.v("<d>"), "<depends_on>", Weight::Label, None).iter_routes().collect();
+    assert_eq!(vec![Route {
+        nodes: vec![Value::from("<a>"), Value::from("<c>"), Value::from("<d>")],
+        predicates: vec![Value::from("<depends_on>"), Value::from("<depends_on>")],
+        cost: Some(5.0)
+    }], routes);
+
+    let weight = Weight::Edge { to: Value::from("<to>"), cost: Value::from("<km>") };
+    let routes: Vec<Route> = g.v("<x>").weighted_path_to(&g.v("<y>"), "<route>", weight, None).iter_routes().collect();
+    assert_eq!(1, routes.len());
+    assert_eq!(Value::from("_:r2"), routes[0].predicates[0]);
+    assert_eq!(Some(7.5), routes[0].cost);
+
+    // a heuristic that never overestimates finds the same paths
+    let zero: Heuristic = Rc::new(|_, _| 0.0);
+    let routes: Vec<Route> = g.v("<a>").weighted_path_to(&g.v("<d>"), "<depends_on>", Weight::Label, Some(zero)).iter_routes().collect();
+    assert_eq!(Some(5.0), routes[0].cost);
+
+    // only along the links
+    assert_eq!(0, g.v("<d>").weighted_path_to(&g.v("<a>"), "<depends_on>", Weight::Label, None).iter_values().count());
+}